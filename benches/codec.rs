@@ -0,0 +1,167 @@
+use bytes::{Bytes, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use pocket_relay_dump::{
+    capture::value::{encode_fields, Field, TdfValue},
+    servers::packet::{Packet, PacketCodec, PacketDebug},
+};
+use std::hint::black_box;
+use tokio_util::codec::{Decoder, Encoder};
+
+fn field(tag: &str, value: TdfValue) -> Field {
+    Field {
+        tag: tag.to_string(),
+        value,
+    }
+}
+
+/// Contents shaped like a login response, a handful of mixed fields
+fn small_contents() -> Bytes {
+    let fields = vec![
+        field("AGUP", TdfValue::VarInt(0)),
+        field("LDHT", TdfValue::String(String::new())),
+        field("NTOS", TdfValue::VarInt(0)),
+        field("PCTK", TdfValue::String("a".repeat(64))),
+        field(
+            "SESS",
+            TdfValue::Group(vec![
+                field("BUID", TdfValue::VarInt(978651371)),
+                field("FRST", TdfValue::VarInt(0)),
+                field(
+                    "KEY",
+                    TdfValue::String("11229301_9b171d92cc562b293e602ee8325612e7".into()),
+                ),
+                field("LLOG", TdfValue::VarInt(1403663841)),
+                field("MAIL", TdfValue::String("test@example.com".into())),
+                field(
+                    "PDTL",
+                    TdfValue::Group(vec![
+                        field("DSNM", TdfValue::String("Player".into())),
+                        field("LAST", TdfValue::VarInt(1403663841)),
+                        field("PID", TdfValue::VarInt(978651371)),
+                        field("STAS", TdfValue::VarInt(2)),
+                    ]),
+                ),
+                field("UID", TdfValue::VarInt(978651371)),
+            ]),
+        ),
+    ];
+    Bytes::from(encode_fields(&fields))
+}
+
+/// Contents shaped like a large stats response, big enough to need
+/// the jumbo length extension
+fn large_contents() -> Bytes {
+    let entries = (0..4000)
+        .map(|index| {
+            TdfValue::Group(vec![
+                field("ENID", TdfValue::VarInt(index)),
+                field("PNAM", TdfValue::String(format!("Player{}", index))),
+                field("RANK", TdfValue::VarInt(index + 1)),
+                field(
+                    "STAT",
+                    TdfValue::List((0..4).map(|v| TdfValue::String(v.to_string())).collect()),
+                ),
+            ])
+        })
+        .collect();
+    Bytes::from(encode_fields(&[field("LDLS", TdfValue::List(entries))]))
+}
+
+fn payloads() -> Vec<(&'static str, Packet)> {
+    vec![
+        ("small", Packet::new_notify(0x1, 0xA, small_contents())),
+        ("large", Packet::new_notify(0x7, 0x4, large_contents())),
+    ]
+}
+
+fn encoded(packet: &Packet) -> BytesMut {
+    let mut buffer = BytesMut::new();
+    packet.write(&mut buffer);
+    buffer
+}
+
+fn packet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packet");
+    for (name, packet) in payloads() {
+        let buffer = encoded(&packet);
+        group.throughput(Throughput::Bytes(buffer.len() as u64));
+
+        group.bench_function(format!("read/{}", name), |b| {
+            b.iter_batched(
+                || buffer.clone(),
+                |mut buffer| Packet::read(black_box(&mut buffer)),
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_function(format!("write/{}", name), |b| {
+            b.iter(|| {
+                let mut buffer = BytesMut::with_capacity(packet.encoded_length());
+                black_box(&packet).write(&mut buffer);
+                buffer
+            })
+        });
+    }
+    group.finish();
+}
+
+fn codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("codec");
+    for (name, packet) in payloads() {
+        let buffer = encoded(&packet);
+        group.throughput(Throughput::Bytes(buffer.len() as u64));
+
+        group.bench_function(format!("decode/{}", name), |b| {
+            b.iter_batched(
+                || buffer.clone(),
+                |mut buffer| PacketCodec.decode(black_box(&mut buffer)),
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_function(format!("encode/{}", name), |b| {
+            b.iter_batched(
+                || packet.clone(),
+                |packet| {
+                    let mut buffer = BytesMut::new();
+                    PacketCodec.encode(packet, &mut buffer).map(|_| buffer)
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn debug(c: &mut Criterion) {
+    let mut group = c.benchmark_group("debug");
+    for (name, packet) in payloads() {
+        group.bench_function(format!("header/{}", name), |b| {
+            b.iter(|| {
+                format!(
+                    "{:?}",
+                    PacketDebug {
+                        packet: black_box(&packet),
+                        contents: false,
+                    }
+                )
+            })
+        });
+
+        group.bench_function(format!("contents/{}", name), |b| {
+            b.iter(|| {
+                format!(
+                    "{:?}",
+                    PacketDebug {
+                        packet: black_box(&packet),
+                        contents: true,
+                    }
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, packet, codec, debug);
+criterion_main!(benches);
@@ -0,0 +1,228 @@
+//! Compares two capture sessions (official and Pocket Relay) by aligning
+//! their request/response pairs and collecting the payload differences
+
+use super::conformance::{diff, is_ignored, FieldMismatch};
+use crate::capture::{
+    packet_log::{pair_packets, read_packet_log, PacketPair, PacketRecord},
+    value::{decode_fields, fields_to_json},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::HashMap, io, path::Path};
+
+/// Comparison of two capture sessions
+#[derive(Serialize)]
+pub struct SessionComparison {
+    pub matched: usize,
+    pub mismatched: usize,
+    /// Exchanges only found in the left session
+    pub left_only: usize,
+    /// Exchanges only found in the right session
+    pub right_only: usize,
+    /// Aligned exchanges in the order they happened
+    pub exchanges: Vec<AlignedExchange>,
+}
+
+/// Request/response pair aligned with the matching pair from the other session
+#[derive(Serialize)]
+pub struct AlignedExchange {
+    pub component: u16,
+    pub command: u16,
+    /// Index of the request within the left session, None if the
+    /// exchange is only in the right session
+    pub left: Option<u64>,
+    /// Index of the request within the right session, None if the
+    /// exchange is only in the left session
+    pub right: Option<u64>,
+    #[serde(flatten)]
+    pub outcome: ExchangeOutcome,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum ExchangeOutcome {
+    /// Requests and responses matched
+    Match,
+    /// Requests or responses differed, paths are prefixed with
+    /// `request.` or `response.`
+    Mismatch { mismatches: Vec<FieldMismatch> },
+    /// The exchange only happened in the left session
+    LeftOnly,
+    /// The exchange only happened in the right session
+    RightOnly,
+}
+
+/// Loads the sessions in the `left` and `right` directories and aligns
+/// their exchanges. The nth exchange for a command in one session is
+/// aligned with the nth exchange for the same command in the other
+///
+/// `ignore` Field paths excluded from the comparison along with their
+///          children ("response.SESS.SKEY")
+pub fn compare_sessions(
+    left: &Path,
+    right: &Path,
+    ignore: &[String],
+) -> io::Result<SessionComparison> {
+    let left_packets = read_packet_log(left)?;
+    let right_packets = read_packet_log(right)?;
+    let left_pairs = pair_packets(&left_packets);
+    let right_pairs = pair_packets(&right_packets);
+
+    // Right pair for each left pair
+    let mut matches: Vec<Option<usize>> = vec![None; left_pairs.len()];
+    let mut right_matched = vec![false; right_pairs.len()];
+    {
+        let mut occurrences: HashMap<(u16, u16), Vec<usize>> = HashMap::new();
+        for (index, pair) in right_pairs.iter().enumerate().rev() {
+            occurrences
+                .entry((pair.request.component, pair.request.command))
+                .or_default()
+                .push(index);
+        }
+        for (index, pair) in left_pairs.iter().enumerate() {
+            let key = (pair.request.component, pair.request.command);
+            if let Some(right) = occurrences.get_mut(&key).and_then(Vec::pop) {
+                matches[index] = Some(right);
+                right_matched[right] = true;
+            }
+        }
+    }
+
+    let mut comparison = SessionComparison {
+        matched: 0,
+        mismatched: 0,
+        left_only: 0,
+        right_only: 0,
+        exchanges: Vec::new(),
+    };
+
+    // Exchanges only in the right session are placed before the next
+    // aligned exchange that follows them
+    let mut next_right = 0;
+    let mut push_right_only = |comparison: &mut SessionComparison, until: usize| {
+        while next_right < until {
+            if !right_matched[next_right] {
+                let request = right_pairs[next_right].request;
+                comparison.right_only += 1;
+                comparison.exchanges.push(AlignedExchange {
+                    component: request.component,
+                    command: request.command,
+                    left: None,
+                    right: Some(request.index),
+                    outcome: ExchangeOutcome::RightOnly,
+                });
+            }
+            next_right += 1;
+        }
+    };
+
+    for (left_pair, matched) in left_pairs.iter().zip(&matches) {
+        let request = left_pair.request;
+        let Some(right_index) = *matched else {
+            comparison.left_only += 1;
+            comparison.exchanges.push(AlignedExchange {
+                component: request.component,
+                command: request.command,
+                left: Some(request.index),
+                right: None,
+                outcome: ExchangeOutcome::LeftOnly,
+            });
+            continue;
+        };
+
+        push_right_only(&mut comparison, right_index + 1);
+
+        let right_pair = &right_pairs[right_index];
+        let outcome = compare_exchanges(left_pair, right_pair, ignore);
+        match outcome {
+            ExchangeOutcome::Match => comparison.matched += 1,
+            _ => comparison.mismatched += 1,
+        }
+        comparison.exchanges.push(AlignedExchange {
+            component: request.component,
+            command: request.command,
+            left: Some(request.index),
+            right: Some(right_pair.request.index),
+            outcome,
+        });
+    }
+    push_right_only(&mut comparison, right_pairs.len());
+
+    Ok(comparison)
+}
+
+/// Compares the requests and the responses of two aligned exchanges
+fn compare_exchanges(left: &PacketPair, right: &PacketPair, ignore: &[String]) -> ExchangeOutcome {
+    let mut mismatches = Vec::new();
+
+    compare_packets(
+        "request",
+        Some(left.request),
+        Some(right.request),
+        &mut mismatches,
+    );
+    compare_packets("response", left.response, right.response, &mut mismatches);
+
+    mismatches.retain(|mismatch| !is_ignored(&mismatch.path, ignore));
+
+    if mismatches.is_empty() {
+        ExchangeOutcome::Match
+    } else {
+        ExchangeOutcome::Mismatch { mismatches }
+    }
+}
+
+/// Collects the differences between two packets prefixing the paths
+/// with `prefix`, the left packet is the expected value
+fn compare_packets(
+    prefix: &str,
+    left: Option<&PacketRecord>,
+    right: Option<&PacketRecord>,
+    output: &mut Vec<FieldMismatch>,
+) {
+    let (left, right) = match (left, right) {
+        (Some(left), Some(right)) => (left, right),
+        (None, None) => return,
+        (left, right) => {
+            let describe = |record: Option<&PacketRecord>| record.map(|_| Value::from("present"));
+            output.push(FieldMismatch {
+                path: prefix.to_string(),
+                expected: describe(left),
+                actual: describe(right),
+            });
+            return;
+        }
+    };
+
+    if left.ty != right.ty {
+        output.push(FieldMismatch {
+            path: format!("{}.$type", prefix),
+            expected: Some(Value::from(left.ty)),
+            actual: Some(Value::from(right.ty)),
+        });
+    }
+    if left.error != right.error {
+        output.push(FieldMismatch {
+            path: format!("{}.$error", prefix),
+            expected: Some(Value::from(left.error)),
+            actual: Some(Value::from(right.error)),
+        });
+    }
+
+    // Contents can only be compared when both sessions captured them
+    let (Some(left_value), Some(right_value)) = (decode_record(left), decode_record(right)) else {
+        return;
+    };
+    diff(prefix, &left_value, &right_value, output);
+}
+
+/// Decodes the record contents as JSON, undecodable contents are
+/// compared as their hex string
+fn decode_record(record: &PacketRecord) -> Option<Value> {
+    let hex = record.contents.as_deref()?;
+    let packet = record.to_packet();
+    Some(match decode_fields(&packet.contents) {
+        Ok(fields) => fields_to_json(&fields),
+        Err(_) => Value::from(hex),
+    })
+}
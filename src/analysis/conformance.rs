@@ -0,0 +1,361 @@
+//! Replays the requests from a capture session against a Pocket Relay
+//! server and compares its responses with the official responses that
+//! were recorded, producing a compatibility scorecard
+
+use crate::{
+    capture::{
+        packet_log::{pair_packets, read_packet_log, PacketRecord},
+        value::{decode_fields, fields_to_json},
+    },
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::{FrameType, Packet, PacketCodec},
+    },
+};
+use futures_util::{SinkExt, StreamExt};
+use hyper::{header, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, io, path::Path, time::Duration};
+use thiserror::Error;
+use tokio::time::timeout;
+use tokio_util::codec::Framed;
+
+/// The file name of the report within the session directory
+pub const REPORT_FILE: &str = "conformance.json";
+
+/// Pocket Relay endpoint for upgrading a HTTP connection to Blaze
+const UPGRADE_ENDPOINT: &str = "/api/server/upgrade";
+
+/// Maximum time to wait for each response from the server
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors that can prevent the conformance check from running
+#[derive(Debug, Error)]
+pub enum ConformanceError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to connect to server: {0}")]
+    Connect(#[from] reqwest::Error),
+    #[error("Server refused the upgrade with status {0}")]
+    UpgradeRefused(StatusCode),
+}
+
+/// Options for the conformance check
+pub struct ConformanceOptions {
+    /// Base URL of the Pocket Relay server
+    pub url: String,
+    /// Field paths that differ between sessions (tokens, IDs, timestamps)
+    /// which are excluded from the comparison along with their children
+    pub ignore: Vec<String>,
+}
+
+/// Compatibility scorecard for a capture session
+#[derive(Serialize)]
+pub struct ConformanceReport {
+    /// The server that was checked
+    pub url: String,
+    pub total: usize,
+    pub matched: usize,
+    pub mismatched: usize,
+    pub failed: usize,
+    /// Percentage of requests with matching responses
+    pub score: f64,
+    /// Results grouped by command
+    pub commands: Vec<CommandScore>,
+    /// Result of each replayed request
+    pub cases: Vec<CaseResult>,
+}
+
+/// Results for a single command
+#[derive(Serialize)]
+pub struct CommandScore {
+    pub component: u16,
+    pub command: u16,
+    pub component_name: Option<&'static str>,
+    pub command_name: Option<&'static str>,
+    pub total: usize,
+    pub matched: usize,
+    pub mismatched: usize,
+    pub failed: usize,
+}
+
+/// Result of replaying a single request
+#[derive(Serialize)]
+pub struct CaseResult {
+    /// Index of the request within the capture session
+    pub index: u64,
+    pub component: u16,
+    pub command: u16,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Response matched the official response
+    Match,
+    /// Response differed from the official response
+    Mismatch { mismatches: Vec<FieldMismatch> },
+    /// No response was received
+    Failed { reason: String },
+}
+
+/// Difference between the official response and the server response
+#[derive(Serialize)]
+pub struct FieldMismatch {
+    /// Path to the field, `$type` and `$error` for the frame header
+    pub path: String,
+    /// Value in the official response, None if missing
+    pub expected: Option<Value>,
+    /// Value in the server response, None if missing
+    pub actual: Option<Value>,
+}
+
+/// Replays the recorded requests from the session in `dir` against the
+/// server in order over a single connection and writes the report to the
+/// session directory. Requests without a recorded response are skipped
+pub async fn check_conformance(
+    dir: &Path,
+    options: &ConformanceOptions,
+) -> Result<ConformanceReport, ConformanceError> {
+    let packets = read_packet_log(dir)?;
+    let mut framed = connect(&options.url).await?;
+    let mut cases = Vec::new();
+
+    for pair in pair_packets(&packets) {
+        let Some(expected) = pair.response else {
+            continue;
+        };
+        if pair.request.contents.is_none() || expected.contents.is_none() {
+            continue;
+        }
+
+        let request = pair.request;
+        let outcome = match replay(&mut framed, request).await {
+            Ok(actual) => compare(expected, &actual, &options.ignore),
+            Err(reason) => Outcome::Failed { reason },
+        };
+
+        cases.push(CaseResult {
+            index: request.index,
+            component: request.component,
+            command: request.command,
+            outcome,
+        });
+    }
+
+    _ = framed.close().await;
+
+    let report = create_report(&options.url, cases);
+    std::fs::write(dir.join(REPORT_FILE), serde_json::to_vec_pretty(&report)?)?;
+    Ok(report)
+}
+
+/// Connects to the Pocket Relay server upgrading the HTTP connection
+/// to a Blaze connection
+pub(crate) async fn connect(
+    url: &str,
+) -> Result<Framed<reqwest::Upgraded, PacketCodec>, ConformanceError> {
+    let url = format!("{}{}", url.trim_end_matches('/'), UPGRADE_ENDPOINT);
+    let response = reqwest::Client::new()
+        .get(url)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "blaze")
+        .send()
+        .await?;
+
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(ConformanceError::UpgradeRefused(response.status()));
+    }
+
+    let upgraded = response.upgrade().await?;
+    Ok(Framed::new(upgraded, PacketCodec))
+}
+
+/// Sends the recorded request and waits for the response with the same
+/// sequence number, notifications received in the meantime are ignored
+async fn replay(
+    framed: &mut Framed<reqwest::Upgraded, PacketCodec>,
+    request: &PacketRecord,
+) -> Result<Packet, String> {
+    let packet = request.to_packet();
+    let seq = packet.frame.seq;
+    framed
+        .send(packet)
+        .await
+        .map_err(|err| format!("Failed to send request: {}", err))?;
+
+    let response = timeout(RESPONSE_TIMEOUT, async {
+        while let Some(packet) = framed.next().await {
+            let packet = packet.map_err(|err| format!("Failed to read response: {}", err))?;
+            let frame = &packet.frame;
+            if frame.seq == seq && matches!(frame.ty, FrameType::Response | FrameType::Error) {
+                return Ok(packet);
+            }
+        }
+        Err("Server closed the connection".to_string())
+    })
+    .await;
+
+    response.unwrap_or_else(|_| Err(format!("No response after {:?}", RESPONSE_TIMEOUT)))
+}
+
+/// Compares the server response with the official response
+fn compare(expected: &PacketRecord, actual: &Packet, ignore: &[String]) -> Outcome {
+    let mut mismatches = Vec::new();
+
+    let expected_packet = expected.to_packet();
+    if expected_packet.frame.ty != actual.frame.ty {
+        mismatches.push(FieldMismatch {
+            path: "$type".to_string(),
+            expected: Some(Value::from(format!("{:?}", expected_packet.frame.ty))),
+            actual: Some(Value::from(format!("{:?}", actual.frame.ty))),
+        });
+    }
+    if expected_packet.frame.error != actual.frame.error {
+        mismatches.push(FieldMismatch {
+            path: "$error".to_string(),
+            expected: Some(Value::from(expected_packet.frame.error)),
+            actual: Some(Value::from(actual.frame.error)),
+        });
+    }
+
+    let decode = |contents: &[u8]| {
+        decode_fields(contents)
+            .map(|fields| fields_to_json(&fields))
+            .map_err(|err| err.to_string())
+    };
+
+    match (decode(&expected_packet.contents), decode(&actual.contents)) {
+        (Ok(expected), Ok(actual)) => diff("", &expected, &actual, &mut mismatches),
+        (_, Err(err)) => {
+            return Outcome::Failed {
+                reason: format!("Failed to decode response: {}", err),
+            }
+        }
+        (Err(err), _) => {
+            return Outcome::Failed {
+                reason: format!("Failed to decode official response: {}", err),
+            }
+        }
+    }
+
+    mismatches.retain(|mismatch| !is_ignored(&mismatch.path, ignore));
+
+    if mismatches.is_empty() {
+        Outcome::Match
+    } else {
+        Outcome::Mismatch { mismatches }
+    }
+}
+
+/// Whether the path is one of the ignored paths or within one
+pub(crate) fn is_ignored(path: &str, ignore: &[String]) -> bool {
+    ignore.iter().any(|ignored| {
+        path.strip_prefix(ignored.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+    })
+}
+
+/// Recursively collects the differences between two decoded values
+pub(crate) fn diff(path: &str, expected: &Value, actual: &Value, output: &mut Vec<FieldMismatch>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, expected_value) in expected {
+                let path = join_path(path, key);
+                match actual.get(key) {
+                    Some(actual_value) => diff(&path, expected_value, actual_value, output),
+                    None => output.push(FieldMismatch {
+                        path,
+                        expected: Some(expected_value.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+            for (key, actual_value) in actual {
+                if !expected.contains_key(key) {
+                    output.push(FieldMismatch {
+                        path: join_path(path, key),
+                        expected: None,
+                        actual: Some(actual_value.clone()),
+                    });
+                }
+            }
+        }
+        (Value::Array(expected_values), Value::Array(actual_values))
+            if expected_values.len() == actual_values.len() =>
+        {
+            for (index, (expected, actual)) in expected_values.iter().zip(actual_values).enumerate()
+            {
+                diff(&format!("{}[{}]", path, index), expected, actual, output);
+            }
+        }
+        (expected, actual) if expected != actual => output.push(FieldMismatch {
+            path: path.to_string(),
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        }),
+        _ => {}
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Creates the scorecard from the case results
+fn create_report(url: &str, cases: Vec<CaseResult>) -> ConformanceReport {
+    let mut commands: BTreeMap<(u16, u16), CommandScore> = BTreeMap::new();
+
+    for case in &cases {
+        let score = commands
+            .entry((case.component, case.command))
+            .or_insert_with(|| CommandScore {
+                component: case.component,
+                command: case.command,
+                component_name: get_component_name(case.component),
+                command_name: get_command_name(component_key(case.component, case.command), false),
+                total: 0,
+                matched: 0,
+                mismatched: 0,
+                failed: 0,
+            });
+
+        score.total += 1;
+        match case.outcome {
+            Outcome::Match => score.matched += 1,
+            Outcome::Mismatch { .. } => score.mismatched += 1,
+            Outcome::Failed { .. } => score.failed += 1,
+        }
+    }
+
+    let commands: Vec<CommandScore> = commands.into_values().collect();
+    let total = cases.len();
+    let matched = commands.iter().map(|score| score.matched).sum();
+    let mismatched = commands.iter().map(|score| score.mismatched).sum();
+    let failed = commands.iter().map(|score| score.failed).sum();
+    let score = if total == 0 {
+        0.0
+    } else {
+        matched as f64 / total as f64 * 100.0
+    };
+
+    ConformanceReport {
+        url: url.to_string(),
+        total,
+        matched,
+        mismatched,
+        failed,
+        score,
+        commands,
+        cases,
+    }
+}
@@ -0,0 +1,5 @@
+//! Offline analysis of capture sessions
+
+pub mod compare;
+pub mod conformance;
+pub mod notifications;
@@ -0,0 +1,136 @@
+//! Extracts the notifications from a capture session grouped by the request
+//! that most likely triggered them, and replays them to a test client at the
+//! recorded timing for developing notification handling in Pocket Relay
+
+use crate::{
+    capture::packet_log::PacketRecord,
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::{FrameType, PacketCodec},
+    },
+};
+use futures_util::{SinkExt, StreamExt};
+use log::debug;
+use serde::Serialize;
+use std::{collections::HashMap, io, net::SocketAddr, time::Duration};
+use tokio::{
+    net::TcpListener,
+    time::{sleep_until, Instant},
+};
+use tokio_util::codec::Framed;
+
+/// Notifications following the same request
+#[derive(Serialize)]
+pub struct NotificationGroup {
+    /// The last request sent on the connection before the notifications,
+    /// None for notifications received before any request
+    pub trigger: Option<PacketSummary>,
+    pub notifications: Vec<PacketSummary>,
+}
+
+/// Identifying details of a packet
+#[derive(Serialize)]
+pub struct PacketSummary {
+    /// Index of the packet within the capture session
+    pub index: u64,
+    /// Unix timestamp in microseconds the packet was captured
+    pub time: u64,
+    pub connection: u32,
+    pub component: u16,
+    pub command: u16,
+    pub component_name: Option<&'static str>,
+    pub command_name: Option<&'static str>,
+}
+
+impl PacketSummary {
+    fn new(record: &PacketRecord) -> Self {
+        let notify = FrameType::from(record.ty) == FrameType::Notify;
+        Self {
+            index: record.index,
+            time: record.time,
+            connection: record.connection,
+            component: record.component,
+            command: record.command,
+            component_name: get_component_name(record.component),
+            command_name: get_command_name(component_key(record.component, record.command), notify),
+        }
+    }
+}
+
+/// Groups the notifications in the packet log by the last request sent on
+/// their connection before they were received, in capture order
+pub fn group_notifications(packets: &[PacketRecord]) -> Vec<NotificationGroup> {
+    let mut groups: Vec<NotificationGroup> = Vec::new();
+    // Index of the group for the latest request on each connection
+    let mut current: HashMap<u32, usize> = HashMap::new();
+
+    for record in packets {
+        match FrameType::from(record.ty) {
+            FrameType::Request => {
+                current.insert(record.connection, groups.len());
+                groups.push(NotificationGroup {
+                    trigger: Some(PacketSummary::new(record)),
+                    notifications: Vec::new(),
+                });
+            }
+            FrameType::Notify => {
+                let index = *current.entry(record.connection).or_insert_with(|| {
+                    groups.push(NotificationGroup {
+                        trigger: None,
+                        notifications: Vec::new(),
+                    });
+                    groups.len() - 1
+                });
+                groups[index].notifications.push(PacketSummary::new(record));
+            }
+            FrameType::Response | FrameType::Error | FrameType::Unknown(_) => {}
+        }
+    }
+
+    groups.retain(|group| !group.notifications.is_empty());
+    groups
+}
+
+/// Waits for a client to connect on `addr` then sends it the notifications
+/// from the packet log with the same spacing they were recorded at. Only
+/// notifications for `component` are sent when provided. Anything sent by
+/// the client is read and discarded
+pub async fn replay_notifications(
+    packets: &[PacketRecord],
+    addr: SocketAddr,
+    component: Option<u16>,
+) -> io::Result<usize> {
+    let notifications: Vec<&PacketRecord> = packets
+        .iter()
+        .filter(|record| FrameType::from(record.ty) == FrameType::Notify)
+        .filter(|record| component.is_none_or(|component| record.component == component))
+        .filter(|record| record.contents.is_some())
+        .collect();
+
+    let listener = TcpListener::bind(addr).await?;
+    debug!("Waiting for client on {}", addr);
+    let (stream, client) = listener.accept().await?;
+    debug!(
+        "Replaying {} notifications to {}",
+        notifications.len(),
+        client
+    );
+
+    let (mut sink, mut stream) = Framed::new(stream, PacketCodec).split();
+    let reader = tokio::spawn(async move { while let Some(Ok(_)) = stream.next().await {} });
+
+    let start = Instant::now();
+    let first = notifications
+        .first()
+        .map(|record| record.time)
+        .unwrap_or_default();
+
+    for record in &notifications {
+        let offset = Duration::from_micros(record.time.saturating_sub(first));
+        sleep_until(start + offset).await;
+        sink.send(record.to_packet()).await?;
+    }
+
+    reader.abort();
+    Ok(notifications.len())
+}
@@ -0,0 +1,314 @@
+//! Detection of the plugin being injected after the game started. An
+//! injector can load the plugin after the game already resolved the
+//! redirector and connected to the official servers, those connections
+//! bypass the hooks and the proxy so nothing would be captured until the
+//! game reconnects. Existing connections are recorded in the manifest and
+//! can optionally be reset to force the game to reconnect through the proxy
+
+use crate::{
+    capture::{capture, now_millis},
+    config::config,
+    tasks,
+};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+#[cfg(feature = "plugin")]
+use std::{ffi::c_void, net::Ipv4Addr};
+use std::{net::SocketAddrV4, sync::OnceLock, time::Duration};
+use tokio::time::sleep;
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::{
+    Foundation::{ERROR_INSUFFICIENT_BUFFER, FALSE, FILETIME, NO_ERROR},
+    NetworkManagement::IpHelper::{
+        GetExtendedTcpTable, SetTcpEntry, MIB_TCPROW_LH, MIB_TCPROW_LH_0, MIB_TCPROW_OWNER_PID,
+        MIB_TCPTABLE_OWNER_PID, MIB_TCP_STATE_DELETE_TCB, MIB_TCP_STATE_ESTAB,
+        TCP_TABLE_OWNER_PID_ALL,
+    },
+    Networking::WinSock::AF_INET,
+    System::{
+        SystemInformation::GetSystemTimeAsFileTime,
+        Threading::{GetCurrentProcess, GetCurrentProcessId, GetProcessTimes},
+    },
+};
+
+/// Time given to the servers to start listening before connections are
+/// reset, otherwise the game would reconnect before the proxy is ready
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// The state detected when the plugin was attached
+static STATE: OnceLock<AttachState> = OnceLock::new();
+
+/// State of the game when the plugin was attached
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AttachState {
+    /// Whether the game had already made connections before the plugin
+    /// was attached
+    pub late: bool,
+    /// Milliseconds the game process had been running when the plugin
+    /// was attached
+    pub process_age_ms: Option<u64>,
+    /// Connections the game had open when the plugin was attached
+    pub existing_connections: Vec<ExistingConnection>,
+    /// Result of resetting the existing connections, None if they
+    /// weren't reset
+    pub reconnect: Option<ReconnectResult>,
+}
+
+/// TCP connection the game had open when the plugin was attached
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ExistingConnection {
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+}
+
+impl ExistingConnection {
+    /// Connections to loopback are to other local programs such as the
+    /// Origin client rather than the game servers and are never reset
+    fn is_loopback(&self) -> bool {
+        self.remote.ip().is_loopback()
+    }
+}
+
+/// Result of forcing the game to reconnect
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ReconnectResult {
+    /// Unix timestamp in milliseconds of when the connections were reset
+    pub time: u64,
+    /// Number of connections that were reset
+    pub reset: usize,
+    /// Number of connections that couldn't be reset, resetting
+    /// connections requires running the game as administrator
+    pub failed: usize,
+}
+
+/// Obtains the state detected when the plugin was attached
+pub fn state() -> Option<&'static AttachState> {
+    STATE.get()
+}
+
+/// Detects whether the game made connections before the plugin was
+/// attached and records the state in the manifest. Should be called
+/// before hooking so the connections made by the proxy aren't included
+pub fn detect() {
+    let existing_connections = match unsafe { process_connections() } {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to list existing game connections: {}", err);
+            Vec::new()
+        }
+    };
+
+    let state = AttachState {
+        late: !existing_connections.is_empty(),
+        process_age_ms: unsafe { process_age_ms() },
+        existing_connections,
+        reconnect: None,
+    };
+
+    if state.late {
+        warn!(
+            "Plugin was attached after the game connected, {} existing connections won't be captured until the game reconnects",
+            state.existing_connections.len()
+        );
+    } else {
+        debug!("Plugin attached before the game made any connections");
+    }
+
+    if let Some(capture) = capture() {
+        if state.late {
+            capture.record_event(
+                "attach",
+                &format!(
+                    "Attached late with {} existing connections",
+                    state.existing_connections.len()
+                ),
+            );
+        }
+        let manifest_state = state.clone();
+        capture.update_manifest(|manifest| manifest.attach = Some(manifest_state));
+    }
+
+    _ = STATE.set(state);
+}
+
+/// Resets the game connections that existed when the plugin was attached
+/// if the plugin was attached late and forcing a reconnect is enabled.
+/// Should be called after the servers are started
+pub fn start_reconnect() {
+    let Some(state) = state() else {
+        return;
+    };
+    if !state.late || !config().force_reconnect_on_attach {
+        return;
+    }
+
+    tasks::spawn("attach-reconnect", async move {
+        sleep(RECONNECT_DELAY).await;
+
+        let mut result = ReconnectResult {
+            time: now_millis(),
+            reset: 0,
+            failed: 0,
+        };
+
+        for connection in &state.existing_connections {
+            if connection.is_loopback() {
+                continue;
+            }
+
+            match unsafe { reset_connection(connection) } {
+                Ok(()) => {
+                    debug!("Reset existing connection to {}", connection.remote);
+                    result.reset += 1;
+                }
+                Err(code) => {
+                    warn!(
+                        "Failed to reset existing connection to {}: {}",
+                        connection.remote, code
+                    );
+                    result.failed += 1;
+                }
+            }
+        }
+
+        info!(
+            "Forced game to reconnect, reset {} connections ({} failed)",
+            result.reset, result.failed
+        );
+
+        if let Some(capture) = capture() {
+            capture.record_event(
+                "attach",
+                &format!(
+                    "Reset {} existing connections ({} failed)",
+                    result.reset, result.failed
+                ),
+            );
+            capture.update_manifest(|manifest| {
+                if let Some(attach) = &mut manifest.attach {
+                    attach.reconnect = Some(result);
+                }
+            });
+        }
+    });
+}
+
+/// Lists the established IPv4 TCP connections owned by the game process
+#[cfg(feature = "plugin")]
+unsafe fn process_connections() -> std::io::Result<Vec<ExistingConnection>> {
+    let mut size: u32 = 0;
+    let mut buffer: Vec<u8> = Vec::new();
+
+    // The table can grow between calls so retry until the buffer fits
+    loop {
+        let result = GetExtendedTcpTable(
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut size,
+            FALSE,
+            AF_INET as u32,
+            TCP_TABLE_OWNER_PID_ALL,
+            0,
+        );
+        match result {
+            NO_ERROR => break,
+            ERROR_INSUFFICIENT_BUFFER => buffer.resize(size as usize, 0),
+            code => return Err(std::io::Error::from_raw_os_error(code as i32)),
+        }
+    }
+
+    let process_id = GetCurrentProcessId();
+    let table = &*(buffer.as_ptr() as *const MIB_TCPTABLE_OWNER_PID);
+    let rows: &[MIB_TCPROW_OWNER_PID] =
+        std::slice::from_raw_parts(table.table.as_ptr(), table.dwNumEntries as usize);
+
+    Ok(rows
+        .iter()
+        .filter(|row| row.dwOwningPid == process_id && row.dwState == MIB_TCP_STATE_ESTAB as u32)
+        .map(|row| ExistingConnection {
+            local: socket_addr(row.dwLocalAddr, row.dwLocalPort),
+            remote: socket_addr(row.dwRemoteAddr, row.dwRemotePort),
+        })
+        .collect())
+}
+
+/// Converts an address and port from the TCP table which are both in
+/// network byte order, only the low 16 bits of the port are used
+#[cfg(feature = "plugin")]
+fn socket_addr(address: u32, port: u32) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(address)),
+        u16::from_be(port as u16),
+    )
+}
+
+/// Resets the connection forcing the game to reconnect, returns the
+/// error code on failure
+#[cfg(feature = "plugin")]
+unsafe fn reset_connection(connection: &ExistingConnection) -> Result<(), u32> {
+    let row = MIB_TCPROW_LH {
+        Anonymous: MIB_TCPROW_LH_0 {
+            State: MIB_TCP_STATE_DELETE_TCB,
+        },
+        dwLocalAddr: u32::from(*connection.local.ip()).to_be(),
+        dwLocalPort: connection.local.port().to_be() as u32,
+        dwRemoteAddr: u32::from(*connection.remote.ip()).to_be(),
+        dwRemotePort: connection.remote.port().to_be() as u32,
+    };
+
+    match SetTcpEntry(&row) {
+        NO_ERROR => Ok(()),
+        code => Err(code),
+    }
+}
+
+/// Milliseconds since the game process was created
+#[cfg(feature = "plugin")]
+unsafe fn process_age_ms() -> Option<u64> {
+    let empty = FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut created, mut exited, mut kernel, mut user) = (empty, empty, empty, empty);
+    if GetProcessTimes(
+        GetCurrentProcess(),
+        &mut created,
+        &mut exited,
+        &mut kernel,
+        &mut user,
+    ) == FALSE
+    {
+        return None;
+    }
+
+    let mut now = empty;
+    GetSystemTimeAsFileTime(&mut now);
+
+    // File times are in 100 nanosecond intervals
+    let elapsed = filetime_value(&now).checked_sub(filetime_value(&created))?;
+    Some(elapsed / 10_000)
+}
+
+#[cfg(feature = "plugin")]
+fn filetime_value(time: &FILETIME) -> u64 {
+    ((time.dwHighDateTime as u64) << 32) | time.dwLowDateTime as u64
+}
+
+/// Builds without the plugin aren't attached to the game so there are
+/// never existing connections
+#[cfg(not(feature = "plugin"))]
+unsafe fn process_connections() -> std::io::Result<Vec<ExistingConnection>> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(feature = "plugin"))]
+unsafe fn reset_connection(_connection: &ExistingConnection) -> Result<(), u32> {
+    Err(0)
+}
+
+#[cfg(not(feature = "plugin"))]
+unsafe fn process_age_ms() -> Option<u64> {
+    None
+}
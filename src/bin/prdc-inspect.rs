@@ -0,0 +1,775 @@
+//! Command line tool for inspecting the packets within a capture session
+//!
+//! Usage:
+//!   prdc-inspect <session-dir>                 Lists the captured packets
+//!   prdc-inspect <session-dir> <index>         Shows a single packet
+//!   prdc-inspect <session-dir> <index> --hex   Shows a packet as a hex dump
+//!                                              alongside the decoded contents
+//!   prdc-inspect <session-dir> [filters]       Prints the packets matching the
+//!                                              filters as JSON lines
+//!
+//!   prdc-inspect <session-dir> --export-parquet <output-dir>
+//!                                              Exports the session as Parquet
+//!                                              tables (requires the "parquet"
+//!                                              feature)
+//!   prdc-inspect <session-dir> --export-fixtures <output-dir>
+//!                                              Exports the request/response
+//!                                              pairs as server test fixtures
+//!   prdc-inspect <session-dir> --redecode <output-dir>
+//!                                              Replays the packets through the
+//!                                              capture decoders rebuilding
+//!                                              their outputs
+//!   prdc-inspect <session-dir> --timeline <output.html>
+//!                                              Exports the session as an HTML
+//!                                              timeline
+//!   prdc-inspect <session-dir> --conformance <url> [--ignore <path>]...
+//!                                              Replays the requests against a
+//!                                              Pocket Relay server and scores
+//!                                              its responses
+//!   prdc-inspect <session-dir> --compare <other-session-dir> [--ignore <path>]...
+//!                                              Aligns the request/response pairs
+//!                                              of both sessions side by side and
+//!                                              shows the payload differences
+//!   prdc-inspect <session-dir> --notifications
+//!                                              Prints the notifications grouped
+//!                                              by the request before them
+//!   prdc-inspect <session-dir> --replay-notifications <addr> [--component <id>]
+//!                                              Replays the notifications to the
+//!                                              first client to connect
+//!   prdc-inspect <session-dir> --preview       Previews the start of every file
+//!                                              in the session
+//!   prdc-inspect <session-dir> --migrate       Rewrites the session files from
+//!                                              older plugin versions in the
+//!                                              current format
+//!   prdc-inspect --list-sessions               Lists the sessions in the dump
+//!                                              directory with their files
+//!   prdc-inspect --delete-session <session-dir>
+//!                                              Securely deletes the session and
+//!                                              removes it from the upload queue
+//!   prdc-inspect --import-pcap <file> <output-dir> [--port <port>]...
+//!                                              Imports the Blaze packets from a
+//!                                              pcap file as a new session, only
+//!                                              connections on the given ports
+//!                                              (default 42127 and 42128)
+//!   prdc-inspect --schema [output-dir]         Prints the JSON Schema for the
+//!                                              session files or writes one
+//!                                              schema file each (requires the
+//!                                              "schema" feature)
+//!
+//! Filters:
+//!   --component <id>  --command <id>  --name <text>  --text <text>
+//!   --from <time>     --to <time>     --limit <count>
+
+use pocket_relay_dump::{
+    analysis::{
+        compare::{compare_sessions, ExchangeOutcome},
+        conformance::{check_conformance, ConformanceOptions},
+        notifications::{group_notifications, replay_notifications},
+    },
+    capture::{
+        fixtures::export_fixtures,
+        format::{migrate_session, FORMAT_VERSION},
+        import::{import_pcap, DEFAULT_PORTS},
+        inspect::side_by_side,
+        packet_log::{read_packet_log, PacketRecord},
+        query::{query_packets, PacketQuery},
+        replay::replay_session,
+        review::{delete_session, list_sessions, preview_session},
+        timeline::export_timeline,
+    },
+    definitions,
+    servers::{
+        components::{self, component_key, get_command_name, get_component_name},
+        packet::{FrameType, PacketDebug},
+    },
+};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+const USAGE: &str = "Usage: prdc-inspect <session-dir> [index] [--hex] [filters]";
+const IMPORT_USAGE: &str =
+    "Usage: prdc-inspect --import-pcap <file> <output-dir> [--port <port>]...";
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(dir) = args.next().map(PathBuf::from) else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    if dir.as_os_str() == "--list-sessions" {
+        return sessions();
+    }
+
+    if dir.as_os_str() == "--delete-session" {
+        let Some(session) = args.next() else {
+            eprintln!("Usage: prdc-inspect --delete-session <session-dir>");
+            return ExitCode::FAILURE;
+        };
+        return delete(Path::new(&session));
+    }
+
+    if dir.as_os_str() == "--import-pcap" {
+        let (Some(input), Some(output)) = (args.next(), args.next()) else {
+            eprintln!("{}", IMPORT_USAGE);
+            return ExitCode::FAILURE;
+        };
+
+        let mut ports = Vec::new();
+        while let Some(arg) = args.next() {
+            let port = match (arg.as_str(), args.next()) {
+                ("--port", Some(value)) => value.parse().ok(),
+                _ => None,
+            };
+            let Some(port) = port else {
+                eprintln!("{}", IMPORT_USAGE);
+                return ExitCode::FAILURE;
+            };
+            ports.push(port);
+        }
+        if ports.is_empty() {
+            ports.extend(DEFAULT_PORTS);
+        }
+
+        return import(Path::new(&input), Path::new(&output), &ports);
+    }
+
+    if dir.as_os_str() == "--schema" {
+        return schema(args.next().map(PathBuf::from).as_deref());
+    }
+
+    let mut index: Option<u64> = None;
+    let mut hex = false;
+    let mut query = PacketQuery::default();
+    let mut filtered = false;
+
+    while let Some(arg) = args.next() {
+        let valid = match arg.as_str() {
+            "--hex" => {
+                hex = true;
+                true
+            }
+            "--export-parquet" => {
+                let Some(output) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                return export(&dir, &PathBuf::from(output));
+            }
+            "--conformance" => {
+                let Some(url) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                // Remaining arguments are paths to ignore
+                let mut ignore = Vec::new();
+                while let Some(flag) = args.next() {
+                    match (flag.as_str(), args.next()) {
+                        ("--ignore", Some(path)) => ignore.push(path),
+                        _ => {
+                            eprintln!("{}", USAGE);
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+                return conformance(&dir, ConformanceOptions { url, ignore });
+            }
+            "--compare" => {
+                let Some(other) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                // Remaining arguments are paths to ignore
+                let mut ignore = Vec::new();
+                while let Some(flag) = args.next() {
+                    match (flag.as_str(), args.next()) {
+                        ("--ignore", Some(path)) => ignore.push(path),
+                        _ => {
+                            eprintln!("{}", USAGE);
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+                return compare(&dir, Path::new(&other), &ignore);
+            }
+            "--notifications" => return notifications(&dir),
+            "--preview" => return preview(&dir),
+            "--migrate" => return migrate(&dir),
+            "--replay-notifications" => {
+                let Some(addr) = args.next().and_then(|value| value.parse().ok()) else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                let component = match (args.next().as_deref(), args.next()) {
+                    (None, _) => None,
+                    (Some("--component"), Some(value)) => match parse_id(&value) {
+                        Some(value) => Some(value),
+                        None => {
+                            eprintln!("{}", USAGE);
+                            return ExitCode::FAILURE;
+                        }
+                    },
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        return ExitCode::FAILURE;
+                    }
+                };
+                return replay(&dir, addr, component);
+            }
+            "--export-fixtures" => {
+                let Some(output) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                return fixtures(&dir, &PathBuf::from(output));
+            }
+            "--redecode" => {
+                let Some(output) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                return redecode(&dir, &PathBuf::from(output));
+            }
+            "--timeline" => {
+                let Some(output) = args.next() else {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::FAILURE;
+                };
+                return timeline(&dir, &PathBuf::from(output));
+            }
+            flag if flag.starts_with("--") => {
+                filtered = true;
+                args.next()
+                    .is_some_and(|value| apply_filter(&mut query, flag, &value))
+            }
+            value => value.parse().map(|value| index = Some(value)).is_ok(),
+        };
+
+        if !valid {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    definitions::load();
+    components::initialize();
+
+    if filtered {
+        let matches = match query_packets(&dir, &query) {
+            Ok(value) => value,
+            Err(err) => {
+                eprintln!("Failed to query packet log: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        for packet in matches {
+            match serde_json::to_string(&packet) {
+                Ok(value) => println!("{}", value),
+                Err(err) => eprintln!("Failed to encode packet: {}", err),
+            }
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let records = match read_packet_log(&dir) {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to read packet log: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let Some(index) = index else {
+        for record in &records {
+            println!("{}", summary(record));
+        }
+        return ExitCode::SUCCESS;
+    };
+
+    let Some(record) = records.iter().find(|record| record.index == index) else {
+        eprintln!("No packet with index {}", index);
+        return ExitCode::FAILURE;
+    };
+
+    if record.contents.is_none() {
+        eprintln!("Packet contents weren't captured for this packet");
+    }
+
+    let packet = record.to_packet();
+    println!("{}", summary(record));
+
+    if hex {
+        print!("{}", side_by_side(&packet));
+    } else {
+        println!(
+            "{:?}",
+            PacketDebug {
+                packet: &packet,
+                contents: true,
+            }
+        );
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn conformance(dir: &Path, options: ConformanceOptions) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to create runtime: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match runtime.block_on(check_conformance(dir, &options)) {
+        Ok(report) => {
+            for score in &report.commands {
+                println!(
+                    "{}->{} ({:#06x}->{:#06x}): {}/{} matched, {} mismatched, {} failed",
+                    score.component_name.unwrap_or("Unknown"),
+                    score.command_name.unwrap_or("Unknown"),
+                    score.component,
+                    score.command,
+                    score.matched,
+                    score.total,
+                    score.mismatched,
+                    score.failed
+                );
+            }
+            println!(
+                "Score: {:.1}% ({} of {} requests matched)",
+                report.score, report.matched, report.total
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to check conformance: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn compare(left: &Path, right: &Path, ignore: &[String]) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    let comparison = match compare_sessions(left, right, ignore) {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to compare sessions: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let index = |value: Option<u64>| match value {
+        Some(value) => format!("#{}", value),
+        None => "-".to_string(),
+    };
+
+    // Each exchange is marked "=" matched, "~" differs, "<" left only
+    // and ">" right only, differences are listed below the exchange
+    for exchange in &comparison.exchanges {
+        let key = component_key(exchange.component, exchange.command);
+        let name = format!(
+            "{}->{}",
+            get_component_name(exchange.component).unwrap_or("Unknown"),
+            get_command_name(key, false).unwrap_or("Unknown")
+        );
+        let marker = match &exchange.outcome {
+            ExchangeOutcome::Match => '=',
+            ExchangeOutcome::Mismatch { .. } => '~',
+            ExchangeOutcome::LeftOnly => '<',
+            ExchangeOutcome::RightOnly => '>',
+        };
+
+        println!(
+            "{} {:<8} {:<8} {}",
+            marker,
+            index(exchange.left),
+            index(exchange.right),
+            name
+        );
+
+        if let ExchangeOutcome::Mismatch { mismatches } = &exchange.outcome {
+            for mismatch in mismatches {
+                let value = |value: &Option<serde_json::Value>| match value {
+                    Some(value) => value.to_string(),
+                    None => "(missing)".to_string(),
+                };
+                println!(
+                    "      {}: {} | {}",
+                    mismatch.path,
+                    value(&mismatch.expected),
+                    value(&mismatch.actual)
+                );
+            }
+        }
+    }
+
+    println!(
+        "{} matched, {} differ, {} only in {}, {} only in {}",
+        comparison.matched,
+        comparison.mismatched,
+        comparison.left_only,
+        left.display(),
+        comparison.right_only,
+        right.display()
+    );
+    ExitCode::SUCCESS
+}
+
+fn notifications(dir: &Path) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    let records = match read_packet_log(dir) {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to read packet log: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match serde_json::to_string_pretty(&group_notifications(&records)) {
+        Ok(value) => {
+            println!("{}", value);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to encode notifications: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn replay(dir: &Path, addr: SocketAddr, component: Option<u16>) -> ExitCode {
+    let records = match read_packet_log(dir) {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to read packet log: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to create runtime: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("Waiting for a client on {}", addr);
+    match runtime.block_on(replay_notifications(&records, addr, component)) {
+        Ok(count) => {
+            println!("Replayed {} notifications", count);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to replay notifications: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn fixtures(dir: &Path, output: &Path) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    match export_fixtures(dir, output) {
+        Ok(count) => {
+            println!("Exported {} fixture cases", count);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to export fixtures: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn redecode(dir: &Path, output: &Path) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    match replay_session(dir, output) {
+        Ok(summary) => {
+            println!(
+                "Decoded {} packets ({} without contents skipped)",
+                summary.decoded, summary.skipped
+            );
+            for name in summary.failed {
+                eprintln!("Decoder {} panicked and was stopped", name);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to replay session: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn timeline(dir: &Path, output: &Path) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    match export_timeline(dir, output) {
+        Ok(count) => {
+            println!("Exported timeline with {} events", count);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to export timeline: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn sessions() -> ExitCode {
+    let sessions = match list_sessions() {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to list sessions: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for session in &sessions {
+        let mut notes = Vec::new();
+        if session.current {
+            notes.push("current");
+        }
+        if session.queued_for_upload {
+            notes.push("queued for upload");
+        }
+        println!(
+            "{} {} bytes {}",
+            session.path.display(),
+            session.size,
+            notes.join(", ")
+        );
+        for file in &session.files {
+            println!("  {:>12} {}", file.size, file.path);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+fn preview(dir: &Path) -> ExitCode {
+    let sessions = match list_sessions() {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Failed to list sessions: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let Some(session) = sessions
+        .iter()
+        .find(|session| session.path.canonicalize().ok().as_deref() == Some(dir.as_path()))
+    else {
+        eprintln!("Not a capture session in the dump directory");
+        return ExitCode::FAILURE;
+    };
+
+    match preview_session(session) {
+        Ok(value) => {
+            print!("{}", value);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to preview session: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn migrate(dir: &Path) -> ExitCode {
+    match migrate_session(dir) {
+        Ok(summary) => {
+            for path in &summary.migrated {
+                println!("Migrated {}", path.display());
+            }
+            println!(
+                "Migrated {} files to format version {}, {} were already current",
+                summary.migrated.len(),
+                FORMAT_VERSION,
+                summary.current.len()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to migrate session: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn delete(dir: &Path) -> ExitCode {
+    print!(
+        "Permanently delete {}? The files are overwritten and can't be recovered [y/N] ",
+        dir.display()
+    );
+    _ = std::io::Write::flush(&mut std::io::stdout());
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() || !answer.trim().eq_ignore_ascii_case("y")
+    {
+        println!("Nothing was deleted");
+        return ExitCode::SUCCESS;
+    }
+
+    match delete_session(dir) {
+        Ok(()) => {
+            println!("Deleted {}", dir.display());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to delete session: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn import(input: &Path, output: &Path, ports: &[u16]) -> ExitCode {
+    match import_pcap(input, output, ports) {
+        Ok(summary) => {
+            println!(
+                "Imported {} packets from {} connections",
+                summary.packets, summary.connections
+            );
+            if summary.rejected > 0 {
+                println!(
+                    "Skipped {} streams on the ports that weren't Blaze traffic",
+                    summary.rejected
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to import pcap: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn export(dir: &Path, output: &Path) -> ExitCode {
+    definitions::load();
+    components::initialize();
+
+    match pocket_relay_dump::capture::export::export_parquet(dir, output) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Failed to export session: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+fn export(_dir: &Path, _output: &Path) -> ExitCode {
+    eprintln!("Parquet export requires building with the \"parquet\" feature");
+    ExitCode::FAILURE
+}
+
+#[cfg(feature = "schema")]
+fn schema(output: Option<&Path>) -> ExitCode {
+    use pocket_relay_dump::capture::schema::{schemas_json, write_schemas};
+
+    let Some(output) = output else {
+        match serde_json::to_string_pretty(&schemas_json()) {
+            Ok(value) => println!("{}", value),
+            Err(err) => {
+                eprintln!("Failed to serialize schemas: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+        return ExitCode::SUCCESS;
+    };
+
+    match write_schemas(output) {
+        Ok(paths) => {
+            for path in &paths {
+                println!("Wrote {}", path.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("Failed to write schemas: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(not(feature = "schema"))]
+fn schema(_output: Option<&Path>) -> ExitCode {
+    eprintln!("Schema generation requires building with the \"schema\" feature");
+    ExitCode::FAILURE
+}
+
+/// Applies a filter flag to the query, returns false if the
+/// flag is unknown or its value is invalid
+fn apply_filter(query: &mut PacketQuery, flag: &str, value: &str) -> bool {
+    match flag {
+        "--component" => query.component = parse_id(value),
+        "--command" => query.command = parse_id(value),
+        "--name" => query.name = Some(value.to_string()),
+        "--text" => query.text = Some(value.to_string()),
+        "--from" => query.from = value.parse().ok(),
+        "--to" => query.to = value.parse().ok(),
+        "--limit" => query.limit = value.parse().ok(),
+        _ => return false,
+    }
+
+    // Parsing failed if the filter is still unset
+    match flag {
+        "--component" => query.component.is_some(),
+        "--command" => query.command.is_some(),
+        "--from" => query.from.is_some(),
+        "--to" => query.to.is_some(),
+        "--limit" => query.limit.is_some(),
+        _ => true,
+    }
+}
+
+/// Parses a component or command ID in either decimal or hex (0x prefixed)
+fn parse_id(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x") {
+        Some(value) => u16::from_str_radix(value, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Creates a single line summary of a packet record
+fn summary(record: &PacketRecord) -> String {
+    let ty = FrameType::from(record.ty);
+    let key = component_key(record.component, record.command);
+    let component = get_component_name(record.component).unwrap_or("Unknown");
+    let command = get_command_name(key, matches!(ty, FrameType::Notify)).unwrap_or("Unknown");
+
+    format!(
+        "#{:<6} {:>16} conn {:<3} {:?} {:?} ({}) {}->{} ({:#06x}->{:#06x}) {} bytes",
+        record.index,
+        record.time,
+        record.connection,
+        record.direction,
+        ty,
+        record.seq,
+        component,
+        command,
+        record.component,
+        record.command,
+        record.length
+    )
+}
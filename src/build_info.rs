@@ -0,0 +1,40 @@
+//! Details of the plugin build stamped into every capture artifact so
+//! analysis can account for format changes between plugin versions
+
+use crate::constants::{APP_VERSION, BUILD_TIME, GIT_HASH};
+use serde::{Deserialize, Serialize};
+
+/// Details of the plugin build that produced an artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BuildInfo {
+    /// Version of the plugin
+    pub version: String,
+    /// Short hash of the git commit the plugin was built from
+    pub git_hash: String,
+    /// Unix timestamp in seconds of when the plugin was built
+    pub build_time: u64,
+    /// Optional cargo features the plugin was built with
+    pub features: Vec<String>,
+}
+
+/// Obtains the details of the running plugin build
+pub fn build_info() -> BuildInfo {
+    let features = [
+        ("plugin", cfg!(feature = "plugin")),
+        ("sqlite", cfg!(feature = "sqlite")),
+        ("parquet", cfg!(feature = "parquet")),
+        ("schema", cfg!(feature = "schema")),
+    ];
+
+    BuildInfo {
+        version: APP_VERSION.to_string(),
+        git_hash: GIT_HASH.to_string(),
+        build_time: BUILD_TIME.parse().unwrap_or_default(),
+        features: features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+    }
+}
@@ -0,0 +1,173 @@
+//! Anonymization of player identifiers in captured packets. Identifying
+//! fields are rewritten before packets reach the packet log, sinks and
+//! decoders so they never touch the disk
+
+use super::value::{decode_fields, encode_fields, Field, TdfValue};
+use crate::servers::packet::Packet;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::BuildHasher,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+
+/// The currently active anonymization level
+static LEVEL: AtomicU8 = AtomicU8::new(Anonymization::Off as u8);
+
+/// Key for pseudonyms, random for each run so pseudonyms are stable
+/// within a session but can't be linked across sessions
+static PSEUDONYM_KEY: OnceLock<RandomState> = OnceLock::new();
+
+/// Tags of string fields holding player names
+const NAME_TAGS: &[&str] = &["NAME", "DSNM", "PNAM", "PERS"];
+
+/// Tags of var int fields holding player and account IDs
+const ID_TAGS: &[&str] = &["PID", "USID", "BUID", "UID"];
+
+/// Tags of var int fields holding IP addresses
+const IP_TAGS: &[&str] = &["IP"];
+
+/// Tags of fields holding secrets which are always removed
+const SECRET_TAGS: &[&str] = &["MAIL", "PASS", "SKEY", "AUTH", "TOKN", "LKEY"];
+
+/// How much player identifying data is removed from captures
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Anonymization {
+    /// Packets are captured unchanged
+    #[default]
+    Off = 0,
+    /// Names, IDs and addresses are replaced with stable pseudonyms so
+    /// players can still be told apart, secrets are removed
+    Pseudonymize = 1,
+    /// Names, IDs, addresses and secrets are removed, contents that
+    /// can't be decoded are left out of the capture
+    Redact = 2,
+}
+
+impl Anonymization {
+    /// Obtains the currently active level
+    pub fn current() -> Self {
+        match LEVEL.load(Ordering::Acquire) {
+            1 => Anonymization::Pseudonymize,
+            2 => Anonymization::Redact,
+            _ => Anonymization::Off,
+        }
+    }
+
+    /// Makes this level the currently active level
+    pub fn activate(self) {
+        LEVEL.store(self as u8, Ordering::Release);
+    }
+
+    /// Applies the level to the packet, returns None if the packet
+    /// contents should be left out of the capture
+    pub fn apply(self, packet: &Packet) -> Option<Cow<Packet>> {
+        if self == Anonymization::Off || packet.contents.is_empty() {
+            return Some(Cow::Borrowed(packet));
+        }
+
+        let Ok(mut fields) = decode_fields(&packet.contents) else {
+            // Identifiers can't be found in contents that don't decode
+            return match self {
+                Anonymization::Redact => None,
+                _ => Some(Cow::Borrowed(packet)),
+            };
+        };
+
+        if !self.anonymize_fields(&mut fields) {
+            return Some(Cow::Borrowed(packet));
+        }
+
+        Some(Cow::Owned(Packet::new(
+            packet.frame.clone(),
+            Bytes::from(encode_fields(&fields)),
+        )))
+    }
+
+    /// Rewrites identifying fields, returns whether any field changed
+    fn anonymize_fields(self, fields: &mut [Field]) -> bool {
+        let mut changed = false;
+        for field in fields {
+            let tag = field.tag.trim_end();
+            changed |= match &mut field.value {
+                TdfValue::String(value) if SECRET_TAGS.contains(&tag) => {
+                    value.clear();
+                    true
+                }
+                TdfValue::String(value) if NAME_TAGS.contains(&tag) => {
+                    *value = match self {
+                        Anonymization::Pseudonymize => format!("player-{:08x}", pseudonym(value)),
+                        _ => String::new(),
+                    };
+                    true
+                }
+                TdfValue::VarInt(value) if ID_TAGS.contains(&tag) || IP_TAGS.contains(&tag) => {
+                    *value = match self {
+                        Anonymization::Pseudonymize => pseudonym(&value.to_string()) as i128,
+                        _ => 0,
+                    };
+                    true
+                }
+                value => self.anonymize_value(value),
+            };
+        }
+        changed
+    }
+
+    /// Rewrites identifying fields nested within the value
+    fn anonymize_value(self, value: &mut TdfValue) -> bool {
+        match value {
+            TdfValue::Group(fields) => self.anonymize_fields(fields),
+            TdfValue::Union {
+                value: Some(field), ..
+            } => self.anonymize_fields(std::slice::from_mut(field.as_mut())),
+            TdfValue::List(values) => values.iter_mut().fold(false, |changed, value| {
+                self.anonymize_value(value) | changed
+            }),
+            TdfValue::Map(entries) => entries.iter_mut().fold(false, |changed, (_, value)| {
+                self.anonymize_value(value) | changed
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// Creates a 32-bit pseudonym for the value, 32 bits keeps pseudonyms
+/// valid for fields holding IPv4 addresses
+fn pseudonym(value: &str) -> u32 {
+    let key = PSEUDONYM_KEY.get_or_init(RandomState::new);
+    key.hash_one(value) as u32
+}
+
+impl FromStr for Anonymization {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Anonymization::Off),
+            "pseudonymize" => Ok(Anonymization::Pseudonymize),
+            "redact" => Ok(Anonymization::Redact),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Anonymization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Anonymization::Off => "off",
+            Anonymization::Pseudonymize => "pseudonymize",
+            Anonymization::Redact => "redact",
+        })
+    }
+}
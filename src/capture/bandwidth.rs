@@ -0,0 +1,67 @@
+use super::Direction;
+use crate::servers::{components::get_component_name, packet::Packet};
+use serde::Serialize;
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Running totals of the traffic passing through the proxy per
+/// session, direction and component
+#[derive(Default)]
+pub struct Bandwidth {
+    totals: Mutex<BTreeMap<BandwidthKey, BandwidthCounter>>,
+}
+
+/// Session ID, direction and component
+type BandwidthKey = (u32, u8, u16);
+
+#[derive(Default, Clone, Copy)]
+struct BandwidthCounter {
+    packets: u64,
+    bytes: u64,
+}
+
+/// Traffic totals for a single session, direction and component
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BandwidthEntry {
+    pub session: u32,
+    pub direction: Direction,
+    pub component: u16,
+    pub component_name: Option<&'static str>,
+    /// Number of packets
+    pub packets: u64,
+    /// Number of bytes including the packet headers
+    pub bytes: u64,
+}
+
+impl Bandwidth {
+    /// Adds a packet to the running totals
+    pub fn record(&self, session: u32, direction: Direction, packet: &Packet) {
+        let key = (session, direction as u8, packet.frame.component);
+        let totals = &mut *self.totals.lock().expect("Bandwidth lock poisoned");
+        let counter = totals.entry(key).or_default();
+        counter.packets += 1;
+        counter.bytes += packet.encoded_length() as u64;
+    }
+
+    /// Creates a snapshot of the running totals
+    pub fn snapshot(&self) -> Vec<BandwidthEntry> {
+        let totals = self.totals.lock().expect("Bandwidth lock poisoned");
+        totals
+            .iter()
+            .map(
+                |(&(session, direction, component), counter)| BandwidthEntry {
+                    session,
+                    direction: if direction == Direction::Send as u8 {
+                        Direction::Send
+                    } else {
+                        Direction::Receive
+                    },
+                    component,
+                    component_name: get_component_name(component),
+                    packets: counter.packets,
+                    bytes: counter.bytes,
+                },
+            )
+            .collect()
+    }
+}
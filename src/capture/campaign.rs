@@ -0,0 +1,189 @@
+//! Collection campaigns spanning multiple runs of the game. Each run still
+//! writes its own session directory, the campaign file links the sessions
+//! together with cumulative totals and carries the sample counts between
+//! runs so kinds of packets already sampled aren't sampled again
+
+use super::{
+    collection::is_valid_code, dump_dir, now_millis, samples::SampleCount, Capture, Direction,
+};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::{io, path::PathBuf};
+
+/// The name of the directory within the dump directory campaigns are
+/// stored in
+pub const CAMPAIGNS_DIR: &str = "campaigns";
+
+/// The file name of the campaign file within the campaign directory
+pub const CAMPAIGN_FILE: &str = "campaign.json";
+
+/// Campaign file shared by every run of the game within the campaign
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Campaign {
+    /// Name of the campaign
+    pub name: String,
+    /// Unix timestamp in milliseconds of when the first run started
+    pub created_at: u64,
+    /// Every run of the game within the campaign in the order they started
+    pub runs: Vec<CampaignRun>,
+    /// Totals over every run
+    pub totals: CampaignTotals,
+    /// Number of samples kept of each kind of packet over every run
+    pub samples: Vec<SampleCount>,
+}
+
+/// Run of the game within a campaign
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CampaignRun {
+    /// Name of the session directory the run was captured into
+    pub session: String,
+    /// Unix timestamp in milliseconds of when the run started
+    pub started_at: u64,
+    /// Unix timestamp in milliseconds of when the run finished, None if
+    /// the game didn't close cleanly
+    pub finished_at: Option<u64>,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Totals over every run within a campaign
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CampaignTotals {
+    pub runs: usize,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl Campaign {
+    /// Recomputes the totals from the runs
+    fn update_totals(&mut self) {
+        self.totals = CampaignTotals {
+            runs: self.runs.len(),
+            packets_sent: self.runs.iter().map(|run| run.packets_sent).sum(),
+            packets_received: self.runs.iter().map(|run| run.packets_received).sum(),
+            bytes_sent: self.runs.iter().map(|run| run.bytes_sent).sum(),
+            bytes_received: self.runs.iter().map(|run| run.bytes_received).sum(),
+        };
+    }
+}
+
+/// Obtains the path of the campaign file for the campaign with `name`
+pub fn campaign_path(name: &str) -> io::Result<PathBuf> {
+    Ok(dump_dir()?
+        .join(CAMPAIGNS_DIR)
+        .join(name)
+        .join(CAMPAIGN_FILE))
+}
+
+/// Reads the campaign with `name`, None if it hasn't been started
+pub fn read_campaign(name: &str) -> io::Result<Option<Campaign>> {
+    match std::fs::read(campaign_path(name)?) {
+        Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn write_campaign(campaign: &Campaign) -> io::Result<()> {
+    let path = campaign_path(&campaign.name)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let bytes = serde_json::to_vec_pretty(campaign)?;
+    std::fs::write(path, bytes)
+}
+
+impl Capture {
+    /// Name of the session directory
+    fn session_name(&self) -> String {
+        self.dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Adds the session as a run of the campaign with `name`, starting the
+    /// campaign if it doesn't exist. Returns whether the session joined
+    pub(super) fn join_campaign(&self, name: &str, started_at: u64) -> bool {
+        if !is_valid_code(name) {
+            error!("Invalid campaign name {:?}", name);
+            return false;
+        }
+
+        let mut campaign = match read_campaign(name) {
+            Ok(Some(value)) => value,
+            Ok(None) => Campaign {
+                name: name.to_string(),
+                created_at: started_at,
+                ..Default::default()
+            },
+            Err(err) => {
+                // Starting over would lose the earlier runs
+                error!("Failed to read campaign {}: {}", name, err);
+                return false;
+            }
+        };
+
+        self.samples.restore(&campaign.samples);
+        campaign.runs.push(CampaignRun {
+            session: self.session_name(),
+            started_at,
+            ..Default::default()
+        });
+        campaign.update_totals();
+
+        if let Err(err) = write_campaign(&campaign) {
+            error!("Failed to write campaign {}: {}", name, err);
+            return false;
+        }
+
+        debug!("Joined campaign {} as run {}", name, campaign.runs.len());
+        true
+    }
+
+    /// Records the totals of the finished session in its campaign
+    pub(super) fn finish_campaign_run(&self, name: &str) {
+        let mut campaign = match read_campaign(name) {
+            Ok(Some(value)) => value,
+            Ok(None) => return,
+            Err(err) => {
+                error!("Failed to read campaign {}: {}", name, err);
+                return;
+            }
+        };
+
+        let session = self.session_name();
+        let Some(run) = campaign.runs.iter_mut().find(|run| run.session == session) else {
+            return;
+        };
+
+        run.finished_at = Some(now_millis());
+        run.packets_sent = 0;
+        run.packets_received = 0;
+        run.bytes_sent = 0;
+        run.bytes_received = 0;
+        for entry in self.bandwidth.snapshot() {
+            match entry.direction {
+                Direction::Send => {
+                    run.packets_sent += entry.packets;
+                    run.bytes_sent += entry.bytes;
+                }
+                Direction::Receive => {
+                    run.packets_received += entry.packets;
+                    run.bytes_received += entry.bytes;
+                }
+            }
+        }
+
+        campaign.samples = self.samples.counts();
+        campaign.update_totals();
+
+        if let Err(err) = write_campaign(&campaign) {
+            error!("Failed to write campaign {}: {}", name, err);
+        }
+    }
+}
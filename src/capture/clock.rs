@@ -0,0 +1,165 @@
+use super::capture;
+use crate::tasks;
+use log::{debug, error};
+use serde::Serialize;
+use std::{
+    io,
+    sync::OnceLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{net::UdpSocket, time::timeout};
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::System::SystemInformation::GetTickCount64;
+
+/// NTP server used to estimate the local clock offset
+const NTP_SERVER: &str = "pool.ntp.org:123";
+
+/// Maximum time to wait for a response from the NTP server
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Seconds between the NTP epoch (1900) and the unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Wall clock time captured alongside a monotonic instant when the
+/// session started, all packet timestamps are derived from this so
+/// that they can't jump when the system clock is adjusted
+static ANCHOR: OnceLock<(u64, Instant)> = OnceLock::new();
+
+/// Clock details recorded in the session manifest so that captures
+/// from different machines can be correlated
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ClockInfo {
+    /// Unix timestamp in microseconds of the wall clock when the
+    /// session started
+    pub wall_start_us: u64,
+    /// Milliseconds since the system booted when the session started,
+    /// zero in builds without the plugin
+    pub monotonic_start_ms: u64,
+    /// Estimated offset of the local clock, None until measured
+    pub ntp: Option<NtpEstimate>,
+}
+
+/// Estimated offset of the local clock from an NTP server
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NtpEstimate {
+    /// The server that was queried
+    pub server: &'static str,
+    /// Milliseconds to add to local timestamps to get the server time
+    pub offset_ms: f64,
+    /// Round trip time of the query in milliseconds
+    pub round_trip_ms: f64,
+    /// Unix timestamp in microseconds of when the estimate was made
+    pub measured_at: u64,
+}
+
+impl Default for ClockInfo {
+    /// Creates the clock info for the session, anchoring the
+    /// session clock if it hasn't been already
+    fn default() -> Self {
+        let (wall_start_us, _) = anchor();
+        Self {
+            wall_start_us: *wall_start_us,
+            monotonic_start_ms: uptime_ms(),
+            ntp: None,
+        }
+    }
+}
+
+/// Obtains the milliseconds since the system booted
+#[cfg(feature = "plugin")]
+fn uptime_ms() -> u64 {
+    unsafe { GetTickCount64() }
+}
+
+#[cfg(not(feature = "plugin"))]
+fn uptime_ms() -> u64 {
+    0
+}
+
+fn anchor() -> &'static (u64, Instant) {
+    ANCHOR.get_or_init(|| (wall_micros(), Instant::now()))
+}
+
+/// Obtains the current unix timestamp in microseconds from the
+/// monotonic session clock
+pub fn now_micros() -> u64 {
+    let (wall, instant) = anchor();
+    wall + instant.elapsed().as_micros() as u64
+}
+
+fn wall_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+/// Spawns a task that estimates the local clock offset and stores
+/// it in the session manifest
+pub fn start_sync() {
+    let Some(capture) = capture() else {
+        return;
+    };
+
+    tasks::spawn("clock-sync", async move {
+        match estimate_offset().await {
+            Ok(estimate) => {
+                debug!(
+                    "Estimated clock offset {:.3}ms (round trip {:.3}ms)",
+                    estimate.offset_ms, estimate.round_trip_ms
+                );
+                capture.update_manifest(|manifest| manifest.clock.ntp = Some(estimate));
+            }
+            Err(err) => error!("Failed to estimate clock offset: {}", err),
+        }
+    });
+}
+
+/// Estimates the local clock offset using a single SNTP query
+async fn estimate_offset() -> io::Result<NtpEstimate> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(NTP_SERVER).await?;
+
+    // Version 3 client request
+    let mut request = [0u8; 48];
+    request[0] = 0x1B;
+
+    let mut response = [0u8; 48];
+
+    let sent = now_micros();
+    socket.send(&request).await?;
+    let length = timeout(NTP_TIMEOUT, socket.recv(&mut response))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "NTP request timed out"))??;
+    let received = now_micros();
+
+    if length < 48 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "NTP response too short",
+        ));
+    }
+
+    let server_received = ntp_micros(&response[32..40]) as i128;
+    let server_sent = ntp_micros(&response[40..48]) as i128;
+    let (sent, received) = (sent as i128, received as i128);
+
+    let offset = ((server_received - sent) + (server_sent - received)) / 2;
+    let round_trip = (received - sent) - (server_sent - server_received);
+
+    Ok(NtpEstimate {
+        server: NTP_SERVER,
+        offset_ms: offset as f64 / 1000.0,
+        round_trip_ms: round_trip as f64 / 1000.0,
+        measured_at: received as u64,
+    })
+}
+
+/// Converts an NTP timestamp into a unix timestamp in microseconds
+fn ntp_micros(bytes: &[u8]) -> u64 {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64;
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as u64;
+    seconds.saturating_sub(NTP_UNIX_OFFSET) * 1_000_000 + ((fraction * 1_000_000) >> 32)
+}
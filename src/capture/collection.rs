@@ -0,0 +1,110 @@
+use super::{append_jsonl, capture, clock::now_micros, Capture};
+use crate::errors::{self, CaptureIoError};
+use log::debug;
+use serde::Serialize;
+
+/// The file name of the markers file within the session directory
+pub const MARKERS_FILE: &str = "markers.jsonl";
+
+/// Maximum length of a collection session code
+const MAX_CODE_LENGTH: usize = 32;
+
+/// Marker recorded in the capture to line up captures from multiple
+/// players in the same collection session
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Marker {
+    /// Unix timestamp in microseconds from the session clock
+    pub time: u64,
+    /// The marker time corrected by the NTP offset estimate, None
+    /// if the offset hasn't been measured
+    pub corrected_time: Option<u64>,
+    /// What created the marker
+    pub source: MarkerSource,
+    /// Label describing the marker
+    pub label: String,
+    /// ID of the game the marker belongs to, game markers are seen by
+    /// every player in the game at roughly the same time
+    pub game_id: Option<u64>,
+    /// The active collection session code
+    pub collection: Option<String>,
+}
+
+/// What created a [Marker]
+#[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MarkerSource {
+    /// Created by the user through the control API
+    User,
+    /// Created from a game wide server notification
+    Game,
+    /// Created by the user pressing a marker hotkey in game
+    Hotkey,
+}
+
+/// Validates a collection session code entered by the user, codes
+/// are limited to letters, digits and dashes
+pub fn is_valid_code(code: &str) -> bool {
+    !code.is_empty()
+        && code.len() <= MAX_CODE_LENGTH
+        && code
+            .chars()
+            .all(|value| value.is_ascii_alphanumeric() || value == '-')
+}
+
+/// Records a marker for a game wide event if a collection session
+/// is active, used by the decoders for synchronized markers
+pub fn record_game_marker(label: &str, game_id: u64) {
+    let Some(capture) = capture() else {
+        return;
+    };
+
+    if capture.collection().is_some() {
+        capture.record_marker(MarkerSource::Game, label, Some(game_id));
+    }
+}
+
+impl Capture {
+    /// Obtains the active collection session code
+    pub fn collection(&self) -> Option<String> {
+        self.manifest
+            .lock()
+            .expect("Manifest lock poisoned")
+            .collection
+            .clone()
+    }
+
+    /// Sets or clears the collection session code
+    pub fn set_collection(&self, code: Option<String>) {
+        debug!("Switching collection session to {:?}", code);
+        self.update_manifest(|manifest| manifest.collection = code);
+    }
+
+    /// Appends a marker to the markers file
+    pub fn record_marker(&self, source: MarkerSource, label: &str, game_id: Option<u64>) {
+        let time = now_micros();
+        let (collection, offset_ms) = {
+            let manifest = self.manifest.lock().expect("Manifest lock poisoned");
+            let offset = manifest.clock.ntp.as_ref().map(|ntp| ntp.offset_ms);
+            (manifest.collection.clone(), offset)
+        };
+
+        let marker = Marker {
+            time,
+            corrected_time: offset_ms
+                .map(|offset| (time as i64 + (offset * 1000.0) as i64).max(0) as u64),
+            source,
+            label: label.to_string(),
+            game_id,
+            collection,
+        };
+
+        if let Err(err) = append_jsonl(&self.dir.join(MARKERS_FILE), &marker) {
+            errors::report(CaptureIoError::Write {
+                what: "capture marker",
+                source: err,
+            });
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! Crash reports for crashes while the plugin is loaded. An unhandled
+//! exception filter writes the faulting module and the last packets seen
+//! by the proxy into the session directory, so crashes caused by the plugin
+//! can be told apart from game bugs. The minidump holds game memory so it's
+//! written next to the sessions instead, where it's never hashed or uploaded
+
+use super::{
+    anonymize::Anonymization, privacy::PrivacyCategories, profile::CaptureProfile, Direction,
+};
+use crate::{build_info::BuildInfo, config::config, errors::ErrorSummary, servers::packet::Packet};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+#[cfg(feature = "plugin")]
+use super::{capture, now_millis, packet_log::PacketRecord, write_jsonl_header};
+#[cfg(feature = "plugin")]
+use crate::{build_info::build_info, constants::APP_VERSION, errors};
+#[cfg(feature = "plugin")]
+use log::{error, info};
+#[cfg(feature = "plugin")]
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    os::windows::io::AsRawHandle,
+    ptr::null,
+    sync::atomic::{AtomicBool, AtomicUsize},
+};
+#[cfg(feature = "plugin")]
+use windows_sys::{
+    core::PCWSTR,
+    Win32::{
+        Foundation::HMODULE,
+        System::{
+            Diagnostics::Debug::{
+                MiniDumpWithIndirectlyReferencedMemory, MiniDumpWithThreadInfo, MiniDumpWriteDump,
+                SetUnhandledExceptionFilter, EXCEPTION_POINTERS, LPTOP_LEVEL_EXCEPTION_FILTER,
+                MINIDUMP_EXCEPTION_INFORMATION,
+            },
+            LibraryLoader::{
+                GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+                GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            },
+            Threading::{GetCurrentProcess, GetCurrentProcessId, GetCurrentThreadId},
+        },
+    },
+};
+
+/// Directory within the session directory crash reports are written to
+pub const CRASH_DIR: &str = "crash";
+
+/// Directory within the dump directory minidumps are written to, named
+/// after the session that crashed
+pub const MINIDUMP_DIR: &str = "crash-dumps";
+
+#[cfg(feature = "plugin")]
+/// Value returned from the filter to let the next handler run
+const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+
+/// Packets most recently seen by the proxy, oldest first
+static RECENT: Mutex<VecDeque<RecentPacket>> = Mutex::new(VecDeque::new());
+
+/// Index of the next packet added to the recent packets
+static RECENT_INDEX: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "plugin")]
+/// The filter that was installed before ours, stored as an address
+static PREVIOUS_FILTER: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "plugin")]
+/// Whether a crash report has been written, only the first crash of
+/// the process is reported
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Settings for crash reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrashConfig {
+    /// Whether crash reports are written
+    pub enabled: bool,
+    /// Number of recent packets included in crash reports
+    pub recent_packets: usize,
+}
+
+impl Default for CrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            recent_packets: 200,
+        }
+    }
+}
+
+/// Details of the crash written alongside the minidump
+#[derive(Debug, Serialize)]
+pub struct CrashReport {
+    /// Unix timestamp in milliseconds of the crash
+    pub time: u64,
+    /// Version of the plugin loaded at the time of the crash
+    pub plugin_version: &'static str,
+    /// Details of the plugin build loaded at the time of the crash
+    pub build: BuildInfo,
+    /// The exception code
+    pub code: String,
+    /// Address the exception occurred at
+    pub address: String,
+    /// Path of the module containing the exception address
+    pub module: Option<String>,
+    /// Whether the exception occurred within the plugin
+    pub in_plugin: bool,
+    /// Whether the minidump was written, see [minidump_path]
+    pub minidump: bool,
+    /// Errors reported before the crash by error code, None if they
+    /// couldn't be read without waiting
+    pub errors: Option<Vec<ErrorSummary>>,
+}
+
+/// Packet remembered for crash reports, only turned into a record when
+/// a crash report is written to keep remembering cheap
+#[cfg_attr(not(feature = "plugin"), allow(dead_code))]
+struct RecentPacket {
+    index: u64,
+    time: u64,
+    connection: u32,
+    direction: Direction,
+    packet: Packet,
+    /// Whether the packet contents are included in crash reports
+    contents: bool,
+}
+
+#[cfg(feature = "plugin")]
+/// Installs the crash handler if crash reports are enabled
+pub fn install() {
+    if !config().crash.enabled {
+        return;
+    }
+
+    let previous = unsafe { SetUnhandledExceptionFilter(Some(exception_filter)) };
+    PREVIOUS_FILTER.store(
+        previous.map(|value| value as usize).unwrap_or_default(),
+        Ordering::Release,
+    );
+    info!("Installed crash handler");
+}
+
+/// Obtains the path of the minidump for the session named `session`
+/// within the dump directory `root`
+pub fn minidump_path(root: &Path, session: &str) -> PathBuf {
+    root.join(MINIDUMP_DIR).join(format!("{}.dmp", session))
+}
+
+/// Remembers a packet seen by the proxy so it can be included in a crash
+/// report. Contents are only kept when they'd be captured unchanged
+pub fn remember(time: u64, connection: u32, direction: Direction, packet: &Packet) {
+    let limit = config().crash.recent_packets;
+    if limit == 0 {
+        return;
+    }
+
+    let contents = CaptureProfile::current().packet_contents()
+        && Anonymization::current() == Anonymization::Off
+        && PrivacyCategories::current().packet_contents(&packet.frame);
+    let packet = RecentPacket {
+        index: RECENT_INDEX.fetch_add(1, Ordering::Relaxed),
+        time,
+        connection,
+        direction,
+        packet: packet.clone(),
+        contents,
+    };
+
+    let recent = &mut *RECENT.lock().expect("Recent packets lock poisoned");
+    while recent.len() >= limit {
+        recent.pop_front();
+    }
+    recent.push_back(packet);
+}
+
+#[cfg(feature = "plugin")]
+unsafe extern "system" fn exception_filter(info: *const EXCEPTION_POINTERS) -> i32 {
+    if !REPORTED.swap(true, Ordering::AcqRel) {
+        write_report(info);
+    }
+
+    let previous = PREVIOUS_FILTER.load(Ordering::Acquire);
+    if previous == 0 {
+        return EXCEPTION_CONTINUE_SEARCH;
+    }
+    let previous: LPTOP_LEVEL_EXCEPTION_FILTER = std::mem::transmute(previous);
+    match previous {
+        Some(previous) => previous(info),
+        None => EXCEPTION_CONTINUE_SEARCH,
+    }
+}
+
+#[cfg(feature = "plugin")]
+/// Writes the crash report into the session directory
+unsafe fn write_report(info: *const EXCEPTION_POINTERS) {
+    let Some(capture) = capture() else {
+        return;
+    };
+
+    let dir = capture.dir.join(CRASH_DIR);
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        error!("Failed to create crash directory: {}", err);
+        return;
+    }
+
+    let minidump = match write_session_minidump(&capture.dir, info) {
+        Ok(()) => true,
+        Err(err) => {
+            error!("Failed to write crash minidump: {}", err);
+            false
+        }
+    };
+
+    let (code, address) = match info.as_ref().and_then(|info| info.ExceptionRecord.as_ref()) {
+        Some(record) => (
+            record.ExceptionCode as u32,
+            record.ExceptionAddress as usize,
+        ),
+        None => (0, 0),
+    };
+    let module = module_at(address);
+    let plugin = module_at(exception_filter as usize);
+
+    let report = CrashReport {
+        time: now_millis(),
+        plugin_version: APP_VERSION,
+        build: build_info(),
+        code: format!("{:#010X}", code),
+        address: format!("{:#X}", address),
+        module: module.and_then(|module| module_path(module)),
+        in_plugin: module.is_some() && module == plugin,
+        minidump,
+        errors: errors::try_summary(),
+    };
+    error!("Game crashed: {:?}", report);
+
+    let result = File::create(dir.join("crash.json"))
+        .and_then(|file| Ok(serde_json::to_writer_pretty(file, &report)?));
+    if let Err(err) = result {
+        error!("Failed to write crash report: {}", err);
+    }
+
+    // The crashing thread may be holding the lock
+    if let Ok(recent) = RECENT.try_lock() {
+        if let Err(err) = write_recent(&dir.join("packets.jsonl"), &recent) {
+            error!("Failed to write recent packets: {}", err);
+        }
+    }
+}
+
+#[cfg(feature = "plugin")]
+/// Writes the minidump for the session in `dir` outside of the session
+/// directory, so it's left out of the integrity file and uploads
+unsafe fn write_session_minidump(dir: &Path, info: *const EXCEPTION_POINTERS) -> io::Result<()> {
+    let (Some(root), Some(session)) = (dir.parent(), dir.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Session directory has no parent",
+        ));
+    };
+    let path = minidump_path(root, &session.to_string_lossy());
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_minidump(&path, info)
+}
+
+#[cfg(feature = "plugin")]
+/// Writes a minidump of the game process to the file at `path`
+unsafe fn write_minidump(path: &Path, info: *const EXCEPTION_POINTERS) -> io::Result<()> {
+    let file = File::create(path)?;
+    let exception = MINIDUMP_EXCEPTION_INFORMATION {
+        ThreadId: GetCurrentThreadId(),
+        ExceptionPointers: info as *mut EXCEPTION_POINTERS,
+        ClientPointers: 0,
+    };
+
+    let result = MiniDumpWriteDump(
+        GetCurrentProcess(),
+        GetCurrentProcessId(),
+        file.as_raw_handle() as isize,
+        MiniDumpWithThreadInfo | MiniDumpWithIndirectlyReferencedMemory,
+        if info.is_null() { null() } else { &exception },
+        null(),
+        null(),
+    );
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "plugin")]
+fn write_recent(path: &Path, recent: &VecDeque<RecentPacket>) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_jsonl_header(&mut writer)?;
+    for recent in recent {
+        let record = PacketRecord::new(
+            recent.index,
+            recent.time,
+            recent.connection,
+            recent.direction,
+            &recent.packet,
+            recent.contents,
+        );
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+#[cfg(feature = "plugin")]
+/// Finds the module containing the provided address
+unsafe fn module_at(address: usize) -> Option<HMODULE> {
+    let mut module: HMODULE = 0;
+    let found = GetModuleHandleExW(
+        GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+        address as PCWSTR,
+        &mut module,
+    );
+    if found == 0 {
+        None
+    } else {
+        Some(module)
+    }
+}
+
+#[cfg(feature = "plugin")]
+/// Obtains the file path of the module
+unsafe fn module_path(module: HMODULE) -> Option<String> {
+    let mut buffer = [0u16; 260];
+    let length = GetModuleFileNameW(module, buffer.as_mut_ptr(), buffer.len() as u32);
+    if length == 0 {
+        return None;
+    }
+    Some(String::from_utf16_lossy(&buffer[..length as usize]))
+}
@@ -0,0 +1,225 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        append_jsonl,
+        value::{find, Field, TdfValue},
+        Direction,
+    },
+    errors::{self, CaptureIoError},
+    servers::{
+        components::{authentication, component_key, get_command_name},
+        packet::FrameType,
+    },
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The file name entitlement list records are written to
+pub const ENTITLEMENTS_FILE: &str = "entitlements.jsonl";
+
+/// The file name of the entitlement summary
+const SUMMARY_FILE: &str = "entitlements.json";
+
+/// Decoder for the Authentication entitlement commands, recording the
+/// exact entitlement tags and group names the official server returns.
+/// The game has no inventory or store component so entitlements are
+/// the only record of owned content
+pub struct EntitlementsDecoder {
+    /// The path entitlement list records are written to
+    path: PathBuf,
+    /// The path the summary is written to
+    summary_path: PathBuf,
+    /// Group names requested by list requests waiting for their
+    /// response by connection and sequence number
+    pending: HashMap<(u32, u16), Vec<String>>,
+    /// Every entitlement tag seen for each group name
+    summary: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// Entitlements returned for a list request
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct EntitlementListRecord {
+    /// Unix timestamp in microseconds the list was captured
+    time: u64,
+    /// ID of the proxied connection the list was seen on
+    connection: u32,
+    /// The command that returned the list
+    command: u16,
+    command_name: Option<&'static str>,
+    /// Group names the client asked for
+    requested_groups: Vec<String>,
+    /// Entitlements included in the response
+    entitlements: Vec<EntitlementRecord>,
+}
+
+/// Single entitlement within a list
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct EntitlementRecord {
+    /// The entitlement ID
+    id: Option<u64>,
+    /// The entitlement tag ("ME3_PRC_EXTENDEDCUT")
+    tag: Option<String>,
+    /// The group name the entitlement belongs to ("ME3PCOffers")
+    group: Option<String>,
+    /// The product ID
+    product: Option<String>,
+    /// The project ID
+    project: Option<String>,
+    /// The date the entitlement was granted
+    granted: Option<String>,
+    /// The date the entitlement was terminated
+    terminated: Option<String>,
+    /// The entitlement status
+    status: Option<u64>,
+    /// The entitlement type
+    entitlement_type: Option<u64>,
+    /// Number of uses remaining
+    use_count: Option<u64>,
+    /// The entitlement version
+    version: Option<u64>,
+}
+
+impl EntitlementsDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(ENTITLEMENTS_FILE),
+            summary_path: dir.join(SUMMARY_FILE),
+            pending: HashMap::new(),
+            summary: BTreeMap::new(),
+        }
+    }
+
+    fn write_summary(&self) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(&self.summary)?;
+        std::fs::write(&self.summary_path, bytes)
+    }
+}
+
+impl PacketDecoder for EntitlementsDecoder {
+    fn name(&self) -> &'static str {
+        "entitlements"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        if frame.component != authentication::COMPONENT
+            || !matches!(
+                frame.command,
+                authentication::LIST_USER_ENTITLEMENTS_2
+                    | authentication::LIST_ENTITLEMENTS
+                    | authentication::LIST_PERSONAL_ENTITLEMENTS_2
+            )
+        {
+            return;
+        }
+
+        let Some(fields) = ctx.fields else {
+            return;
+        };
+
+        match (ctx.direction, frame.ty) {
+            (Direction::Send, FrameType::Request) => {
+                self.pending
+                    .insert((ctx.connection, frame.seq), requested_groups(fields));
+            }
+            (Direction::Receive, FrameType::Response) => {
+                let requested_groups = self
+                    .pending
+                    .remove(&(ctx.connection, frame.seq))
+                    .unwrap_or_default();
+
+                let entitlements: Vec<EntitlementRecord> = find(fields, "NLST")
+                    .and_then(TdfValue::as_list)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(TdfValue::as_group)
+                    .map(EntitlementRecord::from_fields)
+                    .collect();
+
+                for entitlement in &entitlements {
+                    if let (Some(group), Some(tag)) = (&entitlement.group, &entitlement.tag) {
+                        self.summary
+                            .entry(group.clone())
+                            .or_default()
+                            .insert(tag.clone());
+                    }
+                }
+
+                let key = component_key(frame.component, frame.command);
+                let record = EntitlementListRecord {
+                    time: ctx.time,
+                    connection: ctx.connection,
+                    command: frame.command,
+                    command_name: get_command_name(key, false),
+                    requested_groups,
+                    entitlements,
+                };
+                if let Err(err) = append_jsonl(&self.path, &record) {
+                    errors::report(CaptureIoError::Write {
+                        what: "entitlement record",
+                        source: err,
+                    });
+                }
+                if let Err(err) = self.write_summary() {
+                    errors::report(CaptureIoError::Write {
+                        what: "entitlement summary",
+                        source: err,
+                    });
+                }
+            }
+            (Direction::Receive, FrameType::Error) => {
+                self.pending.remove(&(ctx.connection, frame.seq));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Finds the group names a list request asked for, requests either
+/// list the group names or provide a single group name
+fn requested_groups(fields: &[Field]) -> Vec<String> {
+    if let Some(values) = find(fields, "GNLS").and_then(TdfValue::as_list) {
+        return values
+            .iter()
+            .filter_map(TdfValue::as_str)
+            .map(str::to_string)
+            .collect();
+    }
+
+    find(fields, "GNAM")
+        .and_then(TdfValue::as_str)
+        .filter(|value| !value.is_empty())
+        .map(|value| vec![value.to_string()])
+        .unwrap_or_default()
+}
+
+impl EntitlementRecord {
+    fn from_fields(fields: &[Field]) -> Self {
+        let string = |tag: &str| {
+            find(fields, tag)
+                .and_then(TdfValue::as_str)
+                .map(str::to_string)
+        };
+        let number = |tag: &str| find(fields, tag).and_then(TdfValue::as_u64);
+
+        Self {
+            id: number("ID"),
+            tag: string("TAG"),
+            group: string("GNAM"),
+            product: string("PRID"),
+            project: string("PJID"),
+            granted: string("GDAY"),
+            terminated: string("TDAY"),
+            status: number("STAT"),
+            entitlement_type: number("TYPE"),
+            use_count: number("UCNT"),
+            version: number("VER"),
+        }
+    }
+}
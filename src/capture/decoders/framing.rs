@@ -0,0 +1,159 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{append_jsonl, Direction},
+    errors::{self, CaptureIoError},
+    servers::packet::{FrameType, PacketOptions},
+};
+use log::{debug, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// The file name framing records are written to
+pub const FRAMING_FILE: &str = "framing.jsonl";
+
+/// The largest length that fits in a frame without the jumbo extension
+const MAX_STANDARD_LENGTH: usize = 0xFFFF;
+
+/// Frames above this size are very likely the result of the stream
+/// framing becoming misaligned rather than real payloads
+const SUSPICIOUS_LENGTH: usize = 4 * 1024 * 1024;
+
+/// Decoder auditing the framing of large and unusual packets. Every jumbo
+/// frame, frame carrying a context and frame showing a framing anomaly is
+/// recorded, as
+/// large responses (e.g. stats) are where framing bugs tend to hide
+pub struct FramingDecoder {
+    /// The path framing records are written to
+    path: PathBuf,
+}
+
+/// Framing details of an audited packet
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct FramingRecord {
+    /// Unix timestamp in microseconds the packet was captured
+    time: u64,
+    /// ID of the proxied connection the packet was seen on
+    connection: u32,
+    /// The direction the packet was travelling
+    direction: Direction,
+    component: u16,
+    command: u16,
+    ty: FrameType,
+    seq: u16,
+    /// Raw bits of the frame options
+    options: u8,
+    /// Length of the packet contents in bytes
+    length: usize,
+    /// Whether the frame used the jumbo length extension
+    jumbo: bool,
+    /// Context value from the frame header if present
+    context: Option<u32>,
+    /// Anomalies found with the frame
+    anomalies: Vec<FramingAnomaly>,
+}
+
+/// Anomaly found while auditing a frame
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum FramingAnomaly {
+    /// The jumbo flag was set for a length that fits without it
+    UnneededJumbo,
+    /// The length needs the jumbo extension but the flag wasn't set
+    MissingJumbo,
+    /// The jumbo context flag was set without the frame having a context
+    OrphanJumboContext,
+    /// The frame type isn't one of the known types
+    UnknownType { value: u8 },
+    /// The length is large enough that the framing has likely desynced
+    SuspiciousLength,
+    /// The contents didn't decode as complete TDF values
+    DecodeFailed { error: String },
+}
+
+impl FramingDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(FRAMING_FILE),
+        }
+    }
+}
+
+impl PacketDecoder for FramingDecoder {
+    fn name(&self) -> &'static str {
+        "framing"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        let length = ctx.packet.contents.len();
+        let jumbo = frame.options.contains(PacketOptions::JUMBO_FRAME);
+
+        let mut anomalies = Vec::new();
+
+        if jumbo && length <= MAX_STANDARD_LENGTH {
+            anomalies.push(FramingAnomaly::UnneededJumbo);
+        } else if !jumbo && length > MAX_STANDARD_LENGTH {
+            anomalies.push(FramingAnomaly::MissingJumbo);
+        }
+
+        if frame.options.contains(PacketOptions::JUMBO_CONTEXT)
+            && !frame.options.contains(PacketOptions::HAS_CONTEXT)
+        {
+            anomalies.push(FramingAnomaly::OrphanJumboContext);
+        }
+
+        if let FrameType::Unknown(value) = frame.ty {
+            anomalies.push(FramingAnomaly::UnknownType { value });
+        }
+
+        if length > SUSPICIOUS_LENGTH {
+            anomalies.push(FramingAnomaly::SuspiciousLength);
+        }
+
+        if let Some(err) = ctx.decode_error {
+            anomalies.push(FramingAnomaly::DecodeFailed {
+                error: err.to_string(),
+            });
+        }
+
+        if !jumbo && frame.context.is_none() && anomalies.is_empty() {
+            return;
+        }
+
+        if anomalies.is_empty() {
+            debug!(
+                "Audited frame {:#06x}->{:#06x} ({}) of {} bytes (Jumbo: {}, Context: {:?})",
+                frame.component, frame.command, frame.seq, length, jumbo, frame.context
+            );
+        } else {
+            warn!(
+                "Framing anomalies in {:#06x}->{:#06x} ({}) of {} bytes: {:?}",
+                frame.component, frame.command, frame.seq, length, anomalies
+            );
+        }
+
+        let record = FramingRecord {
+            time: ctx.time,
+            connection: ctx.connection,
+            direction: ctx.direction,
+            component: frame.component,
+            command: frame.command,
+            ty: frame.ty,
+            seq: frame.seq,
+            options: frame.options.bits(),
+            length,
+            jumbo,
+            context: frame.context,
+            anomalies,
+        };
+
+        if let Err(err) = append_jsonl(&self.path, &record) {
+            errors::report(CaptureIoError::Write {
+                what: "framing record",
+                source: err,
+            });
+        }
+    }
+}
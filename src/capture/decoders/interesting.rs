@@ -0,0 +1,294 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        append_jsonl,
+        value::{Field, TdfValue},
+        Direction,
+    },
+    config::config,
+    errors::{self, CaptureIoError},
+    locale,
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::FrameType,
+    },
+    ui,
+};
+use log::debug;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The file name interesting packet records are written to
+pub const INTERESTING_FILE: &str = "interesting.jsonl";
+
+/// Number of packets of a command seen before its payloads are checked
+/// for being unusually large
+const MIN_LENGTH_HISTORY: u64 = 20;
+
+/// How many times larger than the average for its command a payload must
+/// be to be flagged
+const LARGE_PAYLOAD_FACTOR: u64 = 4;
+
+/// Payloads smaller than this are never flagged as large
+const MIN_LARGE_PAYLOAD: usize = 1024;
+
+/// Minimum time between notifications, every notification is a dialog
+/// so they're limited to avoid burying the game in them
+const NOTIFICATION_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Decoder flagging packets that are likely to hold novel data so they
+/// can be found among hours of routine traffic
+pub struct InterestingDecoder {
+    /// The path interesting packet records are written to
+    path: PathBuf,
+    /// Components and commands already checked against the definitions
+    checked: HashSet<(u16, u16)>,
+    /// Error codes already seen for each component and command
+    errors: HashSet<(u16, u16, u16)>,
+    /// Number of packets and total payload length for each component,
+    /// command and frame type
+    lengths: HashMap<(u16, u16, u8), (u64, u64)>,
+    /// Tags seen for each component, command and frame type
+    tags: HashMap<(u16, u16, u8), HashSet<String>>,
+    /// Capture time in microseconds of the packet the last notification
+    /// was shown for
+    last_notification: Option<u64>,
+}
+
+/// Packet flagged as interesting
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct InterestingRecord {
+    /// Unix timestamp in microseconds the packet was captured
+    time: u64,
+    /// ID of the proxied connection the packet was seen on
+    connection: u32,
+    /// The direction the packet was travelling
+    direction: Direction,
+    component: u16,
+    command: u16,
+    component_name: Option<&'static str>,
+    command_name: Option<&'static str>,
+    ty: FrameType,
+    seq: u16,
+    error: u16,
+    /// Length of the packet contents in bytes
+    length: usize,
+    /// Why the packet was flagged
+    reasons: Vec<InterestingReason>,
+}
+
+/// Reason a packet was flagged as interesting
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum InterestingReason {
+    /// The component isn't in the known definitions
+    UnknownComponent,
+    /// The command isn't in the known definitions for its component
+    UnknownCommand,
+    /// The payload is much larger than earlier packets of the command
+    LargePayload { average: u64 },
+    /// Error code not seen before for the command
+    ErrorResponse { error: u16 },
+    /// Tags not seen in earlier packets of the command
+    NewTags { tags: Vec<String> },
+}
+
+impl InterestingReason {
+    fn kind(&self) -> &'static str {
+        match self {
+            InterestingReason::UnknownComponent => "unknown_component",
+            InterestingReason::UnknownCommand => "unknown_command",
+            InterestingReason::LargePayload { .. } => "large_payload",
+            InterestingReason::ErrorResponse { .. } => "error_response",
+            InterestingReason::NewTags { .. } => "new_tags",
+        }
+    }
+}
+
+impl InterestingDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(INTERESTING_FILE),
+            checked: HashSet::new(),
+            errors: HashSet::new(),
+            lengths: HashMap::new(),
+            tags: HashMap::new(),
+            last_notification: None,
+        }
+    }
+
+    /// Checks the packet against every heuristic
+    fn classify(&mut self, ctx: &PacketContext) -> Vec<InterestingReason> {
+        let frame = &ctx.packet.frame;
+        let notify = matches!(frame.ty, FrameType::Notify);
+        let key = (frame.component, frame.command, u8::from(frame.ty));
+        let mut reasons = Vec::new();
+
+        if self.checked.insert((frame.component, frame.command)) {
+            if get_component_name(frame.component).is_none() {
+                reasons.push(InterestingReason::UnknownComponent);
+            } else if get_command_name(component_key(frame.component, frame.command), notify)
+                .is_none()
+            {
+                reasons.push(InterestingReason::UnknownCommand);
+            }
+        }
+
+        let is_error = matches!(frame.ty, FrameType::Error) || frame.error != 0;
+        if is_error
+            && self
+                .errors
+                .insert((frame.component, frame.command, frame.error))
+        {
+            reasons.push(InterestingReason::ErrorResponse { error: frame.error });
+        }
+
+        let length = ctx.packet.contents.len();
+        let (count, total) = self.lengths.entry(key).or_default();
+        if *count >= MIN_LENGTH_HISTORY && length >= MIN_LARGE_PAYLOAD {
+            let average = *total / *count;
+            if length as u64 > average * LARGE_PAYLOAD_FACTOR {
+                reasons.push(InterestingReason::LargePayload { average });
+            }
+        }
+        *count += 1;
+        *total += length as u64;
+
+        if let Some(fields) = ctx.fields {
+            let mut paths = Vec::new();
+            collect_tags("", fields, &mut paths);
+
+            // Every tag is new in the first packet of a command
+            let seen_before = self.tags.contains_key(&key);
+            let known = self.tags.entry(key).or_default();
+            let tags: Vec<String> = paths
+                .into_iter()
+                .filter(|path| known.insert(path.clone()))
+                .collect();
+            if seen_before && !tags.is_empty() {
+                reasons.push(InterestingReason::NewTags { tags });
+            }
+        }
+
+        reasons
+    }
+
+    /// Shows a notification for the flagged packet unless one was
+    /// shown recently
+    fn notify(&mut self, record: &InterestingRecord) {
+        if !config().notify_interesting {
+            return;
+        }
+        if self.last_notification.is_some_and(|value| {
+            record.time.saturating_sub(value) < NOTIFICATION_COOLDOWN.as_micros() as u64
+        }) {
+            return;
+        }
+        self.last_notification = Some(record.time);
+
+        let packet = format!(
+            "{}->{}",
+            record
+                .component_name
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{:#06x}", record.component)),
+            record
+                .command_name
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{:#06x}", record.command)),
+        );
+        let reasons = record
+            .reasons
+            .iter()
+            .map(InterestingReason::kind)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        ui::show_notification(
+            &locale::text("interesting.title"),
+            &locale::format(
+                "interesting.message",
+                &[("packet", packet.as_str()), ("reasons", reasons.as_str())],
+            ),
+        );
+    }
+}
+
+/// Collects the dotted tag path of every field including the fields
+/// nested within groups, lists of groups and unions
+fn collect_tags(prefix: &str, fields: &[Field], out: &mut Vec<String>) {
+    for field in fields {
+        let path = if prefix.is_empty() {
+            field.tag.clone()
+        } else {
+            format!("{}.{}", prefix, field.tag)
+        };
+        collect_value_tags(&path, &field.value, out);
+        out.push(path);
+    }
+}
+
+fn collect_value_tags(path: &str, value: &TdfValue, out: &mut Vec<String>) {
+    match value {
+        TdfValue::Group(fields) => collect_tags(path, fields, out),
+        TdfValue::List(values) => {
+            for value in values {
+                collect_value_tags(path, value, out);
+            }
+        }
+        TdfValue::Union {
+            value: Some(field), ..
+        } => collect_tags(path, std::slice::from_ref(field.as_ref()), out),
+        _ => {}
+    }
+}
+
+impl PacketDecoder for InterestingDecoder {
+    fn name(&self) -> &'static str {
+        "interesting"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let reasons = self.classify(ctx);
+        if reasons.is_empty() {
+            return;
+        }
+
+        let frame = &ctx.packet.frame;
+        let key = component_key(frame.component, frame.command);
+        let record = InterestingRecord {
+            time: ctx.time,
+            connection: ctx.connection,
+            direction: ctx.direction,
+            component: frame.component,
+            command: frame.command,
+            component_name: get_component_name(frame.component),
+            command_name: get_command_name(key, matches!(frame.ty, FrameType::Notify)),
+            ty: frame.ty,
+            seq: frame.seq,
+            error: frame.error,
+            length: ctx.packet.contents.len(),
+            reasons,
+        };
+
+        debug!(
+            "Interesting packet {:#06x}->{:#06x}: {:?}",
+            frame.component, frame.command, record.reasons
+        );
+
+        if let Err(err) = append_jsonl(&self.path, &record) {
+            errors::report(CaptureIoError::Write {
+                what: "interesting packet record",
+                source: err,
+            });
+        }
+
+        self.notify(&record);
+    }
+}
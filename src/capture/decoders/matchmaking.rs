@@ -0,0 +1,211 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        collection::record_game_marker,
+        value::{fields_to_json, find, Field},
+    },
+    definitions::annotate_json,
+    servers::{components::game_manager, packet::FrameType},
+};
+use log::error;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the directory within the capture that match timelines are
+/// written to
+const MATCHES_DIR: &str = "matches";
+
+/// Decoder for GameManager packets which reconstructs the lifecycle of each
+/// game and matchmaking session into a JSON timeline per match
+pub struct MatchmakingDecoder {
+    /// Directory the timelines are written to
+    dir: PathBuf,
+    /// Timelines for each game / matchmaking session keyed by file name
+    timelines: HashMap<String, Timeline>,
+}
+
+/// Timeline of events for a single game or matchmaking session
+#[derive(Serialize)]
+struct Timeline {
+    /// The game or matchmaking session ID
+    id: u64,
+    /// The players that have been seen in the game keyed by player ID
+    players: BTreeMap<u64, String>,
+    /// The latest game attributes
+    attributes: Value,
+    /// The events in the order they occurred
+    events: Vec<TimelineEvent>,
+}
+
+#[derive(Serialize)]
+struct TimelineEvent {
+    /// Unix timestamp in microseconds of the event
+    time: u64,
+    /// ID of the proxied connection the event was seen on
+    connection: u32,
+    /// Name of the event
+    event: &'static str,
+    /// The decoded packet contents of the event
+    details: Value,
+}
+
+impl MatchmakingDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.join(MATCHES_DIR),
+            timelines: HashMap::new(),
+        }
+    }
+
+    /// Appends an event to the timeline with the provided name and
+    /// writes the updated timeline to disk
+    fn push_event(&mut self, name: String, id: u64, event: &'static str, ctx: &PacketContext) {
+        let fields = ctx.fields.unwrap_or_default();
+        let timeline = self
+            .timelines
+            .entry(name.clone())
+            .or_insert_with(|| Timeline {
+                id,
+                players: BTreeMap::new(),
+                attributes: Value::Null,
+                events: Vec::new(),
+            });
+
+        let frame = &ctx.packet.frame;
+        let mut details = fields_to_json(fields);
+        annotate_json(frame.component, frame.command, &mut details);
+
+        timeline.update(fields);
+        timeline.events.push(TimelineEvent {
+            time: ctx.time,
+            connection: ctx.connection,
+            event,
+            details,
+        });
+
+        if let Err(err) = write_timeline(&self.dir, &name, timeline) {
+            error!("Failed to write match timeline {}: {}", name, err);
+        }
+    }
+}
+
+impl PacketDecoder for MatchmakingDecoder {
+    fn name(&self) -> &'static str {
+        "matchmaking"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        if frame.component != game_manager::COMPONENT {
+            return;
+        }
+
+        let Some(fields) = ctx.fields else {
+            return;
+        };
+
+        let event = match (frame.ty, frame.command) {
+            (FrameType::Response, game_manager::CREATE_GAME) => "CreateGame",
+            (FrameType::Response, game_manager::JOIN_GAME) => "JoinGame",
+            (FrameType::Response, game_manager::START_MATCHMAKING) => "StartMatchmaking",
+            (FrameType::Notify, game_manager::MATCHMAKING_FAILED) => "MatchmakingFailed",
+            (FrameType::Notify, game_manager::MATCHMAKING_ASYNC_STATUS) => "MatchmakingStatus",
+            (FrameType::Notify, game_manager::GAME_CREATED) => "GameCreated",
+            (FrameType::Notify, game_manager::GAME_REMOVED) => "GameRemoved",
+            (FrameType::Notify, game_manager::GAME_SETUP) => "GameSetup",
+            (FrameType::Notify, game_manager::PLAYER_JOINING) => "PlayerJoining",
+            (FrameType::Notify, game_manager::PLAYER_JOIN_COMPLETED) => "PlayerJoinCompleted",
+            (FrameType::Notify, game_manager::PLAYER_REMOVED) => "PlayerRemoved",
+            (FrameType::Notify, game_manager::HOST_MIGRATION_START) => "HostMigrationStart",
+            (FrameType::Notify, game_manager::HOST_MIGRATION_FINISHED) => "HostMigrationFinished",
+            (FrameType::Notify, game_manager::GAME_ATTRIB_CHANGE) => "GameAttributesChanged",
+            (FrameType::Notify, game_manager::PLAYER_ATTRIB_CHANGE) => "PlayerAttributesChanged",
+            (FrameType::Notify, game_manager::GAME_STATE_CHANGE) => "GameStateChanged",
+            (FrameType::Notify, game_manager::GAME_SETTINGS_CHANGE) => "GameSettingsChanged",
+            (FrameType::Notify, game_manager::GAME_RESET) => "GameReset",
+            _ => return,
+        };
+
+        // Games are identified by their game ID, matchmaking sessions
+        // that haven't resulted in a game by their session ID
+        if let Some(id) = game_id(fields) {
+            self.push_event(format!("game-{}", id), id, event, ctx);
+
+            // Game wide notifications are received by every player in the
+            // game so they are used to line up multi-player captures
+            if frame.ty == FrameType::Notify && is_game_wide(frame.command) {
+                record_game_marker(event, id);
+            }
+        } else if let Some(id) = find(fields, "MSID").and_then(|value| value.as_u64()) {
+            self.push_event(format!("matchmaking-{}", id), id, event, ctx);
+        }
+    }
+}
+
+impl Timeline {
+    /// Updates the tracked players and attributes from the fields of
+    /// an event
+    fn update(&mut self, fields: &[Field]) {
+        if let Some(attributes) = find(fields, "ATTR") {
+            self.attributes = attributes.to_json();
+        }
+
+        // Player data from joining notifications
+        if let Some(player) = find(fields, "PDAT").and_then(|value| value.as_group()) {
+            self.add_player(player);
+        }
+
+        // Player list from game setup notifications
+        if let Some(players) = find(fields, "PROS").and_then(|value| value.as_list()) {
+            players
+                .iter()
+                .filter_map(|value| value.as_group())
+                .for_each(|player| self.add_player(player));
+        }
+    }
+
+    fn add_player(&mut self, player: &[Field]) {
+        let id = find(player, "PID").and_then(|value| value.as_u64());
+        let name = find(player, "NAME").and_then(|value| value.as_str());
+        if let (Some(id), Some(name)) = (id, name) {
+            self.players.insert(id, name.to_string());
+        }
+    }
+}
+
+/// Finds the game ID from the packet fields, the game ID is either at the
+/// top level or within the game data group
+fn game_id(fields: &[Field]) -> Option<u64> {
+    find(fields, "GID")
+        .or_else(|| {
+            find(fields, "GAME")
+                .and_then(|value| value.as_group())
+                .and_then(|game| find(game, "GID"))
+        })
+        .and_then(|value| value.as_u64())
+}
+
+fn write_timeline(dir: &Path, name: &str, timeline: &Timeline) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let bytes = serde_json::to_vec_pretty(timeline)?;
+    std::fs::write(dir.join(format!("{}.json", name)), bytes)
+}
+
+/// Whether the GameManager notification with the provided command is
+/// sent to every player in the game
+fn is_game_wide(command: u16) -> bool {
+    matches!(
+        command,
+        game_manager::GAME_SETUP
+            | game_manager::GAME_STATE_CHANGE
+            | game_manager::GAME_RESET
+            | game_manager::GAME_REMOVED
+            | game_manager::HOST_MIGRATION_START
+            | game_manager::HOST_MIGRATION_FINISHED
+    )
+}
@@ -0,0 +1,81 @@
+use super::{
+    value::{Field, ValueError},
+    Direction,
+};
+use crate::servers::packet::Packet;
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+};
+
+pub mod entitlements;
+pub mod framing;
+pub mod interesting;
+pub mod matchmaking;
+pub mod network;
+pub mod settings;
+pub mod social;
+pub mod stats;
+pub mod unknown_tdf;
+
+/// Context for a packet that passed through the proxy
+pub struct PacketContext<'a> {
+    /// ID of the proxied connection the packet belongs to
+    pub connection: u32,
+    /// The direction the packet was travelling
+    pub direction: Direction,
+    /// Unix timestamp in microseconds of when the packet was captured,
+    /// taken from the monotonic session clock. Decoders use this as their
+    /// only clock so replaying a packet log gives the same outputs
+    pub time: u64,
+    /// The packet itself
+    pub packet: &'a Packet,
+    /// The decoded packet contents, None if the contents couldn't
+    /// be decoded
+    pub fields: Option<&'a [Field]>,
+    /// The error that occurred decoding the packet contents
+    pub decode_error: Option<&'a ValueError>,
+}
+
+/// Decoder that extracts structured data from the packets passing
+/// through the proxy into its own capture files
+pub trait PacketDecoder: Send {
+    /// Name of the decoder used when reporting failures
+    fn name(&self) -> &'static str;
+
+    /// Handles a packet that passed through the proxy
+    fn handle(&mut self, ctx: &PacketContext);
+}
+
+/// Creates all the decoders writing into the provided capture directory
+pub fn create_decoders(dir: &Path) -> Vec<Box<dyn PacketDecoder>> {
+    vec![
+        Box::new(framing::FramingDecoder::new(dir)),
+        Box::new(matchmaking::MatchmakingDecoder::new(dir)),
+        Box::new(network::NetworkDecoder::new(dir)),
+        Box::new(stats::StatsDecoder::new(dir)),
+        Box::new(social::SocialDecoder::new(dir)),
+        Box::new(settings::SettingsDecoder::new(dir)),
+        Box::new(entitlements::EntitlementsDecoder::new(dir)),
+        Box::new(unknown_tdf::UnknownTdfDecoder::new(dir)),
+        Box::new(interesting::InterestingDecoder::new(dir)),
+    ]
+}
+
+/// Passes the packet to each of the decoders in order. A panicking decoder
+/// is removed rather than stopping the capture, returns the names of the
+/// removed decoders
+pub fn run_decoders(
+    decoders: &mut Vec<Box<dyn PacketDecoder>>,
+    ctx: &PacketContext,
+) -> Vec<&'static str> {
+    let mut panicked = Vec::new();
+    decoders.retain_mut(|decoder| {
+        let result = catch_unwind(AssertUnwindSafe(|| decoder.handle(ctx)));
+        if result.is_err() {
+            panicked.push(decoder.name());
+        }
+        result.is_ok()
+    });
+    panicked
+}
@@ -0,0 +1,220 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        value::{find, Field, TdfValue},
+        Direction,
+    },
+    errors::{self, CaptureIoError},
+    servers::{components::user_sessions, packet::FrameType},
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    io,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+/// The file name of the networking report
+const NETWORK_FILE: &str = "network.json";
+
+/// Decoder for the UserSessions networking details reported by the local
+/// client and sent by the server for other players, producing a networking
+/// report for each player
+pub struct NetworkDecoder {
+    /// The path the report is written to
+    path: PathBuf,
+    /// Networking details for each player keyed by user ID, the local
+    /// player is keyed by their connection
+    players: BTreeMap<String, PlayerNetwork>,
+}
+
+/// Networking report for a single player
+#[derive(Default, Serialize)]
+struct PlayerNetwork {
+    /// The player name if known
+    name: Option<String>,
+    /// Internal (LAN) address of the player
+    internal: Option<String>,
+    /// External (WAN) address of the player
+    external: Option<String>,
+    /// The NAT type value
+    nat_type: Option<u64>,
+    /// Name of the NAT type
+    nat_type_name: Option<&'static str>,
+    /// Measured upstream bits per second
+    upstream_bps: Option<u64>,
+    /// Measured downstream bits per second
+    downstream_bps: Option<u64>,
+    /// Latency to each of the ping sites
+    ping_sites: Value,
+    /// Number of network updates seen for this player
+    updates: u32,
+    /// Unix timestamp in microseconds of the last update
+    last_update: u64,
+    /// Diagnostic notes about the players networking
+    notes: Vec<&'static str>,
+}
+
+impl NetworkDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(NETWORK_FILE),
+            players: BTreeMap::new(),
+        }
+    }
+
+    fn write_report(&self) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(&self.players)?;
+        std::fs::write(&self.path, bytes)
+    }
+}
+
+impl PacketDecoder for NetworkDecoder {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        if frame.component != user_sessions::COMPONENT {
+            return;
+        }
+
+        let Some(fields) = ctx.fields else {
+            return;
+        };
+
+        let (key, name, data) = match (ctx.direction, frame.ty, frame.command) {
+            // Local client reporting its own networking details
+            (Direction::Send, FrameType::Request, user_sessions::UPDATE_NETWORK_INFO) => {
+                (format!("local-{}", ctx.connection), None, fields)
+            }
+            // Server providing networking details for a user
+            (
+                Direction::Receive,
+                FrameType::Notify,
+                user_sessions::USER_SESSION_EXTENDED_DATA_UPDATE
+                | user_sessions::USER_ADDED
+                | user_sessions::USER_UPDATED,
+            ) => {
+                let user = find(fields, "USER").and_then(TdfValue::as_group);
+                let id = find(fields, "USID")
+                    .or_else(|| user.and_then(|user| find(user, "ID")))
+                    .and_then(TdfValue::as_u64);
+                let name = user
+                    .and_then(|user| find(user, "NAME"))
+                    .and_then(TdfValue::as_str);
+                let data = find(fields, "DATA").and_then(TdfValue::as_group);
+
+                match (id, data) {
+                    (Some(id), Some(data)) => (id.to_string(), name, data),
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+
+        let player = self.players.entry(key).or_default();
+        if let Some(name) = name {
+            player.name = Some(name.to_string());
+        }
+        player.update(data, ctx.time);
+
+        if let Err(err) = self.write_report() {
+            errors::report(CaptureIoError::Write {
+                what: "network report",
+                source: err,
+            });
+        }
+    }
+}
+
+impl PlayerNetwork {
+    /// Updates the networking details from the provided network data
+    fn update(&mut self, data: &[Field], time: u64) {
+        self.updates += 1;
+        self.last_update = time;
+
+        if let Some(addresses) = find(data, "ADDR").and_then(union_group) {
+            self.internal = find(addresses, "INIP").and_then(format_address);
+            self.external = find(addresses, "EXIP").and_then(format_address);
+        }
+
+        // Local updates use NQOS while server data uses QDAT
+        if let Some(qos) = find(data, "NQOS")
+            .or_else(|| find(data, "QDAT"))
+            .and_then(TdfValue::as_group)
+        {
+            self.nat_type = find(qos, "NATT").and_then(TdfValue::as_u64);
+            self.nat_type_name = self.nat_type.map(nat_type_name);
+            self.upstream_bps = find(qos, "UBPS").and_then(TdfValue::as_u64);
+            self.downstream_bps = find(qos, "DBPS").and_then(TdfValue::as_u64);
+        }
+
+        if let Some(ping_sites) = find(data, "NLMP").or_else(|| find(data, "PSLM")) {
+            self.ping_sites = ping_sites.to_json();
+        }
+
+        self.notes = self.diagnose();
+    }
+
+    /// Creates diagnostic notes about the current networking details
+    fn diagnose(&self) -> Vec<&'static str> {
+        let mut notes = Vec::new();
+
+        match (&self.internal, &self.external) {
+            (_, None) => notes.push("Missing external address"),
+            (Some(internal), Some(external)) if internal == external => {
+                notes.push("Internal and external address match (no NAT)")
+            }
+            (Some(internal), Some(external)) => {
+                let port = |value: &str| value.rsplit(':').next().map(str::to_string);
+                if port(internal.as_str()) != port(external.as_str()) {
+                    notes.push("External port differs from internal port (port translation)");
+                }
+            }
+            _ => {}
+        }
+
+        match self.nat_type {
+            Some(NAT_STRICT) => notes.push("Strict NAT, connections to other players may fail"),
+            Some(NAT_UNKNOWN) | None => notes.push("NAT type unknown"),
+            _ => {}
+        }
+
+        notes
+    }
+}
+
+const NAT_STRICT: u64 = 3;
+const NAT_UNKNOWN: u64 = 4;
+
+fn nat_type_name(value: u64) -> &'static str {
+    match value {
+        0 => "Open",
+        1 => "Moderate",
+        2 => "Sequential",
+        NAT_STRICT => "Strict",
+        _ => "Unknown",
+    }
+}
+
+/// Obtains the group stored within a union value
+fn union_group(value: &TdfValue) -> Option<&[Field]> {
+    match value {
+        TdfValue::Union {
+            value: Some(field), ..
+        } => field.value.as_group(),
+        _ => None,
+    }
+}
+
+/// Formats an address group (IP, PORT) as an address string
+fn format_address(value: &TdfValue) -> Option<String> {
+    let group = value.as_group()?;
+    let ip = find(group, "IP").and_then(TdfValue::as_u64)?;
+    let port = find(group, "PORT").and_then(TdfValue::as_u64)?;
+    Some(format!("{}:{}", Ipv4Addr::from(ip as u32), port))
+}
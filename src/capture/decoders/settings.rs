@@ -0,0 +1,213 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        anonymize::Anonymization,
+        append_jsonl,
+        value::{find, to_hex, Field, TdfValue},
+        Direction,
+    },
+    errors::{self, CaptureIoError},
+    servers::{components::util, packet::FrameType},
+};
+use log::error;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the directory within the capture that the latest value of
+/// each settings key is written to
+const SETTINGS_DIR: &str = "settings";
+
+/// The file name settings records are written to
+pub const SETTINGS_FILE: &str = "settings.jsonl";
+
+/// Decoder for the Util user settings commands, storing the settings blobs
+/// the client saves and loads as named artifacts so the server side
+/// settings storage can be replicated. Blobs are left out when the
+/// anonymization level is redact, only their length and hash are kept
+pub struct SettingsDecoder {
+    /// Directory the settings values are written to
+    dir: PathBuf,
+    /// The path settings records are written to
+    path: PathBuf,
+    /// Keys of load requests waiting for their response by
+    /// connection and sequence number
+    pending: HashMap<(u32, u16), String>,
+}
+
+/// Settings value saved or loaded by the client
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct SettingsRecord<'a> {
+    /// Unix timestamp in microseconds the value was captured
+    time: u64,
+    /// ID of the proxied connection the value was seen on
+    connection: u32,
+    /// The command the value was part of ("save", "load", "load_all")
+    operation: &'static str,
+    /// The settings key
+    key: &'a str,
+    /// Length of the value in bytes
+    length: usize,
+    /// SHA-256 hash of the value as hex
+    sha256: String,
+    /// File within the settings directory holding the value, None if
+    /// the value was left out
+    file: Option<String>,
+}
+
+/// Key deleted by the client
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct DeleteRecord<'a> {
+    time: u64,
+    connection: u32,
+    operation: &'static str,
+    key: &'a str,
+}
+
+impl SettingsDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.join(SETTINGS_DIR),
+            path: dir.join(SETTINGS_FILE),
+            pending: HashMap::new(),
+        }
+    }
+
+    fn record(&self, ctx: &PacketContext, operation: &'static str, key: &str, value: &str) {
+        let file = match Anonymization::current() {
+            Anonymization::Redact => None,
+            _ => match self.write_value(key, value) {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    error!("Failed to write settings value {}: {}", key, err);
+                    None
+                }
+            },
+        };
+
+        let record = SettingsRecord {
+            time: ctx.time,
+            connection: ctx.connection,
+            operation,
+            key,
+            length: value.len(),
+            sha256: to_hex(&Sha256::digest(value.as_bytes())),
+            file,
+        };
+        if let Err(err) = append_jsonl(&self.path, &record) {
+            errors::report(CaptureIoError::Write {
+                what: "settings record",
+                source: err,
+            });
+        }
+    }
+
+    /// Writes the value to the file for its key replacing any
+    /// previous value, returns the file name
+    fn write_value(&self, key: &str, value: &str) -> io::Result<String> {
+        // Keys are chosen by the client so they're made safe for file names
+        let name: String = key
+            .chars()
+            .map(|char| match char {
+                char if char.is_ascii_alphanumeric() || matches!(char, '-' | '_') => char,
+                _ => '_',
+            })
+            .collect();
+        let file = format!("{}.txt", name);
+
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.dir.join(&file), value)?;
+        Ok(file)
+    }
+
+    fn handle_request(&mut self, ctx: &PacketContext, fields: &[Field]) {
+        let frame = &ctx.packet.frame;
+        let key = find(fields, "KEY").and_then(TdfValue::as_str);
+
+        match (frame.command, key) {
+            (util::USER_SETTINGS_SAVE, Some(key)) => {
+                let value = find(fields, "DATA")
+                    .and_then(TdfValue::as_str)
+                    .unwrap_or_default();
+                self.record(ctx, "save", key, value);
+            }
+            (util::USER_SETTINGS_LOAD, Some(key)) => {
+                self.pending
+                    .insert((ctx.connection, frame.seq), key.to_string());
+            }
+            (util::DELETE_USER_SETTINGS, Some(key)) => {
+                let record = DeleteRecord {
+                    time: ctx.time,
+                    connection: ctx.connection,
+                    operation: "delete",
+                    key,
+                };
+                if let Err(err) = append_jsonl(&self.path, &record) {
+                    errors::report(CaptureIoError::Write {
+                        what: "settings record",
+                        source: err,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_response(&mut self, ctx: &PacketContext, fields: &[Field]) {
+        let frame = &ctx.packet.frame;
+
+        match frame.command {
+            util::USER_SETTINGS_LOAD => {
+                let Some(key) = self.pending.remove(&(ctx.connection, frame.seq)) else {
+                    return;
+                };
+                // Error responses for missing keys have no value
+                if let Some(value) = find(fields, "DATA").and_then(TdfValue::as_str) {
+                    self.record(ctx, "load", &key, value);
+                }
+            }
+            util::USER_SETTINGS_LOAD_ALL => {
+                let Some(TdfValue::Map(pairs)) = find(fields, "SMAP") else {
+                    return;
+                };
+                for (key, value) in pairs {
+                    if let (Some(key), Some(value)) = (key.as_str(), value.as_str()) {
+                        self.record(ctx, "load_all", key, value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl PacketDecoder for SettingsDecoder {
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        if frame.component != util::COMPONENT {
+            return;
+        }
+
+        let Some(fields) = ctx.fields else {
+            return;
+        };
+
+        match (ctx.direction, frame.ty) {
+            (Direction::Send, FrameType::Request) => self.handle_request(ctx, fields),
+            (Direction::Receive, FrameType::Response | FrameType::Error) => {
+                self.handle_response(ctx, fields)
+            }
+            _ => {}
+        }
+    }
+}
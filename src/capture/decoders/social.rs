@@ -0,0 +1,225 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        append_jsonl,
+        value::{find, Field, TdfValue},
+    },
+    errors::{self, CaptureIoError},
+    servers::{
+        components::{association_lists, messaging},
+        packet::FrameType,
+    },
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// The file name association list records are written to
+pub const ASSOCIATIONS_FILE: &str = "associations.jsonl";
+/// The file name message records are written to
+pub const MESSAGES_FILE: &str = "messages.jsonl";
+
+/// Decoder for the AssociationLists (friends / recent players) and Messaging
+/// components, writing typed records for each list and message seen
+pub struct SocialDecoder {
+    /// The path association list records are written to
+    associations_path: PathBuf,
+    /// The path message records are written to
+    messages_path: PathBuf,
+}
+
+/// Association list (e.g. friends list) returned by the server
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct AssociationListRecord {
+    /// Unix timestamp in microseconds the list was captured
+    time: u64,
+    /// The command that returned the list
+    command: u16,
+    /// Name of the list
+    name: Option<String>,
+    /// The list type
+    list_type: Option<u64>,
+    /// The maximum number of members in the list
+    max_size: Option<u64>,
+    /// The total number of members the list contains
+    total: Option<u64>,
+    /// The members included in the response
+    members: Vec<ListMemberRecord>,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct ListMemberRecord {
+    /// The user ID of the member
+    id: Option<u64>,
+    /// The member name
+    name: Option<String>,
+    /// Unix timestamp the member was added to the list
+    added: Option<u64>,
+}
+
+/// Message sent through the Messaging component
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct MessageRecord {
+    /// Unix timestamp in microseconds the message was captured
+    time: u64,
+    /// The message ID
+    id: Option<u64>,
+    /// The message flags
+    flags: Option<u64>,
+    /// The message type
+    message_type: Option<u64>,
+    /// Object ID of the message source
+    source: Value,
+    /// Object ID of the message target
+    target: Value,
+    /// The message attributes (payload)
+    attributes: Value,
+    /// The timestamp the server assigned to the message
+    sent: Option<u64>,
+}
+
+impl SocialDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            associations_path: dir.join(ASSOCIATIONS_FILE),
+            messages_path: dir.join(MESSAGES_FILE),
+        }
+    }
+
+    fn handle_lists(&self, ctx: &PacketContext, fields: &[Field]) {
+        let lists = find(fields, "LMAP")
+            .and_then(TdfValue::as_list)
+            .map(|values| values.iter().filter_map(TdfValue::as_group).collect())
+            // Single list responses contain the list at the top level
+            .unwrap_or_else(|| vec![fields]);
+
+        for list in lists {
+            let record = AssociationListRecord::from_fields(ctx, list);
+            if let Err(err) = append_jsonl(&self.associations_path, &record) {
+                errors::report(CaptureIoError::Write {
+                    what: "association list record",
+                    source: err,
+                });
+            }
+        }
+    }
+
+    fn handle_messages(&self, ctx: &PacketContext, fields: &[Field]) {
+        let messages = find(fields, "MSGL")
+            .and_then(TdfValue::as_list)
+            .map(|values| values.iter().filter_map(TdfValue::as_group).collect())
+            .unwrap_or_else(|| vec![fields]);
+
+        for message in messages {
+            let record = MessageRecord::from_fields(ctx, message);
+            if let Err(err) = append_jsonl(&self.messages_path, &record) {
+                errors::report(CaptureIoError::Write {
+                    what: "message record",
+                    source: err,
+                });
+            }
+        }
+    }
+}
+
+impl PacketDecoder for SocialDecoder {
+    fn name(&self) -> &'static str {
+        "social"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        let Some(fields) = ctx.fields else {
+            return;
+        };
+
+        match (frame.component, frame.ty, frame.command) {
+            (
+                association_lists::COMPONENT,
+                FrameType::Response,
+                association_lists::GET_LISTS | association_lists::GET_LIST_FOR_USER,
+            ) => self.handle_lists(ctx, fields),
+            (messaging::COMPONENT, FrameType::Notify, messaging::SEND_MESSAGE)
+            | (messaging::COMPONENT, FrameType::Response, messaging::GET_MESSAGES) => {
+                self.handle_messages(ctx, fields)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl AssociationListRecord {
+    fn from_fields(ctx: &PacketContext, list: &[Field]) -> Self {
+        let info = find(list, "INFO")
+            .and_then(TdfValue::as_group)
+            .unwrap_or(list);
+        let id = find(info, "LID").and_then(TdfValue::as_group);
+
+        let members = find(list, "MEML")
+            .and_then(TdfValue::as_list)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(TdfValue::as_group)
+            .map(ListMemberRecord::from_fields)
+            .collect();
+
+        Self {
+            time: ctx.time,
+            command: ctx.packet.frame.command,
+            name: id
+                .and_then(|id| find(id, "LNM"))
+                .and_then(TdfValue::as_str)
+                .map(str::to_string),
+            list_type: id
+                .and_then(|id| find(id, "TYPE"))
+                .and_then(TdfValue::as_u64),
+            max_size: find(info, "LMS").and_then(TdfValue::as_u64),
+            total: find(list, "TOCT").and_then(TdfValue::as_u64),
+            members,
+        }
+    }
+}
+
+impl ListMemberRecord {
+    fn from_fields(member: &[Field]) -> Self {
+        let user = find(member, "USER").and_then(TdfValue::as_group);
+        Self {
+            id: user
+                .and_then(|user| find(user, "ID"))
+                .and_then(TdfValue::as_u64),
+            name: user
+                .and_then(|user| find(user, "NAME"))
+                .and_then(TdfValue::as_str)
+                .map(str::to_string),
+            added: find(member, "TIME").and_then(TdfValue::as_u64),
+        }
+    }
+}
+
+impl MessageRecord {
+    fn from_fields(ctx: &PacketContext, message: &[Field]) -> Self {
+        let payload = find(message, "PYLD")
+            .and_then(TdfValue::as_group)
+            .unwrap_or(message);
+
+        let json = |fields: &[Field], tag: &str| {
+            find(fields, tag)
+                .map(TdfValue::to_json)
+                .unwrap_or(Value::Null)
+        };
+
+        Self {
+            time: ctx.time,
+            id: find(message, "MGID").and_then(TdfValue::as_u64),
+            flags: find(payload, "FLAG").and_then(TdfValue::as_u64),
+            message_type: find(payload, "TYPE").and_then(TdfValue::as_u64),
+            source: json(message, "SRCE"),
+            target: json(payload, "TARG"),
+            attributes: json(payload, "ATTR"),
+            sent: find(message, "TIME").and_then(TdfValue::as_u64),
+        }
+    }
+}
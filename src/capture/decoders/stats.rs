@@ -0,0 +1,204 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::value::{Field, TdfValue},
+    servers::{
+        components::{component_key, get_command_name, stats},
+        packet::FrameType,
+    },
+};
+use log::error;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the directory within the capture that the stats
+/// CSV files are written to
+const STATS_DIR: &str = "stats";
+
+/// Decoder for Stats component responses which flattens the stat values
+/// into a CSV file for each category (command)
+pub struct StatsDecoder {
+    /// Directory the CSV files are written to
+    dir: PathBuf,
+    /// Tables for each of the categories
+    tables: HashMap<String, CsvTable>,
+}
+
+/// Table of flattened stat rows
+#[derive(Default)]
+struct CsvTable {
+    /// Column names in the order they were first seen
+    columns: Vec<String>,
+    /// Rows of column name to value
+    rows: Vec<HashMap<String, String>>,
+}
+
+impl StatsDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.join(STATS_DIR),
+            tables: HashMap::new(),
+        }
+    }
+}
+
+impl PacketDecoder for StatsDecoder {
+    fn name(&self) -> &'static str {
+        "stats"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let frame = &ctx.packet.frame;
+        if frame.component != stats::COMPONENT
+            || !matches!(frame.ty, FrameType::Response | FrameType::Notify)
+        {
+            return;
+        }
+
+        let Some(fields) = ctx.fields else {
+            return;
+        };
+
+        let key = component_key(frame.component, frame.command);
+        let category = match get_command_name(key, matches!(frame.ty, FrameType::Notify)) {
+            Some(value) => value.to_string(),
+            None => format!("Unknown-{:#06x}", frame.command),
+        };
+
+        let table = self.tables.entry(category.clone()).or_default();
+
+        for entry in stat_entries(fields) {
+            let mut row = vec![
+                ("time".to_string(), ctx.time.to_string()),
+                ("connection".to_string(), ctx.connection.to_string()),
+            ];
+            for field in entry {
+                flatten(&field.tag, &field.value, &mut row);
+            }
+            table.push(row);
+        }
+
+        if let Err(err) = table.write(&self.dir, &category) {
+            error!("Failed to write stats CSV for {}: {}", category, err);
+        }
+    }
+}
+
+/// Finds the stat entries within the packet fields. When the packet contains
+/// a list of groups (e.g. leaderboard entries) each group is an entry,
+/// otherwise the packet as a whole is a single entry
+fn stat_entries(fields: &[Field]) -> Vec<&[Field]> {
+    let entries = fields.iter().find_map(|field| {
+        let values = field.value.as_list()?;
+        let groups: Vec<&[Field]> = values.iter().filter_map(TdfValue::as_group).collect();
+        if groups.is_empty() || groups.len() != values.len() {
+            None
+        } else {
+            Some(groups)
+        }
+    });
+
+    entries.unwrap_or_else(|| vec![fields])
+}
+
+/// Flattens a value into column name / value pairs, nested
+/// names are joined with a "."
+fn flatten(name: &str, value: &TdfValue, out: &mut Vec<(String, String)>) {
+    match value {
+        TdfValue::Group(fields) => {
+            for field in fields {
+                flatten(&format!("{}.{}", name, field.tag), &field.value, out);
+            }
+        }
+        TdfValue::Map(pairs) => {
+            for (key, value) in pairs {
+                let key = match key {
+                    TdfValue::String(value) => value.clone(),
+                    key => key.to_json().to_string(),
+                };
+                flatten(&format!("{}.{}", name, key), value, out);
+            }
+        }
+        TdfValue::List(values) => {
+            if values.iter().all(is_scalar) {
+                let joined = values
+                    .iter()
+                    .map(scalar_string)
+                    .collect::<Vec<_>>()
+                    .join(";");
+                out.push((name.to_string(), joined));
+            } else {
+                for (index, value) in values.iter().enumerate() {
+                    flatten(&format!("{}.{}", name, index), value, out);
+                }
+            }
+        }
+        TdfValue::Union {
+            value: Some(field), ..
+        } => flatten(&format!("{}.{}", name, field.tag), &field.value, out),
+        value => out.push((name.to_string(), scalar_string(value))),
+    }
+}
+
+fn is_scalar(value: &TdfValue) -> bool {
+    !matches!(
+        value,
+        TdfValue::Group(_) | TdfValue::Map(_) | TdfValue::List(_) | TdfValue::Union { .. }
+    )
+}
+
+fn scalar_string(value: &TdfValue) -> String {
+    match value {
+        TdfValue::String(value) => value.clone(),
+        value => value.to_json().to_string(),
+    }
+}
+
+impl CsvTable {
+    fn push(&mut self, row: Vec<(String, String)>) {
+        for (column, _) in &row {
+            if !self.columns.contains(column) {
+                self.columns.push(column.clone());
+            }
+        }
+        self.rows.push(row.into_iter().collect());
+    }
+
+    /// Writes the whole table to the CSV file for the category, the file
+    /// is rewritten as new columns may have been added
+    fn write(&self, dir: &Path, category: &str) -> io::Result<()> {
+        let mut output = String::new();
+        write_csv_row(&mut output, self.columns.iter().map(String::as_str));
+        for row in &self.rows {
+            let values = self
+                .columns
+                .iter()
+                .map(|column| row.get(column).map(String::as_str).unwrap_or_default());
+            write_csv_row(&mut output, values);
+        }
+
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(format!("{}.csv", category)), output)
+    }
+}
+
+fn write_csv_row<'a, I>(output: &mut String, values: I)
+where
+    I: Iterator<Item = &'a str>,
+{
+    for (index, value) in values.enumerate() {
+        if index > 0 {
+            output.push(',');
+        }
+
+        if value.contains([',', '"', '\n', '\r']) {
+            _ = write!(output, "\"{}\"", value.replace('"', "\"\""));
+        } else {
+            output.push_str(value);
+        }
+    }
+    output.push('\n');
+}
@@ -0,0 +1,155 @@
+use super::{PacketContext, PacketDecoder};
+use crate::{
+    capture::{
+        append_jsonl,
+        value::{to_hex, ValueError, ValueReader},
+        Direction,
+    },
+    errors::{self, CaptureIoError, DecodeError},
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::FrameType,
+    },
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The file name unknown TDF records are written to
+pub const UNKNOWN_TDF_FILE: &str = "unknown_tdf.jsonl";
+
+/// Number of bytes kept before the failing offset
+const BYTES_BEFORE: usize = 32;
+
+/// Number of bytes kept from the failing offset onwards
+const BYTES_AFTER: usize = 64;
+
+/// Number of samples recorded for each command and failure, repeats of
+/// the same failure are counted but not recorded
+const MAX_SAMPLES: u32 = 5;
+
+/// Decoder recording packets whose contents couldn't be decoded as TDF
+/// values, capturing the bytes around the failure so unsupported types
+/// and tags found in the wild can be added to the decoders
+pub struct UnknownTdfDecoder {
+    /// The path unknown TDF records are written to
+    path: PathBuf,
+    /// Number of times each component, command and failure has been seen
+    seen: HashMap<(u16, u16, Option<u8>), u32>,
+}
+
+/// Details of contents that failed to decode
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct UnknownTdfRecord {
+    /// Unix timestamp in microseconds the packet was captured
+    time: u64,
+    /// ID of the proxied connection the packet was seen on
+    connection: u32,
+    /// The direction the packet was travelling
+    direction: Direction,
+    component: u16,
+    command: u16,
+    component_name: Option<&'static str>,
+    command_name: Option<&'static str>,
+    ty: FrameType,
+    seq: u16,
+    /// The error that stopped decoding
+    error: String,
+    /// The unknown wire type, None if the contents ended early
+    wire_type: Option<u8>,
+    /// Offset within the contents where decoding failed
+    offset: usize,
+    /// Tags of the fields being decoded from the outermost inwards
+    path: Vec<String>,
+    /// Length of the packet contents in bytes
+    length: usize,
+    /// Offset within the contents of the first byte in `bytes`
+    bytes_offset: usize,
+    /// Contents surrounding the failing offset as hex
+    bytes: String,
+    /// Number of times this failure has been seen for the command
+    occurrence: u32,
+}
+
+impl UnknownTdfDecoder {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(UNKNOWN_TDF_FILE),
+            seen: HashMap::new(),
+        }
+    }
+}
+
+impl PacketDecoder for UnknownTdfDecoder {
+    fn name(&self) -> &'static str {
+        "unknown_tdf"
+    }
+
+    fn handle(&mut self, ctx: &PacketContext) {
+        let Some(err) = ctx.decode_error else {
+            return;
+        };
+
+        let frame = &ctx.packet.frame;
+        let wire_type = match err {
+            ValueError::UnknownType { ty, .. } => Some(*ty),
+            ValueError::UnexpectedEof(_) | ValueError::TooDeep(_) => None,
+        };
+
+        let occurrence = self
+            .seen
+            .entry((frame.component, frame.command, wire_type))
+            .or_default();
+        *occurrence += 1;
+        if *occurrence > MAX_SAMPLES {
+            return;
+        }
+
+        let contents = &ctx.packet.contents;
+        let offset = err.offset().min(contents.len());
+        let start = offset.saturating_sub(BYTES_BEFORE);
+        let end = (offset + BYTES_AFTER).min(contents.len());
+
+        // Decode again to find the field that failed
+        let mut reader = ValueReader::new(contents);
+        while !reader.is_empty() && reader.read_field().is_ok() {}
+
+        let key = component_key(frame.component, frame.command);
+        let record = UnknownTdfRecord {
+            time: ctx.time,
+            connection: ctx.connection,
+            direction: ctx.direction,
+            component: frame.component,
+            command: frame.command,
+            component_name: get_component_name(frame.component),
+            command_name: get_command_name(key, matches!(frame.ty, FrameType::Notify)),
+            ty: frame.ty,
+            seq: frame.seq,
+            error: err.to_string(),
+            wire_type,
+            offset,
+            path: reader.path,
+            length: contents.len(),
+            bytes_offset: start,
+            bytes: to_hex(&contents[start..end]),
+            occurrence: *occurrence,
+        };
+
+        errors::report(DecodeError::Contents {
+            component: frame.component,
+            command: frame.command,
+            path: record.path.clone(),
+            message: err.to_string(),
+        });
+
+        if let Err(err) = append_jsonl(&self.path, &record) {
+            errors::report(CaptureIoError::Write {
+                what: "unknown TDF record",
+                source: err,
+            });
+        }
+    }
+}
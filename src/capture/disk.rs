@@ -0,0 +1,115 @@
+use super::{capture, profile::CaptureProfile, Capture};
+use crate::{config::config, locale, tasks, ui::show_warning};
+use log::{debug, warn};
+#[cfg(feature = "plugin")]
+use std::{iter::once, os::windows::ffi::OsStrExt, ptr::null_mut};
+use std::{path::Path, time::Duration};
+use tokio::time::interval;
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::{Foundation::FALSE, Storage::FileSystem::GetDiskFreeSpaceExW};
+
+/// Interval between each free space check
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State of the capture based on the available disk space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiskState {
+    /// Enough space is available
+    Ok,
+    /// Space is below the threshold, capture is downgraded to minimal
+    Low,
+    /// Space is critically low, capture is paused
+    Critical,
+}
+
+/// Starts the background task monitoring the free space on the volume
+/// containing the capture directory
+pub fn start_monitor() {
+    if let Some(capture) = capture() {
+        tasks::spawn("disk-monitor", monitor(capture));
+    }
+}
+
+async fn monitor(capture: &'static Capture) {
+    let threshold = config().min_free_space_mb * 1024 * 1024;
+    let mut state = DiskState::Ok;
+    // The profile that was active before capture was downgraded
+    let mut previous_profile = CaptureProfile::current();
+    let mut interval = interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(free) = free_space(&capture.dir) else {
+            continue;
+        };
+
+        let next = if free < threshold / 4 {
+            DiskState::Critical
+        } else if free < threshold {
+            DiskState::Low
+        } else {
+            DiskState::Ok
+        };
+
+        if next == state {
+            continue;
+        }
+
+        debug!("Capture disk state {:?} ({} bytes free)", next, free);
+
+        if state == DiskState::Ok {
+            previous_profile = CaptureProfile::current();
+        }
+
+        match next {
+            DiskState::Ok => {
+                capture.set_paused(false);
+                capture.set_profile(previous_profile);
+                capture.record_event("disk", "Disk space recovered, capture restored");
+            }
+            DiskState::Low => {
+                capture.set_paused(false);
+                capture.set_profile(CaptureProfile::Minimal);
+                capture.record_event("disk", "Disk space low, capture downgraded to minimal");
+                warn!("Disk space low, capture downgraded to minimal");
+                show_warning(
+                    &locale::text("disk.low.title"),
+                    &locale::text("disk.low.message"),
+                );
+            }
+            DiskState::Critical => {
+                capture.set_paused(true);
+                capture.record_event("disk", "Disk space critically low, capture paused");
+                warn!("Disk space critically low, capture paused");
+                show_warning(
+                    &locale::text("disk.critical.title"),
+                    &locale::text("disk.critical.message"),
+                );
+            }
+        }
+
+        state = next;
+    }
+}
+
+/// Obtains the number of free bytes available to the user on the
+/// volume containing the provided `path`
+#[cfg(feature = "plugin")]
+fn free_space(path: &Path) -> Option<u64> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(once(0)).collect();
+    let mut free: u64 = 0;
+
+    let result = unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut free, null_mut(), null_mut()) };
+    if result == FALSE {
+        return None;
+    }
+
+    Some(free)
+}
+
+/// Free space isn't checked in builds without the plugin
+#[cfg(not(feature = "plugin"))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
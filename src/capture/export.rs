@@ -0,0 +1,395 @@
+//! Exports capture sessions as Parquet tables for bulk analysis. Only
+//! available when built with the "parquet" feature
+
+use super::{
+    http_log::read_http_log,
+    packet_log::{pair_packets, read_packet_log, PacketRecord},
+    value::{decode_fields, fields_to_json, find, Field, TdfValue},
+    Direction,
+};
+use crate::{
+    build_info::build_info,
+    servers::{
+        components::{component_key, get_command_name, get_component_name, util},
+        packet::FrameType,
+    },
+};
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, Float64Array, StringArray, UInt16Array, UInt32Array, UInt64Array,
+        UInt8Array,
+    },
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use parquet::{
+    arrow::ArrowWriter, errors::ParquetError, file::properties::WriterProperties, format::KeyValue,
+};
+use std::{fs::File, io, path::Path, sync::Arc};
+use thiserror::Error;
+
+/// Key of the Parquet file metadata holding the plugin build as JSON
+pub const BUILD_METADATA_KEY: &str = "prdc.build";
+
+/// Errors that can occur while exporting
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Arrow(#[from] ArrowError),
+    #[error(transparent)]
+    Parquet(#[from] ParquetError),
+}
+
+pub type ExportResult<T> = Result<T, ExportError>;
+
+/// Exports the capture session in `dir` as Parquet tables written to
+/// the `output` directory:
+/// - packets.parquet: every captured packet
+/// - pairs.parquet: requests matched with their responses
+/// - http.parquet: proxied HTTP exchanges
+/// - telemetry.parquet: telemetry and ticker servers handed to the game
+pub fn export_parquet(dir: &Path, output: &Path) -> ExportResult<()> {
+    std::fs::create_dir_all(output)?;
+
+    let packets = read_packet_log(dir)?;
+    write_table(&output.join("packets.parquet"), packets_batch(&packets)?)?;
+    write_table(&output.join("pairs.parquet"), pairs_batch(&packets)?)?;
+    write_table(
+        &output.join("telemetry.parquet"),
+        telemetry_batch(&packets)?,
+    )?;
+
+    // Sessions without any HTTP exchanges won't have a HTTP log
+    let http = match read_http_log(dir) {
+        Ok(value) => value,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let batch = RecordBatch::try_from_iter(vec![
+        ("time", u64s(http.iter().map(|record| Some(record.time)))),
+        (
+            "method",
+            strings(http.iter().map(|record| Some(&*record.method))),
+        ),
+        ("url", strings(http.iter().map(|record| Some(&*record.url)))),
+        ("status", u16s(http.iter().map(|record| record.status))),
+        (
+            "duration_ms",
+            Arc::new(Float64Array::from_iter_values(
+                http.iter().map(|record| record.duration_ms),
+            )) as ArrayRef,
+        ),
+        (
+            "length",
+            u64s(http.iter().map(|record| Some(record.length as u64))),
+        ),
+        (
+            "body",
+            strings(http.iter().map(|record| record.body.as_deref())),
+        ),
+    ])?;
+    write_table(&output.join("http.parquet"), batch)?;
+
+    Ok(())
+}
+
+fn packets_batch(packets: &[PacketRecord]) -> ExportResult<RecordBatch> {
+    let mut names = Vec::with_capacity(packets.len());
+    let mut contents = Vec::with_capacity(packets.len());
+    let mut bodies = Vec::with_capacity(packets.len());
+
+    for record in packets {
+        names.push(names_of(record));
+
+        let packet = record.to_packet();
+        let recorded = record.contents.is_some();
+        bodies.push(
+            recorded
+                .then(|| decode_fields(&packet.contents).ok())
+                .flatten()
+                .map(|fields| fields_to_json(&fields).to_string()),
+        );
+        contents.push(recorded.then_some(packet.contents));
+    }
+
+    let batch = RecordBatch::try_from_iter(vec![
+        (
+            "index",
+            u64s(packets.iter().map(|record| Some(record.index))),
+        ),
+        ("time", u64s(packets.iter().map(|record| Some(record.time)))),
+        (
+            "connection",
+            u32s(packets.iter().map(|record| record.connection)),
+        ),
+        (
+            "direction",
+            strings(packets.iter().map(|record| Some(direction_name(record)))),
+        ),
+        (
+            "component",
+            u16s(packets.iter().map(|record| Some(record.component))),
+        ),
+        (
+            "command",
+            u16s(packets.iter().map(|record| Some(record.command))),
+        ),
+        (
+            "component_name",
+            strings(names.iter().map(|(component, _)| *component)),
+        ),
+        (
+            "command_name",
+            strings(names.iter().map(|(_, command)| *command)),
+        ),
+        (
+            "error",
+            u16s(packets.iter().map(|record| Some(record.error))),
+        ),
+        ("ty", u8s(packets.iter().map(|record| record.ty))),
+        ("options", u8s(packets.iter().map(|record| record.options))),
+        ("seq", u16s(packets.iter().map(|record| Some(record.seq)))),
+        (
+            "context",
+            u64s(packets.iter().map(|record| record.context.map(u64::from))),
+        ),
+        (
+            "length",
+            u64s(packets.iter().map(|record| Some(record.length as u64))),
+        ),
+        (
+            "contents",
+            Arc::new(BinaryArray::from_iter(
+                contents.iter().map(|value| value.as_deref()),
+            )) as ArrayRef,
+        ),
+        ("body", strings(bodies.iter().map(|value| value.as_deref()))),
+    ])?;
+
+    Ok(batch)
+}
+
+fn pairs_batch(packets: &[PacketRecord]) -> ExportResult<RecordBatch> {
+    let pairs = pair_packets(packets);
+    let names: Vec<_> = pairs.iter().map(|pair| names_of(pair.request)).collect();
+
+    let batch = RecordBatch::try_from_iter(vec![
+        (
+            "connection",
+            u32s(pairs.iter().map(|pair| pair.request.connection)),
+        ),
+        ("seq", u16s(pairs.iter().map(|pair| Some(pair.request.seq)))),
+        (
+            "component",
+            u16s(pairs.iter().map(|pair| Some(pair.request.component))),
+        ),
+        (
+            "command",
+            u16s(pairs.iter().map(|pair| Some(pair.request.command))),
+        ),
+        (
+            "component_name",
+            strings(names.iter().map(|(component, _)| *component)),
+        ),
+        (
+            "command_name",
+            strings(names.iter().map(|(_, command)| *command)),
+        ),
+        (
+            "request_index",
+            u64s(pairs.iter().map(|pair| Some(pair.request.index))),
+        ),
+        (
+            "request_time",
+            u64s(pairs.iter().map(|pair| Some(pair.request.time))),
+        ),
+        (
+            "response_index",
+            u64s(
+                pairs
+                    .iter()
+                    .map(|pair| pair.response.map(|response| response.index)),
+            ),
+        ),
+        (
+            "response_time",
+            u64s(
+                pairs
+                    .iter()
+                    .map(|pair| pair.response.map(|response| response.time)),
+            ),
+        ),
+        (
+            "latency_us",
+            u64s(pairs.iter().map(|pair| {
+                pair.response
+                    .map(|response| response.time.saturating_sub(pair.request.time))
+            })),
+        ),
+        (
+            "error",
+            u16s(pairs.iter().map(|pair| {
+                pair.response
+                    .filter(|response| FrameType::from(response.ty) == FrameType::Error)
+                    .map(|response| response.error)
+            })),
+        ),
+    ])?;
+
+    Ok(batch)
+}
+
+/// Table of the GetTelemetryServer and GetTickerServer exchanges with the
+/// server details from their responses. The telemetry connections
+/// themselves aren't proxied so this is all the session holds of them
+fn telemetry_batch(packets: &[PacketRecord]) -> ExportResult<RecordBatch> {
+    let pairs: Vec<_> = pair_packets(packets)
+        .into_iter()
+        .filter(|pair| {
+            pair.request.component == util::COMPONENT
+                && matches!(
+                    pair.request.command,
+                    util::GET_TELEMETRY_SERVER | util::GET_TICKER_SERVER
+                )
+        })
+        .collect();
+
+    // Server details are only known when the response contents were recorded
+    let fields: Vec<_> = pairs
+        .iter()
+        .map(|pair| {
+            pair.response
+                .filter(|response| FrameType::from(response.ty) == FrameType::Response)
+                .filter(|response| response.contents.is_some())
+                .and_then(|response| decode_fields(&response.to_packet().contents).ok())
+        })
+        .collect();
+    let bodies: Vec<_> = fields
+        .iter()
+        .map(|fields| {
+            fields
+                .as_deref()
+                .map(|fields| fields_to_json(fields).to_string())
+        })
+        .collect();
+
+    let batch = RecordBatch::try_from_iter(vec![
+        (
+            "connection",
+            u32s(pairs.iter().map(|pair| pair.request.connection)),
+        ),
+        (
+            "server",
+            strings(pairs.iter().map(|pair| {
+                Some(match pair.request.command {
+                    util::GET_TELEMETRY_SERVER => "telemetry",
+                    _ => "ticker",
+                })
+            })),
+        ),
+        (
+            "request_time",
+            u64s(pairs.iter().map(|pair| Some(pair.request.time))),
+        ),
+        (
+            "response_time",
+            u64s(
+                pairs
+                    .iter()
+                    .map(|pair| pair.response.map(|response| response.time)),
+            ),
+        ),
+        (
+            "error",
+            u16s(pairs.iter().map(|pair| {
+                pair.response
+                    .filter(|response| FrameType::from(response.ty) == FrameType::Error)
+                    .map(|response| response.error)
+            })),
+        ),
+        (
+            "address",
+            strings(
+                fields
+                    .iter()
+                    .map(|fields| response_field(fields, "ADRS").and_then(TdfValue::as_str)),
+            ),
+        ),
+        (
+            "port",
+            u16s(fields.iter().map(|fields| {
+                response_field(fields, "PORT")
+                    .and_then(TdfValue::as_u64)
+                    .and_then(|value| u16::try_from(value).ok())
+            })),
+        ),
+        ("body", strings(bodies.iter().map(|value| value.as_deref()))),
+    ])?;
+
+    Ok(batch)
+}
+
+/// Finds a field in a decoded response, None if it wasn't decoded
+fn response_field<'a>(fields: &'a Option<Vec<Field>>, tag: &str) -> Option<&'a TdfValue> {
+    fields.as_deref().and_then(|fields| find(fields, tag))
+}
+
+fn write_table(path: &Path, batch: RecordBatch) -> ExportResult<()> {
+    let file = File::create(path)?;
+
+    // The plugin build is stored in the file metadata
+    let build = serde_json::to_string(&build_info()).map_err(io::Error::from)?;
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            BUILD_METADATA_KEY.to_string(),
+            build,
+        )]))
+        .build();
+
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+fn u64s<I: Iterator<Item = Option<u64>>>(values: I) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter(values))
+}
+
+fn u32s<I: Iterator<Item = u32>>(values: I) -> ArrayRef {
+    Arc::new(UInt32Array::from_iter_values(values))
+}
+
+fn u16s<I: Iterator<Item = Option<u16>>>(values: I) -> ArrayRef {
+    Arc::new(UInt16Array::from_iter(values))
+}
+
+fn u8s<I: Iterator<Item = u8>>(values: I) -> ArrayRef {
+    Arc::new(UInt8Array::from_iter_values(values))
+}
+
+fn strings<'a, I>(values: I) -> ArrayRef
+where
+    I: Iterator<Item = Option<&'a str>>,
+{
+    Arc::new(StringArray::from_iter(values))
+}
+
+fn names_of(record: &PacketRecord) -> (Option<&'static str>, Option<&'static str>) {
+    let notify = FrameType::from(record.ty) == FrameType::Notify;
+    let key = component_key(record.component, record.command);
+    (
+        get_component_name(record.component),
+        get_command_name(key, notify),
+    )
+}
+
+fn direction_name(record: &PacketRecord) -> &'static str {
+    match record.direction {
+        Direction::Send => "send",
+        Direction::Receive => "receive",
+    }
+}
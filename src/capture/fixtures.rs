@@ -0,0 +1,121 @@
+//! Exports recorded request/response pairs as JSON fixtures for the
+//! Pocket Relay server test suite, one file per command containing
+//! every observed exchange for that command
+
+use super::{
+    packet_log::{pair_packets, read_packet_log, PacketRecord},
+    value::{decode_fields, fields_to_json},
+};
+use crate::servers::{
+    components::{component_key, get_command_name, get_component_name},
+    packet::FrameType,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, io, path::Path};
+
+/// Fixture file for a single command
+#[derive(Serialize)]
+pub struct CommandFixtures {
+    pub component: u16,
+    pub command: u16,
+    pub component_name: Option<&'static str>,
+    pub command_name: Option<&'static str>,
+    /// Every observed exchange for the command in capture order
+    pub cases: Vec<FixtureCase>,
+}
+
+/// Single request with the response the official server gave
+#[derive(Serialize)]
+pub struct FixtureCase {
+    /// Index of the request within the capture session
+    pub index: u64,
+    pub request: FixturePacket,
+    pub response: FixturePacket,
+}
+
+/// Packet within a fixture
+#[derive(Serialize)]
+pub struct FixturePacket {
+    /// Frame type name
+    pub ty: FrameType,
+    /// Error code for error responses, otherwise zero
+    pub error: u16,
+    /// Raw encoded contents as hex
+    pub contents: String,
+    /// Decoded contents, None if the contents couldn't be decoded
+    pub body: Option<Value>,
+}
+
+impl FixturePacket {
+    fn new(record: &PacketRecord, contents: &str) -> Self {
+        let packet = record.to_packet();
+        Self {
+            ty: packet.frame.ty,
+            error: packet.frame.error,
+            contents: contents.to_string(),
+            body: decode_fields(&packet.contents)
+                .ok()
+                .map(|fields| fields_to_json(&fields)),
+        }
+    }
+}
+
+/// Exports the request/response pairs from the capture session in `dir`
+/// as fixture files in the `output` directory. Pairs without a response
+/// or without recorded contents are skipped. Returns the number of cases
+/// that were exported
+pub fn export_fixtures(dir: &Path, output: &Path) -> io::Result<usize> {
+    let packets = read_packet_log(dir)?;
+    let mut commands: BTreeMap<(u16, u16), Vec<FixtureCase>> = BTreeMap::new();
+
+    for pair in pair_packets(&packets) {
+        let request = pair.request;
+        let Some(response) = pair.response else {
+            continue;
+        };
+        let (Some(request_contents), Some(response_contents)) =
+            (&request.contents, &response.contents)
+        else {
+            continue;
+        };
+
+        commands
+            .entry((request.component, request.command))
+            .or_default()
+            .push(FixtureCase {
+                index: request.index,
+                request: FixturePacket::new(request, request_contents),
+                response: FixturePacket::new(response, response_contents),
+            });
+    }
+
+    std::fs::create_dir_all(output)?;
+
+    let mut count = 0;
+    for ((component, command), cases) in commands {
+        let component_name = get_component_name(component);
+        let command_name = get_command_name(component_key(component, command), false);
+        let file_name = format!(
+            "{}_{}.json",
+            component_name.map_or_else(|| format!("{:#06x}", component), str::to_string),
+            command_name.map_or_else(|| format!("{:#06x}", command), str::to_string),
+        );
+
+        count += cases.len();
+
+        let fixtures = CommandFixtures {
+            component,
+            command,
+            component_name,
+            command_name,
+            cases,
+        };
+        std::fs::write(
+            output.join(file_name),
+            serde_json::to_vec_pretty(&fixtures)?,
+        )?;
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,213 @@
+//! Versioning of the structured capture formats. The JSON lines files
+//! record the format version in their header line and the manifest in its
+//! `format_version` field. Records from older versions are migrated to the
+//! current version when read so readers only handle the current format
+//!
+//! Format versions:
+//! 1. Files written before versioning, JSON lines files have no header
+//!    and packet records can be missing the frame context and reserved bits
+//! 2. JSON lines files start with a header line holding the plugin build
+
+use super::{packet_log::PACKETS_FILE, JsonlHeader, JSONL_HEADER_START, MANIFEST_FILE};
+use crate::build_info::build_info;
+use serde_json::Value;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Version of the formats written by this build of the plugin
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Version of files written before the formats were versioned
+pub const UNVERSIONED: u32 = 1;
+
+/// Errors that can occur reading or migrating versioned files
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("Format version {0} is newer than the supported version {FORMAT_VERSION}")]
+    Unsupported(u32),
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<FormatError> for io::Error {
+    fn from(value: FormatError) -> Self {
+        match value {
+            FormatError::IO(err) => err,
+            FormatError::Json(err) => err.into(),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Migration upgrading a record of the JSON lines file with the provided
+/// name to the next version
+type Migration = fn(file: &str, record: &mut Value);
+
+/// Migrations indexed by the version they upgrade from, the first
+/// upgrades version 1 records
+const MIGRATIONS: &[Migration] = &[migrate_v1];
+
+/// Version 1 packet records were written before the frame context and
+/// reserved bits were captured
+fn migrate_v1(file: &str, record: &mut Value) {
+    if file != PACKETS_FILE {
+        return;
+    }
+    if let Value::Object(record) = record {
+        record.entry("context").or_insert(Value::Null);
+        record.entry("reserved").or_insert(Value::from(0));
+    }
+}
+
+/// Checks that files of the provided version can be read
+pub fn check_version(version: u32) -> Result<(), FormatError> {
+    if version > FORMAT_VERSION {
+        return Err(FormatError::Unsupported(version));
+    }
+    Ok(())
+}
+
+/// Migrates a record of the JSON lines file with the provided name from
+/// `version` to the current version
+pub fn migrate(file: &str, version: u32, record: &mut Value) -> Result<(), FormatError> {
+    check_version(version)?;
+    let start = version.saturating_sub(UNVERSIONED) as usize;
+    for migration in MIGRATIONS.iter().skip(start) {
+        migration(file, record);
+    }
+    Ok(())
+}
+
+/// Obtains the format version of the JSON lines file from its header line
+pub fn jsonl_version(header: Option<&JsonlHeader>) -> u32 {
+    header.map_or(UNVERSIONED, |header| header.format_version)
+}
+
+/// Summary of migrating a session
+#[derive(Debug, Default)]
+pub struct MigrationSummary {
+    /// Files that were rewritten in the current format
+    pub migrated: Vec<PathBuf>,
+    /// Files that were already in the current format
+    pub current: Vec<PathBuf>,
+}
+
+/// Rewrites every JSON lines file and the manifest of the session in `dir`
+/// in the current format version
+pub fn migrate_session(dir: &Path) -> Result<MigrationSummary, FormatError> {
+    let mut summary = MigrationSummary::default();
+
+    let mut files = Vec::new();
+    find_jsonl_files(dir, &mut files)?;
+    for path in files {
+        if migrate_jsonl_file(&path)? {
+            summary.migrated.push(path);
+        } else {
+            summary.current.push(path);
+        }
+    }
+
+    let manifest = dir.join(MANIFEST_FILE);
+    if manifest.exists() {
+        if migrate_manifest(&manifest)? {
+            summary.migrated.push(manifest);
+        } else {
+            summary.current.push(manifest);
+        }
+    }
+
+    Ok(summary)
+}
+
+fn find_jsonl_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_jsonl_files(&path, files)?;
+        } else if path.extension().is_some_and(|value| value == "jsonl") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the JSON lines file in the current format version, returns
+/// whether the file needed migrating
+fn migrate_jsonl_file(path: &Path) -> Result<bool, FormatError> {
+    let name = file_name(path);
+    let mut lines = BufReader::new(File::open(path)?).lines();
+
+    let first = lines.next().transpose()?;
+    let header: Option<JsonlHeader> = match &first {
+        Some(line) if line.starts_with(JSONL_HEADER_START) => Some(serde_json::from_str(line)?),
+        _ => None,
+    };
+    let version = jsonl_version(header.as_ref());
+    check_version(version)?;
+    if version == FORMAT_VERSION {
+        return Ok(false);
+    }
+
+    // Written beside the original so a failed migration leaves it intact
+    let output = path.with_extension("jsonl.migrating");
+    let mut writer = BufWriter::new(File::create(&output)?);
+    let mut header = header.unwrap_or(JsonlHeader {
+        header: build_info(),
+        format_version: UNVERSIONED,
+    });
+    header.format_version = FORMAT_VERSION;
+    serde_json::to_writer(&mut writer, &header)?;
+    writer.write_all(b"\n")?;
+
+    let records = first
+        .filter(|line| !line.starts_with(JSONL_HEADER_START))
+        .into_iter()
+        .map(Ok)
+        .chain(lines);
+    for line in records {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut record: Value = serde_json::from_str(&line)?;
+        migrate(&name, version, &mut record)?;
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&output, path)?;
+    Ok(true)
+}
+
+/// Sets the format version of the manifest, returns whether the manifest
+/// needed migrating
+fn migrate_manifest(path: &Path) -> Result<bool, FormatError> {
+    let mut manifest: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+    let version = manifest
+        .get("format_version")
+        .and_then(Value::as_u64)
+        .map_or(UNVERSIONED, |value| value as u32);
+    check_version(version)?;
+    if version == FORMAT_VERSION {
+        return Ok(false);
+    }
+
+    manifest["format_version"] = Value::from(FORMAT_VERSION);
+    std::fs::write(path, serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(true)
+}
+
+/// Name of the file used to select migrations
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,169 @@
+//! Key combinations that place named markers in the capture while the
+//! game is focused, letting players flag moments ("this is where the
+//! kick happened") without leaving the game. The data review window
+//! can also be opened with its own hotkey. Hotkeys are only watched in
+//! builds with the plugin
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "plugin")]
+use super::{capture, collection::MarkerSource};
+#[cfg(feature = "plugin")]
+use crate::{config::config, tasks, ui};
+#[cfg(feature = "plugin")]
+use log::{error, info};
+#[cfg(feature = "plugin")]
+use std::{str::FromStr, thread::sleep, time::Duration};
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::UI::{
+    Input::KeyboardAndMouse::{GetAsyncKeyState, VK_CONTROL, VK_F1, VK_MENU, VK_SHIFT},
+    WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId},
+};
+
+#[cfg(feature = "plugin")]
+/// How often the keyboard state is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Key combination placing a marker with the provided label
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkerHotkey {
+    /// The key combination ("Ctrl+Shift+M", "Alt+F5")
+    pub keys: String,
+    /// Label of the placed marker
+    pub label: String,
+}
+
+impl MarkerHotkey {
+    /// The default hotkeys, Ctrl+Shift+M flags the current moment
+    pub fn defaults() -> Vec<Self> {
+        vec![MarkerHotkey {
+            keys: "Ctrl+Shift+M".to_string(),
+            label: "flagged".to_string(),
+        }]
+    }
+}
+
+#[cfg(feature = "plugin")]
+/// Action performed when a hotkey is pressed
+enum HotkeyAction {
+    /// Place a marker with the label
+    Marker(String),
+    /// Open the data review window
+    Review,
+}
+
+#[cfg(feature = "plugin")]
+/// Parsed key combination, every key must be held
+#[derive(Debug, PartialEq, Eq)]
+struct KeyCombo(Vec<u16>);
+
+#[cfg(feature = "plugin")]
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split('+')
+            .map(|key| {
+                let key = key.trim().to_ascii_uppercase();
+                match key.as_str() {
+                    "CTRL" | "CONTROL" => Ok(VK_CONTROL),
+                    "SHIFT" => Ok(VK_SHIFT),
+                    "ALT" => Ok(VK_MENU),
+                    _ => match key.as_bytes() {
+                        [value] if value.is_ascii_alphanumeric() => Ok(*value as u16),
+                        [b'F', ..] => key[1..]
+                            .parse::<u16>()
+                            .ok()
+                            .filter(|value| (1..=12).contains(value))
+                            .map(|value| VK_F1 + value - 1)
+                            .ok_or_else(|| format!("Unknown key {}", key)),
+                        _ => Err(format!("Unknown key {}", key)),
+                    },
+                }
+            })
+            .collect::<Result<Vec<u16>, String>>()?;
+
+        if keys.is_empty() {
+            return Err("Empty key combination".to_string());
+        }
+        Ok(KeyCombo(keys))
+    }
+}
+
+#[cfg(feature = "plugin")]
+impl KeyCombo {
+    /// Whether every key in the combination is held
+    fn is_held(&self) -> bool {
+        self.0
+            .iter()
+            .all(|key| unsafe { GetAsyncKeyState(*key as i32) } < 0)
+    }
+}
+
+#[cfg(feature = "plugin")]
+/// Starts watching for the configured hotkeys
+pub fn start() {
+    let config = config();
+    let hotkeys: Vec<(KeyCombo, HotkeyAction)> = config
+        .marker_hotkeys
+        .iter()
+        .map(|hotkey| (&hotkey.keys, HotkeyAction::Marker(hotkey.label.clone())))
+        .chain(
+            config
+                .review_hotkey
+                .as_ref()
+                .map(|keys| (keys, HotkeyAction::Review)),
+        )
+        .filter_map(|(keys, action)| match keys.parse::<KeyCombo>() {
+            Ok(combo) => Some((combo, action)),
+            Err(err) => {
+                error!("Invalid hotkey {}: {}", keys, err);
+                None
+            }
+        })
+        .collect();
+
+    if hotkeys.is_empty() {
+        return;
+    }
+
+    tasks::spawn_thread("hotkeys", move || run(hotkeys));
+}
+
+#[cfg(feature = "plugin")]
+fn run(hotkeys: Vec<(KeyCombo, HotkeyAction)>) {
+    // Whether each combination was held when last checked, markers are
+    // only placed when a combination is first pressed
+    let mut held = vec![false; hotkeys.len()];
+
+    loop {
+        sleep(POLL_INTERVAL);
+
+        let focused = is_game_focused();
+        for ((combo, action), held) in hotkeys.iter().zip(held.iter_mut()) {
+            let now = focused && combo.is_held();
+            if now && !*held {
+                match action {
+                    HotkeyAction::Marker(label) => {
+                        info!("Placed capture marker {}", label);
+                        if let Some(capture) = capture() {
+                            capture.record_marker(MarkerSource::Hotkey, label, None);
+                        }
+                    }
+                    HotkeyAction::Review => ui::show_data_review(),
+                }
+            }
+            *held = now;
+        }
+    }
+}
+
+#[cfg(feature = "plugin")]
+/// Whether the foreground window belongs to the game, keys pressed in
+/// other programs are ignored
+fn is_game_focused() -> bool {
+    let mut process = 0;
+    unsafe { GetWindowThreadProcessId(GetForegroundWindow(), &mut process) };
+    process == std::process::id()
+}
@@ -0,0 +1,33 @@
+use super::read_jsonl;
+use serde::{Deserialize, Serialize};
+use std::{io, path::Path};
+
+/// The file name of the HTTP log within the session directory
+pub const HTTP_FILE: &str = "http.jsonl";
+
+/// HTTP exchange proxied to the official servers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HttpRecord {
+    /// Unix timestamp in microseconds the request was received
+    pub time: u64,
+    /// The request method
+    pub method: String,
+    /// The requested URL
+    pub url: String,
+    /// The response status code, None if the request failed
+    pub status: Option<u16>,
+    /// Time in milliseconds taken to receive the response
+    pub duration_ms: f64,
+    /// Length of the response body in bytes
+    pub length: usize,
+    /// The response body, only included when the capture
+    /// profile includes HTTP bodies
+    pub body: Option<String>,
+}
+
+/// Reads all the HTTP records from the HTTP log in the
+/// provided session directory
+pub fn read_http_log(dir: &Path) -> io::Result<Vec<HttpRecord>> {
+    read_jsonl(&dir.join(HTTP_FILE))
+}
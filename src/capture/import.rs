@@ -0,0 +1,413 @@
+//! Imports packet dumps produced by other tools into the capture format
+//! so they can be analyzed alongside the plugin's own captures. Supports
+//! pcap files such as Wireshark exports of unencrypted Blaze traffic.
+//!
+//! Only connections on the Blaze ports are imported and a connection is
+//! dropped as soon as its bytes stop looking like Blaze frames, so other
+//! traffic in the same capture doesn't end up as garbage packets
+
+use super::{
+    manifest::{Manifest, ManifestEvent},
+    packet_log::{PacketRecord, PACKETS_FILE},
+    profile::CaptureProfile,
+    write_jsonl_header, Direction, MANIFEST_FILE,
+};
+use crate::{
+    consent::Consent,
+    constants::{MAIN_PORT, REDIRECTOR_PORT},
+    servers::packet::{FireFrame, FrameType, PacketCodec},
+};
+use bytes::BytesMut;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    path::Path,
+};
+use thiserror::Error;
+use tokio_util::codec::Decoder;
+
+/// Errors that can occur while importing
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Not a pcap file")]
+    InvalidMagic,
+    #[error("Unsupported pcap link type {0}")]
+    UnsupportedLinkType(u32),
+    #[error("Truncated pcap record at offset {0}")]
+    Truncated(usize),
+    #[error("Unsupported pcap version {0}.{1}")]
+    UnsupportedVersion(u16, u16),
+    #[error("Implausible pcap record length at offset {0}")]
+    ImplausibleRecord(usize),
+}
+
+pub type ImportResult<T> = Result<T, ImportError>;
+
+/// pcap link types that can be imported
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+/// TCP protocol number in IP headers
+const IP_PROTOCOL_TCP: u8 = 6;
+
+/// Largest captured frame length accepted in a pcap record, the limit
+/// libpcap itself uses for its snapshot length
+const MAX_RECORD_LENGTH: usize = 262144;
+
+/// Largest Blaze packet length accepted before a stream is considered to
+/// not be carrying Blaze frames
+const MAX_PACKET_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Ports imported when no ports are given, the local redirector and main
+/// server ports
+pub const DEFAULT_PORTS: [u16; 2] = [REDIRECTOR_PORT, MAIN_PORT];
+
+/// Summary of an import
+#[derive(Debug)]
+pub struct ImportSummary {
+    /// Number of TCP connections carrying Blaze packets
+    pub connections: u32,
+    /// Number of Blaze packets imported
+    pub packets: u64,
+    /// Number of connection directions on the ports that were dropped
+    /// because their bytes weren't Blaze frames
+    pub rejected: u32,
+}
+
+/// Imports the Blaze packets from the pcap file at `input` into a new
+/// capture session in the `output` directory. Only TCP connections with
+/// an endpoint on one of the `ports` are imported. Requests are recorded
+/// as sent by the client and everything else as received from the server
+pub fn import_pcap(input: &Path, output: &Path, ports: &[u16]) -> ImportResult<ImportSummary> {
+    let data = std::fs::read(input)?;
+    let segments = read_pcap(&data)?;
+
+    std::fs::create_dir_all(output)?;
+    let mut writer = BufWriter::new(File::create(output.join(PACKETS_FILE))?);
+    write_jsonl_header(&mut writer)?;
+
+    // Connections are keyed by their endpoints in a consistent order so
+    // both directions share the same ID
+    let mut connections: HashMap<(Endpoint, Endpoint), u32> = HashMap::new();
+    let mut streams: HashMap<(Endpoint, Endpoint), Stream> = HashMap::new();
+    let mut index = 0;
+    let mut rejected = 0;
+    let mut started_at = None;
+
+    for segment in segments {
+        if !ports.contains(&segment.source.1) && !ports.contains(&segment.destination.1) {
+            continue;
+        }
+
+        let stream = streams
+            .entry((segment.source, segment.destination))
+            .or_default();
+        if !stream.push(&segment) {
+            continue;
+        }
+
+        let key = if segment.source < segment.destination {
+            (segment.source, segment.destination)
+        } else {
+            (segment.destination, segment.source)
+        };
+        let next_id = connections.len() as u32 + 1;
+
+        loop {
+            if !plausible_frame(&stream.buffer) {
+                stream.rejected = true;
+                stream.pending.clear();
+                stream.buffer.clear();
+                rejected += 1;
+                break;
+            }
+
+            let Some(packet) = PacketCodec.decode(&mut stream.buffer)? else {
+                break;
+            };
+
+            let connection = *connections.entry(key).or_insert(next_id);
+            let direction = match packet.frame.ty {
+                FrameType::Request => Direction::Send,
+                _ => Direction::Receive,
+            };
+
+            let record =
+                PacketRecord::new(index, segment.time, connection, direction, &packet, true);
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+
+            started_at.get_or_insert(segment.time);
+            index += 1;
+        }
+    }
+
+    writer.flush()?;
+
+    let started_at = started_at.unwrap_or_default();
+    let mut manifest = Manifest::new(
+        started_at / 1000,
+        CaptureProfile::Standard,
+        Consent::default(),
+        None,
+    );
+    manifest.clock.wall_start_us = started_at;
+    manifest.events.push(ManifestEvent {
+        time: started_at / 1000,
+        kind: "import",
+        message: format!("Imported from {}", input.display()),
+    });
+    std::fs::write(
+        output.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+
+    Ok(ImportSummary {
+        connections: connections.len() as u32,
+        packets: index,
+        rejected,
+    })
+}
+
+/// Checks the frame header at the start of the buffer could belong to a
+/// Blaze packet, a header that isn't complete yet is assumed to be
+fn plausible_frame(buffer: &BytesMut) -> bool {
+    let mut header = buffer.clone();
+    match FireFrame::read(&mut header) {
+        Some((frame, length)) => {
+            !matches!(frame.ty, FrameType::Unknown(_)) && length <= MAX_PACKET_LENGTH
+        }
+        None => true,
+    }
+}
+
+/// Address and port of one end of a TCP connection
+type Endpoint = (IpAddr, u16);
+
+/// TCP segment read from the pcap file
+struct Segment {
+    /// Unix timestamp in microseconds the segment was captured
+    time: u64,
+    source: Endpoint,
+    destination: Endpoint,
+    seq: u32,
+    syn: bool,
+    payload: Vec<u8>,
+}
+
+/// Reassembles one direction of a TCP connection
+#[derive(Default)]
+struct Stream {
+    /// The next expected sequence number, None until the first segment
+    next: Option<u32>,
+    /// Segments received ahead of the next expected sequence number
+    /// keyed by their sequence number
+    pending: BTreeMap<u32, Vec<u8>>,
+    /// Reassembled bytes waiting to be decoded
+    buffer: BytesMut,
+    /// Whether the stream was found not to carry Blaze frames, further
+    /// segments are ignored
+    rejected: bool,
+}
+
+impl Stream {
+    /// Adds a segment to the stream, retransmitted bytes are discarded and
+    /// segments arriving out of order are held until the gap is filled. A
+    /// rejected stream only accepts a new connection starting with a SYN.
+    /// Returns whether any new bytes were added to the buffer
+    fn push(&mut self, segment: &Segment) -> bool {
+        if segment.syn {
+            self.rejected = false;
+            self.next = Some(segment.seq.wrapping_add(1));
+            self.pending.clear();
+            self.buffer.clear();
+        }
+
+        if self.rejected || segment.payload.is_empty() {
+            return false;
+        }
+
+        self.pending
+            .entry(segment.seq)
+            .or_insert_with(|| segment.payload.clone());
+
+        let next = self.next.get_or_insert(segment.seq);
+        let mut added = false;
+
+        // Take every pending segment starting at or before the next
+        // sequence number, wrapping differences over half the sequence
+        // space mean the segment starts after it
+        while let Some(seq) = self
+            .pending
+            .keys()
+            .copied()
+            .find(|seq| next.wrapping_sub(*seq) <= u32::MAX / 2)
+        {
+            let payload = self.pending.remove(&seq).unwrap_or_default();
+            let skip = next.wrapping_sub(seq) as usize;
+            if skip < payload.len() {
+                self.buffer.extend_from_slice(&payload[skip..]);
+                *next = next.wrapping_add((payload.len() - skip) as u32);
+                added = true;
+            }
+        }
+
+        added
+    }
+}
+
+/// Reads the TCP segments from a pcap file in capture order
+fn read_pcap(data: &[u8]) -> ImportResult<Vec<Segment>> {
+    if data.len() < 24 {
+        return Err(ImportError::InvalidMagic);
+    }
+
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let (little_endian, nanos) = match magic {
+        0xa1b2c3d4 => (true, false),
+        0xa1b23c4d => (true, true),
+        0xd4c3b2a1 => (false, false),
+        0x4d3cb2a1 => (false, true),
+        _ => return Err(ImportError::InvalidMagic),
+    };
+
+    let read_u32 = |offset: usize| {
+        let bytes = [
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ];
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+
+    let read_u16 = |offset: usize| {
+        let bytes = [data[offset], data[offset + 1]];
+        if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    };
+
+    let (major, minor) = (read_u16(4), read_u16(6));
+    if major != 2 {
+        return Err(ImportError::UnsupportedVersion(major, minor));
+    }
+
+    let link_type = read_u32(20);
+    let mut segments = Vec::new();
+    let mut offset = 24;
+
+    while offset < data.len() {
+        if offset + 16 > data.len() {
+            return Err(ImportError::Truncated(offset));
+        }
+
+        let seconds = read_u32(offset) as u64;
+        let fraction = read_u32(offset + 4) as u64;
+        let length = read_u32(offset + 8) as usize;
+        let original = read_u32(offset + 12) as usize;
+        if length > MAX_RECORD_LENGTH || length > original {
+            return Err(ImportError::ImplausibleRecord(offset));
+        }
+
+        let start = offset + 16;
+        let end = start + length;
+        if end > data.len() {
+            return Err(ImportError::Truncated(offset));
+        }
+
+        let micros = if nanos { fraction / 1000 } else { fraction };
+        let time = seconds * 1_000_000 + micros;
+
+        if let Some(segment) = parse_frame(link_type, &data[start..end], time)? {
+            segments.push(segment);
+        }
+
+        offset = end;
+    }
+
+    Ok(segments)
+}
+
+/// Parses a captured frame returning the TCP segment it carries, frames
+/// that aren't TCP over IP are skipped
+fn parse_frame(link_type: u32, frame: &[u8], time: u64) -> ImportResult<Option<Segment>> {
+    let ip = match link_type {
+        LINKTYPE_NULL => frame.get(4..),
+        LINKTYPE_ETHERNET => {
+            // Skip over any VLAN tags
+            let mut offset = 12;
+            while frame.get(offset..offset + 2) == Some(&[0x81, 0x00][..]) {
+                offset += 4;
+            }
+            frame.get(offset + 2..)
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(frame),
+        link_type => return Err(ImportError::UnsupportedLinkType(link_type)),
+    };
+
+    Ok(ip.and_then(|ip| parse_ip(ip, time)))
+}
+
+/// Parses an IPv4 or IPv6 packet containing a TCP segment
+fn parse_ip(ip: &[u8], time: u64) -> Option<Segment> {
+    let version = ip.first()? >> 4;
+    let (source, destination, tcp) = match version {
+        4 => {
+            let header = ((ip[0] & 0x0F) as usize) * 4;
+            let total = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+            if *ip.get(9)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            // Ethernet frames may be padded past the end of the IP packet
+            let tcp = ip.get(header..total.min(ip.len()))?;
+            (IpAddr::from(source), IpAddr::from(destination), tcp)
+        }
+        6 => {
+            // Extension headers aren't followed
+            if *ip.get(6)? != IP_PROTOCOL_TCP {
+                return None;
+            }
+            let length = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+            let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            let tcp = ip.get(40..(40 + length).min(ip.len()))?;
+            (IpAddr::from(source), IpAddr::from(destination), tcp)
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes([*tcp.first()?, *tcp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*tcp.get(2)?, *tcp.get(3)?]);
+    let seq = u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?);
+    let header = ((tcp.get(12)? >> 4) as usize) * 4;
+    let syn = tcp.get(13)? & 0x02 != 0;
+
+    Some(Segment {
+        time,
+        source: (source, source_port),
+        destination: (destination, destination_port),
+        seq,
+        syn,
+        payload: tcp.get(header..)?.to_vec(),
+    })
+}
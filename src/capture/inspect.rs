@@ -0,0 +1,41 @@
+use super::value::hex_dump;
+use crate::servers::packet::{Packet, PacketDebug};
+
+/// Width of the hex dump column in the side by side view
+const HEX_COLUMN_WIDTH: usize = 77;
+
+/// Formats the packet as a hex dump of its contents next to the
+/// stringified packet, for checking the decoded values against
+/// the raw bytes
+pub fn side_by_side(packet: &Packet) -> String {
+    let hex = hex_dump(&packet.contents, 0);
+    let debug = format!(
+        "{:?}",
+        PacketDebug {
+            packet,
+            contents: true,
+        }
+    );
+
+    let mut hex_lines = hex.lines();
+    let mut debug_lines = debug.lines();
+    let mut output = String::new();
+
+    loop {
+        let (left, right) = (hex_lines.next(), debug_lines.next());
+        if left.is_none() && right.is_none() {
+            break;
+        }
+
+        let line = format!(
+            "{:width$} | {}",
+            left.unwrap_or_default(),
+            right.unwrap_or_default(),
+            width = HEX_COLUMN_WIDTH
+        );
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+
+    output
+}
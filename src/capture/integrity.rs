@@ -0,0 +1,106 @@
+use super::value::to_hex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// The file name of the integrity file within the session directory
+pub const INTEGRITY_FILE: &str = "integrity.json";
+
+/// Hashes of every file in a capture session, used to verify that an
+/// uploaded capture wasn't truncated or modified
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Integrity {
+    /// Hash of each file sorted by path
+    pub files: Vec<FileHash>,
+    /// SHA-256 over the path and hash of every file, one `<path> <sha256>`
+    /// line per file in the order of `files`
+    pub root_sha256: String,
+}
+
+/// Hash of a single session file
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FileHash {
+    /// Path of the file relative to the session directory using `/`
+    pub path: String,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// SHA-256 of the file contents as lowercase hex
+    pub sha256: String,
+}
+
+/// Hashes every file within the session directory and writes the
+/// integrity file. Any existing integrity file is replaced
+pub fn write_integrity(dir: &Path) -> io::Result<Integrity> {
+    let mut files = Vec::new();
+    hash_dir(dir, "", &mut files)?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut root = Sha256::new();
+    for file in &files {
+        root.update(file.path.as_bytes());
+        root.update(b" ");
+        root.update(file.sha256.as_bytes());
+        root.update(b"\n");
+    }
+
+    let integrity = Integrity {
+        files,
+        root_sha256: to_hex(&root.finalize()),
+    };
+
+    let bytes = serde_json::to_vec_pretty(&integrity)?;
+    std::fs::write(dir.join(INTEGRITY_FILE), bytes)?;
+
+    Ok(integrity)
+}
+
+/// Recursively hashes the files within `dir`
+///
+/// `dir`    The directory to hash
+/// `prefix` The relative path of `dir` within the session directory
+/// `output` The output list of hashes
+fn hash_dir(dir: &Path, prefix: &str, output: &mut Vec<FileHash>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            hash_dir(&entry.path(), &path, output)?;
+        } else if file_type.is_file() && path != INTEGRITY_FILE {
+            let (size, sha256) = hash_file(&entry.path())?;
+            output.push(FileHash { path, size, sha256 });
+        }
+    }
+    Ok(())
+}
+
+/// Computes the size and SHA-256 hash of the file at `path`
+pub fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+    let mut size = 0;
+
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+        size += count as u64;
+    }
+
+    Ok((size, to_hex(&hasher.finalize())))
+}
@@ -0,0 +1,260 @@
+use super::{
+    anonymize::Anonymization, bandwidth::BandwidthEntry, clock::ClockInfo, format::FORMAT_VERSION,
+    privacy::PrivacyCategories, profile::CaptureProfile, sampling::SamplingStats,
+    writer::WriterStats,
+};
+use crate::{
+    attach::AttachState,
+    build_info::{build_info, BuildInfo},
+    consent::Consent,
+    constants::APP_VERSION,
+    errors::ErrorSummary,
+    fingerprint::GameFingerprint,
+};
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// Manifest describing a capture session, written alongside the
+/// capture files in the session directory
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Manifest {
+    /// Version of the plugin that created the capture
+    pub version: &'static str,
+    /// Details of the plugin build that created the capture
+    pub build: BuildInfo,
+    /// Version of the capture formats used by the session files
+    pub format_version: u32,
+    /// Unix timestamp in milliseconds of when the session started
+    pub started_at: u64,
+    /// Clock details for correlating captures
+    pub clock: ClockInfo,
+    /// Fingerprint of the game executable, filled in once computed
+    pub game: Option<GameFingerprint>,
+    /// State of the game when the plugin was attached
+    pub attach: Option<AttachState>,
+    /// The active capture profile
+    pub profile: CaptureProfile,
+    /// How much player identifying data was removed
+    pub anonymization: Anonymization,
+    /// Categories of data included in the capture
+    pub privacy: PrivacyCategories,
+    /// Active features the user opted into for the session
+    pub consent: Consent,
+    /// Collection session code shared by players capturing the same
+    /// matches, used to group their captures together
+    pub collection: Option<String>,
+    /// Campaign the session is a run of, campaigns group the sessions of
+    /// collection efforts spanning multiple runs of the game
+    pub campaign: Option<String>,
+    /// Upstream Blaze connections made during the session
+    pub connections: Vec<ConnectionRecord>,
+    /// Where the game intended to connect for each intercepted connection
+    pub destinations: Vec<DestinationRecord>,
+    /// Statistics for each finished proxy session
+    pub sessions: Vec<SessionStats>,
+    /// Statistics for each finished connection to the HTTP server
+    pub http_connections: Vec<HttpConnectionStats>,
+    /// Traffic totals per session, direction and component
+    pub bandwidth: Vec<BandwidthEntry>,
+    /// Statistics for the packet sampling rules
+    pub sampling: Vec<SamplingStats>,
+    /// Memory statistics of the capture writer, packets dropped to stay
+    /// within the memory budget are missing from the capture
+    pub writer: WriterStats,
+    /// Notable events that occurred during the session
+    pub events: Vec<ManifestEvent>,
+    /// Errors reported during the session by error code, written when the
+    /// session is saved or finished
+    pub errors: Vec<ErrorSummary>,
+}
+
+impl Manifest {
+    pub fn new(
+        started_at: u64,
+        profile: CaptureProfile,
+        consent: Consent,
+        collection: Option<String>,
+    ) -> Self {
+        Self {
+            version: APP_VERSION,
+            build: build_info(),
+            format_version: FORMAT_VERSION,
+            started_at,
+            clock: ClockInfo::default(),
+            game: None,
+            attach: None,
+            profile,
+            anonymization: Anonymization::current(),
+            privacy: PrivacyCategories::current(),
+            consent,
+            collection,
+            campaign: None,
+            connections: Vec::new(),
+            destinations: Vec::new(),
+            sessions: Vec::new(),
+            http_connections: Vec::new(),
+            bandwidth: Vec::new(),
+            sampling: Vec::new(),
+            writer: WriterStats::default(),
+            events: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// Notable event that occurred during the session
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ManifestEvent {
+    /// Unix timestamp in milliseconds of the event
+    pub time: u64,
+    /// The kind of event
+    pub kind: &'static str,
+    /// Message describing the event
+    pub message: String,
+}
+
+/// Details about an upstream Blaze connection and its SSL handshake.
+///
+/// blaze-ssl-async doesn't expose the negotiated session or the certificate
+/// chain sent by the server, those fields are always None so they can't be
+/// mistaken for captured values
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConnectionRecord {
+    /// The host that was connected to
+    pub host: String,
+    /// The port that was connected to
+    pub port: u16,
+    /// Unix timestamp in milliseconds of when connecting started
+    pub started_at: u64,
+    /// Time in milliseconds taken to connect and complete the handshake
+    pub handshake_ms: f64,
+    /// The negotiated SSL version, not available from blaze-ssl-async
+    pub version: Option<String>,
+    /// The negotiated cipher suite, not available from blaze-ssl-async
+    pub cipher_suite: Option<String>,
+    /// Hex encoded certificates sent by the server, not available from
+    /// blaze-ssl-async
+    pub certificate_chain: Option<Vec<String>>,
+    /// Error message if the connection failed
+    pub error: Option<String>,
+}
+
+/// Destination the game intended to reach for a connection intercepted
+/// by one of the plugin's servers
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DestinationRecord {
+    /// Unix timestamp in milliseconds of when the connection was accepted
+    pub time: u64,
+    /// Name of the plugin server that accepted the connection
+    pub server: &'static str,
+    /// ID of the proxied connection for main server connections
+    pub connection: Option<u32>,
+    /// Address the game connected from
+    pub client: SocketAddr,
+    /// The host the game intended to reach
+    pub host: String,
+    /// The port the game intended to reach
+    pub port: u16,
+    /// How the intended destination was determined
+    pub source: DestinationSource,
+}
+
+/// How the intended destination of a connection was determined
+#[derive(Debug, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DestinationSource {
+    /// The game's host lookup was redirected by the host lookup hook
+    HostLookup,
+    /// The host wasn't looked up through the hook so it was redirected
+    /// by the hosts file
+    HostsFile,
+    /// The server instance the official redirector provides, which the
+    /// local redirector replaced
+    Redirector,
+    /// The Host header of the HTTP request
+    HostHeader,
+}
+
+/// Number of bytes written to and read from a proxied TCP stream
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SocketStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Statistics for a connection to the local HTTP server
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HttpConnectionStats {
+    /// Address the game connected from
+    pub client: SocketAddr,
+    /// Unix timestamp in milliseconds of when the connection was accepted
+    pub started_at: u64,
+    /// Time in milliseconds the connection was open
+    pub duration_ms: f64,
+}
+
+/// Statistics for a single proxied game connection
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionStats {
+    /// ID of the proxied connection
+    pub id: u32,
+    /// The upstream server the connection was directed to
+    pub upstream: String,
+    /// Unix timestamp in milliseconds of when the session started
+    pub started_at: u64,
+    /// Unix timestamp in milliseconds of when the session ended
+    pub ended_at: Option<u64>,
+    /// Number of packets sent by the game client
+    pub packets_sent: u64,
+    /// Number of packets received from the server
+    pub packets_received: u64,
+    /// Largest number of packets queued for the game client
+    pub max_client_queue: usize,
+    /// Largest number of packets queued for the server
+    pub max_server_queue: usize,
+    /// Number of times forwarding to the client waited for queue space
+    pub parked: u64,
+    /// Number of notifications dropped because the client queue was full
+    pub dropped: u64,
+    /// Number of packets dropped by the network condition simulator
+    pub simulated_drops: u64,
+    /// Bytes exchanged with the game client
+    pub client_socket: SocketStats,
+    /// Bytes exchanged with the upstream server after the handshake,
+    /// including reconnected streams
+    pub upstream_socket: SocketStats,
+    /// Why the session ended
+    pub close_reason: Option<String>,
+    /// Whether the session ended because of a failure
+    pub failed: bool,
+}
+
+impl SessionStats {
+    pub fn new(id: u32, started_at: u64, upstream: String) -> Self {
+        Self {
+            id,
+            upstream,
+            started_at,
+            ended_at: None,
+            packets_sent: 0,
+            packets_received: 0,
+            max_client_queue: 0,
+            max_server_queue: 0,
+            parked: 0,
+            dropped: 0,
+            simulated_drops: 0,
+            client_socket: SocketStats::default(),
+            upstream_socket: SocketStats::default(),
+            close_reason: None,
+            failed: false,
+        }
+    }
+}
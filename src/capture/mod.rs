@@ -0,0 +1,643 @@
+use self::{
+    anonymize::Anonymization,
+    bandwidth::Bandwidth,
+    collection::is_valid_code,
+    decoders::{create_decoders, run_decoders, PacketContext, PacketDecoder},
+    format::FORMAT_VERSION,
+    http_log::{HttpRecord, HTTP_FILE},
+    integrity::{write_integrity, Integrity},
+    manifest::{DestinationRecord, Manifest, ManifestEvent},
+    packet_log::{PacketRecord, PACKETS_FILE},
+    privacy::PrivacyCategories,
+    profile::CaptureProfile,
+    samples::Samples,
+    sampling::{Sample, Sampler},
+    sinks::{create_sinks, CaptureSink},
+    triggers::{TriggerChange, Triggers},
+    value::decode_fields,
+    websocket_log::{WebSocketRecord, WEBSOCKET_FILE},
+};
+use crate::{
+    build_info::{build_info, BuildInfo},
+    config::config,
+    errors::{self, CaptureIoError},
+    servers::packet::{FireFrame, Packet},
+};
+use directories::UserDirs;
+use log::{debug, error};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub mod anonymize;
+pub mod bandwidth;
+pub mod campaign;
+pub mod clock;
+pub mod collection;
+pub mod crash;
+pub mod decoders;
+pub mod disk;
+#[cfg(feature = "parquet")]
+pub mod export;
+pub mod fixtures;
+pub mod format;
+pub mod hotkeys;
+pub mod http_log;
+pub mod import;
+pub mod inspect;
+pub mod integrity;
+pub mod manifest;
+#[cfg(feature = "plugin")]
+pub mod overlay;
+pub mod packet_log;
+pub mod privacy;
+pub mod profile;
+pub mod query;
+pub mod replay;
+pub mod retention;
+pub mod review;
+pub mod samples;
+pub mod sampling;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod sinks;
+pub mod timeline;
+pub mod triggers;
+pub mod upload;
+pub mod value;
+pub mod websocket_log;
+pub mod writer;
+
+/// Name of the directory within the users documents folder
+/// that captures are stored in
+const DUMP_DIR_NAME: &str = "pocket-relay-dump";
+
+/// Prefix for the names of session directories
+pub const SESSION_PREFIX: &str = "session-";
+
+/// The file name of the session manifest
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// The capture session for this run of the game
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+/// Capture session, each run of the game is stored within its own
+/// session directory inside the dump directory
+pub struct Capture {
+    /// The directory the session files are written to
+    pub dir: PathBuf,
+    /// The manifest describing the session
+    manifest: Mutex<Manifest>,
+    /// Decoders extracting structured data from captured packets
+    decoders: Mutex<Vec<Box<dyn PacketDecoder>>>,
+    /// Additional storage backends
+    sinks: Mutex<Vec<Box<dyn CaptureSink>>>,
+    /// Sampler for high volume packets
+    sampler: Sampler,
+    /// Representative payloads kept for every kind of packet
+    samples: Samples,
+    /// Whether capturing is paused
+    paused: AtomicBool,
+    /// Index of the next packet written to the packet log
+    packet_index: AtomicU64,
+    /// Running traffic totals
+    pub bandwidth: Bandwidth,
+    /// Recording state driven by the trigger rules
+    pub triggers: Triggers,
+}
+
+/// The direction a packet was travelling through the proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum Direction {
+    /// Sent from the game client to the server
+    Send,
+    /// Received from the server by the game client
+    Receive,
+}
+
+/// Creates the session directory and writes the initial manifest. Should
+/// only be called on initial startup
+pub fn initialize() {
+    match dump_dir() {
+        Ok(root) => initialize_in(&root),
+        Err(err) => errors::report(CaptureIoError::CreateSession(err)),
+    }
+}
+
+/// Creates the session directory within `root` instead of the dump
+/// directory, used when running the proxy outside the game such as in
+/// the test harness. See [initialize]
+pub fn initialize_in(root: &Path) {
+    let profile = config().profile;
+    profile.activate();
+    config().anonymization.activate();
+    config().privacy.activate();
+
+    let started_at = now_millis();
+    let dir = root.join(format!("{}{}", SESSION_PREFIX, started_at));
+
+    if let Err(err) = std::fs::create_dir_all(&dir) {
+        errors::report(CaptureIoError::CreateSession(err));
+        return;
+    }
+
+    debug!("Capture session directory: {}", dir.display());
+
+    let capture = Capture {
+        decoders: Mutex::new(create_decoders(&dir)),
+        sinks: Mutex::new(create_sinks(&dir)),
+        dir,
+        manifest: Mutex::new(Manifest::new(
+            started_at,
+            profile,
+            config().consent,
+            config()
+                .collection
+                .clone()
+                .filter(|code| is_valid_code(code)),
+        )),
+        sampler: Sampler::new(config().sampling.clone()),
+        samples: Samples::new(config().samples_per_command),
+        paused: AtomicBool::new(false),
+        packet_index: AtomicU64::new(0),
+        bandwidth: Bandwidth::default(),
+        triggers: Triggers::new(config().triggers.clone()),
+    };
+
+    let campaign = config()
+        .campaign
+        .clone()
+        .filter(|name| capture.join_campaign(name, started_at));
+    capture.update_manifest(|manifest| manifest.campaign = campaign);
+
+    _ = CAPTURE.set(capture);
+}
+
+/// Finishes the capture session by writing the final traffic totals and
+/// the integrity file, called when the game is closing
+pub fn finish() {
+    let Some(capture) = capture() else {
+        return;
+    };
+
+    // Queued packets are written first so the totals cover them
+    let writer = writer::flush();
+    let bandwidth = capture.bandwidth.snapshot();
+    let errors = errors::summary();
+    let mut campaign = None;
+    capture.update_manifest(|manifest| {
+        manifest.bandwidth = bandwidth;
+        manifest.writer = writer;
+        manifest.errors = errors;
+        campaign = manifest.campaign.clone();
+    });
+
+    if let Some(campaign) = campaign {
+        capture.finish_campaign_run(&campaign);
+    }
+
+    match capture.write_integrity() {
+        Ok(integrity) => debug!("Capture root hash: {}", integrity.root_sha256),
+        Err(err) => {
+            errors::report(CaptureIoError::Integrity(err));
+        }
+    }
+}
+
+/// Finishes the sessions in `root` that an earlier launch left without an
+/// integrity file, the game can exit without reaching [finish] so those
+/// sessions are finished on the next launch instead. Returns the
+/// directories of the recovered sessions
+pub fn recover_unfinished(root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let current = capture().map(|capture| capture.dir.as_path());
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && Some(path.as_path()) != current
+                && !path.join(integrity::INTEGRITY_FILE).exists()
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(SESSION_PREFIX))
+        })
+        .filter(|dir| match write_integrity(dir) {
+            Ok(_) => {
+                debug!("Recovered unfinished capture session {}", dir.display());
+                true
+            }
+            Err(err) => {
+                errors::report(CaptureIoError::Integrity(err));
+                false
+            }
+        })
+        .collect()
+}
+
+/// Obtains the current capture session, None if the session
+/// failed to initialize
+pub fn capture() -> Option<&'static Capture> {
+    CAPTURE.get()
+}
+
+/// Obtains the directory that capture sessions are stored in, within the
+/// user's documents folder. Fails when the documents folder is unknown,
+/// such as for service accounts or when the user profile can't be loaded
+pub fn dump_dir() -> io::Result<PathBuf> {
+    UserDirs::new()
+        .and_then(|dirs| dirs.document_dir().map(|dir| dir.join(DUMP_DIR_NAME)))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "Failed to find the documents folder for the dump directory",
+            )
+        })
+}
+
+/// Resolves the directory of the capture session `name` within the dump
+/// directory. Names that aren't sessions or that would resolve outside of
+/// the dump directory fail with [`io::ErrorKind::InvalidInput`]
+pub fn session_dir(name: &str) -> io::Result<PathBuf> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid session name");
+
+    if !name.starts_with(SESSION_PREFIX) || name.contains(['/', '\\']) || name.contains("..") {
+        return Err(invalid());
+    }
+
+    let root = dump_dir()?.canonicalize()?;
+    let dir = root.join(name).canonicalize()?;
+    if dir.parent() != Some(root.as_path()) {
+        return Err(invalid());
+    }
+
+    Ok(dir)
+}
+
+/// Header record written as the first line of every JSONL file
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct JsonlHeader {
+    /// The plugin build that wrote the file
+    pub header: BuildInfo,
+    /// Version of the format the file was written in
+    pub format_version: u32,
+}
+
+/// Start of the header line, used to tell the header apart from records
+const JSONL_HEADER_START: &str = "{\"header\":";
+
+/// Writes the header line for a new JSONL file
+pub fn write_jsonl_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    let mut line = serde_json::to_vec(&JsonlHeader {
+        header: build_info(),
+        format_version: FORMAT_VERSION,
+    })?;
+    line.push(b'\n');
+    writer.write_all(&line)
+}
+
+/// Appends the provided `record` as a line of JSON to the file at `path`,
+/// new files start with a header line describing the plugin build
+pub fn append_jsonl<T: Serialize>(path: &Path, record: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        write_jsonl_header(&mut file)?;
+    }
+    file.write_all(&line)
+}
+
+/// Reads the header line of the JSONL file at `path`, None for files
+/// written before headers were added
+pub fn read_jsonl_header(path: &Path) -> io::Result<Option<JsonlHeader>> {
+    let file = File::open(path)?;
+    let Some(line) = BufReader::new(file).lines().next().transpose()? else {
+        return Ok(None);
+    };
+    if !line.starts_with(JSONL_HEADER_START) {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+/// Reads every line of JSON from the file at `path`, blank lines and the
+/// header line are skipped. Records written in older format versions are
+/// migrated to the current version
+pub fn read_jsonl<T: DeserializeOwned>(path: &Path) -> io::Result<Vec<T>> {
+    let version = format::jsonl_version(read_jsonl_header(path)?.as_ref());
+    format::check_version(version)?;
+    let name = format::file_name(path);
+
+    let file = File::open(path)?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with(JSONL_HEADER_START) {
+            continue;
+        }
+
+        if version == FORMAT_VERSION {
+            records.push(serde_json::from_str(&line)?);
+        } else {
+            let mut record: serde_json::Value = serde_json::from_str(&line)?;
+            format::migrate(&name, version, &mut record)?;
+            records.push(serde_json::from_value(record)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Obtains the current unix timestamp in milliseconds
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl Capture {
+    /// Applies the provided `update` to the session manifest and
+    /// writes the updated manifest to disk
+    pub fn update_manifest<F>(&self, update: F)
+    where
+        F: FnOnce(&mut Manifest),
+    {
+        let manifest = &mut *self.manifest.lock().expect("Manifest lock poisoned");
+        update(manifest);
+
+        if let Err(err) = self.write_manifest(manifest) {
+            errors::report(CaptureIoError::Write {
+                what: "capture manifest",
+                source: err,
+            });
+        }
+
+        self.with_sinks(|sink| sink.manifest(manifest));
+    }
+
+    /// Switches the active capture profile recording the change
+    /// in the manifest
+    pub fn set_profile(&self, profile: CaptureProfile) {
+        debug!("Switching capture profile to {}", profile);
+        profile.activate();
+        self.update_manifest(|manifest| manifest.profile = profile);
+    }
+
+    /// Switches the active anonymization level recording the change
+    /// in the manifest
+    pub fn set_anonymization(&self, anonymization: Anonymization) {
+        debug!("Switching anonymization level to {}", anonymization);
+        anonymization.activate();
+        self.update_manifest(|manifest| manifest.anonymization = anonymization);
+    }
+
+    /// Switches the captured privacy categories recording the change
+    pub fn set_privacy(&self, privacy: PrivacyCategories) {
+        debug!("Switching privacy categories to {:?}", privacy);
+        privacy.activate();
+        self.update_manifest(|manifest| manifest.privacy = privacy);
+    }
+
+    /// Pauses or resumes capturing packets
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Release);
+    }
+
+    /// Whether capturing is paused
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Number of packets written to the packet log
+    pub fn captured_packets(&self) -> u64 {
+        self.packet_index.load(Ordering::Acquire)
+    }
+
+    /// Records an event in the session manifest
+    ///
+    /// `kind`    The kind of event
+    /// `message` Message describing the event
+    pub fn record_event(&self, kind: &'static str, message: &str) {
+        let event = ManifestEvent {
+            time: now_millis(),
+            kind,
+            message: message.to_string(),
+        };
+        self.update_manifest(|manifest| manifest.events.push(event));
+    }
+
+    /// Applies the trigger and sampling rules to decide whether the packet
+    /// with the provided frame should be captured, the sampling stats in
+    /// the manifest are updated whenever a sampled packet is kept. Nothing
+    /// is captured while capturing is paused
+    pub fn should_capture(&self, frame: &FireFrame) -> bool {
+        if self.paused.load(Ordering::Acquire) {
+            return false;
+        }
+
+        match self.triggers.evaluate(frame) {
+            Some(TriggerChange::Started) => {
+                debug!("Recording started by trigger");
+                self.record_event(
+                    "trigger",
+                    &format!(
+                        "Recording started by {:#06x}->{:#06x}",
+                        frame.component, frame.command
+                    ),
+                );
+            }
+            Some(TriggerChange::StoppedIdle) => {
+                debug!("Recording stopped after being idle");
+                self.record_event("trigger", "Recording stopped after being idle");
+            }
+            Some(TriggerChange::ResumedIdle { idle_seconds }) => {
+                debug!("Recording resumed after being idle");
+                self.record_event(
+                    "trigger",
+                    &format!(
+                        "Recording resumed by {:#06x}->{:#06x} after being idle for {}s",
+                        frame.component, frame.command, idle_seconds
+                    ),
+                );
+            }
+            None => {}
+        }
+
+        if !self.triggers.is_recording() {
+            return false;
+        }
+
+        match self.sampler.sample(frame) {
+            Sample::Unsampled => true,
+            Sample::Kept => {
+                let stats = self.sampler.stats();
+                self.update_manifest(|manifest| manifest.sampling = stats);
+                true
+            }
+            Sample::Dropped => false,
+        }
+    }
+
+    /// Claims a sample number for the packet with the provided frame if
+    /// too few packets of its kind have been kept as samples. Samples
+    /// ignore the capture profile, triggers and sampling but are not
+    /// kept while capturing is paused
+    pub fn take_sample(&self, frame: &FireFrame) -> Option<u32> {
+        if self.paused.load(Ordering::Acquire) {
+            return None;
+        }
+        self.samples.take(frame)
+    }
+
+    /// Records a packet that passed through the proxy on the connection
+    /// with the provided `connection` ID in the packet log and passes
+    /// it to the decoders
+    ///
+    /// `time` Unix timestamp in microseconds the packet was seen
+    pub fn record_packet(&self, time: u64, connection: u32, direction: Direction, packet: &Packet) {
+        let profile = CaptureProfile::current();
+
+        // Identifiers are removed before the packet reaches anything writing to disk
+        let anonymized = Anonymization::current().apply(packet);
+        let contents = profile.packet_contents()
+            && anonymized.is_some()
+            && PrivacyCategories::current().packet_contents(&packet.frame);
+        let packet = anonymized.as_deref().unwrap_or(packet);
+
+        let record = PacketRecord::new(
+            self.packet_index.fetch_add(1, Ordering::AcqRel),
+            time,
+            connection,
+            direction,
+            packet,
+            contents,
+        );
+        if let Err(err) = append_jsonl(&self.dir.join(PACKETS_FILE), &record) {
+            errors::report(CaptureIoError::Write {
+                what: "packet log",
+                source: err,
+            });
+        }
+
+        self.with_sinks(|sink| sink.packet(&record, packet));
+
+        if !contents || !profile.decoders() {
+            return;
+        }
+
+        let decoded = decode_fields(&packet.contents);
+        let ctx = PacketContext {
+            connection,
+            direction,
+            time,
+            packet,
+            fields: decoded.as_deref().ok(),
+            decode_error: decoded.as_ref().err(),
+        };
+
+        let decoders = &mut *self.decoders.lock().expect("Decoders lock poisoned");
+        for name in run_decoders(decoders, &ctx) {
+            error!("Capture decoder {} panicked and has been disabled", name);
+            self.record_event(
+                "decoder",
+                &format!("{} panicked and has been disabled", name),
+            );
+        }
+    }
+
+    /// Records where the game intended to connect for an intercepted
+    /// connection in the manifest
+    pub fn record_destination(&self, record: DestinationRecord) {
+        debug!(
+            "{} connection from {} intended for {}:{} ({:?})",
+            record.server, record.client, record.host, record.port, record.source
+        );
+        self.update_manifest(|manifest| manifest.destinations.push(record));
+    }
+
+    /// Records a proxied HTTP exchange in the HTTP log
+    pub fn record_http(&self, record: HttpRecord) {
+        if let Err(err) = append_jsonl(&self.dir.join(HTTP_FILE), &record) {
+            errors::report(CaptureIoError::Write {
+                what: "HTTP log",
+                source: err,
+            });
+        }
+
+        self.with_sinks(|sink| sink.http(&record));
+    }
+
+    /// Records a frame sent through an upgraded HTTP connection in the
+    /// WebSocket log
+    pub fn record_websocket(&self, record: &WebSocketRecord) {
+        if let Err(err) = append_jsonl(&self.dir.join(WEBSOCKET_FILE), record) {
+            errors::report(CaptureIoError::Write {
+                what: "WebSocket log",
+                source: err,
+            });
+        }
+    }
+
+    /// Passes each sink to the provided action, sinks that panic are
+    /// disabled. Sinks are also written while the manifest is locked
+    /// so disabling a sink is only logged
+    fn with_sinks<F>(&self, mut action: F)
+    where
+        F: FnMut(&mut dyn CaptureSink),
+    {
+        let sinks = &mut *self.sinks.lock().expect("Sinks lock poisoned");
+        sinks.retain_mut(|sink| {
+            let result = catch_unwind(AssertUnwindSafe(|| action(sink.as_mut())));
+            if result.is_err() {
+                error!(
+                    "Capture sink {} panicked and has been disabled",
+                    sink.name()
+                );
+            }
+            result.is_ok()
+        });
+    }
+
+    /// Writes the queued packets, the current traffic totals and error
+    /// counts then the integrity file, so the files on disk are complete up to now without
+    /// ending the session
+    pub fn save(&self) -> io::Result<Integrity> {
+        let writer = writer::flush();
+        let bandwidth = self.bandwidth.snapshot();
+        let errors = errors::summary();
+        self.update_manifest(|manifest| {
+            manifest.bandwidth = bandwidth;
+            manifest.writer = writer;
+            manifest.errors = errors;
+        });
+        self.write_integrity()
+    }
+
+    /// Writes the integrity file containing hashes of every session file,
+    /// the manifest lock is held so the manifest can't change while hashing
+    pub fn write_integrity(&self) -> io::Result<Integrity> {
+        let _manifest = self.manifest.lock().expect("Manifest lock poisoned");
+        write_integrity(&self.dir)
+    }
+
+    fn write_manifest(&self, manifest: &Manifest) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(manifest)?;
+        std::fs::write(self.dir.join(MANIFEST_FILE), bytes)
+    }
+}
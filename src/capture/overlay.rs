@@ -0,0 +1,132 @@
+//! Optional capture status shown in the game window title, letting players
+//! confirm packets are being captured without tabbing out to the console
+
+use super::capture;
+use crate::{config::config, locale, tasks};
+use log::debug;
+use std::{ptr::null_mut, thread::sleep, time::Duration};
+use windows_sys::Win32::{
+    Foundation::{BOOL, HWND, LPARAM},
+    UI::WindowsAndMessaging::{
+        EnumWindows, GetWindow, GetWindowTextW, GetWindowThreadProcessId, IsWindowVisible,
+        SendMessageTimeoutW, GW_OWNER, SMTO_ABORTIFHUNG, WM_SETTEXT,
+    },
+};
+
+/// How often the window title is updated
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum time to wait for the game to apply a title change, the game
+/// window may be busy loading
+const SET_TITLE_TIMEOUT_MS: u32 = 500;
+
+/// Maximum length of the original window title
+const MAX_TITLE_LENGTH: usize = 256;
+
+/// Starts updating the game window title if enabled in the config
+pub fn start() {
+    if !config().title_overlay {
+        return;
+    }
+
+    tasks::spawn_thread("overlay", run);
+}
+
+fn run() {
+    // The game window is created after the plugin is loaded
+    let window = loop {
+        if let Some(window) = find_game_window() {
+            break window;
+        }
+        sleep(UPDATE_INTERVAL);
+    };
+
+    let title = window_title(window);
+    debug!("Showing capture status in game window title");
+
+    let mut current = String::new();
+    loop {
+        let text = status_title(&title);
+        if text != current {
+            set_window_title(window, &text);
+            current = text;
+        }
+        sleep(UPDATE_INTERVAL);
+    }
+}
+
+/// Creates the window title from the original title and the capture state
+fn status_title(title: &str) -> String {
+    let Some(capture) = capture() else {
+        return title.to_string();
+    };
+
+    let state = if capture.is_paused() {
+        "overlay.paused"
+    } else if capture.triggers.is_recording() {
+        "overlay.recording"
+    } else {
+        "overlay.waiting"
+    };
+
+    let seen: u64 = capture
+        .bandwidth
+        .snapshot()
+        .iter()
+        .map(|entry| entry.packets)
+        .sum();
+
+    locale::format(
+        "overlay.title",
+        &[
+            ("title", title),
+            ("state", &locale::text(state)),
+            ("seen", &seen.to_string()),
+            ("captured", &capture.captured_packets().to_string()),
+        ],
+    )
+}
+
+/// Finds the visible top level window owned by the game process
+fn find_game_window() -> Option<HWND> {
+    unsafe extern "system" fn callback(window: HWND, found: LPARAM) -> BOOL {
+        let mut process = 0;
+        GetWindowThreadProcessId(window, &mut process);
+
+        if process == std::process::id()
+            && IsWindowVisible(window) != 0
+            && GetWindow(window, GW_OWNER) == 0
+        {
+            *(found as *mut HWND) = window;
+            // Stop enumerating
+            return 0;
+        }
+        1
+    }
+
+    let mut found: HWND = 0;
+    unsafe { EnumWindows(Some(callback), &mut found as *mut HWND as LPARAM) };
+    (found != 0).then_some(found)
+}
+
+fn window_title(window: HWND) -> String {
+    let mut buffer = [0u16; MAX_TITLE_LENGTH];
+    let length = unsafe { GetWindowTextW(window, buffer.as_mut_ptr(), buffer.len() as i32) };
+    String::from_utf16_lossy(&buffer[..length.max(0) as usize])
+}
+
+/// Sets the window title without waiting on a hung window
+fn set_window_title(window: HWND, title: &str) {
+    let title: Vec<u16> = title.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        SendMessageTimeoutW(
+            window,
+            WM_SETTEXT,
+            0,
+            title.as_ptr() as LPARAM,
+            SMTO_ABORTIFHUNG,
+            SET_TITLE_TIMEOUT_MS,
+            null_mut(),
+        )
+    };
+}
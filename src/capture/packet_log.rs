@@ -0,0 +1,143 @@
+use super::{
+    read_jsonl,
+    value::{from_hex, to_hex},
+    Direction,
+};
+use crate::servers::packet::{FireFrame, FrameType, Packet, PacketOptions};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io, path::Path};
+
+/// The file name of the packet log within the session directory
+pub const PACKETS_FILE: &str = "packets.jsonl";
+
+/// Packet recorded in the packet log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PacketRecord {
+    /// Index of the packet within the session
+    pub index: u64,
+    /// Unix timestamp in microseconds the packet was captured
+    pub time: u64,
+    /// ID of the proxied connection the packet was seen on
+    pub connection: u32,
+    /// The direction the packet was travelling
+    pub direction: Direction,
+    pub component: u16,
+    pub command: u16,
+    pub error: u16,
+    /// Raw frame type
+    pub ty: u8,
+    /// Raw bits of the frame options
+    pub options: u8,
+    pub seq: u16,
+    /// Context value from the frame header if present
+    #[serde(default)]
+    pub context: Option<u32>,
+    /// Low nibbles of the frame type and options bytes
+    #[serde(default)]
+    pub reserved: u8,
+    /// Length of the packet contents in bytes
+    pub length: usize,
+    /// Packet contents as hex, None if the capture profile
+    /// excluded packet contents
+    pub contents: Option<String>,
+}
+
+impl PacketRecord {
+    /// Creates a record for the provided packet
+    ///
+    /// `contents` Whether to include the packet contents
+    pub fn new(
+        index: u64,
+        time: u64,
+        connection: u32,
+        direction: Direction,
+        packet: &Packet,
+        contents: bool,
+    ) -> Self {
+        let frame = &packet.frame;
+        Self {
+            index,
+            time,
+            connection,
+            direction,
+            component: frame.component,
+            command: frame.command,
+            error: frame.error,
+            ty: frame.ty.into(),
+            options: frame.options.bits(),
+            seq: frame.seq,
+            context: frame.context,
+            reserved: frame.reserved,
+            length: packet.contents.len(),
+            contents: contents.then(|| to_hex(&packet.contents)),
+        }
+    }
+
+    /// Recreates the packet from the record, the contents are
+    /// empty if they weren't recorded
+    pub fn to_packet(&self) -> Packet {
+        let contents = self
+            .contents
+            .as_deref()
+            .and_then(from_hex)
+            .map(Bytes::from)
+            .unwrap_or_default();
+
+        Packet::new(
+            FireFrame {
+                component: self.component,
+                command: self.command,
+                error: self.error,
+                ty: FrameType::from(self.ty),
+                options: PacketOptions::from_bits_retain(self.options),
+                seq: self.seq,
+                context: self.context,
+                reserved: self.reserved,
+            },
+            contents,
+        )
+    }
+}
+
+/// Reads all the packet records from the packet log in the
+/// provided session directory
+pub fn read_packet_log(dir: &Path) -> io::Result<Vec<PacketRecord>> {
+    read_jsonl(&dir.join(PACKETS_FILE))
+}
+
+/// Request matched with its response
+pub struct PacketPair<'a> {
+    pub request: &'a PacketRecord,
+    /// The response or error, None if the session ended first
+    pub response: Option<&'a PacketRecord>,
+}
+
+/// Matches each request in the packet log with its response using
+/// the connection and sequence number, in the order of the requests
+pub fn pair_packets(packets: &[PacketRecord]) -> Vec<PacketPair> {
+    let mut pairs: Vec<PacketPair> = Vec::new();
+    // Pending requests keyed by connection and sequence number
+    let mut pending: HashMap<(u32, u16), usize> = HashMap::new();
+
+    for record in packets {
+        match FrameType::from(record.ty) {
+            FrameType::Request => {
+                pending.insert((record.connection, record.seq), pairs.len());
+                pairs.push(PacketPair {
+                    request: record,
+                    response: None,
+                });
+            }
+            FrameType::Response | FrameType::Error => {
+                if let Some(index) = pending.remove(&(record.connection, record.seq)) {
+                    pairs[index].response = Some(record);
+                }
+            }
+            FrameType::Notify | FrameType::Unknown(_) => {}
+        }
+    }
+
+    pairs
+}
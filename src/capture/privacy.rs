@@ -0,0 +1,86 @@
+//! Per-category capture toggles letting players leave out data they don't
+//! want to share. Disabled categories keep their packet headers so the
+//! structure of the session is still captured, only the contents are
+//! left out of the packet log, sinks, decoders and samples
+
+use crate::servers::{
+    components::{messaging, util},
+    packet::FireFrame,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The currently active categories as bits
+static CATEGORIES: AtomicU8 = AtomicU8::new(ALL_BITS);
+
+const TELEMETRY_BIT: u8 = 1;
+const HTTP_BODIES_BIT: u8 = 1 << 1;
+const MESSAGES_BIT: u8 = 1 << 2;
+const ALL_BITS: u8 = TELEMETRY_BIT | HTTP_BODIES_BIT | MESSAGES_BIT;
+
+/// Categories of data that can be left out of captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct PrivacyCategories {
+    /// Telemetry and ticker server details sent to the game
+    pub telemetry: bool,
+    /// Bodies of proxied HTTP responses, the capture profile must
+    /// also include them
+    pub http_bodies: bool,
+    /// Contents of messages sent between players
+    pub messages: bool,
+}
+
+impl Default for PrivacyCategories {
+    fn default() -> Self {
+        Self {
+            telemetry: true,
+            http_bodies: true,
+            messages: true,
+        }
+    }
+}
+
+impl PrivacyCategories {
+    /// Obtains the currently active categories
+    pub fn current() -> Self {
+        let bits = CATEGORIES.load(Ordering::Acquire);
+        Self {
+            telemetry: bits & TELEMETRY_BIT != 0,
+            http_bodies: bits & HTTP_BODIES_BIT != 0,
+            messages: bits & MESSAGES_BIT != 0,
+        }
+    }
+
+    /// Makes these categories the currently active categories
+    pub fn activate(self) {
+        let mut bits = 0;
+        if self.telemetry {
+            bits |= TELEMETRY_BIT;
+        }
+        if self.http_bodies {
+            bits |= HTTP_BODIES_BIT;
+        }
+        if self.messages {
+            bits |= MESSAGES_BIT;
+        }
+        CATEGORIES.store(bits, Ordering::Release);
+    }
+
+    /// Whether the contents of the packet with the provided frame
+    /// may be captured
+    pub fn packet_contents(&self, frame: &FireFrame) -> bool {
+        match frame.component {
+            util::COMPONENT => {
+                self.telemetry
+                    || !matches!(
+                        frame.command,
+                        util::GET_TELEMETRY_SERVER | util::GET_TICKER_SERVER
+                    )
+            }
+            messaging::COMPONENT => self.messages,
+            _ => true,
+        }
+    }
+}
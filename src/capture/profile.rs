@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// The currently active capture profile
+static PROFILE: AtomicU8 = AtomicU8::new(CaptureProfile::Standard as u8);
+
+/// Named capture profiles deciding how much data is captured
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum CaptureProfile {
+    /// Only packet headers are logged, decoders are disabled
+    Minimal = 0,
+    /// Packet headers and contents are logged and decoders are enabled
+    #[default]
+    Standard = 1,
+    /// Everything in standard along with HTTP bodies
+    Full = 2,
+}
+
+impl CaptureProfile {
+    /// Obtains the currently active profile
+    pub fn current() -> Self {
+        match PROFILE.load(Ordering::Acquire) {
+            0 => CaptureProfile::Minimal,
+            2 => CaptureProfile::Full,
+            _ => CaptureProfile::Standard,
+        }
+    }
+
+    /// Makes this profile the currently active profile
+    pub fn activate(self) {
+        PROFILE.store(self as u8, Ordering::Release);
+    }
+
+    /// Whether packet contents should be logged
+    pub fn packet_contents(&self) -> bool {
+        !matches!(self, CaptureProfile::Minimal)
+    }
+
+    /// Whether the structured decoders should run
+    pub fn decoders(&self) -> bool {
+        !matches!(self, CaptureProfile::Minimal)
+    }
+
+    /// Whether HTTP request and response bodies should be logged
+    pub fn http_bodies(&self) -> bool {
+        matches!(self, CaptureProfile::Full)
+    }
+}
+
+impl FromStr for CaptureProfile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(CaptureProfile::Minimal),
+            "standard" => Ok(CaptureProfile::Standard),
+            "full" => Ok(CaptureProfile::Full),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for CaptureProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaptureProfile::Minimal => "minimal",
+            CaptureProfile::Standard => "standard",
+            CaptureProfile::Full => "full",
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use super::{
+    packet_log::{read_packet_log, PacketRecord},
+    value::{decode_fields, fields_to_json},
+};
+use crate::servers::{
+    components::{component_key, get_command_name, get_component_name},
+    packet::{FrameType, PacketDebug},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{io, path::Path};
+
+/// Filters for selecting packets from a capture session, every
+/// filter that is set must match
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PacketQuery {
+    /// Only include packets for this component
+    pub component: Option<u16>,
+    /// Only include packets for this command
+    pub command: Option<u16>,
+    /// Only include packets where the component or command name contains
+    /// this value, ignoring case
+    pub name: Option<String>,
+    /// Only include packets where the stringified packet contains this
+    /// value, ignoring case
+    pub text: Option<String>,
+    /// Only include packets captured at or after this unix timestamp
+    /// in microseconds
+    pub from: Option<u64>,
+    /// Only include packets captured at or before this unix timestamp
+    /// in microseconds
+    pub to: Option<u64>,
+    /// The maximum number of packets to return
+    pub limit: Option<usize>,
+}
+
+/// Packet matching a [PacketQuery]
+#[derive(Serialize)]
+pub struct PacketMatch {
+    #[serde(flatten)]
+    pub record: PacketRecord,
+    /// Name of the packet component
+    pub component_name: Option<&'static str>,
+    /// Name of the packet command
+    pub command_name: Option<&'static str>,
+    /// The decoded packet contents, None if the contents weren't
+    /// captured or couldn't be decoded
+    pub body: Option<Value>,
+}
+
+/// Finds the packets matching the provided `query` within the capture
+/// session in the provided directory
+pub fn query_packets(dir: &Path, query: &PacketQuery) -> io::Result<Vec<PacketMatch>> {
+    let name = query.name.as_ref().map(|value| value.to_lowercase());
+    let text = query.text.as_ref().map(|value| value.to_lowercase());
+    let limit = query.limit.unwrap_or(usize::MAX);
+
+    let mut matches = Vec::new();
+
+    for record in read_packet_log(dir)? {
+        if matches.len() >= limit {
+            break;
+        }
+
+        if query
+            .component
+            .is_some_and(|value| value != record.component)
+            || query.command.is_some_and(|value| value != record.command)
+            || query.from.is_some_and(|value| record.time < value)
+            || query.to.is_some_and(|value| record.time > value)
+        {
+            continue;
+        }
+
+        let ty = FrameType::from(record.ty);
+        let key = component_key(record.component, record.command);
+        let component_name = get_component_name(record.component);
+        let command_name = get_command_name(key, matches!(ty, FrameType::Notify));
+
+        if let Some(name) = &name {
+            let matches_name = [component_name, command_name]
+                .into_iter()
+                .flatten()
+                .any(|value| value.to_lowercase().contains(name));
+            if !matches_name {
+                continue;
+            }
+        }
+
+        let packet = record.to_packet();
+
+        if let Some(text) = &text {
+            let stringified = format!(
+                "{:?}",
+                PacketDebug {
+                    packet: &packet,
+                    contents: true,
+                }
+            );
+            if !stringified.to_lowercase().contains(text) {
+                continue;
+            }
+        }
+
+        let body = record
+            .contents
+            .as_ref()
+            .and_then(|_| decode_fields(&packet.contents).ok())
+            .map(|fields| fields_to_json(&fields));
+
+        matches.push(PacketMatch {
+            record,
+            component_name,
+            command_name,
+            body,
+        });
+    }
+
+    Ok(matches)
+}
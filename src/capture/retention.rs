@@ -0,0 +1,126 @@
+//! Retention policy for old capture sessions, applied to the dump directory
+//! on startup so long running contributors don't have their disks filled.
+//! Uploaded sessions are pruned before sessions that only exist locally
+
+use super::{
+    capture, crash, now_millis,
+    review::{list_sessions, SessionSummary},
+    upload,
+};
+use crate::{config::config, tasks};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::{io, path::Path};
+
+/// Limits on the sessions kept in the dump directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Sessions older than this many days are removed, None keeps
+    /// sessions regardless of age
+    pub max_age_days: Option<u64>,
+    /// Oldest sessions are removed until the sessions use at most this
+    /// many megabytes, None keeps sessions regardless of size
+    pub max_total_mb: Option<u64>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_days: None,
+            max_total_mb: Some(4096),
+        }
+    }
+}
+
+/// Applies the retention policy on its own thread
+pub fn start() {
+    let retention = &config().retention;
+    if retention.max_age_days.is_none() && retention.max_total_mb.is_none() {
+        return;
+    }
+
+    tasks::spawn_thread("retention", || match prune(&config().retention) {
+        Ok((0, _)) => debug!("No capture sessions exceeded the retention policy"),
+        Ok((removed, freed)) => {
+            let message = format!(
+                "Removed {} old capture sessions freeing {:.1} MB",
+                removed,
+                freed as f64 / (1024.0 * 1024.0)
+            );
+            info!("{}", message);
+            if let Some(capture) = capture() {
+                capture.record_event("retention", &message);
+            }
+        }
+        Err(err) => error!("Failed to apply capture retention policy: {}", err),
+    });
+}
+
+/// Removes the sessions exceeding the retention limits, the current
+/// session is never removed. Returns the number of removed sessions
+/// and the number of bytes freed
+fn prune(retention: &RetentionConfig) -> io::Result<(usize, u64)> {
+    let sessions = list_sessions()?;
+    let mut total: u64 = sessions.iter().map(|session| session.size).sum();
+
+    // Uploaded sessions are removed first, oldest first within each
+    let mut candidates: Vec<SessionSummary> = sessions
+        .into_iter()
+        .filter(|session| !session.current)
+        .collect();
+    candidates.sort_by_key(|session| (!session.uploaded, session.started_at));
+
+    let max_age = retention
+        .max_age_days
+        .map(|days| days.saturating_mul(24 * 60 * 60 * 1000));
+    let max_total = retention
+        .max_total_mb
+        .map(|value| value.saturating_mul(1024 * 1024));
+    let now = now_millis();
+
+    let mut removed = 0;
+    let mut freed = 0;
+
+    for session in candidates {
+        let expired = match (max_age, session.started_at) {
+            (Some(max_age), Some(started_at)) => now.saturating_sub(started_at) > max_age,
+            _ => false,
+        };
+        let over_size = max_total.is_some_and(|max_total| total > max_total);
+        if !expired && !over_size {
+            continue;
+        }
+
+        debug!(
+            "Removing capture session {} (uploaded: {})",
+            session.name, session.uploaded
+        );
+        if let Err(err) = std::fs::remove_dir_all(&session.path) {
+            error!("Failed to remove capture session {}: {}", session.name, err);
+            continue;
+        }
+        upload::dequeue(&session.name);
+        remove_minidump(&session.path, &session.name);
+
+        total = total.saturating_sub(session.size);
+        removed += 1;
+        freed += session.size;
+    }
+
+    Ok((removed, freed))
+}
+
+/// Removes the minidump kept next to the removed session in `dir`
+fn remove_minidump(dir: &Path, session: &str) {
+    let Some(root) = dir.parent() else {
+        return;
+    };
+    let path = crash::minidump_path(root, session);
+    if !path.is_file() {
+        return;
+    }
+    if let Err(err) = std::fs::remove_file(&path) {
+        error!("Failed to remove minidump of session {}: {}", session, err);
+    }
+}
@@ -0,0 +1,205 @@
+//! Review of the data collected in the dump directory, lists the capture
+//! sessions with their files, previews their contents and securely deletes
+//! sessions so contributors control what leaves their machine
+
+use super::{capture, crash, dump_dir, upload, value::hex_dump, SESSION_PREFIX};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Number of lines previewed from each text file
+const PREVIEW_LINES: usize = 5;
+
+/// Number of bytes previewed from each file
+const PREVIEW_BYTES: usize = 4096;
+
+/// Size of the zeroed buffer used to overwrite deleted files
+const OVERWRITE_CHUNK: usize = 64 * 1024;
+
+/// Errors that can occur deleting a session
+#[derive(Debug, Error)]
+pub enum ReviewError {
+    #[error("Not a capture session in the dump directory")]
+    InvalidSession,
+    #[error("The current session can't be deleted while the game is running")]
+    CurrentSession,
+    #[error(transparent)]
+    IO(#[from] io::Error),
+}
+
+/// Capture session stored in the dump directory
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    /// Name of the session directory
+    pub name: String,
+    pub path: PathBuf,
+    /// Unix timestamp in milliseconds the session started
+    pub started_at: Option<u64>,
+    /// Total size of the session files in bytes
+    pub size: u64,
+    /// Every file within the session
+    pub files: Vec<FileSummary>,
+    /// Whether the session is waiting to be uploaded
+    pub queued_for_upload: bool,
+    /// Whether the session has been uploaded
+    pub uploaded: bool,
+    /// Whether this is the session currently being captured
+    pub current: bool,
+}
+
+/// File within a capture session
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    /// Path relative to the session directory
+    pub path: String,
+    pub size: u64,
+}
+
+/// Lists the capture sessions in the dump directory, oldest first
+pub fn list_sessions() -> io::Result<Vec<SessionSummary>> {
+    let current = capture().map(|capture| capture.dir.as_path());
+    let mut sessions = Vec::new();
+
+    for entry in std::fs::read_dir(dump_dir()?)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !path.is_dir() || !name.starts_with(SESSION_PREFIX) {
+            continue;
+        }
+
+        let mut files = Vec::new();
+        collect_files(&path, &path, &mut files)?;
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        sessions.push(SessionSummary {
+            started_at: name[SESSION_PREFIX.len()..].parse().ok(),
+            size: files.iter().map(|file| file.size).sum(),
+            files,
+            queued_for_upload: upload::is_queued(&name),
+            uploaded: upload::is_uploaded(&path),
+            current: current == Some(path.as_path()),
+            name,
+            path,
+        });
+    }
+
+    sessions.sort_by_key(|session| session.started_at);
+    Ok(sessions)
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<FileSummary>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            files.push(FileSummary {
+                path: path
+                    .strip_prefix(root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned(),
+                size: metadata.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Creates a preview of the session showing the start of each file,
+/// text files show their first lines and other files a hex dump
+pub fn preview_session(session: &SessionSummary) -> io::Result<String> {
+    let mut output = String::new();
+
+    for file in &session.files {
+        output.push_str(&format!("== {} ({} bytes) ==\n", file.path, file.size));
+
+        let mut bytes = Vec::new();
+        std::fs::File::open(session.path.join(&file.path))?
+            .take(PREVIEW_BYTES as u64)
+            .read_to_end(&mut bytes)?;
+
+        match std::str::from_utf8(&bytes) {
+            Ok(text) => {
+                for line in text.lines().take(PREVIEW_LINES) {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+            // Cutting the preview short may split a character
+            Err(err) if err.error_len().is_none() => {
+                let text = String::from_utf8_lossy(&bytes[..err.valid_up_to()]);
+                for line in text.lines().take(PREVIEW_LINES) {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+            Err(_) => output.push_str(&hex_dump(&bytes[..bytes.len().min(256)], 0)),
+        }
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+/// Securely deletes the session in `dir`, every file and the minidump of
+/// the session are overwritten with zeros before being removed and the
+/// session is removed from the upload queue. Only sessions within the dump
+/// directory can be deleted
+pub fn delete_session(dir: &Path) -> Result<(), ReviewError> {
+    let dump_dir = dump_dir()?.canonicalize()?;
+    let dir = dir.canonicalize()?;
+
+    let name = match dir.file_name() {
+        Some(name) if dir.parent() == Some(dump_dir.as_path()) => {
+            name.to_string_lossy().into_owned()
+        }
+        _ => return Err(ReviewError::InvalidSession),
+    };
+    if !name.starts_with(SESSION_PREFIX) {
+        return Err(ReviewError::InvalidSession);
+    }
+
+    let current = capture().and_then(|capture| capture.dir.canonicalize().ok());
+    if current.as_deref() == Some(dir.as_path()) {
+        return Err(ReviewError::CurrentSession);
+    }
+
+    let mut files = Vec::new();
+    collect_files(&dir, &dir, &mut files)?;
+    for file in &files {
+        overwrite(&dir.join(&file.path))?;
+    }
+
+    // The minidump of a crashed session is kept outside its directory
+    let minidump = crash::minidump_path(&dump_dir, &name);
+    if minidump.is_file() {
+        overwrite(&minidump)?;
+        std::fs::remove_file(&minidump)?;
+    }
+
+    std::fs::remove_dir_all(&dir)?;
+    upload::dequeue(&name);
+    Ok(())
+}
+
+/// Overwrites the contents of the file with zeros
+fn overwrite(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let mut remaining = file.metadata()?.len();
+    let zeros = [0u8; OVERWRITE_CHUNK];
+
+    while remaining > 0 {
+        let length = remaining.min(OVERWRITE_CHUNK as u64) as usize;
+        file.write_all(&zeros[..length])?;
+        remaining -= length as u64;
+    }
+    file.sync_all()
+}
@@ -0,0 +1,190 @@
+//! Archive of the first few payloads of every distinct packet kind. Samples
+//! are kept regardless of the capture profile, triggers and sampling rules
+//! so every capture has a minimal representative dataset
+
+use super::{
+    anonymize::Anonymization, append_jsonl, privacy::PrivacyCategories, Capture, Direction,
+};
+use crate::{
+    errors::{self, CaptureIoError},
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::{FireFrame, FrameType, Packet},
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, io, sync::Mutex};
+
+/// The name of the samples directory within the session directory
+pub const SAMPLES_DIR: &str = "samples";
+
+/// The file name of the samples index within the samples directory
+pub const SAMPLES_INDEX_FILE: &str = "index.jsonl";
+
+/// Decides which packets are kept as samples
+pub struct Samples {
+    /// Number of samples kept for each component, command and frame type
+    counts: Mutex<HashMap<(u16, u16, u8), u32>>,
+    /// Number of samples kept of each kind
+    limit: u32,
+}
+
+/// Number of samples kept of a kind of packet, persisted by campaigns so
+/// kinds sampled in earlier runs aren't sampled again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleCount {
+    pub component: u16,
+    pub command: u16,
+    pub ty: u8,
+    pub count: u32,
+}
+
+/// Entry in the samples index describing a kept sample
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub(crate) struct SampleRecord {
+    /// Unix timestamp in microseconds the packet was seen
+    time: u64,
+    /// ID of the proxied connection the packet was seen on
+    connection: u32,
+    /// The direction the packet was travelling
+    direction: Direction,
+    component: u16,
+    command: u16,
+    component_name: Option<&'static str>,
+    command_name: Option<&'static str>,
+    ty: FrameType,
+    error: u16,
+    /// Number of the sample for its kind starting at 1
+    number: u32,
+    /// Length of the packet contents in bytes
+    length: usize,
+    /// File within the samples directory holding the raw contents, None
+    /// if anonymization or the privacy categories left the contents out
+    file: Option<String>,
+}
+
+impl Samples {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            counts: Mutex::new(HashMap::new()),
+            limit,
+        }
+    }
+
+    /// Claims a sample for the packet with the provided frame, returns
+    /// the sample number if fewer than the limit have been kept for the
+    /// kind of packet
+    pub fn take(&self, frame: &FireFrame) -> Option<u32> {
+        if self.limit == 0 {
+            return None;
+        }
+
+        let counts = &mut *self.counts.lock().expect("Samples lock poisoned");
+        let count = counts
+            .entry((frame.component, frame.command, frame.ty.into()))
+            .or_default();
+        if *count >= self.limit {
+            return None;
+        }
+        *count += 1;
+        Some(*count)
+    }
+
+    /// Adds the counts of samples kept by earlier runs
+    pub fn restore(&self, kept: &[SampleCount]) {
+        let counts = &mut *self.counts.lock().expect("Samples lock poisoned");
+        for value in kept {
+            *counts
+                .entry((value.component, value.command, value.ty))
+                .or_default() += value.count;
+        }
+    }
+
+    /// Obtains the number of samples kept of each kind
+    pub fn counts(&self) -> Vec<SampleCount> {
+        let counts = self.counts.lock().expect("Samples lock poisoned");
+        let mut counts: Vec<SampleCount> = counts
+            .iter()
+            .map(|(&(component, command, ty), &count)| SampleCount {
+                component,
+                command,
+                ty,
+                count,
+            })
+            .collect();
+        counts.sort_by_key(|value| (value.component, value.command, value.ty));
+        counts
+    }
+}
+
+impl Capture {
+    /// Writes the contents of the packet to the samples directory and
+    /// adds it to the samples index
+    ///
+    /// `number` The sample number claimed through [Samples::take]
+    pub fn record_sample(
+        &self,
+        time: u64,
+        connection: u32,
+        direction: Direction,
+        packet: &Packet,
+        number: u32,
+    ) {
+        if let Err(err) = self.write_sample(time, connection, direction, packet, number) {
+            errors::report(CaptureIoError::Write {
+                what: "packet sample",
+                source: err,
+            });
+        }
+    }
+
+    fn write_sample(
+        &self,
+        time: u64,
+        connection: u32,
+        direction: Direction,
+        packet: &Packet,
+        number: u32,
+    ) -> io::Result<()> {
+        let dir = self.dir.join(SAMPLES_DIR);
+        std::fs::create_dir_all(&dir)?;
+
+        let frame = &packet.frame;
+        let ty: u8 = frame.ty.into();
+
+        // Samples skip the capture profile but not anonymization or
+        // the privacy categories
+        let anonymized = Anonymization::current()
+            .apply(packet)
+            .filter(|_| PrivacyCategories::current().packet_contents(frame));
+        let file = match anonymized {
+            Some(packet) => {
+                let name = format!(
+                    "{:04x}-{:04x}-{}-{}.bin",
+                    frame.component, frame.command, ty, number
+                );
+                std::fs::write(dir.join(&name), &packet.contents)?;
+                Some(name)
+            }
+            None => None,
+        };
+
+        let key = component_key(frame.component, frame.command);
+        let record = SampleRecord {
+            time,
+            connection,
+            direction,
+            component: frame.component,
+            command: frame.command,
+            component_name: get_component_name(frame.component),
+            command_name: get_command_name(key, matches!(frame.ty, FrameType::Notify)),
+            ty: frame.ty,
+            error: frame.error,
+            number,
+            length: packet.contents.len(),
+            file,
+        };
+        append_jsonl(&dir.join(SAMPLES_INDEX_FILE), &record)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::servers::{
+    components::util,
+    packet::{FireFrame, FrameType},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Rule for sampling high volume packets, only one in every `keep_one_in`
+/// matching packets is captured. Sampled packets are still forwarded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SamplingRule {
+    /// The component to match
+    pub component: u16,
+    /// The command to match, None to match all commands
+    pub command: Option<u16>,
+    /// Keep one in every N matching packets
+    pub keep_one_in: u64,
+}
+
+impl SamplingRule {
+    /// The default sampling rules, keeps 1 in 50 pings
+    pub fn defaults() -> Vec<SamplingRule> {
+        vec![SamplingRule {
+            component: util::COMPONENT,
+            command: Some(util::PING),
+            keep_one_in: 50,
+        }]
+    }
+
+    fn matches(&self, frame: &FireFrame) -> bool {
+        self.component == frame.component
+            && self
+                .command
+                .map_or(true, |command| command == frame.command)
+    }
+}
+
+/// Sampling statistics for a rule, recorded in the manifest
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SamplingStats {
+    /// The rule the stats are for
+    pub rule: SamplingRule,
+    /// The number of packets that matched the rule
+    pub seen: u64,
+    /// The number of matching packets that were captured
+    pub kept: u64,
+}
+
+/// Decision made by the sampler for a packet
+pub enum Sample {
+    /// Packet didn't match any rules
+    Unsampled,
+    /// Packet matched a rule and should be captured
+    Kept,
+    /// Packet matched a rule and should not be captured
+    Dropped,
+}
+
+/// Applies the sampling rules to captured packets
+pub struct Sampler {
+    /// The rules and their counters
+    rules: Vec<(SamplingRule, RuleCounters)>,
+}
+
+/// Counters for the packets matching a rule
+#[derive(Default)]
+struct RuleCounters {
+    seen: AtomicU64,
+    kept: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(rules: Vec<SamplingRule>) -> Self {
+        Self {
+            rules: rules
+                .into_iter()
+                .map(|rule| (rule, RuleCounters::default()))
+                .collect(),
+        }
+    }
+
+    /// Decides whether the packet with the provided frame should be captured
+    pub fn sample(&self, frame: &FireFrame) -> Sample {
+        let Some((rule, counters)) = self.rules.iter().find(|(rule, _)| rule.matches(frame)) else {
+            return Sample::Unsampled;
+        };
+
+        let index = counters.seen.fetch_add(1, Ordering::AcqRel);
+
+        // Requests and responses are sampled by sequence number so that
+        // kept requests also keep their responses
+        let position = match frame.ty {
+            FrameType::Request | FrameType::Response | FrameType::Error => frame.seq as u64,
+            FrameType::Notify | FrameType::Unknown(_) => index,
+        };
+
+        if position % rule.keep_one_in.max(1) == 0 {
+            counters.kept.fetch_add(1, Ordering::AcqRel);
+            Sample::Kept
+        } else {
+            Sample::Dropped
+        }
+    }
+
+    /// Creates the current stats for each of the rules
+    pub fn stats(&self) -> Vec<SamplingStats> {
+        self.rules
+            .iter()
+            .map(|(rule, counters)| SamplingStats {
+                rule: rule.clone(),
+                seen: counters.seen.load(Ordering::Acquire),
+                kept: counters.kept.load(Ordering::Acquire),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,128 @@
+//! JSON Schema for the files written to capture sessions, generated from
+//! the record types so tools outside the plugin can validate and parse
+//! captures. Every JSON lines file starts with a header line described by
+//! the header schema, the schema for the file describes the other lines
+
+use super::{
+    collection::{Marker, MARKERS_FILE},
+    decoders::{
+        entitlements::{EntitlementListRecord, ENTITLEMENTS_FILE},
+        framing::{FramingRecord, FRAMING_FILE},
+        interesting::{InterestingRecord, INTERESTING_FILE},
+        settings::{DeleteRecord, SettingsRecord, SETTINGS_FILE},
+        social::{AssociationListRecord, MessageRecord, ASSOCIATIONS_FILE, MESSAGES_FILE},
+        unknown_tdf::{UnknownTdfRecord, UNKNOWN_TDF_FILE},
+    },
+    http_log::{HttpRecord, HTTP_FILE},
+    integrity::{Integrity, INTEGRITY_FILE},
+    manifest::Manifest,
+    packet_log::{PacketRecord, PACKETS_FILE},
+    samples::{SampleRecord, SAMPLES_DIR, SAMPLES_INDEX_FILE},
+    websocket_log::{WebSocketRecord, WEBSOCKET_FILE},
+    JsonlHeader, MANIFEST_FILE,
+};
+use crate::servers::breakpoints::{EditRecord, EDITS_FILE};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{RootSchema, SchemaObject},
+    schema_for,
+};
+use serde_json::{Map, Value};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the schema for the header line of the JSON lines files
+pub const JSONL_HEADER_SCHEMA: &str = "jsonl-header";
+
+/// Schema for a file within the session directory
+pub struct FileSchema {
+    /// Path of the file relative to the session directory using `/`
+    pub path: String,
+    pub schema: RootSchema,
+}
+
+impl FileSchema {
+    fn new(path: &str, schema: RootSchema) -> Self {
+        Self {
+            path: path.to_string(),
+            schema,
+        }
+    }
+
+    /// Name of the file the schema is written to
+    pub fn file_name(&self) -> String {
+        format!("{}.schema.json", self.path.replace('/', "-"))
+    }
+}
+
+/// Generates the schema for every structured file in a session
+pub fn schemas() -> Vec<FileSchema> {
+    vec![
+        FileSchema::new(MANIFEST_FILE, schema_for!(Manifest)),
+        FileSchema::new(INTEGRITY_FILE, schema_for!(Integrity)),
+        FileSchema::new(JSONL_HEADER_SCHEMA, schema_for!(JsonlHeader)),
+        FileSchema::new(PACKETS_FILE, schema_for!(PacketRecord)),
+        FileSchema::new(HTTP_FILE, schema_for!(HttpRecord)),
+        FileSchema::new(WEBSOCKET_FILE, schema_for!(WebSocketRecord)),
+        FileSchema::new(MARKERS_FILE, schema_for!(Marker)),
+        FileSchema::new(EDITS_FILE, schema_for!(EditRecord)),
+        FileSchema::new(
+            &format!("{}/{}", SAMPLES_DIR, SAMPLES_INDEX_FILE),
+            schema_for!(SampleRecord),
+        ),
+        FileSchema::new(ASSOCIATIONS_FILE, schema_for!(AssociationListRecord)),
+        FileSchema::new(MESSAGES_FILE, schema_for!(MessageRecord)),
+        FileSchema::new(ENTITLEMENTS_FILE, schema_for!(EntitlementListRecord)),
+        FileSchema::new(SETTINGS_FILE, settings_schema()),
+        FileSchema::new(UNKNOWN_TDF_FILE, schema_for!(UnknownTdfRecord)),
+        FileSchema::new(FRAMING_FILE, schema_for!(FramingRecord)),
+        FileSchema::new(INTERESTING_FILE, schema_for!(InterestingRecord)),
+    ]
+}
+
+/// The settings file holds both saved and deleted keys, deleted keys
+/// would also match the saved key schema if extra fields were allowed
+/// so the lines are described with any of rather than one of
+fn settings_schema() -> RootSchema {
+    let mut gen = SchemaGenerator::default();
+    let mut schema = SchemaObject::default();
+    schema.subschemas().any_of = Some(vec![
+        gen.subschema_for::<SettingsRecord<'static>>(),
+        gen.subschema_for::<DeleteRecord<'static>>(),
+    ]);
+    schema.metadata().title = Some("SettingsLine".to_string());
+
+    RootSchema {
+        meta_schema: gen.settings().meta_schema.clone(),
+        definitions: gen.take_definitions(),
+        schema,
+    }
+}
+
+/// Combines every schema into a single object keyed by the file path
+pub fn schemas_json() -> Value {
+    let schemas: Map<String, Value> = schemas()
+        .into_iter()
+        .map(|value| {
+            let schema = serde_json::to_value(&value.schema).unwrap_or(Value::Null);
+            (value.path, schema)
+        })
+        .collect();
+    Value::Object(schemas)
+}
+
+/// Writes every schema to its own file within the `output` directory,
+/// returns the paths of the written files
+pub fn write_schemas(output: &Path) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(output)?;
+
+    let mut paths = Vec::new();
+    for value in schemas() {
+        let path = output.join(value.file_name());
+        std::fs::write(&path, serde_json::to_vec_pretty(&value.schema)?)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
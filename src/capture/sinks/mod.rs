@@ -0,0 +1,39 @@
+use super::{http_log::HttpRecord, manifest::Manifest, packet_log::PacketRecord};
+use crate::servers::packet::Packet;
+use std::path::Path;
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Additional storage backend that receives everything written to the
+/// capture session files
+pub trait CaptureSink: Send {
+    /// Name of the sink used when reporting failures
+    fn name(&self) -> &'static str;
+
+    /// Stores a packet that passed through the proxy
+    fn packet(&mut self, record: &PacketRecord, packet: &Packet);
+
+    /// Stores a proxied HTTP exchange
+    fn http(&mut self, record: &HttpRecord);
+
+    /// Stores the updated session manifest
+    fn manifest(&mut self, manifest: &Manifest);
+}
+
+/// Creates the sinks enabled by the config writing into the
+/// provided capture directory
+#[allow(unused_variables, unused_mut)]
+pub fn create_sinks(dir: &Path) -> Vec<Box<dyn CaptureSink>> {
+    let mut sinks: Vec<Box<dyn CaptureSink>> = Vec::new();
+
+    #[cfg(feature = "sqlite")]
+    if crate::config::config().sqlite {
+        match sqlite::SqliteSink::open(dir) {
+            Ok(sink) => sinks.push(Box::new(sink)),
+            Err(err) => log::error!("Failed to open SQLite capture database: {}", err),
+        }
+    }
+
+    sinks
+}
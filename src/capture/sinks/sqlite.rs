@@ -0,0 +1,135 @@
+use super::CaptureSink;
+use crate::{
+    capture::{http_log::HttpRecord, manifest::Manifest, packet_log::PacketRecord},
+    servers::packet::Packet,
+};
+use log::error;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+/// The file name of the database within the session directory
+const DATABASE_FILE: &str = "capture.sqlite";
+
+const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+
+CREATE TABLE IF NOT EXISTS session (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    manifest TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS packets (
+    idx INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    connection INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    component INTEGER NOT NULL,
+    command INTEGER NOT NULL,
+    error INTEGER NOT NULL,
+    ty INTEGER NOT NULL,
+    options INTEGER NOT NULL,
+    seq INTEGER NOT NULL,
+    context INTEGER,
+    length INTEGER NOT NULL,
+    contents BLOB
+);
+CREATE INDEX IF NOT EXISTS packets_component_command ON packets (component, command);
+CREATE INDEX IF NOT EXISTS packets_time ON packets (time);
+
+CREATE TABLE IF NOT EXISTS http (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time INTEGER NOT NULL,
+    method TEXT NOT NULL,
+    url TEXT NOT NULL,
+    status INTEGER,
+    duration_ms REAL NOT NULL,
+    length INTEGER NOT NULL,
+    body TEXT
+);
+CREATE INDEX IF NOT EXISTS http_time ON http (time);
+";
+
+/// Sink writing the capture into a SQLite database so that large
+/// captures can be queried with SQL
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    /// Opens the database within the provided capture directory
+    pub fn open(dir: &Path) -> rusqlite::Result<Self> {
+        let connection = Connection::open(dir.join(DATABASE_FILE))?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+}
+
+impl CaptureSink for SqliteSink {
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn packet(&mut self, record: &PacketRecord, packet: &Packet) {
+        let contents = record.contents.as_ref().map(|_| packet.contents.as_ref());
+        let direction = format!("{:?}", record.direction);
+
+        if let Err(err) = self.connection.execute(
+            "INSERT OR REPLACE INTO packets (idx, time, connection, direction, component, \
+             command, error, ty, options, seq, context, length, contents) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                record.index as i64,
+                record.time as i64,
+                record.connection,
+                direction,
+                record.component,
+                record.command,
+                record.error,
+                record.ty,
+                record.options,
+                record.seq,
+                record.context,
+                record.length as i64,
+                contents,
+            ],
+        ) {
+            error!("Failed to write packet to capture database: {}", err);
+        }
+    }
+
+    fn http(&mut self, record: &HttpRecord) {
+        if let Err(err) = self.connection.execute(
+            "INSERT INTO http (time, method, url, status, duration_ms, length, body) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                record.time as i64,
+                record.method,
+                record.url,
+                record.status,
+                record.duration_ms,
+                record.length as i64,
+                record.body,
+            ],
+        ) {
+            error!("Failed to write HTTP exchange to capture database: {}", err);
+        }
+    }
+
+    fn manifest(&mut self, manifest: &Manifest) {
+        let manifest = match serde_json::to_string(manifest) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Failed to encode manifest for capture database: {}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = self.connection.execute(
+            "INSERT OR REPLACE INTO session (id, manifest) VALUES (1, ?1)",
+            params![manifest],
+        ) {
+            error!("Failed to write manifest to capture database: {}", err);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Exports a capture session as a self-contained HTML timeline showing
+//! requests, responses, notifications and HTTP exchanges on a time axis
+//! so captures can be skimmed visually
+
+use super::{
+    http_log::read_http_log,
+    packet_log::{pair_packets, read_packet_log},
+};
+use crate::servers::{
+    components::{component_key, get_command_name, get_component_name},
+    packet::FrameType,
+};
+use serde::Serialize;
+use std::{io, path::Path};
+
+/// The page template, the timeline data replaces the placeholder
+const TEMPLATE: &str = include_str!("timeline.html");
+
+/// Placeholder within the template replaced with the timeline data
+const DATA_PLACEHOLDER: &str = "/*TIMELINE_DATA*/null";
+
+/// Timeline data embedded in the page
+#[derive(Serialize)]
+struct Timeline {
+    /// Unix timestamp in microseconds of the first event
+    start: u64,
+    events: Vec<TimelineEvent>,
+}
+
+/// Single event on the timeline
+#[derive(Serialize)]
+struct TimelineEvent {
+    /// Microseconds since the start of the timeline
+    time: u64,
+    /// Microseconds until the response arrived, None for events
+    /// without a duration
+    duration: Option<u64>,
+    /// The kind of event ("request", "error", "notify", "http")
+    kind: &'static str,
+    /// Lane the event is drawn in, the component name for packets
+    lane: String,
+    /// Component ID used for coloring, None for HTTP exchanges
+    component: Option<u16>,
+    /// Label shown when hovering the event
+    label: String,
+    /// ID of the proxied connection, None for HTTP exchanges
+    connection: Option<u32>,
+    /// Index of the request within the packet log
+    index: Option<u64>,
+}
+
+/// Writes the timeline for the capture session in `dir` to the `output`
+/// HTML file, returns the number of events on the timeline
+pub fn export_timeline(dir: &Path, output: &Path) -> io::Result<usize> {
+    let packets = read_packet_log(dir)?;
+
+    // Sessions without any HTTP exchanges won't have a HTTP log
+    let http = match read_http_log(dir) {
+        Ok(value) => value,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err),
+    };
+
+    let start = packets
+        .iter()
+        .map(|record| record.time)
+        .chain(http.iter().map(|record| record.time))
+        .min()
+        .unwrap_or_default();
+
+    let lane = |component: u16| match get_component_name(component) {
+        Some(name) => name.to_string(),
+        None => format!("{:#06x}", component),
+    };
+    let label = |component: u16, command: u16, notify: bool| {
+        let key = component_key(component, command);
+        match (get_component_name(component), get_command_name(key, notify)) {
+            (Some(component), Some(command)) => format!("{}.{}", component, command),
+            _ => format!("{:#06x}->{:#06x}", component, command),
+        }
+    };
+
+    let mut events = Vec::new();
+
+    // Requests are drawn as bars lasting until their response
+    for pair in pair_packets(&packets) {
+        let request = pair.request;
+        let (kind, duration, suffix) = match pair.response {
+            Some(response) if FrameType::from(response.ty) == FrameType::Error => (
+                "error",
+                Some(response.time.saturating_sub(request.time)),
+                format!(" (error {:#x})", response.error),
+            ),
+            Some(response) => (
+                "request",
+                Some(response.time.saturating_sub(request.time)),
+                String::new(),
+            ),
+            None => ("request", None, " (no response)".to_string()),
+        };
+
+        events.push(TimelineEvent {
+            time: request.time.saturating_sub(start),
+            duration,
+            kind,
+            lane: lane(request.component),
+            component: Some(request.component),
+            label: format!(
+                "{}{}",
+                label(request.component, request.command, false),
+                suffix
+            ),
+            connection: Some(request.connection),
+            index: Some(request.index),
+        });
+    }
+
+    for record in packets
+        .iter()
+        .filter(|record| FrameType::from(record.ty) == FrameType::Notify)
+    {
+        events.push(TimelineEvent {
+            time: record.time.saturating_sub(start),
+            duration: None,
+            kind: "notify",
+            lane: lane(record.component),
+            component: Some(record.component),
+            label: label(record.component, record.command, true),
+            connection: Some(record.connection),
+            index: Some(record.index),
+        });
+    }
+
+    for record in &http {
+        let status = match record.status {
+            Some(status) => status.to_string(),
+            None => "failed".to_string(),
+        };
+        events.push(TimelineEvent {
+            time: record.time.saturating_sub(start),
+            duration: Some((record.duration_ms * 1000.0) as u64),
+            kind: "http",
+            lane: "HTTP".to_string(),
+            component: None,
+            label: format!("{} {} ({})", record.method, record.url, status),
+            connection: None,
+            index: None,
+        });
+    }
+
+    events.sort_by_key(|event| event.time);
+    let count = events.len();
+
+    let data = serde_json::to_string(&Timeline { start, events })?;
+    // Closing tags within strings would end the script element early
+    let data = data.replace("</", "<\\/");
+
+    std::fs::write(output, TEMPLATE.replace(DATA_PLACEHOLDER, &data))?;
+    Ok(count)
+}
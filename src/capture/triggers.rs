@@ -0,0 +1,112 @@
+use super::clock::now_micros;
+use crate::servers::packet::FireFrame;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Rules deciding when recording starts and stops without the
+/// user having to toggle it
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggerConfig {
+    /// Recording only starts once a packet matching one of these is
+    /// seen, recording starts immediately when empty
+    pub start_on: Vec<PacketMatcher>,
+    /// Recording stops once no packets have been seen for this many
+    /// minutes, restarting on the next start trigger or on the next
+    /// packet when there are no start triggers
+    pub idle_stop_minutes: Option<u64>,
+}
+
+/// Matches packets by their component and optionally command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PacketMatcher {
+    pub component: u16,
+    #[serde(default)]
+    pub command: Option<u16>,
+}
+
+impl PacketMatcher {
+    fn matches(&self, frame: &FireFrame) -> bool {
+        self.component == frame.component
+            && (self.command.is_none() || self.command == Some(frame.command))
+    }
+}
+
+/// Change in the recording state caused by a trigger
+#[derive(Debug, Clone, Copy)]
+pub enum TriggerChange {
+    Started,
+    StoppedIdle,
+    /// Recording resumed with the packet ending an idle period, only
+    /// happens when there are no start triggers
+    ResumedIdle {
+        /// How long no packets were seen for in seconds
+        idle_seconds: u64,
+    },
+}
+
+/// Recording state driven by the trigger rules
+pub struct Triggers {
+    config: TriggerConfig,
+    /// Whether packets are currently being recorded
+    recording: AtomicBool,
+    /// Session clock time in microseconds of the last packet seen
+    last_activity: AtomicU64,
+}
+
+impl Triggers {
+    pub fn new(config: TriggerConfig) -> Self {
+        let recording = config.start_on.is_empty();
+        Self {
+            config,
+            recording: AtomicBool::new(recording),
+            last_activity: AtomicU64::new(now_micros()),
+        }
+    }
+
+    /// Whether packets are currently being recorded
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Acquire)
+    }
+
+    /// Manually starts or stops recording
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, Ordering::Release);
+    }
+
+    /// Evaluates the triggers for a packet passing through the proxy,
+    /// returns the change in recording state if there was one
+    pub fn evaluate(&self, frame: &FireFrame) -> Option<TriggerChange> {
+        let now = now_micros();
+        let last_activity = self.last_activity.swap(now, Ordering::AcqRel);
+        let mut change = None;
+
+        // Idle time is only known once the next packet arrives
+        if let Some(minutes) = self.config.idle_stop_minutes {
+            let idle = now.saturating_sub(last_activity);
+            if idle > minutes * 60 * 1_000_000 && self.recording.swap(false, Ordering::AcqRel) {
+                // Nothing else would start recording again so this packet does
+                if self.config.start_on.is_empty() {
+                    self.set_recording(true);
+                    return Some(TriggerChange::ResumedIdle {
+                        idle_seconds: idle / 1_000_000,
+                    });
+                }
+                change = Some(TriggerChange::StoppedIdle);
+            }
+        }
+
+        if !self.is_recording()
+            && self
+                .config
+                .start_on
+                .iter()
+                .any(|matcher| matcher.matches(frame))
+        {
+            self.set_recording(true);
+            change = Some(TriggerChange::Started);
+        }
+
+        change
+    }
+}
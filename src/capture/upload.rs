@@ -0,0 +1,520 @@
+//! Uploads finished capture sessions to the Pocket Relay collection
+//! endpoint. Uploads require consent and an upload key issued by the
+//! Pocket Relay project so the endpoint only accepts data from known
+//! contributors. Sessions that fail to upload are queued and retried on
+//! the next launch. Files are split into segments stored by content hash
+//! so retries and repetitive sessions only send segments the endpoint
+//! doesn't have yet
+
+use super::{
+    dump_dir,
+    integrity::{write_integrity, FileHash},
+    now_millis, recover_unfinished,
+    value::to_hex,
+};
+use crate::{
+    config::config,
+    consent::{self, ActiveFeature, ConsentError},
+    metrics::{self, ErrorCategory},
+    tasks,
+};
+use log::{debug, error, warn};
+use reqwest::{header::AUTHORIZATION, Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::Mutex,
+};
+use thiserror::Error;
+
+/// Header carrying the SHA-256 of uploaded segment contents
+const SHA256_HEADER: &str = "X-Content-SHA256";
+
+/// Size of each uploaded segment in bytes
+const SEGMENT_SIZE: usize = 256 * 1024;
+
+/// The file name of the upload queue within the dump directory
+const QUEUE_FILE: &str = "upload-queue.json";
+
+/// The file name of the marker written to uploaded session directories
+pub const UPLOADED_FILE: &str = "uploaded.json";
+
+/// Guards reading and writing the upload queue file
+static QUEUE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Settings for uploading captures
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// Whether sessions are uploaded once they finish, uploads also
+    /// require consent
+    pub enabled: bool,
+    /// Base URL of the collection endpoint
+    pub endpoint: Option<String>,
+    /// Upload key issued by the Pocket Relay project, uploads are refused
+    /// without one
+    pub key: Option<String>,
+}
+
+/// Errors that can occur while uploading
+#[derive(Debug, Error)]
+pub enum UploadError {
+    #[error(transparent)]
+    Consent(#[from] ConsentError),
+    #[error("Uploads are disabled in the config")]
+    Disabled,
+    #[error("No upload endpoint is configured")]
+    MissingEndpoint,
+    #[error("No upload key is configured, request one from the Pocket Relay project")]
+    MissingKey,
+    #[error("The upload key was rejected by the collection endpoint")]
+    KeyRejected,
+    #[error("Collection endpoint responded with {0}")]
+    Status(StatusCode),
+    #[error(transparent)]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+pub type UploadResult<T> = Result<T, UploadError>;
+
+impl UploadError {
+    /// Whether the upload may succeed if tried again later
+    fn is_retryable(&self) -> bool {
+        match self {
+            UploadError::Http(_) => true,
+            UploadError::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Marker recording when a session was uploaded
+#[derive(Serialize)]
+struct UploadedMarker {
+    /// Unix timestamp in milliseconds the upload completed
+    uploaded_at: u64,
+}
+
+/// Session waiting to be uploaded
+#[derive(Debug, Serialize, Deserialize)]
+struct QueuedUpload {
+    /// Name of the session directory
+    session: String,
+    /// Unix timestamp in milliseconds the session was queued
+    queued_at: u64,
+    /// Number of failed attempts since the session was queued
+    attempts: u32,
+}
+
+/// Endpoint and key checked before uploading
+struct Target<'a> {
+    endpoint: &'a str,
+    key: &'a str,
+}
+
+impl Target<'_> {
+    /// Checks that uploads are allowed and configured
+    fn resolve(config: &UploadConfig) -> UploadResult<Target> {
+        if !config.enabled {
+            return Err(UploadError::Disabled);
+        }
+        consent::require(ActiveFeature::Upload)?;
+
+        let endpoint = config
+            .endpoint
+            .as_deref()
+            .filter(|value| !value.is_empty())
+            .ok_or(UploadError::MissingEndpoint)?;
+        let key = config
+            .key
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .ok_or(UploadError::MissingKey)?;
+
+        Ok(Target {
+            endpoint: endpoint.trim_end_matches('/'),
+            key,
+        })
+    }
+
+    /// URL of a path for the session on the endpoint
+    fn url(&self, session: &str, path: &str) -> String {
+        format!("{}/sessions/{}/{}", self.endpoint, session, path)
+    }
+
+    /// Sends the request with the upload key, mapping rejected keys
+    /// and unsuccessful statuses to errors
+    async fn send(&self, request: RequestBuilder) -> UploadResult<reqwest::Response> {
+        let response = request
+            .header(AUTHORIZATION, format!("Bearer {}", self.key))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(UploadError::KeyRejected),
+            status if !status.is_success() => Err(UploadError::Status(status)),
+            _ => Ok(response),
+        }
+    }
+
+    /// Sends the segment hashes of the session to the endpoint, returning
+    /// the hashes of the segments it doesn't have yet
+    async fn missing_segments(
+        &self,
+        client: &Client,
+        session: &str,
+        segments: &[Segment],
+    ) -> UploadResult<HashSet<String>> {
+        let response = self
+            .send(
+                client
+                    .post(self.url(session, "segments"))
+                    .json(&SegmentManifest { segments }),
+            )
+            .await?;
+        let missing: MissingSegments = response.json().await?;
+        Ok(missing.missing.into_iter().collect())
+    }
+
+    /// Uploads the contents of the segment, segments are stored by their
+    /// hash so they can be shared between sessions
+    async fn upload_segment(
+        &self,
+        client: &Client,
+        dir: &Path,
+        segment: &Segment,
+    ) -> UploadResult<()> {
+        let mut reader = File::open(dir.join(&segment.path))?;
+        reader.seek(SeekFrom::Start(segment.offset))?;
+        let mut contents = vec![0u8; segment.length as usize];
+        reader.read_exact(&mut contents)?;
+
+        self.send(
+            client
+                .put(format!("{}/segments/{}", self.endpoint, segment.sha256))
+                .header(SHA256_HEADER, &segment.sha256)
+                .body(contents),
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// Fixed size piece of a session file identified by its content hash
+#[derive(Debug, Serialize)]
+struct Segment {
+    /// Path of the file relative to the session directory
+    path: String,
+    /// Offset of the segment within the file
+    offset: u64,
+    /// Length of the segment in bytes
+    length: u64,
+    /// SHA-256 of the segment contents as lowercase hex
+    sha256: String,
+}
+
+/// Segments of a session sent to the endpoint before uploading
+#[derive(Serialize)]
+struct SegmentManifest<'a> {
+    segments: &'a [Segment],
+}
+
+/// Response to a [SegmentManifest]
+#[derive(Deserialize)]
+struct MissingSegments {
+    /// Hashes of the segments the endpoint doesn't have
+    missing: Vec<String>,
+}
+
+/// Splits the files into segments and hashes their contents
+fn hash_segments(dir: &Path, files: &[FileHash]) -> io::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut buffer = vec![0u8; SEGMENT_SIZE];
+
+    for file in files {
+        let mut reader = File::open(dir.join(&file.path))?;
+        let mut offset = 0;
+
+        loop {
+            let length = read_segment(&mut reader, &mut buffer)?;
+            if length == 0 {
+                break;
+            }
+
+            segments.push(Segment {
+                path: file.path.clone(),
+                offset,
+                length: length as u64,
+                sha256: to_hex(&Sha256::digest(&buffer[..length])),
+            });
+            offset += length as u64;
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Fills the buffer from the reader, returning the number of bytes read
+/// which is only less than the buffer length at the end of the file
+fn read_segment(reader: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            count => filled += count,
+        }
+    }
+    Ok(filled)
+}
+
+/// Uploads the session in `dir` if uploads are enabled, sessions that
+/// fail to upload are queued to be retried on the next launch
+pub async fn upload_finished(dir: &Path) {
+    match upload_session(dir).await {
+        Ok(()) => {
+            debug!("Uploaded capture session {}", dir.display());
+        }
+        // Uploads are opt-in so not uploading isn't an error
+        Err(UploadError::Disabled) => {}
+        Err(err) if err.is_retryable() => {
+            warn!(
+                "Failed to upload capture session, queued for retry: {}",
+                err
+            );
+            if let Some(session) = dir.file_name() {
+                enqueue(&session.to_string_lossy());
+            }
+        }
+        Err(err) => {
+            error!("Failed to upload capture session: {}", err);
+            metrics::count_error(ErrorCategory::Upload);
+        }
+    }
+}
+
+/// Queues the finished session in `dir` to be uploaded by the retry
+/// queue. Nothing is queued if uploads are disabled or the session was
+/// already uploaded
+pub fn enqueue_finished(dir: &Path) {
+    if !config().upload.enabled || is_uploaded(dir) {
+        return;
+    }
+    if let Some(session) = dir.file_name() {
+        enqueue(&session.to_string_lossy());
+    }
+}
+
+/// Starts retrying the uploads queued by earlier launches, sessions that
+/// an earlier launch left unfinished are finished and queued first
+pub fn start_queue() {
+    tasks::spawn("upload-queue", async {
+        let recovered = tokio::task::spawn_blocking(|| {
+            dump_dir()
+                .map(|root| recover_unfinished(&root))
+                .unwrap_or_default()
+        })
+        .await
+        .unwrap_or_default();
+        for dir in &recovered {
+            enqueue_finished(dir);
+        }
+        retry_queued().await;
+    });
+}
+
+async fn retry_queued() {
+    let queued = read_queue();
+    if queued.is_empty() {
+        return;
+    }
+
+    let root = match dump_dir() {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Not retrying {} queued uploads: {}", queued.len(), err);
+            return;
+        }
+    };
+
+    // Queued sessions are kept until uploads are allowed again
+    if let Err(err) = Target::resolve(&config().upload) {
+        debug!("Not retrying {} queued uploads: {}", queued.len(), err);
+        return;
+    }
+
+    for upload in queued {
+        let dir = root.join(&upload.session);
+        if !dir.is_dir() {
+            debug!("Queued session {} no longer exists", upload.session);
+            update_queue(|queue| queue.retain(|value| value.session != upload.session));
+            continue;
+        }
+
+        match upload_session(&dir).await {
+            Ok(()) => {
+                debug!("Uploaded queued capture session {}", upload.session);
+                update_queue(|queue| queue.retain(|value| value.session != upload.session));
+            }
+            Err(err) if err.is_retryable() => {
+                warn!(
+                    "Failed to upload queued session {}, will retry next launch: {}",
+                    upload.session, err
+                );
+                update_queue(|queue| {
+                    if let Some(value) = queue
+                        .iter_mut()
+                        .find(|value| value.session == upload.session)
+                    {
+                        value.attempts += 1;
+                    }
+                });
+                // The endpoint is likely unreachable for the other sessions too
+                break;
+            }
+            Err(err) => {
+                error!(
+                    "Failed to upload queued session {}, removing it from the queue: {}",
+                    upload.session, err
+                );
+                metrics::count_error(ErrorCategory::Upload);
+                update_queue(|queue| queue.retain(|value| value.session != upload.session));
+            }
+        }
+    }
+}
+
+/// Adds the session to the upload queue
+fn enqueue(session: &str) {
+    update_queue(|queue| {
+        if queue.iter().any(|value| value.session == session) {
+            return;
+        }
+        queue.push(QueuedUpload {
+            session: session.to_string(),
+            queued_at: now_millis(),
+            attempts: 0,
+        });
+    });
+}
+
+/// Whether the session is waiting in the upload queue
+pub fn is_queued(session: &str) -> bool {
+    read_queue().iter().any(|value| value.session == session)
+}
+
+/// Removes the session from the upload queue
+pub fn dequeue(session: &str) {
+    update_queue(|queue| queue.retain(|value| value.session != session));
+}
+
+fn read_queue() -> Vec<QueuedUpload> {
+    let _guard = QUEUE_LOCK.lock().expect("Upload queue lock poisoned");
+    load_queue()
+}
+
+/// Applies the update to the upload queue and writes it back
+fn update_queue<F>(update: F)
+where
+    F: FnOnce(&mut Vec<QueuedUpload>),
+{
+    let _guard = QUEUE_LOCK.lock().expect("Upload queue lock poisoned");
+    let mut queue = load_queue();
+    update(&mut queue);
+
+    let result = dump_dir().and_then(|root| {
+        let path = root.join(QUEUE_FILE);
+        if queue.is_empty() {
+            std::fs::remove_file(&path).or_else(|err| match err.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(err),
+            })
+        } else {
+            serde_json::to_vec_pretty(&queue)
+                .map_err(io::Error::from)
+                .and_then(|bytes| std::fs::write(&path, bytes))
+        }
+    });
+
+    if let Err(err) = result {
+        error!("Failed to write upload queue: {}", err);
+    }
+}
+
+fn load_queue() -> Vec<QueuedUpload> {
+    match dump_dir().and_then(|root| std::fs::read(root.join(QUEUE_FILE))) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+            error!("Failed to parse upload queue: {}", err);
+            Vec::new()
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => {
+            error!("Failed to read upload queue: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Uploads the session in `dir`. The segment hashes of every file are
+/// sent first and only the segments the endpoint doesn't already have
+/// are uploaded, followed by the integrity file which tells the endpoint
+/// the session is complete
+pub async fn upload_session(dir: &Path) -> UploadResult<()> {
+    let config = &config().upload;
+    let target = Target::resolve(config)?;
+
+    let session = dir
+        .file_name()
+        .map(|value| value.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let integrity = write_integrity(dir)?;
+    let segments = hash_segments(dir, &integrity.files)?;
+    let client = Client::new();
+
+    let mut missing = target
+        .missing_segments(&client, &session, &segments)
+        .await?;
+    debug!(
+        "Uploading {} of {} segments for {}",
+        missing.len(),
+        segments.len(),
+        session
+    );
+
+    for segment in &segments {
+        // Removed once uploaded as repeated contents share a segment
+        if missing.remove(&segment.sha256) {
+            target.upload_segment(&client, dir, segment).await?;
+        }
+    }
+
+    target
+        .send(
+            client
+                .post(target.url(&session, "complete"))
+                .json(&integrity),
+        )
+        .await?;
+
+    let marker = UploadedMarker {
+        uploaded_at: now_millis(),
+    };
+    let bytes = serde_json::to_vec(&marker).map_err(io::Error::from)?;
+    std::fs::write(dir.join(UPLOADED_FILE), bytes)?;
+
+    Ok(())
+}
+
+/// Whether the session in `dir` has been uploaded
+pub fn is_uploaded(dir: &Path) -> bool {
+    dir.join(UPLOADED_FILE).is_file()
+}
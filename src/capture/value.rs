@@ -0,0 +1,564 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt::Write;
+use thiserror::Error;
+
+/// Key used by tagged unions that don't have a value set
+const UNION_UNSET: u8 = 0x7F;
+
+/// Maximum nesting of values within groups, lists, maps and unions, deeper
+/// contents are rejected so malformed packets can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+/// Generic decoded TDF value, used by the capture decoders to inspect
+/// packet contents without needing a type for every packet. The serde
+/// representation keeps the value types so it can be encoded back
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TdfValue {
+    VarInt(i128),
+    String(String),
+    Blob(Vec<u8>),
+    Group(Vec<Field>),
+    List(Vec<TdfValue>),
+    Map(Vec<(TdfValue, TdfValue)>),
+    Union { key: u8, value: Option<Box<Field>> },
+    VarIntList(Vec<i128>),
+    ObjectType { component: i128, ty: i128 },
+    ObjectId { component: i128, ty: i128, id: i128 },
+    Float(f32),
+}
+
+/// Tagged field within a group or packet body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Field {
+    /// The decoded tag name
+    pub tag: String,
+    /// The value of the field
+    pub value: TdfValue,
+}
+
+/// Errors that can occur while decoding values
+#[derive(Debug, Error)]
+pub enum ValueError {
+    #[error("Unexpected end of input at offset {0}")]
+    UnexpectedEof(usize),
+    #[error("Unsupported TDF type {ty:#x} at offset {offset}")]
+    UnknownType { ty: u8, offset: usize },
+    #[error("Values nested too deeply at offset {0}")]
+    TooDeep(usize),
+}
+
+pub type ValueResult<T> = Result<T, ValueError>;
+
+impl ValueError {
+    /// Offset within the contents where decoding failed
+    pub fn offset(&self) -> usize {
+        match self {
+            ValueError::UnexpectedEof(offset) => *offset,
+            ValueError::UnknownType { offset, .. } => *offset,
+            ValueError::TooDeep(offset) => *offset,
+        }
+    }
+}
+
+/// Decodes all the fields from the provided packet contents
+pub fn decode_fields(contents: &[u8]) -> ValueResult<Vec<Field>> {
+    let mut r = ValueReader::new(contents);
+    let mut fields = Vec::new();
+    while !r.is_empty() {
+        fields.push(r.read_field()?);
+    }
+    Ok(fields)
+}
+
+/// Encodes the provided fields as packet contents. Empty lists and maps
+/// don't keep their value types so they are encoded as var int values
+pub fn encode_fields(fields: &[Field]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for field in fields {
+        write_field(&mut output, field);
+    }
+    output
+}
+
+/// Finds the value of the field with the provided `tag`
+pub fn find<'a>(fields: &'a [Field], tag: &str) -> Option<&'a TdfValue> {
+    fields
+        .iter()
+        .find(|field| field.tag == tag)
+        .map(|field| &field.value)
+}
+
+/// Converts a list of fields into a JSON object keyed by tag
+pub fn fields_to_json(fields: &[Field]) -> Value {
+    let map: Map<String, Value> = fields
+        .iter()
+        .map(|field| (field.tag.clone(), field.value.to_json()))
+        .collect();
+    Value::Object(map)
+}
+
+/// Encodes the provided bytes as a lowercase hex string
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        _ = write!(&mut output, "{:02x}", byte);
+    }
+    output
+}
+
+/// Decodes a hex string into bytes, None if the string isn't valid hex
+pub fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(value.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+/// Formats the provided bytes as a hex dump of 16 bytes per line, each
+/// line starts with its offset (relative to `base`) and ends with the
+/// printable ASCII characters of the line
+pub fn hex_dump(bytes: &[u8], base: usize) -> String {
+    let mut output = String::new();
+    for (index, line) in bytes.chunks(16).enumerate() {
+        _ = write!(&mut output, "{:08x}  ", base + index * 16);
+
+        for column in 0..16 {
+            match line.get(column) {
+                Some(byte) => {
+                    _ = write!(&mut output, "{:02x} ", byte);
+                }
+                None => output.push_str("   "),
+            }
+            if column == 7 {
+                output.push(' ');
+            }
+        }
+
+        output.push(' ');
+        output.extend(line.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                *byte as char
+            } else {
+                '.'
+            }
+        }));
+        output.push('\n');
+    }
+    output
+}
+
+impl TdfValue {
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            TdfValue::VarInt(value) => u64::try_from(*value).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            TdfValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_group(&self) -> Option<&[Field]> {
+        match self {
+            TdfValue::Group(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[TdfValue]> {
+        match self {
+            TdfValue::List(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Converts the value into its JSON representation. Blobs are
+    /// encoded as hex strings and maps become objects keyed by the
+    /// string form of their keys
+    pub fn to_json(&self) -> Value {
+        match self {
+            TdfValue::VarInt(value) => varint_to_json(*value),
+            TdfValue::String(value) => Value::String(value.clone()),
+            TdfValue::Blob(value) => Value::String(to_hex(value)),
+            TdfValue::Group(fields) => fields_to_json(fields),
+            TdfValue::List(values) => Value::Array(values.iter().map(Self::to_json).collect()),
+            TdfValue::Map(pairs) => {
+                let map: Map<String, Value> = pairs
+                    .iter()
+                    .map(|(key, value)| (key.to_key(), value.to_json()))
+                    .collect();
+                Value::Object(map)
+            }
+            TdfValue::Union { key, value } => {
+                let mut map = Map::new();
+                map.insert("key".to_string(), Value::from(*key));
+                if let Some(field) = value {
+                    map.insert(field.tag.clone(), field.value.to_json());
+                }
+                Value::Object(map)
+            }
+            TdfValue::VarIntList(values) => {
+                Value::Array(values.iter().copied().map(varint_to_json).collect())
+            }
+            TdfValue::ObjectType { component, ty } => serde_json::json!({
+                "component": varint_to_json(*component),
+                "type": varint_to_json(*ty),
+            }),
+            TdfValue::ObjectId { component, ty, id } => serde_json::json!({
+                "component": varint_to_json(*component),
+                "type": varint_to_json(*ty),
+                "id": varint_to_json(*id),
+            }),
+            TdfValue::Float(value) => Value::from(*value),
+        }
+    }
+
+    /// The TDF type byte of the value
+    fn ty(&self) -> u8 {
+        match self {
+            TdfValue::VarInt(_) => 0x0,
+            TdfValue::String(_) => 0x1,
+            TdfValue::Blob(_) => 0x2,
+            TdfValue::Group(_) => 0x3,
+            TdfValue::List(_) => 0x4,
+            TdfValue::Map(_) => 0x5,
+            TdfValue::Union { .. } => 0x6,
+            TdfValue::VarIntList(_) => 0x7,
+            TdfValue::ObjectType { .. } => 0x8,
+            TdfValue::ObjectId { .. } => 0x9,
+            TdfValue::Float(_) => 0xA,
+        }
+    }
+
+    /// Converts the value into a string for use as a JSON object key
+    fn to_key(&self) -> String {
+        match self {
+            TdfValue::String(value) => value.clone(),
+            TdfValue::VarInt(value) => value.to_string(),
+            value => value.to_json().to_string(),
+        }
+    }
+}
+
+fn varint_to_json(value: i128) -> Value {
+    if let Ok(value) = u64::try_from(value) {
+        Value::from(value)
+    } else {
+        Value::from(value as i64)
+    }
+}
+
+/// Reader for decoding [TdfValue]s from raw TDF bytes
+pub struct ValueReader<'a> {
+    /// The buffer being read
+    buffer: &'a [u8],
+    /// The current position within the buffer
+    pub cursor: usize,
+    /// Tags of the fields being read from the outermost field inwards,
+    /// left at the failing field when reading fails
+    pub path: Vec<String>,
+    /// Number of values currently being read within each other
+    depth: usize,
+}
+
+impl<'a> ValueReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            cursor: 0,
+            path: Vec::new(),
+            depth: 0,
+        }
+    }
+
+    /// Whether the reader has reached the end of the buffer
+    pub fn is_empty(&self) -> bool {
+        self.cursor >= self.buffer.len()
+    }
+
+    /// Reads a tagged field
+    pub fn read_field(&mut self) -> ValueResult<Field> {
+        let (tag, ty) = self.read_tag()?;
+        self.path.push(tag);
+        let value = self.read_value(ty)?;
+        let tag = self.path.pop().unwrap_or_default();
+        Ok(Field { tag, value })
+    }
+
+    /// Reads a tag and its value type. Tags are 4 characters of 6 bits
+    /// each packed into 3 bytes followed by a type byte
+    fn read_tag(&mut self) -> ValueResult<(String, u8)> {
+        let bytes = self.read_slice(3)?;
+        let raw = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+        let mut tag = String::with_capacity(4);
+        for shift in [18, 12, 6, 0] {
+            let value = ((raw >> shift) & 0x3F) as u8;
+            if value != 0 {
+                tag.push((value + 0x20) as char);
+            }
+        }
+
+        let ty = self.read_byte()?;
+        Ok((tag, ty))
+    }
+
+    fn read_value(&mut self, ty: u8) -> ValueResult<TdfValue> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ValueError::TooDeep(self.cursor));
+        }
+
+        self.depth += 1;
+        let value = self.read_value_inner(ty);
+        self.depth -= 1;
+        value
+    }
+
+    fn read_value_inner(&mut self, ty: u8) -> ValueResult<TdfValue> {
+        let offset = self.cursor;
+        Ok(match ty {
+            0x0 => TdfValue::VarInt(self.read_varint()?),
+            0x1 => {
+                let length = self.read_length()?;
+                let bytes = self.read_slice(length)?;
+                // Strings include a null terminator
+                let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+                TdfValue::String(String::from_utf8_lossy(bytes).into_owned())
+            }
+            0x2 => {
+                let length = self.read_length()?;
+                TdfValue::Blob(self.read_slice(length)?.to_vec())
+            }
+            0x3 => TdfValue::Group(self.read_group()?),
+            0x4 => {
+                let value_ty = self.read_byte()?;
+                let count = self.read_length()?;
+                let mut values = Vec::with_capacity(count.min(64));
+                for _ in 0..count {
+                    values.push(self.read_value(value_ty)?);
+                }
+                TdfValue::List(values)
+            }
+            0x5 => {
+                let key_ty = self.read_byte()?;
+                let value_ty = self.read_byte()?;
+                let count = self.read_length()?;
+                let mut pairs = Vec::with_capacity(count.min(64));
+                for _ in 0..count {
+                    let key = self.read_value(key_ty)?;
+                    let value = self.read_value(value_ty)?;
+                    pairs.push((key, value));
+                }
+                TdfValue::Map(pairs)
+            }
+            0x6 => {
+                let key = self.read_byte()?;
+                let value = if key == UNION_UNSET {
+                    None
+                } else {
+                    Some(Box::new(self.read_field()?))
+                };
+                TdfValue::Union { key, value }
+            }
+            0x7 => {
+                let count = self.read_length()?;
+                let mut values = Vec::with_capacity(count.min(64));
+                for _ in 0..count {
+                    values.push(self.read_varint()?);
+                }
+                TdfValue::VarIntList(values)
+            }
+            0x8 => TdfValue::ObjectType {
+                component: self.read_varint()?,
+                ty: self.read_varint()?,
+            },
+            0x9 => TdfValue::ObjectId {
+                component: self.read_varint()?,
+                ty: self.read_varint()?,
+                id: self.read_varint()?,
+            },
+            0xA => {
+                let bytes = self.read_slice(4)?;
+                TdfValue::Float(f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            ty => return Err(ValueError::UnknownType { ty, offset }),
+        })
+    }
+
+    /// Reads the fields of a group until the group terminator
+    fn read_group(&mut self) -> ValueResult<Vec<Field>> {
+        // Groups can start with a marker byte of 2
+        if self.peek_byte()? == 2 {
+            self.cursor += 1;
+        }
+
+        let mut fields = Vec::new();
+        while self.peek_byte()? != 0 {
+            fields.push(self.read_field()?);
+        }
+
+        // Skip the terminator
+        self.cursor += 1;
+        Ok(fields)
+    }
+
+    /// Reads a variable length integer, the first byte holds 6 bits of
+    /// the value along with a sign bit and the following bytes hold 7
+    fn read_varint(&mut self) -> ValueResult<i128> {
+        let first = self.read_byte()?;
+        let negative = first & 0x40 != 0;
+
+        let mut value = (first & 0x3F) as u64;
+        let mut shift = 6;
+        let mut byte = first;
+
+        while byte & 0x80 != 0 {
+            byte = self.read_byte()?;
+            if shift < 64 {
+                value |= ((byte & 0x7F) as u64) << shift;
+            }
+            shift += 7;
+        }
+
+        let value = value as i128;
+        Ok(if negative { -value } else { value })
+    }
+
+    fn read_length(&mut self) -> ValueResult<usize> {
+        let offset = self.cursor;
+        let value = self.read_varint()?;
+        usize::try_from(value).map_err(|_| ValueError::UnexpectedEof(offset))
+    }
+
+    fn peek_byte(&self) -> ValueResult<u8> {
+        self.buffer
+            .get(self.cursor)
+            .copied()
+            .ok_or(ValueError::UnexpectedEof(self.cursor))
+    }
+
+    fn read_byte(&mut self) -> ValueResult<u8> {
+        let byte = self.peek_byte()?;
+        self.cursor += 1;
+        Ok(byte)
+    }
+
+    fn read_slice(&mut self, length: usize) -> ValueResult<&'a [u8]> {
+        let end = self
+            .cursor
+            .checked_add(length)
+            .filter(|end| *end <= self.buffer.len())
+            .ok_or(ValueError::UnexpectedEof(self.cursor))?;
+        let slice = &self.buffer[self.cursor..end];
+        self.cursor = end;
+        Ok(slice)
+    }
+}
+
+fn write_field(output: &mut Vec<u8>, field: &Field) {
+    // Tags are up to 4 characters of 6 bits each packed into 3 bytes
+    let mut raw = 0u32;
+    for (index, char) in field.tag.bytes().take(4).enumerate() {
+        raw |= ((char.wrapping_sub(0x20) & 0x3F) as u32) << (18 - index * 6);
+    }
+    output.extend_from_slice(&raw.to_be_bytes()[1..]);
+    output.push(field.value.ty());
+    write_value(output, &field.value);
+}
+
+fn write_value(output: &mut Vec<u8>, value: &TdfValue) {
+    match value {
+        TdfValue::VarInt(value) => write_varint(output, *value),
+        TdfValue::String(value) => {
+            // Strings include a null terminator
+            write_varint(output, value.len() as i128 + 1);
+            output.extend_from_slice(value.as_bytes());
+            output.push(0);
+        }
+        TdfValue::Blob(value) => {
+            write_varint(output, value.len() as i128);
+            output.extend_from_slice(value);
+        }
+        TdfValue::Group(fields) => {
+            for field in fields {
+                write_field(output, field);
+            }
+            output.push(0);
+        }
+        TdfValue::List(values) => {
+            output.push(values.first().map_or(0, TdfValue::ty));
+            write_varint(output, values.len() as i128);
+            for value in values {
+                write_value(output, value);
+            }
+        }
+        TdfValue::Map(pairs) => {
+            let (key_ty, value_ty) = pairs
+                .first()
+                .map_or((0, 0), |(key, value)| (key.ty(), value.ty()));
+            output.push(key_ty);
+            output.push(value_ty);
+            write_varint(output, pairs.len() as i128);
+            for (key, value) in pairs {
+                write_value(output, key);
+                write_value(output, value);
+            }
+        }
+        TdfValue::Union { key, value } => {
+            output.push(*key);
+            if let Some(field) = value {
+                write_field(output, field);
+            }
+        }
+        TdfValue::VarIntList(values) => {
+            write_varint(output, values.len() as i128);
+            for value in values {
+                write_varint(output, *value);
+            }
+        }
+        TdfValue::ObjectType { component, ty } => {
+            write_varint(output, *component);
+            write_varint(output, *ty);
+        }
+        TdfValue::ObjectId { component, ty, id } => {
+            write_varint(output, *component);
+            write_varint(output, *ty);
+            write_varint(output, *id);
+        }
+        TdfValue::Float(value) => output.extend_from_slice(&value.to_be_bytes()),
+    }
+}
+
+/// Writes a variable length integer in the format read by
+/// [ValueReader::read_varint]
+fn write_varint(output: &mut Vec<u8>, value: i128) {
+    let negative = value < 0;
+    let mut value = value.unsigned_abs() as u64;
+
+    let mut first = (value & 0x3F) as u8;
+    if negative {
+        first |= 0x40;
+    }
+    value >>= 6;
+
+    if value == 0 {
+        output.push(first);
+        return;
+    }
+
+    output.push(first | 0x80);
+    while value > 0x7F {
+        output.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
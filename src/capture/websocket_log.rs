@@ -0,0 +1,39 @@
+use super::{read_jsonl, Direction};
+use serde::{Deserialize, Serialize};
+use std::{io, path::Path};
+
+/// The file name of the WebSocket log within the session directory
+pub const WEBSOCKET_FILE: &str = "websocket.jsonl";
+
+/// Frame sent through an upgraded HTTP connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WebSocketRecord {
+    /// Unix timestamp in microseconds the frame was seen
+    pub time: u64,
+    /// ID of the upgraded connection the frame was sent on
+    pub tunnel: u32,
+    /// The URL of the upgraded request
+    pub url: String,
+    /// The direction the frame was travelling
+    pub direction: Direction,
+    /// The frame opcode, None for connections upgraded to protocols
+    /// other than WebSocket which are recorded as raw chunks
+    pub opcode: Option<u8>,
+    /// Whether this is the final frame of a message
+    pub fin: bool,
+    /// Length of the frame payload in bytes
+    pub length: u64,
+    /// The unmasked payload, text frames as text and other frames as
+    /// hex. Only included when the capture includes HTTP bodies
+    pub payload: Option<String>,
+    /// Whether the payload was cut short
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Reads all the WebSocket records from the WebSocket log in the
+/// provided session directory
+pub fn read_websocket_log(dir: &Path) -> io::Result<Vec<WebSocketRecord>> {
+    read_jsonl(&dir.join(WEBSOCKET_FILE))
+}
@@ -0,0 +1,488 @@
+use super::{capture, privacy::PrivacyCategories, profile::CaptureProfile, Direction};
+use crate::{
+    config::config,
+    console,
+    logging::{ConsoleFormat, FILE_ONLY_TARGET},
+    servers::packet::{Packet, PacketDebug},
+    tasks::spawn_thread,
+};
+use bytes::BytesMut;
+use log::{debug, error, log_enabled, warn, Level};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{Condvar, Mutex, MutexGuard, OnceLock},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// How long finishing the capture waits for queued packets to be written
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often flushing checks that the writer thread is still running
+const FLUSH_POLL: Duration = Duration::from_millis(50);
+
+/// The file name of the spill file within the temp directory, the spill
+/// holds packets before anonymization so it's kept out of the dump directory
+const SPILL_FILE: &str = "prdc-writer-spill.bin";
+
+/// What happens to packets queued for the capture writer once the
+/// memory budget is used up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryPolicy {
+    /// Drop the oldest queued packets from the capture to make room
+    #[default]
+    DropOldest,
+    /// Write further packets to a spill file on disk until the writer
+    /// catches up, packets are only dropped if spilling fails
+    Spill,
+}
+
+/// Capture writer memory statistics
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WriterStats {
+    /// Bytes currently held by queued packets
+    pub buffered_bytes: usize,
+    /// Largest number of bytes held by queued packets
+    pub peak_bytes: usize,
+    /// Number of packets dropped from the capture to stay within the budget
+    pub dropped: u64,
+    /// Number of packets written to the spill file
+    pub spilled: u64,
+}
+
+/// Proxied packet waiting to be logged and captured
+struct Job {
+    /// Unix timestamp in microseconds the packet was seen
+    time: u64,
+    connection: u32,
+    direction: Direction,
+    packet: Packet,
+    /// Whether the packet passed sampling and should be captured
+    capture: bool,
+    /// Sample number if the packet is kept in the samples directory
+    sample: Option<u32>,
+}
+
+impl Job {
+    /// Approximate memory held by the job
+    fn size(&self) -> usize {
+        std::mem::size_of::<Job>() + self.packet.contents.len()
+    }
+}
+
+/// Packets waiting for the writer, the spilled packets always come
+/// after the packets held in memory
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Packets over the memory budget waiting for the writer thread to
+    /// spill them to disk, so queueing never waits on file I/O
+    overflow: VecDeque<Job>,
+    /// Whether packets are being spilled, further packets go to the
+    /// overflow until the writer has caught up with the spill file
+    spilling: bool,
+    /// Whether the writer is handling a job
+    busy: bool,
+    stats: WriterStats,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    jobs: VecDeque::new(),
+    overflow: VecDeque::new(),
+    spilling: false,
+    busy: false,
+    stats: WriterStats {
+        buffered_bytes: 0,
+        peak_bytes: 0,
+        dropped: 0,
+        spilled: 0,
+    },
+});
+
+/// Notified when the queue changes
+static CHANGED: Condvar = Condvar::new();
+
+/// The writer thread, started on first use
+static WRITER: OnceLock<Option<JoinHandle<()>>> = OnceLock::new();
+
+/// Queues a proxied packet to be logged and captured on the writer thread
+/// so that formatting and writing never delay forwarding. Queued packets
+/// are limited by the memory budget from the config
+///
+/// `time`    Unix timestamp in microseconds the packet was seen
+/// `capture` Whether the packet should be captured, otherwise it's only logged
+/// `sample`  Sample number if the packet should be kept as a sample
+pub fn queue(
+    time: u64,
+    connection: u32,
+    direction: Direction,
+    packet: &Packet,
+    capture: bool,
+    sample: Option<u32>,
+) {
+    // Nothing to do for packets that are neither captured, sampled or logged
+    if !capture && sample.is_none() && !logs_packets() {
+        return;
+    }
+
+    WRITER.get_or_init(start);
+
+    let job = Job {
+        time,
+        connection,
+        direction,
+        packet: packet.clone(),
+        capture,
+        sample,
+    };
+
+    let config = config();
+    let budget = (config.capture_memory_mb as usize).saturating_mul(1024 * 1024);
+    let queue = &mut *lock();
+
+    if queue.spilling || queue.stats.buffered_bytes + job.size() > budget {
+        match config.capture_memory_policy {
+            MemoryPolicy::DropOldest => {
+                while queue.stats.buffered_bytes + job.size() > budget {
+                    let Some(dropped) = queue.jobs.pop_front() else {
+                        break;
+                    };
+                    queue.stats.buffered_bytes -= dropped.size();
+                    queue.stats.dropped += 1;
+                    if queue.stats.dropped == 1 {
+                        warn!("Capture memory budget reached, dropping the oldest queued packets");
+                    }
+                }
+            }
+            MemoryPolicy::Spill => {
+                if !queue.spilling {
+                    debug!("Capture memory budget reached, spilling packets to disk");
+                    queue.spilling = true;
+                }
+                queue.overflow.push_back(job);
+                CHANGED.notify_all();
+                return;
+            }
+        }
+    }
+
+    queue.stats.buffered_bytes += job.size();
+    queue.stats.peak_bytes = queue.stats.peak_bytes.max(queue.stats.buffered_bytes);
+    queue.jobs.push_back(job);
+    CHANGED.notify_all();
+}
+
+/// Waits for the queued packets to be written, returns the writer stats.
+/// The writer thread is checked between short waits so a writer that has
+/// stopped can't hold up the caller past the timeout. Must not be called
+/// from DllMain, the writer can't make progress under the loader lock
+pub fn flush() -> WriterStats {
+    let deadline = Instant::now() + FLUSH_TIMEOUT;
+    let mut queue = lock();
+
+    while !queue.jobs.is_empty() || queue.spilling || queue.busy {
+        if !writer_running() {
+            warn!("Capture writer stopped with packets still queued");
+            // The queued packets will never be written, packets already
+            // spilled are left out as the writer held the spill file
+            queue.stats.dropped += (queue.jobs.len() + queue.overflow.len()) as u64;
+            break;
+        }
+
+        let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+            warn!("Timed out waiting for the capture writer to finish");
+            break;
+        };
+
+        queue = CHANGED
+            .wait_timeout(queue, remaining.min(FLUSH_POLL))
+            .expect("Capture writer lock poisoned")
+            .0;
+    }
+    queue.stats
+}
+
+/// Obtains the current writer stats
+pub fn stats() -> WriterStats {
+    lock().stats
+}
+
+fn lock() -> MutexGuard<'static, Queue> {
+    QUEUE.lock().expect("Capture writer lock poisoned")
+}
+
+fn start() -> Option<JoinHandle<()>> {
+    spawn_thread("capture-writer", run)
+}
+
+/// Whether the writer thread is running
+fn writer_running() -> bool {
+    WRITER
+        .get()
+        .and_then(Option::as_ref)
+        .is_some_and(|handle| !handle.is_finished())
+}
+
+fn run() {
+    debug!("Capture writer started");
+    remove_stale_spill();
+
+    // Only the writer thread touches the spill file
+    let mut spill: Option<Spill> = None;
+
+    loop {
+        let (job, overflow) = {
+            let mut queue = lock();
+            queue.busy = false;
+            CHANGED.notify_all();
+
+            let mut queue = CHANGED
+                .wait_while(queue, |queue| queue.jobs.is_empty() && !queue.spilling)
+                .expect("Capture writer lock poisoned");
+
+            let job = queue.jobs.pop_front();
+            if let Some(job) = &job {
+                queue.stats.buffered_bytes -= job.size();
+            }
+            queue.busy = true;
+            (job, std::mem::take(&mut queue.overflow))
+        };
+
+        if !overflow.is_empty() {
+            spill_jobs(&mut spill, overflow);
+        }
+
+        let Some(job) = job.or_else(|| unspill(&mut spill)) else {
+            continue;
+        };
+
+        // A panic while handling one packet shouldn't stop the writer
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            // Packets only kept as samples were excluded from the log
+            if job.capture || job.sample.is_none() {
+                debug_log_packet(&job);
+            }
+
+            let Some(capture) = capture() else {
+                return;
+            };
+            if job.capture {
+                capture.record_packet(job.time, job.connection, job.direction, &job.packet);
+            }
+            if let Some(number) = job.sample {
+                capture.record_sample(job.time, job.connection, job.direction, &job.packet, number);
+            }
+        }));
+        if result.is_err() {
+            error!("Capture writer panicked handling a packet");
+        }
+    }
+}
+
+/// Removes a spill file left behind by a game that exited while spilling
+fn remove_stale_spill() {
+    let path = std::env::temp_dir().join(SPILL_FILE);
+    match std::fs::remove_file(&path) {
+        Ok(()) => debug!("Removed stale capture spill file"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => error!("Failed to remove stale capture spill file: {}", err),
+    }
+}
+
+/// Writes the overflowing jobs to the spill file, dropping them if
+/// spilling fails. Runs on the writer thread outside of the queue lock
+fn spill_jobs(spill: &mut Option<Spill>, jobs: VecDeque<Job>) {
+    let mut spilled = 0;
+    let mut dropped = 0;
+
+    if spill.is_none() {
+        match Spill::create() {
+            Ok(value) => *spill = Some(value),
+            Err(err) => error!("Failed to create capture spill file: {}", err),
+        }
+    }
+
+    match spill.as_mut() {
+        Some(spill) => {
+            for job in &jobs {
+                match spill.write(job) {
+                    Ok(()) => spilled += 1,
+                    Err(err) => {
+                        error!("Failed to spill packet: {}", err);
+                        dropped += 1;
+                    }
+                }
+            }
+        }
+        None => dropped += jobs.len() as u64,
+    }
+
+    let queue = &mut *lock();
+    queue.stats.spilled += spilled;
+    queue.stats.dropped += dropped;
+}
+
+/// Reads the next job back from the spill file. Once every spilled job has
+/// been read and nothing else overflowed spilling stops and the spill file
+/// is removed
+fn unspill(spill: &mut Option<Spill>) -> Option<Job> {
+    if let Some(file) = spill.as_mut() {
+        match file.read() {
+            Ok(Some(job)) => return Some(job),
+            Ok(None) => {}
+            Err(err) => {
+                error!("Failed to read spilled packets: {}", err);
+                lock().stats.dropped += file.pending;
+                file.pending = 0;
+            }
+        }
+    }
+
+    {
+        let mut queue = lock();
+        // Packets that overflowed meanwhile are spilled on the next pass
+        if !queue.overflow.is_empty() {
+            return None;
+        }
+        queue.spilling = false;
+    }
+
+    if let Some(file) = spill.take() {
+        file.remove();
+        debug!("Capture writer caught up with spilled packets");
+    }
+    None
+}
+
+/// File holding jobs that didn't fit in the memory budget
+struct Spill {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    /// Number of jobs written but not read back yet
+    pending: u64,
+}
+
+impl Spill {
+    fn create() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(SPILL_FILE);
+        let writer = BufWriter::new(File::create(&path)?);
+        let reader = BufReader::new(File::open(&path)?);
+        Ok(Self {
+            path,
+            writer,
+            reader,
+            pending: 0,
+        })
+    }
+
+    fn write(&mut self, job: &Job) -> io::Result<()> {
+        let mut packet = BytesMut::with_capacity(job.packet.encoded_length());
+        job.packet.write(&mut packet);
+
+        let direction = match job.direction {
+            Direction::Send => 0,
+            Direction::Receive => 1,
+        };
+
+        self.writer.write_all(&job.time.to_le_bytes())?;
+        self.writer.write_all(&job.connection.to_le_bytes())?;
+        self.writer.write_all(&[direction, job.capture as u8])?;
+        // Sample numbers start at 1 so zero marks packets without a sample
+        self.writer
+            .write_all(&job.sample.unwrap_or_default().to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&packet)?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn read(&mut self) -> io::Result<Option<Job>> {
+        if self.pending == 0 {
+            return Ok(None);
+        }
+        self.writer.flush()?;
+
+        let mut time = [0u8; 8];
+        let mut connection = [0u8; 4];
+        let mut flags = [0u8; 2];
+        let mut sample = [0u8; 4];
+        let mut length = [0u8; 4];
+        self.reader.read_exact(&mut time)?;
+        self.reader.read_exact(&mut connection)?;
+        self.reader.read_exact(&mut flags)?;
+        self.reader.read_exact(&mut sample)?;
+        self.reader.read_exact(&mut length)?;
+
+        let direction = match flags[0] {
+            0 => Direction::Send,
+            _ => Direction::Receive,
+        };
+        let length = u32::from_le_bytes(length) as usize;
+
+        let mut packet = vec![0u8; length];
+        self.reader.read_exact(&mut packet)?;
+        let packet = Packet::read(&mut BytesMut::from(packet.as_slice()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Incomplete packet"))?;
+
+        self.pending -= 1;
+        Ok(Some(Job {
+            time: u64::from_le_bytes(time),
+            connection: u32::from_le_bytes(connection),
+            direction,
+            packet,
+            capture: flags[1] != 0,
+            sample: Some(u32::from_le_bytes(sample)).filter(|value| *value != 0),
+        }))
+    }
+
+    fn remove(self) {
+        let Spill {
+            path,
+            writer,
+            reader,
+            ..
+        } = self;
+        drop((writer, reader));
+        if let Err(err) = std::fs::remove_file(&path) {
+            error!("Failed to remove capture spill file: {}", err);
+        }
+    }
+}
+
+/// Whether packets are written to the debug log
+fn logs_packets() -> bool {
+    config().log_packets && log_enabled!(Level::Debug)
+}
+
+fn debug_log_packet(job: &Job) {
+    let packet = &job.packet;
+    if !logs_packets() || !console::logs_packet(&packet.frame) {
+        return;
+    }
+
+    let debug = PacketDebug {
+        packet,
+        contents: CaptureProfile::current().packet_contents()
+            && PrivacyCategories::current().packet_contents(&packet.frame),
+    };
+
+    match config().console_format {
+        ConsoleFormat::Full => debug!("\nOfficial: {:?}\n{:?}", job.direction, debug),
+        ConsoleFormat::Compact => {
+            // The full dump only goes to the log file
+            debug!(target: FILE_ONLY_TARGET, "\nOfficial: {:?}\n{:?}", job.direction, debug);
+            console::print_packet(job.time, job.connection, job.direction, packet);
+        }
+        ConsoleFormat::Quiet => {
+            debug!(target: FILE_ONLY_TARGET, "\nOfficial: {:?}\n{:?}", job.direction, debug);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+use crate::consent::Consent;
+use crate::logging::ConsoleFormat;
+use crate::update::DEFAULT_UPDATE_ENDPOINT;
+use crate::variant::GameVariant;
+use crate::{
+    capture::{
+        anonymize::Anonymization, crash::CrashConfig, dump_dir, hotkeys::MarkerHotkey,
+        privacy::PrivacyCategories, profile::CaptureProfile, retention::RetentionConfig,
+        sampling::SamplingRule, triggers::TriggerConfig, upload::UploadConfig,
+        writer::MemoryPolicy,
+    },
+    servers::{
+        breakpoints::BreakpointRule,
+        conditions::ConditionRule,
+        dns,
+        fuzzer::FuzzConfig,
+        http::HostPin,
+        limits::ConnectionLimits,
+        main::BackpressurePolicy,
+        probing::ProbingConfig,
+        retriever::{ClientProfile, ClientSku, DEFAULT_REQUEST_TIMEOUT},
+        upstream::Upstream,
+    },
+};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::Duration,
+};
+
+/// The file name of the config file within the dump directory
+const CONFIG_FILE: &str = "config.json";
+
+/// The loaded configuration
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Whether the config file didn't exist when the config was loaded
+static FIRST_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Plugin configuration, loaded from the config file in the dump
+/// directory. Missing fields use their default values
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Active features the user has opted into, only passive capture
+    /// runs without consent
+    pub consent: Consent,
+    /// Address the local servers listen on. Anything other than loopback
+    /// exposes the proxy to other machines on the network
+    pub bind_address: IpAddr,
+    /// Addresses other than loopback allowed to use the control API, the
+    /// token from the dump directory is still required
+    pub control_allowlist: Vec<IpAddr>,
+    /// Limits on the connections accepted by the local servers
+    pub connection_limits: ConnectionLimits,
+    /// The capture profile to use on startup
+    pub profile: CaptureProfile,
+    /// How much player identifying data is removed from captures
+    pub anonymization: Anonymization,
+    /// Categories of data included in captures, disabled categories
+    /// only keep their packet headers
+    pub privacy: PrivacyCategories,
+    /// Language for dialogs ("de", "fr"), detected from Windows when unset
+    pub language: Option<String>,
+    /// Seconds to wait for responses to requests made by the plugin
+    pub request_timeout_secs: u64,
+    /// Whether proxied packets are written to the debug log. Formatting
+    /// packets costs more than forwarding them, captures are unaffected
+    pub log_packets: bool,
+    /// How logged packets are shown in the console
+    pub console_format: ConsoleFormat,
+    /// Rules for sampling high volume packets
+    pub sampling: Vec<SamplingRule>,
+    /// Number of payloads kept in the samples directory for each component,
+    /// command and frame type regardless of the profile and sampling
+    pub samples_per_command: u32,
+    /// Minimum free space in megabytes on the dump volume before capturing
+    /// is downgraded, capturing is paused below a quarter of this
+    pub min_free_space_mb: u64,
+    /// Limits on the age and size of the sessions kept in the dump directory
+    pub retention: RetentionConfig,
+    /// Settings for crash reports written when the game crashes
+    pub crash: CrashConfig,
+    /// Collection session code to use on startup
+    pub collection: Option<String>,
+    /// Campaign every run of the game is added to, letters, digits and
+    /// dashes only
+    pub campaign: Option<String>,
+    /// Whether to also write captures into a SQLite database, requires
+    /// the plugin to be built with the "sqlite" feature
+    pub sqlite: bool,
+    /// Memory in megabytes packets queued for the capture writer can use
+    pub capture_memory_mb: u64,
+    /// What happens to queued packets once the memory budget is used up
+    pub capture_memory_policy: MemoryPolicy,
+    /// Maximum number of packets queued for writing to the game client
+    pub client_queue_size: usize,
+    /// Maximum number of packets queued for writing to the official server
+    pub server_queue_size: usize,
+    /// How packets for the game client are handled when its queue is full
+    pub backpressure: BackpressurePolicy,
+    /// Degraded network conditions to simulate in the main proxy, the
+    /// first rule matching a packet is applied
+    pub network_conditions: Vec<ConditionRule>,
+    /// Rules for automatically starting and stopping recording
+    pub triggers: TriggerConfig,
+    /// Key combinations placing named markers while the game is focused
+    pub marker_hotkeys: Vec<MarkerHotkey>,
+    /// Key combination opening the data review window, None to disable
+    pub review_hotkey: Option<String>,
+    /// Whether the capture state and packet counts are shown in the
+    /// game window title
+    pub title_overlay: bool,
+    /// Whether a notification is shown when a packet is flagged as
+    /// interesting, notifications are at most once every five minutes
+    pub notify_interesting: bool,
+    /// Upstream servers for proxied connections, each new connection uses
+    /// the next upstream in the list. The official server is used when empty
+    pub upstreams: Vec<Upstream>,
+    /// Base URL of a local Pocket Relay server every proxied request is
+    /// also sent to, responses from it are discarded
+    pub mirror_url: Option<String>,
+    /// Whether connections the game made before the plugin was injected
+    /// are reset so the game reconnects through the proxy
+    pub force_reconnect_on_attach: bool,
+    /// Game variant reported instead of the one detected from the install
+    pub game_variant: Option<GameVariant>,
+    /// Names of hooks that shouldn't be applied ("host_lookup", "cert_check")
+    pub disabled_hooks: Vec<String>,
+    /// Client build the plugin is running in, selects the client details
+    /// used when requesting the official server instance and the hook
+    /// patterns
+    pub client_sku: ClientSku,
+    /// Client details used instead of the built-in details of the SKU
+    pub client: Option<ClientProfile>,
+    /// DNS over HTTPS providers tried in order when the system DNS can't
+    /// resolve an official server host
+    pub dns_providers: Vec<String>,
+    /// Fixed upstream addresses for HTTP hosts, bypassing DNS which the
+    /// Pocket Relay client often redirects through the hosts file
+    pub http_host_pins: Vec<HostPin>,
+    /// Rules for holding packets before they are forwarded
+    pub breakpoints: Vec<BreakpointRule>,
+    /// Settings for fuzzing official endpoints
+    pub fuzz: FuzzConfig,
+    /// Limits shared by every feature sending its own requests to the
+    /// official server
+    pub probing: ProbingConfig,
+    /// Settings for uploading finished sessions
+    pub upload: UploadConfig,
+    /// URL anonymous usage metrics are posted to, metrics also require
+    /// consent
+    pub metrics_endpoint: Option<String>,
+    /// URL listing the plugin releases checked for updates, update checks
+    /// also require consent
+    pub update_endpoint: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            consent: Consent::default(),
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            control_allowlist: Vec::new(),
+            connection_limits: ConnectionLimits::default(),
+            profile: CaptureProfile::default(),
+            anonymization: Anonymization::default(),
+            privacy: PrivacyCategories::default(),
+            language: None,
+            request_timeout_secs: DEFAULT_REQUEST_TIMEOUT.as_secs(),
+            log_packets: true,
+            console_format: ConsoleFormat::default(),
+            sampling: SamplingRule::defaults(),
+            samples_per_command: 3,
+            min_free_space_mb: 1024,
+            retention: RetentionConfig::default(),
+            crash: CrashConfig::default(),
+            collection: None,
+            campaign: None,
+            sqlite: false,
+            capture_memory_mb: 64,
+            capture_memory_policy: MemoryPolicy::default(),
+            client_queue_size: 512,
+            server_queue_size: 512,
+            backpressure: BackpressurePolicy::default(),
+            network_conditions: Vec::new(),
+            triggers: TriggerConfig::default(),
+            marker_hotkeys: MarkerHotkey::defaults(),
+            review_hotkey: Some("Ctrl+Shift+R".to_string()),
+            title_overlay: false,
+            notify_interesting: false,
+            upstreams: Vec::new(),
+            mirror_url: None,
+            force_reconnect_on_attach: false,
+            game_variant: None,
+            disabled_hooks: Vec::new(),
+            client_sku: ClientSku::Retail,
+            client: None,
+            dns_providers: dns::default_providers(),
+            http_host_pins: Vec::new(),
+            breakpoints: Vec::new(),
+            fuzz: FuzzConfig::default(),
+            probing: ProbingConfig::default(),
+            upload: UploadConfig::default(),
+            metrics_endpoint: None,
+            update_endpoint: DEFAULT_UPDATE_ENDPOINT.to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// The maximum time to wait for responses to requests made by the plugin
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    /// The address the game is directed to for the local servers, the game
+    /// only knows IPv4 so unspecified and IPv6 bind addresses map to loopback
+    pub fn local_address(&self) -> Ipv4Addr {
+        match self.bind_address {
+            IpAddr::V4(address) if !address.is_unspecified() => address,
+            _ => Ipv4Addr::LOCALHOST,
+        }
+    }
+
+    /// The client details used when requesting the official server instance
+    pub fn client_profile(&self) -> ClientProfile {
+        self.client
+            .clone()
+            .unwrap_or_else(|| self.client_sku.profile())
+    }
+}
+
+/// Loads the config file, writing the default config if one doesn't
+/// exist yet. Should only be called on initial startup
+pub fn load() {
+    let path = match dump_dir() {
+        Ok(dir) => dir.join(CONFIG_FILE),
+        Err(err) => {
+            error!("Failed to find config file, using defaults: {}", err);
+            _ = CONFIG.set(Config::default());
+            return;
+        }
+    };
+
+    let config = match std::fs::read(&path) {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Failed to parse config file, using defaults: {}", err);
+                Config::default()
+            }
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            FIRST_RUN.store(true, Ordering::Release);
+            let config = Config::default();
+            if let Err(err) = write(&config) {
+                error!("Failed to write default config file: {}", err);
+            }
+            config
+        }
+        Err(err) => {
+            error!("Failed to read config file, using defaults: {}", err);
+            Config::default()
+        }
+    };
+
+    debug!("Loaded config from {}", path.display());
+
+    _ = CONFIG.set(config);
+}
+
+/// Uses the provided config instead of loading the config file, used
+/// when running the proxy outside the game such as in the test harness.
+/// Has no effect once the config has been loaded or used
+pub fn set(config: Config) {
+    _ = CONFIG.set(config);
+}
+
+/// Reads the config file as it currently is on disk, which can differ
+/// from the loaded config after changes that apply on the next launch
+pub fn read_file() -> io::Result<Config> {
+    let bytes = std::fs::read(dump_dir()?.join(CONFIG_FILE))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Writes the provided config to the config file
+pub fn write(config: &Config) -> io::Result<()> {
+    let dir = dump_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let bytes = serde_json::to_vec_pretty(config)?;
+    std::fs::write(dir.join(CONFIG_FILE), bytes)
+}
+
+/// Writes the choices made in the setup wizard to the config file,
+/// every other setting keeps its loaded value
+pub fn write_choices(
+    profile: CaptureProfile,
+    anonymization: Anonymization,
+    privacy: PrivacyCategories,
+) -> io::Result<()> {
+    let mut value = serde_json::to_value(config())?;
+    value["profile"] = serde_json::to_value(profile)?;
+    value["anonymization"] = serde_json::to_value(anonymization)?;
+    value["privacy"] = serde_json::to_value(privacy)?;
+
+    let dir = dump_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let bytes = serde_json::to_vec_pretty(&value)?;
+    std::fs::write(dir.join(CONFIG_FILE), bytes)
+}
+
+/// Whether this is the first run of the plugin, the config file was
+/// created with the defaults when it was loaded
+pub fn is_first_run() -> bool {
+    FIRST_RUN.load(Ordering::Acquire)
+}
+
+/// Obtains the loaded config, defaults are used if the
+/// config hasn't been loaded
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
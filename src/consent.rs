@@ -0,0 +1,60 @@
+//! Consent for features that go beyond passively capturing the game's
+//! own traffic. Passive capture always runs, anything that sends its own
+//! requests to the official server or sends data elsewhere must be opted
+//! into through the config
+
+use crate::config::config;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Active features the user has opted into, every feature is off
+/// unless explicitly enabled in the config
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct Consent {
+    /// Allows sending synthetic requests to the official server using
+    /// the user's account (macro replays, fuzzing)
+    pub active_probing: bool,
+    /// Allows uploading captures
+    pub uploads: bool,
+    /// Allows sending anonymous metrics about the plugin's own operation
+    pub usage_metrics: bool,
+    /// Allows checking for newer releases of the plugin on startup
+    pub update_check: bool,
+}
+
+/// Feature requiring consent
+#[derive(Debug, Clone, Copy)]
+pub enum ActiveFeature {
+    Probing,
+    Upload,
+    Metrics,
+    UpdateCheck,
+}
+
+#[derive(Debug, Error)]
+#[error("{0:?} requires opting in through the consent section of the config")]
+pub struct ConsentError(pub ActiveFeature);
+
+/// Obtains the consent given in the config
+pub fn consent() -> Consent {
+    config().consent
+}
+
+/// Checks that the user has opted into the provided feature
+pub fn require(feature: ActiveFeature) -> Result<(), ConsentError> {
+    let consent = consent();
+    let allowed = match feature {
+        ActiveFeature::Probing => consent.active_probing,
+        ActiveFeature::Upload => consent.uploads,
+        ActiveFeature::Metrics => consent.usage_metrics,
+        ActiveFeature::UpdateCheck => consent.update_check,
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(ConsentError(feature))
+    }
+}
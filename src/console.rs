@@ -0,0 +1,420 @@
+//! Commands typed into the allocated console, for users who would rather
+//! control the plugin from the keyboard than through the HTTP control API.
+//! Commands are translated into control API routes so both behave the same
+//!
+//! Also holds the filters choosing which packets are written to the debug
+//! log, watching a single component is easier than reading every packet,
+//! the compact one line per packet format for watching live traffic and
+//! the stats summary shown in quiet mode
+
+use crate::{
+    capture::{capture, Direction},
+    config::config,
+    logging::ConsoleFormat,
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        control::handle_control,
+        packet::{FireFrame, FrameType, Packet},
+    },
+    tasks,
+};
+use axum::body::{to_bytes, Body};
+use hyper::{Method, Response};
+use log::error;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead},
+    sync::{Mutex, Once, RwLock},
+    time::Duration,
+};
+use tokio::{runtime::Handle, time::interval};
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::System::Console::{
+    GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    STD_OUTPUT_HANDLE,
+};
+
+/// Packet log filters, packets are only logged when a filter matches
+/// their component or command name. Every packet is logged when empty
+static FILTERS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Times requests were seen keyed by connection and sequence number, used
+/// to show the latency of their responses in compact packet lines
+static PENDING: Mutex<Option<HashMap<(u32, u16), u64>>> = Mutex::new(None);
+
+/// Requests that never get a response would otherwise be kept forever,
+/// the pending requests are forgotten once there are this many
+const MAX_PENDING: usize = 4096;
+
+/// Enables color escape sequences in the console on first use
+static ENABLE_COLORS: Once = Once::new();
+
+/// How often the stats summary is shown in quiet mode
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Width of the component and command column in compact packet lines
+const NAME_WIDTH: usize = 56;
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+/// Text printed for the help command
+const HELP: &str = "\
+Commands:
+  status                 Show the state of the plugin and servers
+  stats                  Show the traffic totals and capture writer stats
+  marker <label>         Place a marker in the capture
+  save                   Write queued packets, totals and the integrity file
+  filter                 List the packet log filters
+  filter add <name>      Only log packets whose component or command name contains <name>
+  filter remove <name>   Remove a packet log filter
+  filter clear           Remove every packet log filter, logging every packet
+  record start|stop      Start or stop recording
+  profile [name]         Show or switch the capture profile
+  review                 Open the data review window
+  get|post|delete <route> [query]
+                         Send any request to the control API
+  help                   Show this list";
+
+/// Starts reading commands from the console on its own thread and the
+/// stats summary in quiet mode, must be called from within the runtime
+pub fn start() {
+    let handle = Handle::current();
+    tasks::spawn_thread("console", move || run(handle));
+
+    if config().console_format == ConsoleFormat::Quiet {
+        tasks::spawn("console-summary", run_summary());
+    }
+}
+
+/// Shows the stats summary whenever it changed since it was last shown
+async fn run_summary() {
+    ENABLE_COLORS.call_once(enable_colors);
+    let mut interval = interval(SUMMARY_INTERVAL);
+    let mut last = String::new();
+
+    loop {
+        interval.tick().await;
+
+        let Some(summary) = summary() else {
+            continue;
+        };
+        if summary != last {
+            println!("{}", summary);
+            last = summary;
+        }
+    }
+}
+
+/// Creates the stats summary line from the capture totals
+fn summary() -> Option<String> {
+    let capture = capture()?;
+
+    let mut sessions = Vec::new();
+    let (mut packets, mut sent, mut received) = (0, 0, 0);
+    for entry in capture.bandwidth.snapshot() {
+        if !sessions.contains(&entry.session) {
+            sessions.push(entry.session);
+        }
+        packets += entry.packets;
+        match entry.direction {
+            Direction::Send => sent += entry.bytes,
+            Direction::Receive => received += entry.bytes,
+        }
+    }
+
+    let state = if capture.is_paused() {
+        "paused"
+    } else if capture.triggers.is_recording() {
+        "recording"
+    } else {
+        "waiting for trigger"
+    };
+
+    Some(format!(
+        "{DIM}[summary]{RESET} {} sessions | {} packets seen, {} captured | {} sent, {} received | {}",
+        sessions.len(),
+        packets,
+        capture.captured_packets(),
+        format_size(sent as usize),
+        format_size(received as usize),
+        state,
+    ))
+}
+
+fn run(handle: Handle) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Failed to read console command: {}", err);
+                return;
+            }
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_command(line) {
+            Ok(requests) => {
+                for (method, route, query) in requests {
+                    let response = handle_control(&method, &route, query.as_deref());
+                    print_response(&handle, response);
+                }
+            }
+            Err(message) => println!("{}", message),
+        }
+    }
+}
+
+/// Control API request made by a command
+type ControlRequest = (Method, String, Option<String>);
+
+/// Translates a console command into the control API requests it makes,
+/// the error is the message to show when the command isn't valid
+fn parse_command(line: &str) -> Result<Vec<ControlRequest>, String> {
+    let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let args = args.trim();
+
+    let request = |method: Method, route: String| (method, route, None);
+
+    let requests = match (command.to_lowercase().as_str(), args) {
+        ("help" | "?", _) => return Err(HELP.to_string()),
+        ("status", "") => vec![request(Method::GET, "status".to_string())],
+        ("stats", "") => vec![
+            request(Method::GET, "stats/bandwidth".to_string()),
+            request(Method::GET, "stats/writer".to_string()),
+        ],
+        ("marker", label) if !label.is_empty() => {
+            vec![request(Method::POST, format!("marker/{}", segment(label)?))]
+        }
+        ("save", "") => vec![request(Method::POST, "save".to_string())],
+        ("filter", "") => vec![request(Method::GET, "filters".to_string())],
+        ("filter", "clear") => vec![request(Method::DELETE, "filters".to_string())],
+        ("filter", args) => {
+            let (action, name) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            let method = match action {
+                "add" => Method::POST,
+                "remove" => Method::DELETE,
+                _ => return Err("Usage: filter [add|remove <name>|clear]".to_string()),
+            };
+            vec![request(
+                method,
+                format!("filters/{}", segment(name.trim())?),
+            )]
+        }
+        ("record", action @ ("start" | "stop")) => {
+            vec![request(Method::POST, format!("recording/{}", action))]
+        }
+        ("profile", "") => vec![request(Method::GET, "profile".to_string())],
+        ("profile", name) => vec![request(Method::POST, format!("profile/{}", segment(name)?))],
+        ("review", "") => vec![request(Method::POST, "review".to_string())],
+        (method @ ("get" | "post" | "delete"), args) if !args.is_empty() => {
+            let method = match method {
+                "get" => Method::GET,
+                "post" => Method::POST,
+                _ => Method::DELETE,
+            };
+            let (route, query) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            let route = route.trim_start_matches('/').to_string();
+            let query = Some(query.trim().to_string()).filter(|value| !value.is_empty());
+            vec![(method, route, query)]
+        }
+        _ => {
+            return Err(format!(
+                "Unknown command \"{}\", type \"help\" for the list of commands",
+                line
+            ))
+        }
+    };
+
+    Ok(requests)
+}
+
+/// Checks the provided value can be used as a single route segment
+fn segment(value: &str) -> Result<&str, String> {
+    if value.is_empty() {
+        return Err("Missing command argument".to_string());
+    }
+    if value.contains('/') {
+        return Err("Command arguments can't contain \"/\"".to_string());
+    }
+    Ok(value)
+}
+
+/// Prints the status and JSON body of a control API response
+fn print_response(handle: &Handle, response: Response<Body>) {
+    let status = response.status();
+    let body = match handle.block_on(to_bytes(response.into_body(), usize::MAX)) {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to read control API response: {}", err);
+            return;
+        }
+    };
+
+    let body = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        .unwrap_or_else(|| String::from_utf8_lossy(&body).into_owned());
+
+    if status.is_success() {
+        println!("{}", body);
+    } else {
+        println!("{}: {}", status, body);
+    }
+}
+
+/// The current packet log filters
+pub fn filters() -> Vec<String> {
+    FILTERS.read().expect("Filters lock poisoned").clone()
+}
+
+/// Adds a packet log filter, returns false if the filter already exists
+pub fn add_filter(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let mut filters = FILTERS.write().expect("Filters lock poisoned");
+    if filters.contains(&name) {
+        return false;
+    }
+    filters.push(name);
+    true
+}
+
+/// Removes a packet log filter, returns false if there was no such filter
+pub fn remove_filter(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let mut filters = FILTERS.write().expect("Filters lock poisoned");
+    let length = filters.len();
+    filters.retain(|value| value != &name);
+    filters.len() != length
+}
+
+/// Removes every packet log filter
+pub fn clear_filters() {
+    FILTERS.write().expect("Filters lock poisoned").clear();
+}
+
+/// Whether a packet with the provided frame should be written to the
+/// debug log according to the packet log filters
+pub fn logs_packet(frame: &FireFrame) -> bool {
+    let filters = FILTERS.read().expect("Filters lock poisoned");
+    if filters.is_empty() {
+        return true;
+    }
+
+    let key = component_key(frame.component, frame.command);
+    let names = [
+        get_component_name(frame.component),
+        get_command_name(key, matches!(frame.ty, FrameType::Notify)),
+    ];
+
+    names
+        .into_iter()
+        .flatten()
+        .map(str::to_lowercase)
+        .any(|name| filters.iter().any(|filter| name.contains(filter.as_str())))
+}
+
+/// Prints the compact console line for a packet, the line holds the
+/// direction, connection, frame type, component and command, content size
+/// and the latency when the packet responds to a request seen earlier
+///
+/// `time` Unix timestamp in microseconds the packet was seen
+pub fn print_packet(time: u64, connection: u32, direction: Direction, packet: &Packet) {
+    ENABLE_COLORS.call_once(enable_colors);
+
+    let frame = &packet.frame;
+    let latency = track_latency(time, connection, frame);
+
+    let (arrow, arrow_color) = match direction {
+        Direction::Send => ("->", CYAN),
+        Direction::Receive => ("<-", GREEN),
+    };
+    let (ty, ty_color) = match frame.ty {
+        FrameType::Request => ("REQ", CYAN),
+        FrameType::Response => ("RES", GREEN),
+        FrameType::Notify => ("NTF", YELLOW),
+        FrameType::Error => ("ERR", RED),
+        FrameType::Unknown(_) => ("???", DIM),
+    };
+    let ty_color = if frame.error != 0 { RED } else { ty_color };
+
+    let key = component_key(frame.component, frame.command);
+    let name = format!(
+        "{}\u{2192}{}",
+        get_component_name(frame.component)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{:#06x}", frame.component)),
+        get_command_name(key, matches!(frame.ty, FrameType::Notify))
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{:#06x}", frame.command)),
+    );
+
+    let latency = latency
+        .map(|value| format!("{:>8.1} ms", value as f64 / 1000.0))
+        .unwrap_or_default();
+
+    println!(
+        "{arrow_color}{arrow}{RESET} {DIM}#{connection:<3}{RESET} {ty_color}{ty}{RESET} {name:<width$} {size:>9} {DIM}{latency}{RESET}",
+        size = format_size(packet.contents.len()),
+        width = NAME_WIDTH,
+    );
+}
+
+/// Remembers when requests were seen and obtains the latency in
+/// microseconds of responses to them
+fn track_latency(time: u64, connection: u32, frame: &FireFrame) -> Option<u64> {
+    let mut pending = PENDING.lock().expect("Pending requests lock poisoned");
+    let pending = pending.get_or_insert_with(HashMap::new);
+
+    match frame.ty {
+        FrameType::Request => {
+            if pending.len() >= MAX_PENDING {
+                pending.clear();
+            }
+            pending.insert((connection, frame.seq), time);
+            None
+        }
+        FrameType::Response | FrameType::Error => pending
+            .remove(&(connection, frame.seq))
+            .map(|started| time.saturating_sub(started)),
+        _ => None,
+    }
+}
+
+/// Formats a number of bytes for the compact packet lines and summary
+fn format_size(length: usize) -> String {
+    if length < 1024 {
+        format!("{} B", length)
+    } else if length < 1024 * 1024 {
+        format!("{:.1} KB", length as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", length as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Allows the console to interpret the color escape sequences, consoles
+/// on older versions of Windows show them as text instead
+#[cfg(feature = "plugin")]
+fn enable_colors() {
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) != 0 {
+            SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING);
+        }
+    }
+}
+
+/// Terminals outside of Windows interpret the color escape sequences
+#[cfg(not(feature = "plugin"))]
+fn enable_colors() {}
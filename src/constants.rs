@@ -9,3 +9,8 @@ pub const MAIN_PORT: u16 = 42128;
 pub const TELEMETRY_PORT: u16 = 42129;
 /// The local HTTP server port
 pub const HTTP_PORT: u16 = 42131;
+
+/// Short hash of the git commit the plugin was built from
+pub const GIT_HASH: &str = env!("PRDC_GIT_HASH");
+/// Unix timestamp in seconds of when the plugin was built
+pub const BUILD_TIME: &str = env!("PRDC_BUILD_TIME");
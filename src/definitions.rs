@@ -0,0 +1,328 @@
+use crate::capture::{
+    dump_dir,
+    value::{Field, TdfValue},
+};
+use log::{debug, error};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, io, sync::OnceLock};
+
+/// The file name of the definitions file within the dump directory
+const DEFINITIONS_FILE: &str = "definitions.json";
+
+/// The loaded definitions
+static DEFINITIONS: OnceLock<Definitions> = OnceLock::new();
+
+/// External definitions extending the built-in component tables, loaded
+/// from the definitions file in the dump directory when present
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Definitions {
+    /// Additional command names
+    pub commands: Vec<NameDefinition>,
+    /// Additional notification names
+    pub notifications: Vec<NameDefinition>,
+    /// Hints for naming the integer values of fields
+    pub hints: Vec<Hint>,
+    /// Names for error codes
+    pub errors: Vec<ErrorDefinition>,
+}
+
+/// Name for a command or notification
+#[derive(Deserialize)]
+pub struct NameDefinition {
+    pub component: u16,
+    pub command: u16,
+    pub name: String,
+}
+
+/// Name for an error code
+#[derive(Deserialize)]
+pub struct ErrorDefinition {
+    /// The component the error belongs to, applies to all
+    /// components when not specified
+    #[serde(default)]
+    pub component: Option<u16>,
+    pub code: u16,
+    pub name: String,
+}
+
+/// Hint mapping the integer values of a tag to names
+#[derive(Deserialize)]
+pub struct Hint {
+    /// The component the hint applies to, applies to all
+    /// components when not specified
+    #[serde(default)]
+    pub component: Option<u16>,
+    /// The command the hint applies to, applies to all
+    /// commands when not specified
+    #[serde(default)]
+    pub command: Option<u16>,
+    /// The tag of the field
+    pub tag: String,
+    /// How the values are interpreted
+    #[serde(default)]
+    pub kind: HintKind,
+    /// The names for each value, for bitfields each value is a bit
+    pub values: BTreeMap<u64, String>,
+}
+
+/// How the value of a [Hint] is interpreted
+#[derive(Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HintKind {
+    /// The value is one of the named values
+    #[default]
+    Enum,
+    /// The value is a combination of the named bits
+    Bitfield,
+}
+
+impl Hint {
+    fn new(component: u16, tag: &str, kind: HintKind, values: &[(u64, &str)]) -> Self {
+        Self {
+            component: Some(component),
+            command: None,
+            tag: tag.to_string(),
+            kind,
+            values: values
+                .iter()
+                .map(|(value, name)| (*value, name.to_string()))
+                .collect(),
+        }
+    }
+
+    fn matches(&self, component: u16, command: u16, tag: &str) -> bool {
+        self.tag == tag
+            && self.component.is_none_or(|value| value == component)
+            && self.command.is_none_or(|value| value == command)
+    }
+
+    /// Describes the provided value using the hint names, None if
+    /// the value isn't known
+    pub fn describe(&self, value: u64) -> Option<String> {
+        match self.kind {
+            HintKind::Enum => self.values.get(&value).cloned(),
+            HintKind::Bitfield => {
+                let names: Vec<&str> = self
+                    .values
+                    .iter()
+                    .filter(|(bit, _)| **bit != 0 && value & **bit == **bit)
+                    .map(|(_, name)| name.as_str())
+                    .collect();
+                if names.is_empty() {
+                    self.values.get(&value).cloned()
+                } else {
+                    Some(names.join(" | "))
+                }
+            }
+        }
+    }
+}
+
+/// Loads the definitions file merging it with the built-in definitions.
+/// Should only be called on initial startup
+pub fn load() {
+    let read = dump_dir().and_then(|dir| {
+        let path = dir.join(DEFINITIONS_FILE);
+        std::fs::read(&path).map(|bytes| (path, bytes))
+    });
+
+    let mut definitions: Definitions = match read {
+        Ok((path, bytes)) => match serde_json::from_slice(&bytes) {
+            Ok(value) => {
+                debug!("Loaded definitions from {}", path.display());
+                value
+            }
+            Err(err) => {
+                error!("Failed to parse definitions file: {}", err);
+                Definitions::default()
+            }
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Definitions::default(),
+        Err(err) => {
+            error!("Failed to read definitions file: {}", err);
+            Definitions::default()
+        }
+    };
+
+    // Built-ins come after the external definitions so they can be overridden
+    definitions.hints.extend(builtin_hints());
+    definitions.errors.extend(builtin_errors());
+
+    _ = DEFINITIONS.set(definitions);
+}
+
+/// Obtains the loaded definitions
+pub fn definitions() -> &'static Definitions {
+    DEFINITIONS.get_or_init(Definitions::default)
+}
+
+/// Finds the hint for the provided field tag
+pub fn find_hint(component: u16, command: u16, tag: &str) -> Option<&'static Hint> {
+    definitions()
+        .hints
+        .iter()
+        .find(|hint| hint.matches(component, command, tag))
+}
+
+/// Whether any hint applies to fields of the provided command, used to
+/// skip decoding contents just to describe them
+pub fn has_hints(component: u16, command: u16) -> bool {
+    definitions().hints.iter().any(|hint| {
+        hint.component.is_none_or(|value| value == component)
+            && hint.command.is_none_or(|value| value == command)
+    })
+}
+
+/// Finds the name of the provided error code
+pub fn error_name(component: u16, code: u16) -> Option<&'static str> {
+    definitions()
+        .errors
+        .iter()
+        .find(|error| error.code == code && error.component.is_none_or(|value| value == component))
+        .map(|error| error.name.as_str())
+}
+
+/// Collects descriptions of all the hinted values within the provided
+/// fields as `PATH = VALUE (NAME)` lines
+pub fn describe_fields(component: u16, command: u16, fields: &[Field]) -> Vec<String> {
+    let mut output = Vec::new();
+    for field in fields {
+        describe_value(
+            component,
+            command,
+            &field.tag,
+            &field.tag,
+            &field.value,
+            &mut output,
+        );
+    }
+    output
+}
+
+fn describe_value(
+    component: u16,
+    command: u16,
+    tag: &str,
+    path: &str,
+    value: &TdfValue,
+    output: &mut Vec<String>,
+) {
+    match value {
+        TdfValue::VarInt(_) => {
+            let Some(value) = value.as_u64() else {
+                return;
+            };
+            if let Some(name) =
+                find_hint(component, command, tag).and_then(|hint| hint.describe(value))
+            {
+                output.push(format!("{} = {} ({})", path, value, name));
+            }
+        }
+        TdfValue::Group(fields) => {
+            for field in fields {
+                let path = format!("{}.{}", path, field.tag);
+                describe_value(component, command, &field.tag, &path, &field.value, output);
+            }
+        }
+        TdfValue::List(values) => {
+            for (index, value) in values.iter().enumerate() {
+                let path = format!("{}[{}]", path, index);
+                describe_value(component, command, tag, &path, value, output);
+            }
+        }
+        TdfValue::Map(pairs) => {
+            for (index, (_, value)) in pairs.iter().enumerate() {
+                let path = format!("{}[{}]", path, index);
+                describe_value(component, command, tag, &path, value, output);
+            }
+        }
+        TdfValue::Union {
+            value: Some(field), ..
+        } => {
+            let path = format!("{}.{}", path, field.tag);
+            describe_value(component, command, &field.tag, &path, &field.value, output);
+        }
+        _ => {}
+    }
+}
+
+/// Adds a `<TAG>_name` entry next to every hinted value within the
+/// JSON representation of a packets fields
+pub fn annotate_json(component: u16, command: u16, value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let names: Vec<(String, String)> = map
+                .iter()
+                .filter_map(|(tag, value)| {
+                    let name = find_hint(component, command, tag)?.describe(value.as_u64()?)?;
+                    Some((format!("{}_name", tag), name))
+                })
+                .collect();
+
+            for value in map.values_mut() {
+                annotate_json(component, command, value);
+            }
+
+            for (key, name) in names {
+                map.insert(key, Value::String(name));
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                annotate_json(component, command, value);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[rustfmt::skip]
+fn builtin_hints() -> Vec<Hint> {
+    use crate::servers::components::{game_manager as g, user_sessions as us};
+
+    vec![
+        Hint::new(g::COMPONENT, "GSTA", HintKind::Enum, &[
+            (0x0, "NewState"),
+            (0x1, "Initializing"),
+            (0x2, "Virtual"),
+            (0x82, "PreGame"),
+            (0x83, "InGame"),
+            (0x4, "PostGame"),
+            (0x5, "Migrating"),
+            (0x6, "Destructing"),
+            (0x7, "Resetable"),
+            (0x8, "ReplaySetup"),
+        ]),
+        Hint::new(us::COMPONENT, "NATT", HintKind::Enum, &[
+            (0, "Open"),
+            (1, "Moderate"),
+            (2, "Sequential"),
+            (3, "Strict"),
+            (4, "Unknown"),
+        ]),
+    ]
+}
+
+#[rustfmt::skip]
+fn builtin_errors() -> Vec<ErrorDefinition> {
+    [
+        (0x4000, "Cancelled"),
+        (0x4001, "Disconnected"),
+        (0x4002, "DuplicateLogin"),
+        (0x4003, "AuthorizationRequired"),
+        (0x4004, "Timeout"),
+        (0x4005, "ComponentNotFound"),
+        (0x4006, "CommandNotFound"),
+        (0x4007, "AuthenticationRequired"),
+        (0x4009, "System"),
+    ]
+    .into_iter()
+    .map(|(code, name)| ErrorDefinition {
+        component: None,
+        code,
+        name: name.to_string(),
+    })
+    .collect()
+}
@@ -0,0 +1,206 @@
+//! Error taxonomy shared by the servers and the capture. Every error has a
+//! stable code, grouped by what failed (`E1xx` running the local servers,
+//! `E2xx` upstream servers, `E3xx` decoding, `E4xx` capture files), which
+//! is logged with the error and counted for the status output, session
+//! manifest and crash reports so bug reports can be triaged mechanically
+
+use crate::{
+    capture::now_millis,
+    metrics::{self, ErrorCategory},
+    servers::retriever::InstanceError,
+};
+use log::{error, warn};
+use serde::Serialize;
+use std::{collections::BTreeMap, io, sync::Mutex};
+use thiserror::Error;
+
+/// Counts and latest occurrence of each error code reported this run
+static REPORTED: Mutex<BTreeMap<&'static str, ErrorSummary>> = Mutex::new(BTreeMap::new());
+
+/// Error reported by the plugin
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error(transparent)]
+    Bind(#[from] BindError),
+    #[error(transparent)]
+    Serve(#[from] ServeError),
+    #[error(transparent)]
+    Upstream(#[from] UpstreamError),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    CaptureIo(#[from] CaptureIoError),
+}
+
+/// Errors starting the local servers
+#[derive(Debug, Error)]
+pub enum BindError {
+    #[error("Failed to bind {server} server to port {port}: {source}")]
+    Listen {
+        server: &'static str,
+        port: u16,
+        source: io::Error,
+    },
+}
+
+/// Errors serving connections accepted by the local servers
+#[derive(Debug, Error)]
+pub enum ServeError {
+    #[error("Failed to upgrade client HTTP connection: {0}")]
+    Upgrade(hyper::Error),
+}
+
+/// Errors reaching the official servers or another upstream
+#[derive(Debug, Error)]
+pub enum UpstreamError {
+    #[error("Failed to create official instance: {0}")]
+    Instance(#[from] InstanceError),
+    #[error("Failed to obtain session with upstream {upstream}: {source}")]
+    Connect { upstream: String, source: io::Error },
+    #[error("Failed to create HTTP client: {0}")]
+    HttpClient(reqwest::Error),
+    #[error("Failed to send HTTP request: {0}")]
+    HttpRequest(reqwest::Error),
+    #[error("Failed to read HTTP response body: {0}")]
+    HttpBody(reqwest::Error),
+    #[error("Failed to upgrade HTTP connection to the server: {0}")]
+    HttpUpgrade(reqwest::Error),
+}
+
+/// Errors decoding traffic passing through the proxy
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("Failed to decode {component:#06x}->{command:#06x} contents at {path:?}: {message}")]
+    Contents {
+        component: u16,
+        command: u16,
+        /// Tag path of the group the decoding failed in
+        path: Vec<String>,
+        message: String,
+    },
+}
+
+/// Errors writing the capture session files
+#[derive(Debug, Error)]
+pub enum CaptureIoError {
+    #[error("Failed to create capture session directory: {0}")]
+    CreateSession(io::Error),
+    #[error("Failed to write {what}: {source}")]
+    Write {
+        /// What was being written, such as "packet log"
+        what: &'static str,
+        source: io::Error,
+    },
+    #[error("Failed to write capture integrity file: {0}")]
+    Integrity(io::Error),
+}
+
+impl PluginError {
+    /// Stable code identifying the kind of error
+    pub fn code(&self) -> &'static str {
+        match self {
+            PluginError::Bind(BindError::Listen { .. }) => "E101",
+            PluginError::Serve(err) => match err {
+                ServeError::Upgrade(_) => "E103",
+            },
+            PluginError::Upstream(err) => match err {
+                UpstreamError::Instance(_) => "E201",
+                UpstreamError::Connect { .. } => "E202",
+                UpstreamError::HttpClient(_) => "E203",
+                UpstreamError::HttpRequest(_) => "E204",
+                UpstreamError::HttpBody(_) => "E205",
+                UpstreamError::HttpUpgrade(_) => "E206",
+            },
+            PluginError::Decode(DecodeError::Contents { .. }) => "E301",
+            PluginError::CaptureIo(err) => match err {
+                CaptureIoError::CreateSession(_) => "E401",
+                CaptureIoError::Write { .. } => "E402",
+                CaptureIoError::Integrity(_) => "E403",
+            },
+        }
+    }
+
+    /// Category the error is counted under in the usage metrics
+    fn category(&self) -> Option<ErrorCategory> {
+        match self {
+            PluginError::Upstream(UpstreamError::Connect { .. }) => {
+                Some(ErrorCategory::UpstreamConnect)
+            }
+            PluginError::CaptureIo(_) => Some(ErrorCategory::CaptureWrite),
+            _ => None,
+        }
+    }
+}
+
+impl From<PluginError> for io::Error {
+    fn from(value: PluginError) -> Self {
+        io::Error::new(io::ErrorKind::Other, value)
+    }
+}
+
+/// Occurrences of an error code
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ErrorSummary {
+    pub code: &'static str,
+    /// Number of times the error was reported
+    pub count: u64,
+    /// Unix timestamp in milliseconds of the first occurrence
+    pub first_at: u64,
+    /// Unix timestamp in milliseconds of the latest occurrence
+    pub last_at: u64,
+    /// Message of the latest occurrence
+    pub last_message: String,
+}
+
+/// Logs the error with its code and counts it, returns the error so it
+/// can still be propagated
+pub fn report(err: impl Into<PluginError>) -> PluginError {
+    let err = err.into();
+    let code = err.code();
+    let message = err.to_string();
+
+    // Undecodable contents are expected for packets without definitions
+    if matches!(err, PluginError::Decode(_)) {
+        warn!("[{}] {}", code, message);
+    } else {
+        error!("[{}] {}", code, message);
+    }
+
+    if let Some(category) = err.category() {
+        metrics::count_error(category);
+    }
+
+    let now = now_millis();
+    let reported = &mut *REPORTED.lock().expect("Reported errors lock poisoned");
+    let summary = reported.entry(code).or_insert_with(|| ErrorSummary {
+        code,
+        count: 0,
+        first_at: now,
+        last_at: now,
+        last_message: String::new(),
+    });
+    summary.count += 1;
+    summary.last_at = now;
+    summary.last_message = message;
+
+    err
+}
+
+/// Summary of every error code reported this run
+pub fn summary() -> Vec<ErrorSummary> {
+    REPORTED
+        .lock()
+        .expect("Reported errors lock poisoned")
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Summary of every error code reported this run without waiting for the
+/// lock, None if the lock is held. Used by the crash handler where the
+/// crashing thread may hold the lock
+pub fn try_summary() -> Option<Vec<ErrorSummary>> {
+    let reported = REPORTED.try_lock().ok()?;
+    Some(reported.values().cloned().collect())
+}
@@ -0,0 +1,132 @@
+use crate::{
+    capture::{capture, integrity::hash_file},
+    hooks,
+    pattern::{self, PatternMatch},
+    variant::GameVariant,
+};
+use log::{debug, error};
+use serde::Serialize;
+use std::path::Path;
+#[cfg(feature = "plugin")]
+use std::{ffi::c_void, os::windows::ffi::OsStrExt};
+#[cfg(feature = "plugin")]
+use windows_sys::{
+    w,
+    Win32::Storage::FileSystem::{
+        GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO,
+    },
+};
+
+/// Details identifying the build of the game executable
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GameFingerprint {
+    /// Path to the game executable
+    pub path: String,
+    /// Size of the executable in bytes
+    pub size: Option<u64>,
+    /// SHA-256 of the executable as lowercase hex
+    pub sha256: Option<String>,
+    /// File version from the executable version resource
+    pub version: Option<String>,
+    /// The game variant detected when hooking
+    pub variant: Option<GameVariant>,
+    /// Addresses the hook patterns were found at
+    pub patterns: Vec<PatternMatch>,
+}
+
+/// Computes the fingerprint of the running game executable and adds it
+/// to the capture manifest. Hashing the executable is slow so this is
+/// done on a blocking thread, should be called after hooking so the
+/// pattern matches are known
+pub fn start() {
+    tokio::task::spawn_blocking(|| {
+        let fingerprint = match compute() {
+            Some(value) => value,
+            None => return,
+        };
+
+        debug!(
+            "Game fingerprint: {} (Version: {:?}, SHA-256: {:?})",
+            fingerprint.path, fingerprint.version, fingerprint.sha256
+        );
+
+        if let Some(capture) = capture() {
+            capture.update_manifest(|manifest| manifest.game = Some(fingerprint));
+        }
+    });
+}
+
+/// Computes the fingerprint of the running game executable
+pub fn compute() -> Option<GameFingerprint> {
+    let path = match std::env::current_exe() {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to determine game executable path: {}", err);
+            return None;
+        }
+    };
+
+    let (size, sha256) = match hash_file(&path) {
+        Ok((size, sha256)) => (Some(size), Some(sha256)),
+        Err(err) => {
+            error!("Failed to hash game executable: {}", err);
+            (None, None)
+        }
+    };
+
+    Some(GameFingerprint {
+        path: path.display().to_string(),
+        size,
+        sha256,
+        version: unsafe { file_version(&path) },
+        variant: hooks::variant(),
+        patterns: pattern::matches(),
+    })
+}
+
+/// Reads the file version from the version resource of the file at
+/// `path` in the `major.minor.build.revision` format
+#[cfg(feature = "plugin")]
+pub unsafe fn file_version(path: &Path) -> Option<String> {
+    let path: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    let mut handle = 0;
+    let size = GetFileVersionInfoSizeW(path.as_ptr(), &mut handle);
+    if size == 0 {
+        return None;
+    }
+
+    let mut data = vec![0u8; size as usize];
+    if GetFileVersionInfoW(path.as_ptr(), 0, size, data.as_mut_ptr().cast()) == 0 {
+        return None;
+    }
+
+    let mut info: *mut c_void = std::ptr::null_mut();
+    let mut length = 0;
+    if VerQueryValueW(data.as_ptr().cast(), w!("\\"), &mut info, &mut length) == 0
+        || info.is_null()
+        || (length as usize) < std::mem::size_of::<VS_FIXEDFILEINFO>()
+    {
+        return None;
+    }
+
+    let info = &*(info as *const VS_FIXEDFILEINFO);
+    Some(format!(
+        "{}.{}.{}.{}",
+        info.dwFileVersionMS >> 16,
+        info.dwFileVersionMS & 0xFFFF,
+        info.dwFileVersionLS >> 16,
+        info.dwFileVersionLS & 0xFFFF
+    ))
+}
+
+/// Version resources aren't read in builds without the plugin
+#[cfg(not(feature = "plugin"))]
+pub unsafe fn file_version(_path: &Path) -> Option<String> {
+    None
+}
@@ -1,14 +1,31 @@
-use crate::pattern::{fill_bytes, Pattern};
-use log::debug;
+use crate::{pattern::Patch, servers::retriever::ClientSku, variant::GameVariant};
+use serde::Serialize;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    OnceLock,
+};
+
+#[cfg(feature = "plugin")]
+use crate::{
+    capture::capture,
+    config::config,
+    memory,
+    pattern::{self, fill_bytes, Pattern},
+};
+#[cfg(feature = "plugin")]
+use log::{debug, error, info};
+#[cfg(feature = "plugin")]
 use std::{
     alloc::{alloc, Layout},
     ffi::{CStr, CString},
 };
+#[cfg(feature = "plugin")]
 use windows_sys::{
     core::PCSTR,
     Win32::Networking::WinSock::{gethostbyname, HOSTENT},
 };
 
+#[cfg(feature = "plugin")]
 const VERIFY_CERTIFICATE_PATTERN: Pattern = Pattern {
     name: "VerifyCertificate",
     start: 0x401000,
@@ -17,6 +34,7 @@ const VERIFY_CERTIFICATE_PATTERN: Pattern = Pattern {
     op: &[0xB8, 0xE4, 0xFF, 0xFF, 0xFF, 0x5B, 0x59, 0xC3],
 };
 
+#[cfg(feature = "plugin")]
 const HOSTNAME_LOOKUP_PATTERN: Pattern = Pattern {
     name: "gethostbyname",
     start: 0x401000,
@@ -43,11 +61,198 @@ const HOSTNAME_LOOKUP_PATTERN: Pattern = Pattern {
     ],
 };
 
+/// Name of the file in the session directory describing the applied hooks
+pub const HOOKS_FILE: &str = "hooks.json";
+
+/// Hook that can be applied to the game
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Hook {
+    /// Redirects lookups for the redirector host to the local proxy
+    HostLookup,
+    /// Disables verification of the server certificate
+    CertCheck,
+}
+
+impl Hook {
+    /// Name of the hook used in the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::HostLookup => "host_lookup",
+            Hook::CertCheck => "cert_check",
+        }
+    }
+}
+
+/// Outcome of applying a hook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStatus {
+    /// The hook pattern was found and patched
+    Applied,
+    /// The memory couldn't be made writable so the hook wasn't patched
+    ProtectFailed,
+    /// The hook pattern wasn't found in the game
+    NotFound,
+    /// The hook was disabled in the config
+    Disabled,
+    /// The client SKU doesn't have a pattern for the hook
+    Incompatible,
+}
+
+/// Details of a hook written to the hooks file for diagnosing
+/// hooks on unfamiliar game builds
+#[derive(Debug, Serialize)]
+pub struct HookRecord {
+    /// The hook
+    pub hook: Hook,
+    /// Name of the pattern used for the hook
+    pub pattern: Option<&'static str>,
+    /// Outcome of applying the hook
+    pub status: HookStatus,
+    /// The memory changed by the hook
+    pub patch: Option<Patch>,
+    /// Address of the plugin function the game is redirected to
+    pub trampoline: Option<usize>,
+}
+
+/// Hooks file contents
+#[derive(Debug, Serialize)]
+pub struct HooksReport {
+    /// The game variant detected when hooking
+    pub variant: GameVariant,
+    /// The client SKU the hook patterns were selected for
+    pub sku: ClientSku,
+    pub hooks: Vec<HookRecord>,
+}
+
+/// Patterns used to apply the hooks for a client SKU, hooks that don't
+/// have a pattern are incompatible with the SKU
+#[cfg(feature = "plugin")]
+struct PatternSet {
+    host_lookup: Option<&'static Pattern>,
+    cert_check: Option<&'static Pattern>,
+}
+
+#[cfg(feature = "plugin")]
+impl ClientSku {
+    /// The pattern set to use for the SKU
+    fn patterns(&self) -> PatternSet {
+        match self {
+            ClientSku::Retail => PatternSet {
+                host_lookup: Some(&HOSTNAME_LOOKUP_PATTERN),
+                cert_check: Some(&VERIFY_CERTIFICATE_PATTERN),
+            },
+            // The demo resolves the redirector through the same DirtySDK
+            // lookup, its certificate check hasn't been located
+            ClientSku::Demo => PatternSet {
+                host_lookup: Some(&HOSTNAME_LOOKUP_PATTERN),
+                cert_check: None,
+            },
+        }
+    }
+}
+
+/// The game variant selected when hooking
+static VARIANT: OnceLock<GameVariant> = OnceLock::new();
+
+/// Obtains the game variant selected when hooking
+pub fn variant() -> Option<GameVariant> {
+    VARIANT.get().copied()
+}
+
+/// Whether the host lookup hook has redirected a lookup
+static LOOKUP_REDIRECTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the game's lookup of the redirector host went through the host
+/// lookup hook, otherwise the hosts file is redirecting the game
+pub fn lookup_redirected() -> bool {
+    LOOKUP_REDIRECTED.load(Ordering::Acquire)
+}
+
+#[cfg(feature = "plugin")]
 pub unsafe fn hook() {
-    hook_host_lookup();
-    hook_cert_check();
+    let config = config();
+
+    // The variant is only reported, every known variant runs the same
+    // executable so the patterns depend on the client SKU. The configured
+    // variant overrides detection for unrecognized installs
+    let variant = config.game_variant.unwrap_or_else(GameVariant::detect);
+    _ = VARIANT.set(variant);
+
+    pattern::load_cache();
+
+    let sku = config.client_sku;
+    let patterns = sku.patterns();
+    let apply = |hook: Hook,
+                 pattern: Option<&'static Pattern>,
+                 trampoline: Option<usize>,
+                 action: unsafe fn(&Pattern) -> Option<Patch>| {
+        let mut record = HookRecord {
+            hook,
+            pattern: pattern.map(|pattern| pattern.name),
+            status: HookStatus::Incompatible,
+            patch: None,
+            trampoline: None,
+        };
+
+        let Some(pattern) = pattern else {
+            info!("Skipping {} hook for {:?}", hook.name(), sku);
+            return record;
+        };
+        if config.disabled_hooks.iter().any(|name| name == hook.name()) {
+            info!("Hook {} disabled by config", hook.name());
+            record.status = HookStatus::Disabled;
+            return record;
+        }
+
+        record.patch = action(pattern);
+        record.status = match &record.patch {
+            Some(Patch {
+                patched: Some(_), ..
+            }) => {
+                record.trampoline = trampoline;
+                HookStatus::Applied
+            }
+            Some(_) => HookStatus::ProtectFailed,
+            None => HookStatus::NotFound,
+        };
+        record
+    };
+
+    let report = HooksReport {
+        variant,
+        sku,
+        hooks: vec![
+            apply(
+                Hook::HostLookup,
+                patterns.host_lookup,
+                Some(fake_gethostbyname as usize),
+                hook_host_lookup,
+            ),
+            apply(Hook::CertCheck, patterns.cert_check, None, hook_cert_check),
+        ],
+    };
+
+    pattern::save_cache();
+
+    info!("Applied hooks for {:?} on game variant {:?}", sku, variant);
+    if let Some(capture) = capture() {
+        capture.record_event(
+            "hooks",
+            &format!("Hooked {:?} on game variant {:?}", sku, variant),
+        );
+
+        let result = serde_json::to_vec_pretty(&report)
+            .map_err(std::io::Error::from)
+            .and_then(|bytes| std::fs::write(capture.dir.join(HOOKS_FILE), bytes));
+        if let Err(err) = result {
+            error!("Failed to write hooks file: {}", err);
+        }
+    }
 }
 
+#[cfg(feature = "plugin")]
 #[no_mangle]
 pub unsafe extern "system" fn fake_gethostbyname(name: PCSTR) -> *mut HOSTENT {
     // Resolve the name
@@ -62,7 +267,9 @@ pub unsafe extern "system" fn fake_gethostbyname(name: PCSTR) -> *mut HOSTENT {
         return gethostbyname(name);
     }
 
-    debug!("Responding with localhost redirect");
+    let local_address = config().local_address();
+    debug!("Responding with redirect to {}", local_address);
+    LOOKUP_REDIRECTED.store(true, Ordering::Release);
     let host = CString::new("gosredirector.ea.com").unwrap();
 
     // Empty aliases
@@ -71,7 +278,8 @@ pub unsafe extern "system" fn fake_gethostbyname(name: PCSTR) -> *mut HOSTENT {
     *aliases = std::ptr::null_mut();
 
     // Create the target address
-    let mut address: Vec<i8> = [127, 0, 0, 1]
+    let mut address: Vec<i8> = local_address
+        .octets()
         .iter()
         .chain(host.as_bytes_with_nul())
         .map(|value| *value as i8)
@@ -97,38 +305,42 @@ pub unsafe extern "system" fn fake_gethostbyname(name: PCSTR) -> *mut HOSTENT {
     Box::into_raw(result)
 }
 
-unsafe fn hook_host_lookup() {
+#[cfg(feature = "plugin")]
+unsafe fn hook_host_lookup(pattern: &Pattern) -> Option<Patch> {
     Pattern::apply_with_transform(
-        &HOSTNAME_LOOKUP_PATTERN,
+        pattern,
         4,
         |addr| {
             // Initial -> f652b0
 
             // == Obtain the address from the call ????
             // call ???? (Obtain the relative call distance)
-            let distance = *(addr.add(1 /* Skip call opcode */) as *const usize);
+            let distance: usize = memory::read(addr.add(1 /* Skip call opcode */) as usize)?;
 
             // Relative jump -> EEF240 (jump to jmp in thunk table)
-            let jmp_address = addr.add(5 /* Skip call opcode + address */ + distance);
+            let jmp_address = (addr as usize)
+                .wrapping_add(5 /* Skip call opcode + address */)
+                .wrapping_add(distance);
 
             // == Address to the final ptr
             // jmp dword ptr ds:[????]
-            let address = *(jmp_address.add(2 /* Skip ptr jmp opcode */) as *const usize);
+            let address: usize = memory::read(jmp_address + 2 /* Skip ptr jmp opcode */)?;
 
             // Invalid call at -> 019A4DF1
 
-            address as *const u8
+            Some(address as *const u8)
         },
         |addr| {
             // Replace the address with our faker function
             let ptr: *mut usize = addr as *mut usize;
             *ptr = fake_gethostbyname as usize;
         },
-    );
+    )
 }
 
-unsafe fn hook_cert_check() {
-    Pattern::apply(&VERIFY_CERTIFICATE_PATTERN, 8, |addr| {
+#[cfg(feature = "plugin")]
+unsafe fn hook_cert_check(pattern: &Pattern) -> Option<Patch> {
+    Pattern::apply(pattern, 8, |addr| {
         fill_bytes(addr.add(1), &[0; 4]);
-    });
+    })
 }
@@ -1,15 +1,36 @@
 #![allow(clippy::missing_safety_doc)]
 
+#[cfg(feature = "plugin")]
 use windows_sys::Win32::System::SystemServices::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH};
 
+#[cfg(feature = "plugin")]
 use crate::servers::start_servers;
 
+pub mod analysis;
+pub mod attach;
+pub mod build_info;
+pub mod capture;
+pub mod config;
+pub mod consent;
+pub mod console;
 pub mod constants;
+pub mod definitions;
+pub mod errors;
+pub mod fingerprint;
 pub mod hooks;
+pub mod locale;
 pub mod logging;
+pub mod memory;
+pub mod metrics;
 pub mod pattern;
 pub mod servers;
+pub mod system_events;
+pub mod tasks;
+pub mod ui;
+pub mod update;
+pub mod variant;
 
+#[cfg(feature = "plugin")]
 #[no_mangle]
 #[allow(non_snake_case, unused_variables)]
 unsafe extern "system" fn DllMain(dll_module: usize, call_reason: u32, _: *mut ()) -> bool {
@@ -19,27 +40,61 @@ unsafe extern "system" fn DllMain(dll_module: usize, call_reason: u32, _: *mut (
             AllocConsole();
 
             logging::setup();
+            config::load();
+            logging::set_console_format(config::config().console_format);
+            capture::initialize();
+            capture::crash::install();
+            definitions::load();
             servers::components::initialize();
+            servers::access::init();
+
+            // Explain what is collected and let the user choose on first run
+            if config::is_first_run() {
+                ui::show_setup_wizard();
+            }
+
+            // Existing connections must be listed before the proxy makes its own
+            attach::detect();
 
             // Handles the DLL being attached to the game
             unsafe { hooks::hook() };
 
             // Spawn UI and prepare task set
-            std::thread::spawn(|| {
+            tasks::spawn_thread("runtime", || {
                 // Create tokio async runtime
                 let runtime = tokio::runtime::Builder::new_multi_thread()
                     .enable_all()
+                    .thread_name(format!("{}worker", tasks::THREAD_PREFIX))
                     .build()
                     .expect("Failed building the Runtime");
 
                 runtime.block_on(async move {
                     start_servers();
+                    attach::start_reconnect();
+                    capture::disk::start_monitor();
+                    capture::clock::start_sync();
+                    capture::hotkeys::start();
+                    capture::overlay::start();
+                    fingerprint::start();
+                    system_events::start();
+                    capture::upload::start_queue();
+                    console::start();
+                    capture::retention::start();
+                    metrics::start();
+                    update::start();
                     // Block for CTRL+C to keep servers alive when window closes
                     _ = tokio::signal::ctrl_c().await;
+                    servers::shutdown().await;
+                    capture::finish();
+                    if let Some(capture) = capture::capture() {
+                        capture::upload::upload_finished(&capture.dir).await;
+                    }
                 });
             });
         }
         DLL_PROCESS_DETACH => {
+            // Only loader safe work may happen here, the session is finished
+            // on shutdown or recovered by the next launch
             use windows_sys::Win32::System::Console::FreeConsole;
             FreeConsole();
         }
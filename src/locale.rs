@@ -0,0 +1,111 @@
+//! Translations for user facing dialogs. String tables are embedded JSON
+//! objects keyed by message ID, the language is taken from the config
+//! or detected from the Windows user locale (the `LANG` environment
+//! variable in builds without the plugin). Messages missing from a
+//! table fall back to English
+
+use crate::config::config;
+use log::{debug, warn};
+use std::{collections::HashMap, sync::OnceLock};
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::{
+    Globalization::GetUserDefaultLocaleName, System::SystemServices::LOCALE_NAME_MAX_LENGTH,
+};
+
+/// The language used when no table matches the user's language
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// Embedded string tables keyed by language code
+const TABLES: &[(&str, &str)] = &[
+    ("en", include_str!("locales/en.json")),
+    ("de", include_str!("locales/de.json")),
+    ("es", include_str!("locales/es.json")),
+    ("fr", include_str!("locales/fr.json")),
+];
+
+type Table = HashMap<String, String>;
+
+/// Tables for the active language and the default language
+struct Locale {
+    active: Table,
+    fallback: Table,
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+fn locale() -> &'static Locale {
+    LOCALE.get_or_init(|| {
+        let language = config()
+            .language
+            .clone()
+            .or_else(detect_language)
+            .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+        debug!("Using language {}", language);
+
+        Locale {
+            active: load_table(&language)
+                .or_else(|| load_table(DEFAULT_LANGUAGE))
+                .unwrap_or_default(),
+            fallback: load_table(DEFAULT_LANGUAGE).unwrap_or_default(),
+        }
+    })
+}
+
+/// Loads the table for the language, regional variants such as "de-AT"
+/// use the table for their base language
+fn load_table(language: &str) -> Option<Table> {
+    let language = language
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let (_, source) = TABLES.iter().find(|(code, _)| *code == language)?;
+
+    match serde_json::from_str(source) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("Failed to parse {} string table: {}", language, err);
+            None
+        }
+    }
+}
+
+/// Detects the language from the Windows user locale ("en-US")
+#[cfg(feature = "plugin")]
+fn detect_language() -> Option<String> {
+    let mut buffer = [0u16; LOCALE_NAME_MAX_LENGTH as usize];
+    let length = unsafe { GetUserDefaultLocaleName(buffer.as_mut_ptr(), buffer.len() as i32) };
+    if length <= 1 {
+        return None;
+    }
+    // The length includes the null terminator
+    Some(String::from_utf16_lossy(&buffer[..length as usize - 1]))
+}
+
+/// Detects the language from the `LANG` environment variable ("en_US.UTF-8")
+#[cfg(not(feature = "plugin"))]
+fn detect_language() -> Option<String> {
+    std::env::var("LANG")
+        .ok()
+        .filter(|value| !value.is_empty() && value != "C" && value != "POSIX")
+}
+
+/// Obtains the translated message with the provided ID, the ID itself
+/// is returned if no table has the message
+pub fn text(id: &str) -> String {
+    let locale = locale();
+    locale
+        .active
+        .get(id)
+        .or_else(|| locale.fallback.get(id))
+        .cloned()
+        .unwrap_or_else(|| id.to_string())
+}
+
+/// Obtains the translated message with the provided ID replacing each
+/// `{name}` placeholder with its argument
+pub fn format(id: &str, args: &[(&str, &str)]) -> String {
+    args.iter().fold(text(id), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), value)
+    })
+}
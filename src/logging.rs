@@ -1,15 +1,69 @@
 use directories::UserDirs;
-use log::LevelFilter;
+use log::{Level, LevelFilter, Record};
 use log4rs::{
     append::{console::ConsoleAppender, file::FileAppender},
-    config::{Appender, Root},
+    config::{Appender, Logger, Root},
     encode::pattern::PatternEncoder,
+    filter::{Filter, Response},
     init_config, Config,
 };
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// The pattern to use when logging
 const LOGGING_PATTERN: &str = "[{d} {h({l})} {M}] {m}{n}";
 
+/// Log target only written to the log file, used for the full packet
+/// dumps when the console shows compact packet lines
+pub const FILE_ONLY_TARGET: &str = "file_only";
+
+/// Log target for the start and end of proxied sessions, these are still
+/// shown in the console in quiet mode
+pub const LIFECYCLE_TARGET: &str = "lifecycle";
+
+/// Whether the console only shows lifecycle events, warnings and errors.
+/// Logging starts before the config is loaded so this is set afterwards
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// How proxied packets are shown in the console
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsoleFormat {
+    /// The full packet dump, the same as the log file
+    #[default]
+    Full,
+    /// One colored line per packet with its direction, component and
+    /// command, size and response latency. Full dumps only go to the
+    /// log file
+    Compact,
+    /// Only session starts and ends, warnings, errors and a periodic stats
+    /// summary. Everything else only goes to the log file
+    Quiet,
+}
+
+/// Filter for the console appender hiding everything other than lifecycle
+/// events, warnings and errors while in quiet mode
+#[derive(Debug)]
+struct QuietFilter;
+
+impl Filter for QuietFilter {
+    fn filter(&self, record: &Record) -> Response {
+        if !QUIET.load(Ordering::Relaxed)
+            || record.level() <= Level::Warn
+            || record.target() == LIFECYCLE_TARGET
+        {
+            Response::Neutral
+        } else {
+            Response::Reject
+        }
+    }
+}
+
+/// Applies the console format from the loaded config
+pub fn set_console_format(format: ConsoleFormat) {
+    QUIET.store(format == ConsoleFormat::Quiet, Ordering::Relaxed);
+}
+
 /// Setup function for setting up the Log4rs logging configuring it
 /// for all the different modules and and setting up file and stdout logging
 pub fn setup() {
@@ -33,8 +87,18 @@ pub fn setup() {
     const APPENDERS: [&str; 2] = ["stdout", "file"];
 
     let config = Config::builder()
-        .appender(Appender::builder().build("stdout", console))
+        .appender(
+            Appender::builder()
+                .filter(Box::new(QuietFilter))
+                .build("stdout", console),
+        )
         .appender(Appender::builder().build("file", file))
+        .logger(
+            Logger::builder()
+                .appender("file")
+                .additive(false)
+                .build(FILE_ONLY_TARGET, LevelFilter::Debug),
+        )
         .build(
             Root::builder()
                 .appenders(APPENDERS)
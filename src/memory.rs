@@ -0,0 +1,106 @@
+//! Checked reads of game memory for the pattern scanner and hooks. Memory
+//! is only read after VirtualQuery reports it as committed and readable so
+//! scanning guard pages or unmapped memory can't crash the game. Builds
+//! without the plugin treat all memory as unreadable
+
+use std::mem::size_of;
+#[cfg(feature = "plugin")]
+use std::{ffi::c_void, mem::MaybeUninit};
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::System::Memory::{
+    VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
+    PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE,
+    PAGE_WRITECOPY,
+};
+
+/// Protection flags that allow reading
+#[cfg(feature = "plugin")]
+const READABLE: u32 = PAGE_READONLY
+    | PAGE_READWRITE
+    | PAGE_WRITECOPY
+    | PAGE_EXECUTE_READ
+    | PAGE_EXECUTE_READWRITE
+    | PAGE_EXECUTE_WRITECOPY;
+
+/// Queries the memory region containing the address, returning the end
+/// of the region and whether it can be read
+#[cfg(feature = "plugin")]
+fn query(addr: usize) -> Option<(usize, bool)> {
+    let mut info = MaybeUninit::<MEMORY_BASIC_INFORMATION>::uninit();
+    let written = unsafe {
+        VirtualQuery(
+            addr as *const c_void,
+            info.as_mut_ptr(),
+            size_of::<MEMORY_BASIC_INFORMATION>(),
+        )
+    };
+    if written == 0 {
+        return None;
+    }
+    let info = unsafe { info.assume_init() };
+
+    let end = (info.BaseAddress as usize).checked_add(info.RegionSize)?;
+    let readable = info.State == MEM_COMMIT
+        && info.Protect & (PAGE_GUARD | PAGE_NOACCESS) == 0
+        && info.Protect & READABLE != 0;
+    Some((end, readable))
+}
+
+#[cfg(not(feature = "plugin"))]
+fn query(_addr: usize) -> Option<(usize, bool)> {
+    None
+}
+
+/// Finds the readable ranges of memory between `start` and `end`, regions
+/// next to each other are merged into a single range
+pub fn readable_ranges(start: usize, end: usize) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut addr = start;
+
+    while addr < end {
+        let Some((region_end, readable)) = query(addr) else {
+            break;
+        };
+        let range_end = region_end.min(end);
+
+        if readable {
+            match ranges.last_mut() {
+                Some(last) if last.1 == addr => last.1 = range_end,
+                _ => ranges.push((addr, range_end)),
+            }
+        }
+
+        if region_end <= addr {
+            break;
+        }
+        addr = region_end;
+    }
+
+    ranges
+}
+
+/// Whether all the memory from `addr` for `length` bytes can be read
+pub fn is_readable(addr: usize, length: usize) -> bool {
+    let Some(end) = addr.checked_add(length) else {
+        return false;
+    };
+    readable_ranges(addr, end).first() == Some(&(addr, end))
+}
+
+/// Reads `length` bytes starting at `addr`, None if the memory can't be
+/// read. The memory must not be freed by another thread while reading
+pub unsafe fn read_bytes(addr: usize, length: usize) -> Option<Vec<u8>> {
+    if !is_readable(addr, length) {
+        return None;
+    }
+    Some(std::slice::from_raw_parts(addr as *const u8, length).to_vec())
+}
+
+/// Reads a value at `addr` which doesn't need to be aligned, None if the
+/// memory can't be read
+pub unsafe fn read<T: Copy>(addr: usize) -> Option<T> {
+    if !is_readable(addr, size_of::<T>()) {
+        return None;
+    }
+    Some(std::ptr::read_unaligned(addr as *const T))
+}
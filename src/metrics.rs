@@ -0,0 +1,199 @@
+//! Anonymous usage metrics about the plugin's own operation, used by the
+//! maintainers to learn which game builds and Windows versions the plugin
+//! runs on. Reports only contain the plugin version, the game build, the
+//! Windows version, session counts and error counts by category, never
+//! game data. Nothing is sent unless the user opts in through the config
+
+use crate::{
+    capture::review::list_sessions,
+    config::config,
+    consent::{self, ActiveFeature, ConsentError},
+    constants::APP_VERSION,
+    fingerprint::file_version,
+    hooks, tasks,
+    variant::GameVariant,
+};
+use log::{debug, warn};
+use serde::Serialize;
+#[cfg(feature = "plugin")]
+use std::mem::{size_of, transmute};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::time::sleep;
+#[cfg(feature = "plugin")]
+use windows_sys::{
+    s, w,
+    Win32::System::{
+        LibraryLoader::{GetModuleHandleW, GetProcAddress},
+        SystemInformation::OSVERSIONINFOW,
+    },
+};
+
+/// Delay before the first report so errors during startup are included
+const FIRST_REPORT_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Delay between each following report
+const REPORT_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Errors counted since the last report
+static ERRORS: Mutex<BTreeMap<ErrorCategory, u64>> = Mutex::new(BTreeMap::new());
+
+/// Category of error counted in the metrics, categories describe which part
+/// of the plugin failed without any details of the failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// A local server stopped and was restarted
+    ServerRestart,
+    /// Connecting to an upstream server failed
+    UpstreamConnect,
+    /// A connection looped back to the plugin's own servers
+    ConnectionLoop,
+    /// Writing capture files failed
+    CaptureWrite,
+    /// Uploading a session failed
+    Upload,
+    /// Accepting a connection on a local server failed
+    Accept,
+}
+
+/// Counts an error of the provided category for the next report
+pub fn count_error(category: ErrorCategory) {
+    *ERRORS
+        .lock()
+        .expect("Metrics lock poisoned")
+        .entry(category)
+        .or_default() += 1;
+}
+
+/// Report sent to the metrics endpoint
+#[derive(Debug, Serialize)]
+pub struct MetricsReport {
+    pub plugin_version: &'static str,
+    /// Windows version in the `major.minor.build` format
+    pub os_version: Option<String>,
+    /// File version of the game executable
+    pub game_version: Option<String>,
+    /// The game variant the hooks were selected for
+    pub game_variant: Option<GameVariant>,
+    /// Number of capture sessions in the dump directory
+    pub sessions: usize,
+    /// Seconds the plugin has been running
+    pub uptime_secs: u64,
+    /// Errors counted since the last report
+    pub errors: BTreeMap<ErrorCategory, u64>,
+}
+
+/// Errors that can occur sending metrics
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error(transparent)]
+    Consent(#[from] ConsentError),
+    #[error("No metrics endpoint is configured")]
+    MissingEndpoint,
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+/// Starts sending reports in the background if the user opted in
+pub fn start() {
+    if consent::require(ActiveFeature::Metrics).is_err() {
+        return;
+    }
+
+    tasks::spawn("metrics", async {
+        let started = Instant::now();
+        sleep(FIRST_REPORT_DELAY).await;
+
+        loop {
+            let report = create_report(started);
+            match send_report(&report).await {
+                Ok(()) => {
+                    debug!("Sent usage metrics: {:?}", report);
+                    // Counts that were sent are removed so each is only reported once
+                    let errors = &mut *ERRORS.lock().expect("Metrics lock poisoned");
+                    for (category, count) in &report.errors {
+                        if let Some(value) = errors.get_mut(category) {
+                            *value = value.saturating_sub(*count);
+                        }
+                    }
+                    errors.retain(|_, count| *count > 0);
+                }
+                Err(err) => warn!("Failed to send usage metrics: {}", err),
+            }
+
+            sleep(REPORT_INTERVAL).await;
+        }
+    });
+}
+
+/// Creates a report of the current state of the plugin
+pub fn create_report(started: Instant) -> MetricsReport {
+    MetricsReport {
+        plugin_version: APP_VERSION,
+        os_version: unsafe { os_version() },
+        game_version: std::env::current_exe()
+            .ok()
+            .and_then(|path| unsafe { file_version(&path) }),
+        game_variant: hooks::variant(),
+        sessions: list_sessions().map(|sessions| sessions.len()).unwrap_or(0),
+        uptime_secs: started.elapsed().as_secs(),
+        errors: ERRORS.lock().expect("Metrics lock poisoned").clone(),
+    }
+}
+
+/// Sends the report to the configured endpoint
+async fn send_report(report: &MetricsReport) -> Result<(), MetricsError> {
+    consent::require(ActiveFeature::Metrics)?;
+    let endpoint = config()
+        .metrics_endpoint
+        .as_deref()
+        .ok_or(MetricsError::MissingEndpoint)?;
+
+    reqwest::Client::new()
+        .post(endpoint)
+        .timeout(config().request_timeout())
+        .json(report)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Signature of RtlGetVersion from ntdll
+#[cfg(feature = "plugin")]
+type RtlGetVersion = unsafe extern "system" fn(*mut OSVERSIONINFOW) -> i32;
+
+/// Obtains the Windows version. GetVersionEx reports the version the game
+/// is manifested for rather than the actual version so RtlGetVersion is
+/// used instead
+#[cfg(feature = "plugin")]
+unsafe fn os_version() -> Option<String> {
+    let ntdll = GetModuleHandleW(w!("ntdll.dll"));
+    if ntdll == 0 {
+        return None;
+    }
+    let function = GetProcAddress(ntdll, s!("RtlGetVersion"))?;
+    let function: RtlGetVersion = transmute(function);
+
+    let mut info: OSVERSIONINFOW = std::mem::zeroed();
+    info.dwOSVersionInfoSize = size_of::<OSVERSIONINFOW>() as u32;
+    if function(&mut info) != 0 {
+        return None;
+    }
+
+    Some(format!(
+        "{}.{}.{}",
+        info.dwMajorVersion, info.dwMinorVersion, info.dwBuildNumber
+    ))
+}
+
+/// The Windows version isn't known in builds without the plugin
+#[cfg(not(feature = "plugin"))]
+unsafe fn os_version() -> Option<String> {
+    None
+}
@@ -1,10 +1,205 @@
+use crate::{
+    capture::{dump_dir, value::to_hex},
+    fingerprint::file_version,
+    memory,
+};
 use log::{debug, error, warn};
-use std::ffi::c_void;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io, sync::Mutex, time::UNIX_EPOCH};
+#[cfg(feature = "plugin")]
+use std::{ffi::c_void, ptr::null};
+#[cfg(feature = "plugin")]
 use windows_sys::Win32::{
     Foundation::{GetLastError, FALSE},
-    System::Memory::{VirtualProtect, PAGE_PROTECTION_FLAGS, PAGE_READWRITE},
+    System::{
+        LibraryLoader::GetModuleHandleW,
+        Memory::{VirtualProtect, PAGE_PROTECTION_FLAGS, PAGE_READWRITE},
+    },
 };
 
+/// The file name of the pattern cache within the dump directory
+const CACHE_FILE: &str = "pattern_cache.json";
+
+/// Results of every pattern search made while hooking
+static MATCHES: Mutex<Vec<PatternMatch>> = Mutex::new(Vec::new());
+
+/// Pattern offsets found on previous launches, None until loaded
+static CACHE: Mutex<Option<PatternCache>> = Mutex::new(None);
+
+/// Identifies the build of the game executable the cached offsets were
+/// found in. The file hash isn't used as hashing the executable would
+/// take longer than the scan it avoids
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheKey {
+    /// Size of the executable in bytes
+    size: u64,
+    /// Unix timestamp in seconds the executable was last modified
+    modified: u64,
+    /// File version from the executable version resource
+    version: Option<String>,
+}
+
+/// Offsets of found patterns relative to the game module base so they
+/// stay valid when the module is loaded at a different address
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PatternCache {
+    key: Option<CacheKey>,
+    offsets: BTreeMap<String, usize>,
+    /// Whether the cache changed since it was loaded
+    #[serde(skip)]
+    changed: bool,
+}
+
+/// Loads the pattern cache, discarding it if it was created for a
+/// different game executable. Should be called before applying patterns
+pub fn load_cache() {
+    let key = match cache_key() {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to read game executable details: {}", err);
+            return;
+        }
+    };
+
+    let mut cache = match dump_dir().and_then(|dir| std::fs::read(dir.join(CACHE_FILE))) {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+            error!("Failed to parse pattern cache: {}", err);
+            PatternCache::default()
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => PatternCache::default(),
+        Err(err) => {
+            error!("Failed to read pattern cache: {}", err);
+            PatternCache::default()
+        }
+    };
+
+    if cache.key.as_ref() != Some(&key) {
+        if cache.key.is_some() {
+            debug!("Game executable changed, discarding pattern cache");
+        }
+        cache = PatternCache {
+            key: Some(key),
+            offsets: BTreeMap::new(),
+            changed: true,
+        };
+    }
+
+    *CACHE.lock().expect("Pattern cache lock poisoned") = Some(cache);
+}
+
+/// Writes the pattern cache if any offsets changed
+pub fn save_cache() {
+    let guard = &mut *CACHE.lock().expect("Pattern cache lock poisoned");
+    let Some(cache) = guard.as_mut().filter(|cache| cache.changed) else {
+        return;
+    };
+
+    let result = serde_json::to_vec_pretty(cache)
+        .map_err(io::Error::from)
+        .and_then(|bytes| std::fs::write(dump_dir()?.join(CACHE_FILE), bytes));
+    match result {
+        Ok(()) => cache.changed = false,
+        Err(err) => error!("Failed to write pattern cache: {}", err),
+    }
+}
+
+fn cache_key() -> io::Result<CacheKey> {
+    let path = std::env::current_exe()?;
+    let metadata = std::fs::metadata(&path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_secs())
+        .unwrap_or_default();
+
+    Ok(CacheKey {
+        size: metadata.len(),
+        modified,
+        version: unsafe { file_version(&path) },
+    })
+}
+
+/// Base address of the game executable module
+#[cfg(feature = "plugin")]
+fn module_base() -> usize {
+    unsafe { GetModuleHandleW(null()) as usize }
+}
+
+/// Builds without the plugin aren't loaded into the game
+#[cfg(not(feature = "plugin"))]
+fn module_base() -> usize {
+    0
+}
+
+/// Makes the memory region writable, returning the previous protection
+/// or the error code when it couldn't be changed
+#[cfg(feature = "plugin")]
+unsafe fn make_writable(addr: usize, length: usize) -> Result<u32, u32> {
+    let mut old_protect: PAGE_PROTECTION_FLAGS = 0;
+    if VirtualProtect(
+        addr as *const c_void,
+        length,
+        PAGE_READWRITE,
+        &mut old_protect,
+    ) == FALSE
+    {
+        return Err(GetLastError());
+    }
+    Ok(old_protect)
+}
+
+/// Memory protection can't be changed in builds without the plugin
+#[cfg(not(feature = "plugin"))]
+unsafe fn make_writable(_addr: usize, _length: usize) -> Result<u32, u32> {
+    Err(0)
+}
+
+/// Restores the protection of a memory region made writable
+#[cfg(feature = "plugin")]
+unsafe fn restore_protection(addr: usize, length: usize, protect: u32) {
+    let mut old_protect: PAGE_PROTECTION_FLAGS = 0;
+    VirtualProtect(addr as *const c_void, length, protect, &mut old_protect);
+}
+
+#[cfg(not(feature = "plugin"))]
+unsafe fn restore_protection(_addr: usize, _length: usize, _protect: u32) {}
+
+/// Result of searching for a pattern
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PatternMatch {
+    /// The name of the pattern
+    pub name: &'static str,
+    /// The address the pattern was found at
+    pub address: Option<usize>,
+    /// Whether the address came from the pattern cache
+    pub cached: bool,
+}
+
+/// Obtains the results of every pattern search made so far
+pub fn matches() -> Vec<PatternMatch> {
+    MATCHES
+        .lock()
+        .expect("Pattern matches lock poisoned")
+        .clone()
+}
+
+/// Memory changed when applying a pattern
+#[derive(Debug, Clone, Serialize)]
+pub struct Patch {
+    /// The address the pattern was found at
+    pub match_address: usize,
+    /// The address of the memory that was changed
+    pub address: usize,
+    /// Length of the changed region in bytes
+    pub length: usize,
+    /// Hex encoded bytes before patching
+    pub original: String,
+    /// Hex encoded bytes after patching, None if the memory couldn't
+    /// be made writable
+    pub patched: Option<String>,
+}
+
 /// Represents a pattern that can be patched
 pub struct Pattern {
     /// The name of the pattern
@@ -20,107 +215,205 @@ pub struct Pattern {
 }
 
 impl Pattern {
-    /// Attempts to apply a pattern
+    /// Attempts to apply a pattern, returning the changed memory or
+    /// None if the pattern wasn't found
     ///
     /// # Arguments
     /// * pattern - The pattern to use
     /// * length - The length of memory to protect
     /// * action - The action to take on the memory
-    pub unsafe fn apply<F>(&self, length: usize, action: F)
+    pub unsafe fn apply<F>(&self, length: usize, action: F) -> Option<Patch>
     where
         F: FnOnce(*mut u8),
     {
         let Some(addr) = self.find() else {
             warn!("Failed to find {} hook position", self.name);
-            return;
+            return None;
         };
 
         debug!("Found {} @ {:#016x}", self.name, addr as usize);
 
-        Self::use_memory(addr, length, action)
+        Self::use_memory(addr, addr, length, action)
     }
 
     /// Attempts to apply a pattern with a transformed
-    /// address
+    /// address, returning the changed memory or None if
+    /// the pattern wasn't found or couldn't be transformed
     ///
     /// # Arguments
     /// * pattern - The pattern to use
     /// * length - The length of memory to protect
-    /// * transform - Transformer for transforming the located address
+    /// * transform - Transformer for transforming the located address,
+    ///   None if the address couldn't be transformed
     /// * action - The action to take on the memory
-    pub unsafe fn apply_with_transform<F, T, P>(&self, length: usize, transform: T, action: F)
+    pub unsafe fn apply_with_transform<F, T, P>(
+        &self,
+        length: usize,
+        transform: T,
+        action: F,
+    ) -> Option<Patch>
     where
-        T: FnOnce(*const u8) -> *const P,
+        T: FnOnce(*const u8) -> Option<*const P>,
         F: FnOnce(*mut P),
     {
-        let Some(addr) = self.find() else {
+        let Some(match_addr) = self.find() else {
             warn!("Failed to find {} hook position", self.name);
-            return;
+            return None;
         };
 
-        debug!("Found {} @ {:#016x}", self.name, addr as usize);
+        debug!("Found {} @ {:#016x}", self.name, match_addr as usize);
 
         // Transform the address
-        let addr = transform(addr);
-        Self::use_memory(addr, length, action)
+        let Some(addr) = transform(match_addr) else {
+            warn!("Failed to resolve {} hook address", self.name);
+            return None;
+        };
+        Self::use_memory(match_addr, addr, length, action)
     }
 
     /// Attempts to find a matching pattern anywhere between the start and
-    /// end address
+    /// end address, the result is recorded in the pattern matches.
+    ///
+    /// Addresses cached from previous launches are checked first and the
+    /// scan is only made when there isn't a cached address still matching
     unsafe fn find(&self) -> Option<*const u8> {
-        (self.start..=self.end)
+        let base = module_base();
+        let cached = self.find_cached(base);
+        let addr = cached.or_else(|| self.scan());
+
+        if cached.is_none() {
+            if let Some(cache) = CACHE.lock().expect("Pattern cache lock poisoned").as_mut() {
+                let name = self.name.to_string();
+                match addr {
+                    Some(addr) => {
+                        cache.offsets.insert(name, addr as usize - base);
+                    }
+                    None => {
+                        cache.offsets.remove(&name);
+                    }
+                }
+                cache.changed = true;
+            }
+        }
+
+        MATCHES
+            .lock()
+            .expect("Pattern matches lock poisoned")
+            .push(PatternMatch {
+                name: self.name,
+                address: addr.map(|addr| addr as usize),
+                cached: cached.is_some(),
+            });
+
+        addr
+    }
+
+    /// Obtains the cached address of the pattern if it's within the search
+    /// range and the memory there still matches the pattern
+    unsafe fn find_cached(&self, base: usize) -> Option<*const u8> {
+        let offset = *CACHE
+            .lock()
+            .expect("Pattern cache lock poisoned")
+            .as_ref()?
+            .offsets
+            .get(self.name)?;
+        let addr = base.checked_add(offset)?;
+
+        let matches =
+            memory::read_bytes(addr, self.op.len()).is_some_and(|bytes| self.compare_mask(&bytes));
+        if !(self.start..=self.end).contains(&addr) || !matches {
+            warn!("Cached {} address no longer matches, scanning", self.name);
+            return None;
+        }
+
+        debug!("Using cached {} address", self.name);
+        Some(addr as *const u8)
+    }
+
+    /// Scans the readable memory between the start and end address for
+    /// the pattern, unreadable memory such as guard pages is skipped
+    unsafe fn scan(&self) -> Option<*const u8> {
+        let length = self.op.len();
+        let end = self.end.saturating_add(length);
+
+        memory::readable_ranges(self.start, end)
+            .into_iter()
+            .find_map(|(start, end)| {
+                let bytes = std::slice::from_raw_parts(start as *const u8, end - start);
+                bytes
+                    .windows(length)
+                    .position(|window| self.compare_mask(window))
+                    .map(|offset| start + offset)
+            })
+            .filter(|addr| *addr <= self.end)
             .map(|addr| addr as *const u8)
-            .find(|addr| self.compare_mask(*addr))
     }
 
-    /// Compares the opcodes after the provided address using the provided
-    /// opcode and pattern
+    /// Compares the provided bytes with the opcode pattern
     ///
     /// # Arguments
-    /// * addr - The address to start matching from
-    unsafe fn compare_mask(&self, addr: *const u8) -> bool {
+    /// * bytes - The bytes to match, at least as long as the pattern
+    fn compare_mask(&self, bytes: &[u8]) -> bool {
         self.mask
             .chars()
-            .enumerate()
             .zip(self.op)
-            .all(|((offset, mask), op)| mask == '?' || *addr.add(offset) == *op)
+            .zip(bytes)
+            .all(|((mask, op), byte)| mask == '?' || byte == op)
     }
 
     /// Attempts to apply virtual protect READ/WRITE access
     /// over the memory at the provided address for the length
-    /// provided.
+    /// provided. The memory before and after the action is
+    /// returned, None if the memory can't be read
     ///
     /// # Arguments
+    /// * match_addr - The address the pattern was found at
     /// * addr - The address to protect
     /// * length - The protected region
     /// * action - The action to execute on the memory
-    unsafe fn use_memory<F, P>(addr: *const P, length: usize, action: F)
+    unsafe fn use_memory<F, P>(
+        match_addr: *const u8,
+        addr: *const P,
+        length: usize,
+        action: F,
+    ) -> Option<Patch>
     where
         F: FnOnce(*mut P),
     {
-        let mut old_protect: PAGE_PROTECTION_FLAGS = 0;
+        let Some(original) = memory::read_bytes(addr as usize, length) else {
+            error!(
+                "Memory region @ {:#016x} length {} isn't readable",
+                addr as usize, length
+            );
+            return None;
+        };
 
-        // Protect the memory region
-        if VirtualProtect(
-            addr as *const c_void,
+        let mut patch = Patch {
+            match_address: match_addr as usize,
+            address: addr as usize,
             length,
-            PAGE_READWRITE,
-            &mut old_protect,
-        ) == FALSE
-        {
-            let error = GetLastError();
+            original: to_hex(&original),
+            patched: None,
+        };
 
-            error!(
-                "Failed to protect memory region @ {:#016x} length {} error: {:#4x}",
-                addr as usize, length, error
-            );
-            return;
-        }
+        // Protect the memory region
+        let old_protect = match make_writable(addr as usize, length) {
+            Ok(value) => value,
+            Err(error) => {
+                error!(
+                    "Failed to protect memory region @ {:#016x} length {} error: {:#4x}",
+                    addr as usize, length, error
+                );
+                return Some(patch);
+            }
+        };
 
         action(addr.cast_mut());
+        patch.patched = memory::read_bytes(addr as usize, length).map(|bytes| to_hex(&bytes));
 
         // Un-protect the memory region
-        VirtualProtect(addr as *const c_void, length, old_protect, &mut old_protect);
+        restore_protection(addr as usize, length, old_protect);
+        Some(patch)
     }
 }
 
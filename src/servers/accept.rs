@@ -0,0 +1,131 @@
+//! Handling of errors accepting connections on the local listeners. Most
+//! accept errors only affect the connection being accepted or clear up on
+//! their own (running out of sockets while the game is loading), these are
+//! retried with a backoff instead of stopping the server
+
+use crate::{
+    capture::capture,
+    metrics::{self, ErrorCategory},
+};
+use log::{error, warn};
+use std::{io, time::Duration};
+use tokio::time::sleep;
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::Networking::WinSock::{WSAEMFILE, WSAENOBUFS};
+
+/// Delay before retrying after the first transient error
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+/// Longest delay between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Raw OS errors for running out of sockets or buffer space
+#[cfg(feature = "plugin")]
+const EXHAUSTED_ERRORS: &[i32] = &[WSAEMFILE, WSAENOBUFS];
+/// EMFILE, ENFILE and ENOBUFS
+#[cfg(all(not(feature = "plugin"), target_os = "linux"))]
+const EXHAUSTED_ERRORS: &[i32] = &[24, 23, 105];
+/// EMFILE, ENFILE and ENOBUFS
+#[cfg(all(not(feature = "plugin"), target_os = "macos"))]
+const EXHAUSTED_ERRORS: &[i32] = &[24, 23, 55];
+#[cfg(all(
+    not(feature = "plugin"),
+    not(any(target_os = "linux", target_os = "macos"))
+))]
+const EXHAUSTED_ERRORS: &[i32] = &[];
+
+/// Accept errors of a listener since the last accepted connection
+pub struct AcceptErrors {
+    /// Name of the server the listener belongs to
+    name: &'static str,
+    /// Delay before the next retry
+    backoff: Duration,
+    /// Number of transient errors in a row
+    failures: u32,
+}
+
+impl AcceptErrors {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            backoff: MIN_BACKOFF,
+            failures: 0,
+        }
+    }
+
+    /// Resets the backoff once a connection was accepted
+    pub fn accepted(&mut self) {
+        if self.failures > 0 {
+            warn!(
+                "Server {} accepting connections again after {} errors",
+                self.name, self.failures
+            );
+        }
+        self.backoff = MIN_BACKOFF;
+        self.failures = 0;
+    }
+
+    /// Handles an error accepting a connection. Transient errors are
+    /// retried after the backoff, fatal errors are returned to stop the
+    /// server so the supervisor rebinds it and reports the failure
+    pub async fn handle(&mut self, err: io::Error) -> io::Result<()> {
+        metrics::count_error(ErrorCategory::Accept);
+
+        if !is_transient(&err) {
+            error!("Server {} failed to accept connections: {}", self.name, err);
+            record_event(&format!(
+                "Server {} failed to accept connections: {}",
+                self.name, err
+            ));
+            return Err(io::Error::new(
+                err.kind(),
+                format!("Failed to accept connections: {}", err),
+            ));
+        }
+
+        // Only the first error in a row is recorded so a persistent
+        // failure doesn't fill the manifest
+        self.failures += 1;
+        if self.failures == 1 {
+            record_event(&format!(
+                "Server {} failed to accept a connection: {}",
+                self.name, err
+            ));
+        }
+
+        warn!(
+            "Server {} failed to accept a connection, retrying in {:?}: {}",
+            self.name, self.backoff, err
+        );
+        sleep(self.backoff).await;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        Ok(())
+    }
+}
+
+/// Whether an accept error only affects a single connection or is likely
+/// to clear up, rather than the listener itself being broken
+fn is_transient(err: &io::Error) -> bool {
+    if matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::OutOfMemory
+    ) {
+        return true;
+    }
+
+    // Running out of sockets or buffer space clears up once other
+    // connections close
+    err.raw_os_error()
+        .is_some_and(|code| EXHAUSTED_ERRORS.contains(&code))
+}
+
+fn record_event(message: &str) {
+    if let Some(capture) = capture() {
+        capture.record_event("accept_error", message);
+    }
+}
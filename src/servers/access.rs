@@ -0,0 +1,171 @@
+//! Access control for the control API. Requests must come from loopback
+//! or an allowlisted address and carry the token written to the dump
+//! directory, so other machines on the network or other local programs
+//! can't manipulate the capture
+
+use crate::{
+    capture::{dump_dir, value::to_hex},
+    config::config,
+};
+use axum::extract::Request;
+use hyper::header::AUTHORIZATION;
+use log::{error, warn};
+use std::{io, net::SocketAddr, sync::OnceLock};
+use thiserror::Error;
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::Security::Cryptography::{
+    BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+};
+
+/// The file name of the token file within the dump directory
+pub const TOKEN_FILE: &str = "control-token.txt";
+
+/// Name of the query parameter the token can be provided in for
+/// clients that can't set headers such as browsers opening a WebSocket
+pub const TOKEN_PARAM: &str = "token";
+
+/// Length of generated tokens in bytes
+const TOKEN_LENGTH: usize = 32;
+
+/// The token requests must provide, None if it couldn't be created
+static TOKEN: OnceLock<Option<String>> = OnceLock::new();
+
+/// Reasons a request was refused
+#[derive(Debug, Error)]
+pub enum AccessError {
+    #[error("Address {0} is not allowed to use the control API")]
+    AddressNotAllowed(SocketAddr),
+    #[error("Missing or invalid control API token")]
+    InvalidToken,
+    #[error("Control API token is unavailable")]
+    TokenUnavailable,
+}
+
+/// Loads the token from the dump directory, creating it if it doesn't
+/// exist yet. Called on startup so the token is available before the
+/// first request
+pub fn init() {
+    TOKEN.get_or_init(load_token);
+}
+
+/// Checks whether the request is allowed to use the control API
+///
+/// `addr` The address the request came from
+pub fn authorize(req: &Request, addr: SocketAddr) -> Result<(), AccessError> {
+    let ip = addr.ip();
+    if !ip.is_loopback() && !config().control_allowlist.contains(&ip) {
+        warn!("Refused control API request from {}", addr);
+        return Err(AccessError::AddressNotAllowed(addr));
+    }
+
+    let Some(token) = TOKEN.get_or_init(load_token) else {
+        return Err(AccessError::TokenUnavailable);
+    };
+
+    let provided = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| query_token(req.uri().query()));
+
+    match provided {
+        Some(provided) if constant_eq(provided.trim().as_bytes(), token.as_bytes()) => Ok(()),
+        _ => {
+            warn!(
+                "Refused control API request from {} with invalid token",
+                addr
+            );
+            Err(AccessError::InvalidToken)
+        }
+    }
+}
+
+/// Finds the token in the request query string
+fn query_token(query: Option<&str>) -> Option<String> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query?).ok()?;
+    pairs
+        .into_iter()
+        .find(|(key, _)| key == TOKEN_PARAM)
+        .map(|(_, value)| value)
+}
+
+/// Compares the values without returning early so the time taken
+/// doesn't reveal how much of the token matched
+fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn load_token() -> Option<String> {
+    let dir = match dump_dir() {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to load control API token: {}", err);
+            return None;
+        }
+    };
+    let path = dir.join(TOKEN_FILE);
+
+    match std::fs::read_to_string(&path) {
+        Ok(value) if !value.trim().is_empty() => return Some(value.trim().to_string()),
+        Ok(_) => {}
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => {
+            error!("Failed to read control API token: {}", err);
+            return None;
+        }
+    }
+
+    let token = match generate_token() {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to generate control API token: {}", err);
+            return None;
+        }
+    };
+
+    let result = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, &token));
+    if let Err(err) = result {
+        error!("Failed to write control API token: {}", err);
+        return None;
+    }
+
+    Some(token)
+}
+
+/// Generates a new random token encoded as hex
+#[cfg(feature = "plugin")]
+fn generate_token() -> io::Result<String> {
+    let mut bytes = [0u8; TOKEN_LENGTH];
+    let status = unsafe {
+        BCryptGenRandom(
+            0,
+            bytes.as_mut_ptr(),
+            bytes.len() as u32,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    };
+    if status != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("BCryptGenRandom failed with status {:#x}", status),
+        ));
+    }
+
+    Ok(to_hex(&bytes))
+}
+
+/// Generates a new random token encoded as hex, builds without the plugin
+/// read the random bytes from the system random device
+#[cfg(not(feature = "plugin"))]
+fn generate_token() -> io::Result<String> {
+    use std::io::Read;
+
+    let mut bytes = [0u8; TOKEN_LENGTH];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(to_hex(&bytes))
+}
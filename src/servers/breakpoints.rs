@@ -0,0 +1,304 @@
+use crate::{
+    capture::{
+        append_jsonl, capture,
+        clock::now_micros,
+        value::{decode_fields, encode_fields, from_hex, to_hex, Field},
+        Direction,
+    },
+    config::config,
+    consent::{self, ActiveFeature},
+    locale,
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::{FireFrame, FrameType, Packet},
+    },
+    ui::{show_editor, EditorChoice},
+};
+use bytes::Bytes;
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
+use tokio::sync::oneshot;
+
+/// Rule for holding packets before they are forwarded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BreakpointRule {
+    /// Direction to hold packets in, both directions when not set
+    #[serde(default)]
+    pub direction: Option<Direction>,
+    pub component: u16,
+    /// Command to hold, any command of the component when not set
+    #[serde(default)]
+    pub command: Option<u16>,
+}
+
+impl BreakpointRule {
+    fn matches(&self, direction: Direction, frame: &FireFrame) -> bool {
+        self.direction.is_none_or(|value| value == direction)
+            && self.component == frame.component
+            && self.command.is_none_or(|value| value == frame.command)
+    }
+}
+
+/// Action to take for a held packet
+#[derive(Debug)]
+pub enum BreakpointAction {
+    /// Forward the packet unchanged
+    Continue,
+    /// Forward a modified version of the packet
+    Modify(Packet),
+    /// Drop the packet without forwarding it
+    Drop,
+}
+
+/// Details about a packet held at a breakpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct HeldPacket {
+    /// ID used to resolve the breakpoint
+    pub id: u32,
+    /// ID of the proxied connection
+    pub connection: u32,
+    pub direction: Direction,
+    pub component: u16,
+    pub command: u16,
+    pub ty: FrameType,
+    pub seq: u16,
+    /// Packet contents as hex
+    pub contents: String,
+}
+
+/// Packet held at a breakpoint along with the sender for its action
+struct Held {
+    packet: HeldPacket,
+    /// Frame of the held packet, reused for modified packets
+    frame: FireFrame,
+    tx: oneshot::Sender<BreakpointAction>,
+}
+
+/// The file name of the edit log within the session directory
+pub const EDITS_FILE: &str = "edits.jsonl";
+
+/// Packet modified at a breakpoint, both versions are recorded
+#[derive(Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EditRecord {
+    /// Unix timestamp in microseconds of when the packet was modified
+    pub time: u64,
+    /// ID of the breakpoint the packet was held at
+    pub breakpoint: u32,
+    pub connection: u32,
+    pub direction: Direction,
+    pub component: u16,
+    pub command: u16,
+    pub seq: u16,
+    /// The original packet contents as hex
+    pub original: String,
+    /// The modified packet contents as hex
+    pub modified: String,
+}
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Packets currently held at breakpoints
+static HELD: Mutex<Vec<Held>> = Mutex::new(Vec::new());
+
+/// Checks the packet against the breakpoint rules, when a rule matches the
+/// packet is held until the user decides what to do with it. Returns the
+/// packet to forward or None if it should be dropped
+pub async fn check(connection: u32, direction: Direction, packet: Packet) -> Option<Packet> {
+    let rules = &config().breakpoints;
+    if !rules
+        .iter()
+        .any(|rule| rule.matches(direction, &packet.frame))
+    {
+        return Some(packet);
+    }
+
+    let frame = &packet.frame;
+    let id = NEXT_ID.fetch_add(1, Ordering::AcqRel);
+    let held = HeldPacket {
+        id,
+        connection,
+        direction,
+        component: frame.component,
+        command: frame.command,
+        ty: frame.ty,
+        seq: frame.seq,
+        contents: to_hex(&packet.contents),
+    };
+
+    let key = component_key(frame.component, frame.command);
+    let name = format!(
+        "{}->{} ({:#06x}->{:#06x})",
+        get_component_name(frame.component).unwrap_or("Unknown"),
+        get_command_name(key, frame.ty == FrameType::Notify).unwrap_or("Unknown"),
+        frame.component,
+        frame.command
+    );
+
+    debug!(
+        "Holding {:?} packet {} at breakpoint {}",
+        direction, name, id
+    );
+    if let Some(capture) = capture() {
+        capture.record_event(
+            "breakpoint",
+            &format!("Held {:?} {} as #{}", direction, name, id),
+        );
+    }
+
+    let (tx, rx) = oneshot::channel();
+    HELD.lock().expect("Breakpoints lock poisoned").push(Held {
+        packet: held,
+        frame: frame.clone(),
+        tx,
+    });
+
+    open_editor(id, connection, direction, &name, &packet);
+
+    // Forwarding resumes even if the breakpoint is abandoned
+    let action = rx.await.unwrap_or(BreakpointAction::Continue);
+    debug!("Breakpoint {} resolved: {:?}", id, action);
+
+    match action {
+        BreakpointAction::Continue => Some(packet),
+        // Forwarding edited packets is active probing
+        BreakpointAction::Modify(modified) => match consent::require(ActiveFeature::Probing) {
+            Ok(()) => {
+                record_edit(id, connection, direction, &packet, &modified);
+                Some(modified)
+            }
+            Err(err) => {
+                warn!("Forwarding the original packet held at {}: {}", id, err);
+                Some(packet)
+            }
+        },
+        BreakpointAction::Drop => None,
+    }
+}
+
+/// Opens the packet editor for a held packet. Contents are edited as the
+/// typed JSON form of their fields, contents that can't be decoded are
+/// edited as hex instead
+fn open_editor(id: u32, connection: u32, direction: Direction, name: &str, packet: &Packet) {
+    let (text, as_json) = match decode_fields(&packet.contents)
+        .ok()
+        .and_then(|fields| serde_json::to_string_pretty(&fields).ok())
+    {
+        Some(value) => (value, true),
+        None => (to_hex(&packet.contents), false),
+    };
+
+    let parse = move |text: &str| -> Result<Vec<u8>, String> {
+        if as_json {
+            serde_json::from_str::<Vec<Field>>(text)
+                .map(|fields| encode_fields(&fields))
+                .map_err(|err| err.to_string())
+        } else {
+            from_hex(text.trim()).ok_or_else(|| locale::text("editor.invalid.hex"))
+        }
+    };
+
+    let frame = packet.frame.clone();
+    show_editor(
+        &locale::format("editor.title", &[("id", &id.to_string())]),
+        &locale::format(
+            "editor.message",
+            &[
+                ("direction", &format!("{:?}", direction)),
+                ("name", name),
+                ("connection", &connection.to_string()),
+            ],
+        ),
+        &text,
+        move |text| parse(text).err(),
+        move |choice| {
+            let action = match choice {
+                EditorChoice::Forward => BreakpointAction::Continue,
+                EditorChoice::Drop => BreakpointAction::Drop,
+                EditorChoice::ForwardEdited(text) => match parse(&text) {
+                    Ok(contents) => {
+                        BreakpointAction::Modify(Packet::new(frame, Bytes::from(contents)))
+                    }
+                    Err(err) => {
+                        error!("Failed to encode edited packet: {}", err);
+                        BreakpointAction::Continue
+                    }
+                },
+            };
+            resolve(id, action);
+        },
+    );
+}
+
+/// Records both versions of a packet modified at a breakpoint
+fn record_edit(
+    id: u32,
+    connection: u32,
+    direction: Direction,
+    original: &Packet,
+    modified: &Packet,
+) {
+    let Some(capture) = capture() else {
+        return;
+    };
+
+    let frame = &original.frame;
+    let record = EditRecord {
+        time: now_micros(),
+        breakpoint: id,
+        connection,
+        direction,
+        component: frame.component,
+        command: frame.command,
+        seq: frame.seq,
+        original: to_hex(&original.contents),
+        modified: to_hex(&modified.contents),
+    };
+
+    if let Err(err) = append_jsonl(&capture.dir.join(EDITS_FILE), &record) {
+        error!("Failed to record packet edit: {}", err);
+    }
+    capture.record_event(
+        "breakpoint",
+        &format!("Packet held at #{} was modified", id),
+    );
+}
+
+/// Resolves the breakpoint with the provided `id`, returns false if no
+/// packet is held with the ID
+pub fn resolve(id: u32, action: BreakpointAction) -> bool {
+    let held = {
+        let held = &mut *HELD.lock().expect("Breakpoints lock poisoned");
+        let Some(index) = held.iter().position(|held| held.packet.id == id) else {
+            return false;
+        };
+        held.remove(index)
+    };
+
+    if held.tx.send(action).is_err() {
+        warn!("Breakpoint {} was resolved after its session ended", id);
+    }
+    true
+}
+
+/// Creates the action for replacing the contents of the held packet
+/// with `id` by the provided hex encoded contents
+pub fn modify_contents(id: u32, contents: &str) -> Option<BreakpointAction> {
+    let contents = Bytes::from(from_hex(contents)?);
+    let held = HELD.lock().expect("Breakpoints lock poisoned");
+    let frame = held.iter().find(|held| held.packet.id == id)?.frame.clone();
+    Some(BreakpointAction::Modify(Packet::new(frame, contents)))
+}
+
+/// Lists the packets currently held at breakpoints
+pub fn held() -> Vec<HeldPacket> {
+    HELD.lock()
+        .expect("Breakpoints lock poisoned")
+        .iter()
+        .map(|held| held.packet.clone())
+        .collect()
+}
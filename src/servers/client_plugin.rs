@@ -0,0 +1,211 @@
+//! Handshake with the Pocket Relay client's plugin system over the control
+//! API. The client exchanges versions with the plugin to decide whether it
+//! needs updating and pushes config changes so the plugin can be managed
+//! through the client rather than by editing the config file
+//!
+//! Pushed config is written to the config file and applied on the next
+//! launch, consent can't be pushed as it must be chosen by the user
+
+use crate::{
+    config::{self, config},
+    constants::APP_VERSION,
+    servers::control::json_response,
+};
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::Request,
+};
+use hyper::{Method, Response, StatusCode};
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+/// Route prefix for plugin requests within the control API
+pub const PLUGIN_PREFIX: &str = "plugin/";
+
+/// Name the plugin identifies itself with
+pub const PLUGIN_NAME: &str = "pocket-relay-data-collect";
+
+/// Version of the handshake protocol spoken by the plugin
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Largest request body accepted by the plugin routes
+const MAX_BODY_LENGTH: u64 = 64 * 1024;
+
+/// Config fields the client isn't allowed to push
+const PROTECTED_FIELDS: &[&str] = &["consent"];
+
+/// The client that most recently completed the handshake
+static CLIENT: Mutex<Option<HandshakeRequest>> = Mutex::new(None);
+
+/// Handshake sent by the client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandshakeRequest {
+    /// Version of the Pocket Relay client
+    pub client_version: String,
+    /// Version of the handshake protocol spoken by the client
+    pub protocol_version: u32,
+}
+
+/// Handshake response sent to the client
+#[derive(Debug, Serialize)]
+pub struct HandshakeResponse {
+    pub name: &'static str,
+    /// Version of the plugin
+    pub version: &'static str,
+    /// Version of the handshake protocol spoken by the plugin
+    pub protocol_version: u32,
+    /// Whether the plugin can be managed by the client
+    pub compatible: bool,
+    /// The currently loaded config
+    pub config: Value,
+}
+
+/// Obtains the client that most recently completed the handshake
+pub fn client() -> Option<HandshakeRequest> {
+    CLIENT.lock().expect("Plugin client lock poisoned").clone()
+}
+
+/// Handles a plugin request from the client
+///
+/// `route` The request path with the control and plugin prefix removed
+pub async fn handle_plugin(req: Request, route: &str) -> Response<Body> {
+    let route = route.trim_end_matches('/').to_string();
+    let method = req.method().clone();
+
+    let body = match read_body(req).await {
+        Ok(value) => value,
+        Err(response) => return response,
+    };
+
+    match (&method, route.as_str()) {
+        (&Method::POST, "handshake") => handshake(&body),
+        (&Method::POST, "config") => push_config(&body),
+        _ => json_response(StatusCode::NOT_FOUND, json!({ "error": "Unknown route" })),
+    }
+}
+
+/// Reads the request body as JSON, bodies are limited in size
+async fn read_body(req: Request) -> Result<Value, Response<Body>> {
+    let too_large = || {
+        json_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            json!({ "error": "Request body is too large" }),
+        )
+    };
+
+    let body = req.into_body();
+    if body.size_hint().lower() > MAX_BODY_LENGTH {
+        return Err(too_large());
+    }
+
+    let bytes = to_bytes(body, usize::MAX).await.map_err(|err| {
+        json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+    })?;
+    if bytes.len() as u64 > MAX_BODY_LENGTH {
+        return Err(too_large());
+    }
+    if bytes.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    serde_json::from_slice(&bytes)
+        .map_err(|err| json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() })))
+}
+
+/// Exchanges versions with the client
+fn handshake(body: &Value) -> Response<Body> {
+    let request: HandshakeRequest = match serde_json::from_value(body.clone()) {
+        Ok(value) => value,
+        Err(err) => {
+            return json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+        }
+    };
+
+    let compatible = request.protocol_version == PROTOCOL_VERSION;
+    if compatible {
+        info!(
+            "Pocket Relay client {} connected to the plugin",
+            request.client_version
+        );
+        *CLIENT.lock().expect("Plugin client lock poisoned") = Some(request);
+    } else {
+        error!(
+            "Pocket Relay client {} uses plugin protocol {} but {} is required",
+            request.client_version, request.protocol_version, PROTOCOL_VERSION
+        );
+    }
+
+    let config = serde_json::to_value(config()).unwrap_or(Value::Null);
+    json_response(
+        StatusCode::OK,
+        json!(HandshakeResponse {
+            name: PLUGIN_NAME,
+            version: APP_VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            compatible,
+            config,
+        }),
+    )
+}
+
+/// Merges the pushed config fields into the config file, the changes are
+/// applied on the next launch
+fn push_config(body: &Value) -> Response<Body> {
+    if client().is_none() {
+        return json_response(
+            StatusCode::CONFLICT,
+            json!({ "error": "Handshake must be completed first" }),
+        );
+    }
+
+    let Value::Object(fields) = body else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": "Config must be an object" }),
+        );
+    };
+
+    if let Some(field) = PROTECTED_FIELDS
+        .iter()
+        .find(|field| fields.contains_key(**field))
+    {
+        return json_response(
+            StatusCode::FORBIDDEN,
+            json!({ "error": format!("The {} field can't be pushed", field) }),
+        );
+    }
+
+    // Earlier pushes are only in the file until the next launch
+    let current = config::read_file().map(|value| serde_json::to_value(value));
+    let mut value = match current {
+        Ok(Ok(value)) => value,
+        _ => serde_json::to_value(config()).unwrap_or_else(|_| json!({})),
+    };
+    for (key, field) in fields {
+        value[key] = field.clone();
+    }
+
+    // Checked against the config so a bad push can't break the next launch
+    let merged: config::Config = match serde_json::from_value(value) {
+        Ok(value) => value,
+        Err(err) => {
+            return json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+        }
+    };
+
+    if let Err(err) = config::write(&merged) {
+        error!("Failed to write pushed config: {}", err);
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": err.to_string() }),
+        );
+    }
+
+    debug!(
+        "Pocket Relay client pushed config fields: {:?}",
+        fields.keys().collect::<Vec<_>>()
+    );
+    json_response(StatusCode::OK, json!({ "restart_required": true }))
+}
@@ -1,3 +1,4 @@
+use crate::definitions::{definitions, NameDefinition};
 use std::collections::HashMap;
 
 /// Key created from a component and command
@@ -18,15 +19,35 @@ static COMPONENT_NAMES: &[(u16, &str)] = &[
 static mut COMMANDS: Option<HashMap<ComponentKey, &'static str>> = None;
 static mut NOTIFICATIONS: Option<HashMap<ComponentKey, &'static str>> = None;
 
-/// Initializes the stored component state. Should only be
-/// called on initial startup
+/// Initializes the stored component state merging in any names from
+/// the external definitions. Should only be called on initial startup
 pub fn initialize() {
+    let definitions = definitions();
+
+    let mut commands = commands();
+    extend_names(&mut commands, &definitions.commands);
+
+    let mut notifications = notifications();
+    extend_names(&mut notifications, &definitions.notifications);
+
     unsafe {
-        COMMANDS = Some(commands());
-        NOTIFICATIONS = Some(notifications())
+        COMMANDS = Some(commands);
+        NOTIFICATIONS = Some(notifications)
     }
 }
 
+fn extend_names(
+    names: &mut HashMap<ComponentKey, &'static str>,
+    definitions: &'static [NameDefinition],
+) {
+    names.extend(definitions.iter().map(|definition| {
+        (
+            component_key(definition.component, definition.command),
+            definition.name.as_str(),
+        )
+    }));
+}
+
 pub fn get_component_name(component: u16) -> Option<&'static str> {
     COMPONENT_NAMES
         .iter()
@@ -0,0 +1,119 @@
+use super::packet::FireFrame;
+use crate::{
+    capture::Direction,
+    consent::{self, ActiveFeature},
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::Instant;
+
+/// Rule describing degraded network conditions to simulate for
+/// the packets it matches
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConditionRule {
+    /// Only apply to packets travelling in this direction
+    pub direction: Option<Direction>,
+    /// Only apply to packets for this component
+    pub component: Option<u16>,
+    /// Delay in milliseconds added to each packet
+    pub delay_ms: u64,
+    /// Maximum random variation in milliseconds applied to the delay
+    pub jitter_ms: u64,
+    /// Percentage (0-100) of packets that are dropped instead of forwarded
+    pub loss_percent: f64,
+}
+
+/// What should happen to a packet after applying the conditions
+pub enum Verdict {
+    /// Forward the packet once the instant has been reached
+    Forward(Instant),
+    /// Drop the packet
+    Drop,
+}
+
+/// Network condition simulator for a single proxied session. Packets stay
+/// in order within each direction as the proxied connections are TCP
+pub struct NetworkConditions {
+    /// The rules to apply, the first matching rule is used
+    rules: &'static [ConditionRule],
+    /// The latest forward time for each direction
+    last_due: [Instant; 2],
+    /// Random state for jitter and loss
+    random: u64,
+}
+
+/// Whether the missing consent for the simulator has been logged
+static CONSENT_WARNED: AtomicBool = AtomicBool::new(false);
+
+impl NetworkConditions {
+    /// Creates the simulator for the provided rules, the rules are ignored
+    /// unless the user has opted into active probing as degrading the
+    /// connection changes what the official servers see
+    pub fn new(rules: &'static [ConditionRule]) -> Self {
+        let rules = match consent::require(ActiveFeature::Probing) {
+            Ok(()) => rules,
+            Err(err) => {
+                if !rules.is_empty() && !CONSENT_WARNED.swap(true, Ordering::Relaxed) {
+                    warn!("Ignoring the network conditions: {}", err);
+                }
+                &[]
+            }
+        };
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|value| value.as_nanos() as u64)
+            .unwrap_or_default();
+
+        let now = Instant::now();
+        Self {
+            rules,
+            last_due: [now, now],
+            random: seed | 1,
+        }
+    }
+
+    /// Applies the conditions to a packet travelling in the provided direction
+    pub fn apply(&mut self, direction: Direction, frame: &FireFrame) -> Verdict {
+        let now = Instant::now();
+
+        let Some(rule) = self.rules.iter().find(|rule| {
+            rule.direction.is_none_or(|value| value == direction)
+                && rule.component.is_none_or(|value| value == frame.component)
+        }) else {
+            return Verdict::Forward(now);
+        };
+
+        if rule.loss_percent > 0.0 && self.next_fraction() * 100.0 < rule.loss_percent {
+            return Verdict::Drop;
+        }
+
+        let mut delay = rule.delay_ms as f64;
+        if rule.jitter_ms > 0 {
+            delay += (self.next_fraction() * 2.0 - 1.0) * rule.jitter_ms as f64;
+        }
+        let due = now + Duration::from_micros((delay.max(0.0) * 1000.0) as u64);
+
+        // Jitter can't cause packets to overtake earlier packets
+        let last_due = &mut self.last_due[direction as usize];
+        let due = due.max(*last_due);
+        *last_due = due;
+
+        Verdict::Forward(due)
+    }
+
+    /// Generates a random value in the range 0..1 using xorshift
+    fn next_fraction(&mut self) -> f64 {
+        let mut value = self.random;
+        value ^= value << 13;
+        value ^= value >> 7;
+        value ^= value << 17;
+        self.random = value;
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,434 @@
+use crate::capture::{
+    capture,
+    collection::{is_valid_code, MarkerSource},
+    dump_dir,
+    profile::CaptureProfile,
+    query::{query_packets, PacketQuery},
+    session_dir, writer, Capture, SESSION_PREFIX,
+};
+use crate::config::config;
+use crate::consent::{self, ActiveFeature};
+use crate::console;
+use crate::errors;
+use crate::servers::{
+    access::{self, AccessError},
+    breakpoints::{self, BreakpointAction},
+    fuzzer, macros, probing,
+    status::status,
+    supervisor,
+};
+use crate::ui;
+use axum::body::Body;
+use hyper::{header::CONTENT_TYPE, Method, Response, StatusCode};
+use serde_json::{json, Value};
+use std::io;
+
+/// Path prefix for requests to the control API, requests to the HTTP
+/// server starting with this prefix are handled locally rather than
+/// being proxied
+pub const CONTROL_PREFIX: &str = "/_control/";
+
+/// Handles a request to the control API
+///
+/// `method` The request method
+/// `route`  The request path with the control prefix removed
+/// `query`  The request query string
+pub fn handle_control(method: &Method, route: &str, query: Option<&str>) -> Response<Body> {
+    let segments: Vec<&str> = route
+        .trim_end_matches('/')
+        .split('/')
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["status"]) => {
+            // Include the state of the local servers along with the official server
+            let mut value = json!(status());
+            value["servers"] = json!(supervisor::servers());
+            value["errors"] = json!(errors::summary());
+            json_response(StatusCode::OK, value)
+        }
+        (&Method::GET, ["profile"]) => json_response(
+            StatusCode::OK,
+            json!({ "profile": CaptureProfile::current() }),
+        ),
+        (&Method::POST, ["profile", name]) => set_profile(name),
+        (&Method::POST, ["integrity"]) => write_integrity(),
+        (&Method::POST, ["save"]) => with_capture(|capture| match capture.save() {
+            Ok(integrity) => json_response(StatusCode::OK, json!(integrity)),
+            Err(err) => json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": err.to_string() }),
+            ),
+        }),
+        (&Method::GET, ["collection"]) => with_capture(|capture| {
+            json_response(
+                StatusCode::OK,
+                json!({ "collection": capture.collection() }),
+            )
+        }),
+        (&Method::POST, ["collection", code]) => set_collection(code),
+        (&Method::DELETE, ["collection"]) => with_capture(|capture| {
+            capture.set_collection(None);
+            json_response(StatusCode::OK, json!({ "collection": null }))
+        }),
+        (&Method::POST, ["marker", label]) => with_capture(|capture| {
+            capture.record_marker(MarkerSource::User, label, None);
+            json_response(StatusCode::OK, json!({ "marker": label }))
+        }),
+        (&Method::GET, ["stats", "bandwidth"]) => with_capture(|capture| {
+            json_response(
+                StatusCode::OK,
+                json!({ "bandwidth": capture.bandwidth.snapshot() }),
+            )
+        }),
+        (&Method::GET, ["stats", "writer"]) => {
+            json_response(StatusCode::OK, json!({ "writer": writer::stats() }))
+        }
+        (&Method::GET, ["filters"]) => {
+            json_response(StatusCode::OK, json!({ "filters": console::filters() }))
+        }
+        (&Method::POST, ["filters", name]) => {
+            console::add_filter(name);
+            json_response(StatusCode::OK, json!({ "filters": console::filters() }))
+        }
+        (&Method::DELETE, ["filters", name]) => {
+            if !console::remove_filter(name) {
+                return json_response(
+                    StatusCode::NOT_FOUND,
+                    json!({ "error": "No filter with that name" }),
+                );
+            }
+            json_response(StatusCode::OK, json!({ "filters": console::filters() }))
+        }
+        (&Method::DELETE, ["filters"]) => {
+            console::clear_filters();
+            json_response(StatusCode::OK, json!({ "filters": [] }))
+        }
+        (&Method::GET, ["recording"]) => with_capture(|capture| {
+            json_response(
+                StatusCode::OK,
+                json!({ "recording": capture.triggers.is_recording() }),
+            )
+        }),
+        (&Method::POST, ["recording", action @ ("start" | "stop")]) => with_capture(|capture| {
+            let recording = *action == "start";
+            capture.triggers.set_recording(recording);
+            capture.record_event(
+                "recording",
+                &format!(
+                    "Recording {} through the control API",
+                    if recording { "started" } else { "stopped" }
+                ),
+            );
+            json_response(StatusCode::OK, json!({ "recording": recording }))
+        }),
+        (&Method::GET, ["breakpoints"]) => {
+            json_response(StatusCode::OK, json!({ "held": breakpoints::held() }))
+        }
+        (&Method::POST, ["breakpoints", id, action]) => resolve_breakpoint(id, action, query),
+        (&Method::POST, ["macros", "record", name]) => {
+            if !macros::is_valid_name(name) {
+                return json_response(
+                    StatusCode::BAD_REQUEST,
+                    json!({ "error": "Invalid macro name" }),
+                );
+            }
+            macros::start_recording(name);
+            json_response(StatusCode::OK, json!({ "recording": name }))
+        }
+        (&Method::POST, ["macros", "stop"]) => match macros::stop_recording() {
+            Ok(Some(steps)) => json_response(StatusCode::OK, json!({ "steps": steps })),
+            Ok(None) => json_response(
+                StatusCode::CONFLICT,
+                json!({ "error": "No macro is being recorded" }),
+            ),
+            Err(err) => json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": err.to_string() }),
+            ),
+        },
+        (&Method::POST, ["macros", "replay", name]) => replay_macro(name, query),
+        (&Method::POST, ["fuzz", session, index]) => start_fuzzing(session, index),
+        (&Method::GET, ["probing"]) => json_response(StatusCode::OK, json!(probing::status())),
+        (&Method::POST, ["probing", action @ ("stop" | "resume")]) => {
+            probing::set_stopped(*action == "stop");
+            json_response(StatusCode::OK, json!(probing::status()))
+        }
+        (&Method::GET, ["sessions"]) => list_sessions(),
+        (&Method::POST, ["review"]) => {
+            ui::show_data_review();
+            json_response(StatusCode::ACCEPTED, json!({}))
+        }
+        (&Method::GET, ["sessions", name, "packets"]) => query_session(name, query),
+        _ => json_response(StatusCode::NOT_FOUND, json!({ "error": "Unknown route" })),
+    }
+}
+
+/// Switches the active capture profile
+fn set_profile(name: &str) -> Response<Body> {
+    let Ok(profile) = name.parse::<CaptureProfile>() else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": "Unknown capture profile" }),
+        );
+    };
+
+    match capture() {
+        Some(capture) => capture.set_profile(profile),
+        None => profile.activate(),
+    }
+
+    json_response(StatusCode::OK, json!({ "profile": profile }))
+}
+
+/// Writes the integrity file for the current capture session
+fn write_integrity() -> Response<Body> {
+    with_capture(|capture| match capture.write_integrity() {
+        Ok(integrity) => json_response(StatusCode::OK, json!(integrity)),
+        Err(err) => json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": err.to_string() }),
+        ),
+    })
+}
+
+/// Sets the collection session code for the current capture session
+fn set_collection(code: &str) -> Response<Body> {
+    if !is_valid_code(code) {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": "Invalid collection session code" }),
+        );
+    }
+
+    with_capture(|capture| {
+        capture.set_collection(Some(code.to_string()));
+        json_response(StatusCode::OK, json!({ "collection": code }))
+    })
+}
+
+/// Resolves a held breakpoint with the provided action, modified contents
+/// are provided as hex in the "contents" query parameter
+fn resolve_breakpoint(id: &str, action: &str, query: Option<&str>) -> Response<Body> {
+    let Ok(id) = id.parse::<u32>() else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": "Invalid breakpoint ID" }),
+        );
+    };
+
+    let action = match action {
+        "continue" => BreakpointAction::Continue,
+        "drop" => BreakpointAction::Drop,
+        "modify" => {
+            let contents =
+                serde_urlencoded::from_str::<Vec<(String, String)>>(query.unwrap_or_default())
+                    .ok()
+                    .and_then(|params| params.into_iter().find(|(key, _)| key == "contents"))
+                    .and_then(|(_, contents)| breakpoints::modify_contents(id, &contents));
+
+            match contents {
+                Some(value) => value,
+                None => {
+                    return json_response(
+                        StatusCode::BAD_REQUEST,
+                        json!({ "error": "Missing or invalid contents" }),
+                    )
+                }
+            }
+        }
+        _ => {
+            return json_response(
+                StatusCode::NOT_FOUND,
+                json!({ "error": "Unknown breakpoint action" }),
+            )
+        }
+    };
+
+    if breakpoints::resolve(id, action) {
+        json_response(StatusCode::OK, json!({ "resolved": id }))
+    } else {
+        json_response(
+            StatusCode::NOT_FOUND,
+            json!({ "error": "No packet held with that ID" }),
+        )
+    }
+}
+
+/// Starts replaying a saved macro, the query parameters override
+/// field values by their path within each request
+fn replay_macro(name: &str, query: Option<&str>) -> Response<Body> {
+    if !macros::is_valid_name(name) {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": "Invalid macro name" }),
+        );
+    }
+
+    let mut parameters: Vec<(String, String)> =
+        match serde_urlencoded::from_str(query.unwrap_or_default()) {
+            Ok(value) => value,
+            Err(err) => {
+                return json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+            }
+        };
+
+    // The access token isn't a macro parameter
+    parameters.retain(|(key, _)| key != access::TOKEN_PARAM);
+
+    if let Err(err) = consent::require(ActiveFeature::Probing) {
+        return json_response(StatusCode::FORBIDDEN, json!({ "error": err.to_string() }));
+    }
+
+    match macros::load(name).and_then(|value| macros::start_replay(value, parameters)) {
+        Ok(results) => json_response(StatusCode::ACCEPTED, json!({ "results": results })),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            json_response(StatusCode::NOT_FOUND, json!({ "error": "Unknown macro" }))
+        }
+        Err(err) => json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": err.to_string() }),
+        ),
+    }
+}
+
+/// Starts fuzzing a request from a capture session if fuzzing is enabled
+fn start_fuzzing(session: &str, index: &str) -> Response<Body> {
+    if !config().fuzz.enabled {
+        return json_response(
+            StatusCode::FORBIDDEN,
+            json!({ "error": "Fuzzing is disabled in the config" }),
+        );
+    }
+
+    if let Err(err) = consent::require(ActiveFeature::Probing) {
+        return json_response(StatusCode::FORBIDDEN, json!({ "error": err.to_string() }));
+    }
+
+    let Ok(index) = index.parse::<u64>() else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({ "error": "Invalid packet index" }),
+        );
+    };
+
+    match fuzzer::start(session, index) {
+        Ok(results) => json_response(StatusCode::ACCEPTED, json!({ "results": results })),
+        Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+            json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            json_response(StatusCode::NOT_FOUND, json!({ "error": err.to_string() }))
+        }
+        Err(err) => json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": err.to_string() }),
+        ),
+    }
+}
+
+/// Lists the capture sessions in the dump directory
+fn list_sessions() -> Response<Body> {
+    let sessions: Vec<String> = match dump_dir().and_then(std::fs::read_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(SESSION_PREFIX))
+            .collect(),
+        Err(err) => {
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": err.to_string() }),
+            )
+        }
+    };
+
+    json_response(StatusCode::OK, json!({ "sessions": sessions }))
+}
+
+/// Queries the packets within a capture session, the session name
+/// "current" refers to the active session
+fn query_session(name: &str, query: Option<&str>) -> Response<Body> {
+    let dir = if name == "current" {
+        match capture() {
+            Some(capture) => capture.dir.clone(),
+            None => {
+                return json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({ "error": "Capture session not available" }),
+                )
+            }
+        }
+    } else {
+        match session_dir(name) {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == io::ErrorKind::InvalidInput => {
+                return json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return json_response(StatusCode::NOT_FOUND, json!({ "error": "Unknown session" }))
+            }
+            Err(err) => {
+                return json_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({ "error": err.to_string() }),
+                )
+            }
+        }
+    };
+
+    let query: PacketQuery = match serde_urlencoded::from_str(query.unwrap_or_default()) {
+        Ok(value) => value,
+        Err(err) => {
+            return json_response(StatusCode::BAD_REQUEST, json!({ "error": err.to_string() }))
+        }
+    };
+
+    match query_packets(&dir, &query) {
+        Ok(packets) => json_response(StatusCode::OK, json!({ "packets": packets })),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            json_response(StatusCode::NOT_FOUND, json!({ "error": "Unknown session" }))
+        }
+        Err(err) => json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": err.to_string() }),
+        ),
+    }
+}
+
+/// Handles a request that requires the capture session, responding
+/// with an error if the session isn't available
+fn with_capture<F>(action: F) -> Response<Body>
+where
+    F: FnOnce(&'static Capture) -> Response<Body>,
+{
+    match capture() {
+        Some(capture) => action(capture),
+        None => json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({ "error": "Capture session not available" }),
+        ),
+    }
+}
+
+/// Creates the response for a request refused by access control
+pub fn access_denied(err: AccessError) -> Response<Body> {
+    let status = match err {
+        AccessError::AddressNotAllowed(_) => StatusCode::FORBIDDEN,
+        AccessError::InvalidToken => StatusCode::UNAUTHORIZED,
+        AccessError::TokenUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    json_response(status, json!({ "error": err.to_string() }))
+}
+
+/// Creates a JSON response with the provided status and body
+pub fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
@@ -0,0 +1,258 @@
+//! Host lookups for the official servers. The system DNS is tried first,
+//! falling back to each of the configured DNS over HTTPS providers in
+//! order. Results are cached in memory for their TTL so repeated lookups
+//! don't leave the machine
+
+use crate::config::config;
+use futures_util::future::join;
+use log::debug;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// DNS over HTTPS providers used when none are configured, both
+/// support the JSON API
+const DEFAULT_PROVIDERS: &[&str] = &[
+    "https://cloudflare-dns.com/dns-query",
+    "https://dns.google/resolve",
+];
+
+/// Time addresses from the system DNS are cached for, the system
+/// doesn't expose the TTL of its records
+const SYSTEM_TTL: Duration = Duration::from_secs(60);
+
+/// Shortest time addresses are cached for, records with a TTL of zero
+/// would otherwise be looked up for every request
+const MIN_TTL: Duration = Duration::from_secs(5);
+
+/// DNS record type for IPv4 addresses
+const RECORD_A: u16 = 1;
+/// DNS record type for IPv6 addresses
+const RECORD_AAAA: u16 = 28;
+
+/// Cached lookup results keyed by host
+static CACHE: OnceLock<Mutex<HashMap<String, CacheEntry>>> = OnceLock::new();
+
+struct CacheEntry {
+    addresses: Vec<IpAddr>,
+    expires_at: Instant,
+}
+
+/// Errors that can occur looking up a host
+#[derive(Debug, Error)]
+pub enum DnsError {
+    #[error("Failed to request lookup from {0}: {1}")]
+    Request(String, reqwest::Error),
+    #[error("No addresses found for {0}")]
+    NoAddresses(String),
+}
+
+/// Creates the default list of DNS over HTTPS providers
+pub fn default_providers() -> Vec<String> {
+    DEFAULT_PROVIDERS
+        .iter()
+        .map(|value| value.to_string())
+        .collect()
+}
+
+/// Looks up the IPv4 and IPv6 addresses of the provided host, IPv4
+/// addresses are listed first. Loopback addresses from the system DNS
+/// are ignored as they're usually redirects in the hosts file
+pub async fn resolve(host: &str) -> Result<Vec<IpAddr>, DnsError> {
+    if let Ok(address) = host.parse::<IpAddr>() {
+        return Ok(vec![address]);
+    }
+
+    if let Some(addresses) = cached(host) {
+        return Ok(addresses);
+    }
+
+    let (addresses, ttl) = match lookup_system(host).await {
+        Some(addresses) => (addresses, SYSTEM_TTL),
+        None => lookup_providers(host).await?,
+    };
+
+    debug!("Resolved {} to {:?} for {:?}", host, addresses, ttl);
+
+    cache().lock().expect("DNS cache lock poisoned").insert(
+        host.to_string(),
+        CacheEntry {
+            addresses: addresses.clone(),
+            expires_at: Instant::now() + ttl.max(MIN_TTL),
+        },
+    );
+
+    Ok(addresses)
+}
+
+fn cache() -> &'static Mutex<HashMap<String, CacheEntry>> {
+    CACHE.get_or_init(Default::default)
+}
+
+/// Obtains the cached addresses for the host if they haven't expired
+fn cached(host: &str) -> Option<Vec<IpAddr>> {
+    let cache = &mut *cache().lock().expect("DNS cache lock poisoned");
+    let entry = cache.get(host)?;
+    if entry.expires_at <= Instant::now() {
+        cache.remove(host);
+        return None;
+    }
+    Some(entry.addresses.clone())
+}
+
+/// Attempts to lookup the host using the system DNS
+async fn lookup_system(host: &str) -> Option<Vec<IpAddr>> {
+    let addresses: Vec<IpAddr> = tokio::net::lookup_host((host, 0))
+        .await
+        .ok()?
+        .map(|value| value.ip())
+        .filter(|ip| !ip.is_loopback())
+        .collect();
+
+    if addresses.is_empty() {
+        None
+    } else {
+        Some(sort_addresses(addresses))
+    }
+}
+
+/// Looks up the host using each of the DNS over HTTPS providers until
+/// one provides addresses. Returns the addresses and the shortest TTL
+/// of the records
+async fn lookup_providers(host: &str) -> Result<(Vec<IpAddr>, Duration), DnsError> {
+    let providers = &config().dns_providers;
+    let client = reqwest::Client::new();
+
+    let mut last_error = None;
+
+    for provider in providers {
+        let (a, aaaa) = join(
+            lookup_provider(&client, provider, host, RECORD_A),
+            lookup_provider(&client, provider, host, RECORD_AAAA),
+        )
+        .await;
+
+        let mut answers = Vec::new();
+        for result in [a, aaaa] {
+            match result {
+                Ok(value) => answers.extend(value),
+                Err(err) => {
+                    debug!("DNS lookup for {} failed: {}", host, err);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        let ttl = answers.iter().map(|answer| answer.ttl).min();
+        let addresses: Vec<IpAddr> = answers
+            .into_iter()
+            .filter_map(|answer| answer.data.parse().ok())
+            .collect();
+
+        if let (false, Some(ttl)) = (addresses.is_empty(), ttl) {
+            return Ok((sort_addresses(addresses), Duration::from_secs(ttl as u64)));
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| DnsError::NoAddresses(host.to_string())))
+}
+
+/// Requests the records of the provided type for the host from a
+/// DNS over HTTPS provider
+async fn lookup_provider(
+    client: &reqwest::Client,
+    provider: &str,
+    host: &str,
+    record_type: u16,
+) -> Result<Vec<Answer>, DnsError> {
+    let response: LookupResponse = async {
+        client
+            .get(provider)
+            .query(&[("name", host), ("type", &record_type.to_string())])
+            .header("Accept", "application/dns-json")
+            .timeout(config().request_timeout())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+    .await
+    .map_err(|err| DnsError::Request(provider.to_string(), err))?;
+
+    // Answers may include the CNAME records followed to the address
+    Ok(response
+        .answer
+        .into_iter()
+        .filter(|answer| answer.ty == record_type)
+        .collect())
+}
+
+/// Orders IPv4 addresses before IPv6 addresses, some networks advertise
+/// IPv6 without being able to route it
+fn sort_addresses(mut addresses: Vec<IpAddr>) -> Vec<IpAddr> {
+    addresses.sort_by_key(|address| address.is_ipv6());
+    addresses.dedup();
+    addresses
+}
+
+/// Structure for the lookup responses from the DNS over HTTPS JSON API
+///
+/// # Structure
+///
+/// ```
+/// {
+///   "Status": 0,
+///   "TC": false,
+///   "RD": true,
+///   "RA": true,
+///   "AD": false,
+///   "CD": false,
+///   "Question": [
+///     {
+///       "name": "gosredirector.ea.com.",
+///       "type": 1
+///     }
+///   ],
+///   "Answer": [
+///     {
+///       "name": "gosredirector.ea.com.",
+///       "type": 1,
+///       "TTL": 300,
+///       "data": "159.153.64.175"
+///     }
+///   ],
+///   "Comment": "Response from 2600:1403:a::43."
+/// }
+/// ```
+#[derive(Deserialize)]
+struct LookupResponse {
+    /// Missing when the host has no records of the requested type
+    #[serde(rename = "Answer", default)]
+    answer: Vec<Answer>,
+}
+
+/// Structure for answer portion of request
+///
+/// # Structure
+/// ```
+/// {
+///   "name": "gosredirector.ea.com.",
+///   "type": 1,
+///   "TTL": 300,
+///   "data": "159.153.64.175"
+/// }
+/// ```
+#[derive(Deserialize)]
+struct Answer {
+    #[serde(rename = "type")]
+    ty: u16,
+    #[serde(rename = "TTL")]
+    ttl: u32,
+    data: String,
+}
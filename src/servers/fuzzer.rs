@@ -0,0 +1,212 @@
+use crate::{
+    capture::{
+        append_jsonl, dump_dir, now_millis,
+        packet_log::read_packet_log,
+        session_dir,
+        value::{decode_fields, encode_fields, fields_to_json},
+    },
+    config::config,
+    servers::{
+        macros::apply_parameter,
+        packet::{FrameType, Packet},
+        probing,
+        retriever::{OfficialInstance, OfficialSession, RetrieverError},
+    },
+    tasks,
+};
+use bytes::Bytes;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+
+/// Name of the directory within the dump directory fuzzing results are stored in
+const FUZZ_DIR: &str = "fuzz";
+
+/// Settings for fuzzing official endpoints
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FuzzConfig {
+    /// Whether fuzzing can be started, off by default as it sends
+    /// requests the game never would
+    pub enabled: bool,
+    /// Maximum number of variants sent per fuzzing run
+    pub max_variants: usize,
+    /// Fields to mutate and the bounds of their values
+    pub mutations: Vec<FieldMutation>,
+}
+
+impl Default for FuzzConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_variants: 50,
+            mutations: Vec::new(),
+        }
+    }
+}
+
+/// Mutation of a single field, fields are selected by their dot
+/// separated path within the request (e.g. `ATTR.SCOR`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldMutation {
+    pub path: String,
+    #[serde(flatten)]
+    pub bounds: MutationBounds,
+}
+
+/// Bounds of the values a field is mutated within
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MutationBounds {
+    /// Integers between `min` and `max` inclusive
+    Range { min: i64, max: i64 },
+    /// Each of the provided values
+    Values { values: Vec<String> },
+    /// Strings with lengths up to `max_length`
+    Length { max_length: usize },
+}
+
+impl MutationBounds {
+    /// Creates the values for the field, the bounds are always included
+    fn values(&self) -> Vec<String> {
+        match self {
+            MutationBounds::Range { min, max } => {
+                let (min, max) = (*min.min(max), *min.max(max));
+                let mut values = vec![min, max, min + (max - min) / 2];
+                if min < 0 && max > 0 {
+                    values.push(0);
+                }
+                values.dedup();
+                values.iter().map(ToString::to_string).collect()
+            }
+            MutationBounds::Values { values } => values.clone(),
+            MutationBounds::Length { max_length } => [0, 1, max_length / 2, *max_length]
+                .into_iter()
+                .map(|length| "A".repeat(length))
+                .collect(),
+        }
+    }
+}
+
+/// Response to a single variant
+#[derive(Serialize)]
+pub struct FuzzResult {
+    /// Variant number, zero is the unmodified request
+    pub variant: usize,
+    /// Path of the mutated field, None for the unmodified request
+    pub path: Option<String>,
+    /// The value the field was set to
+    pub value: Option<String>,
+    /// Frame type of the response, None if no response was received
+    pub ty: Option<FrameType>,
+    pub error: u16,
+    /// Decoded response contents
+    pub body: Option<Value>,
+    /// Reason the request failed
+    pub failure: Option<String>,
+}
+
+/// Starts fuzzing the request with the provided index from a capture
+/// session in the background. Returns the name of the results file
+/// within the fuzz directory
+pub fn start(session: &str, index: u64) -> io::Result<String> {
+    let root = dump_dir()?;
+    let record = read_packet_log(&session_dir(session)?)?
+        .into_iter()
+        .find(|record| record.index == index)
+        .filter(|record| FrameType::from(record.ty) == FrameType::Request)
+        .filter(|record| record.contents.is_some())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Request not found"))?;
+
+    let dir = root.join(FUZZ_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let name = format!("{}-{}-{}.jsonl", session, index, now_millis());
+    let path = dir.join(&name);
+
+    tasks::spawn("fuzzer", async move {
+        if let Err(err) = run(record.to_packet(), &path).await {
+            error!("Fuzzing stopped: {}", err);
+        }
+    });
+
+    Ok(name)
+}
+
+/// Sends the unmodified request followed by each variant, recording
+/// each response to the results file at `path`
+async fn run(request: Packet, path: &std::path::Path) -> io::Result<()> {
+    let fuzz = &config().fuzz;
+    let fields = decode_fields(&request.contents)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    // Each variant changes a single field from the original request
+    let variants: Vec<(String, String)> = fuzz
+        .mutations
+        .iter()
+        .flat_map(|mutation| {
+            mutation
+                .bounds
+                .values()
+                .into_iter()
+                .map(|value| (mutation.path.clone(), value))
+        })
+        .take(fuzz.max_variants)
+        .collect();
+
+    let instance = OfficialInstance::obtain()
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let (host, port) = instance.target();
+    let session = OfficialSession::connect(&host, port).await?;
+
+    let frame = &request.frame;
+    debug!(
+        "Fuzzing {:#06x}->{:#06x} with {} variants",
+        frame.component,
+        frame.command,
+        variants.len()
+    );
+
+    let baseline = std::iter::once(None).chain(variants.into_iter().map(Some));
+    for (variant, mutation) in baseline.enumerate() {
+        let mut fields = fields.clone();
+        if let Some((path, value)) = &mutation {
+            apply_parameter(&mut fields, path, value);
+        }
+
+        let contents = Bytes::from(encode_fields(&fields));
+        // Variants are paced by the shared probing limiter
+        let response = probing::request(&session, frame.component, frame.command, contents).await;
+
+        let (packet, failure) = match &response {
+            Ok(packet) => (Some(packet), None),
+            Err(RetrieverError::Packet(err)) => (Some(err.packet()), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        let (field, value) = mutation.unzip();
+        let result = FuzzResult {
+            variant,
+            path: field,
+            value,
+            ty: packet.map(|packet| packet.frame.ty),
+            error: packet.map(|packet| packet.frame.error).unwrap_or_default(),
+            body: packet
+                .and_then(|packet| decode_fields(&packet.contents).ok())
+                .map(|fields| fields_to_json(&fields)),
+            failure,
+        };
+        append_jsonl(path, &result)?;
+
+        if matches!(response, Err(RetrieverError::Probing(_))) {
+            break;
+        }
+    }
+
+    debug!(
+        "Finished fuzzing {:#06x}->{:#06x}",
+        frame.component, frame.command
+    );
+    Ok(())
+}
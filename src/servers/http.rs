@@ -1,46 +1,226 @@
+use crate::capture::{
+    capture,
+    clock::now_micros,
+    http_log::HttpRecord,
+    manifest::{DestinationRecord, DestinationSource, HttpConnectionStats},
+    now_millis,
+    privacy::PrivacyCategories,
+    profile::CaptureProfile,
+};
+use crate::config::config;
 use crate::constants::HTTP_PORT;
-use hyper::body::Body;
-use hyper::header::HOST;
-use hyper::service::service_fn;
-use hyper::{server::conn::Http, Request};
-use hyper::{Response, StatusCode};
-use log::{debug, error};
-use native_windows_gui::error_message;
-use reqwest::Client;
+use crate::errors::{self, BindError, UpstreamError};
+use crate::servers::{
+    access,
+    client_plugin::{handle_plugin, PLUGIN_PREFIX},
+    control::{access_denied, handle_control, json_response, CONTROL_PREFIX},
+    dns,
+    limits::{ConnectionLimit, ListenerLimits},
+    loop_guard, mirror,
+    supervisor::shutdown_token,
+    upgrade,
+};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    routing::any,
+    serve::IncomingStream,
+    Router,
+};
+use hyper::header::{HOST, TRANSFER_ENCODING};
+use hyper::{Method, Response, StatusCode};
+use log::{debug, error, warn};
+use reqwest::{Client, ClientBuilder, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::convert::Infallible;
-use std::net::Ipv4Addr;
+use std::future::{ready, Ready};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, OnceLock,
+};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use tokio::net::TcpListener;
+use tower::{Layer, Service};
 
-pub async fn start_server() {
-    // Initializing the underlying TCP listener
-    let listener = match TcpListener::bind((Ipv4Addr::UNSPECIFIED, HTTP_PORT)).await {
-        Ok(value) => value,
-        Err(err) => {
-            error_message("Failed to start http", &err.to_string());
-            error!("Failed to start http: {}", err);
-            return;
+/// Connection limits for the HTTP listener
+static LIMITS: OnceLock<ListenerLimits> = OnceLock::new();
+
+/// Fixed upstream address for proxied HTTP requests to a host, used
+/// instead of looking up the host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostPin {
+    /// Host header value the pin applies to, without the port
+    pub host: String,
+    /// Address requests for the host are sent to, loopback addresses
+    /// are ignored as they would proxy requests back to this machine
+    pub address: IpAddr,
+}
+
+/// State shared by the requests made on a single connection. The state
+/// is dropped once the connection closes, which records the connection
+/// in the manifest
+struct Connection {
+    /// Address of the connected client
+    addr: SocketAddr,
+    /// Whether the intended destination of the connection was recorded
+    recorded: AtomicBool,
+    /// Unix timestamp in milliseconds of when the connection was accepted
+    started_at: u64,
+    start: Instant,
+}
+
+type ConnectionState = Arc<Connection>;
+
+impl Connection {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            recorded: AtomicBool::new(false),
+            started_at: now_millis(),
+            start: Instant::now(),
         }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Upgraded connections are counted until the upgrade
+        if let Some(capture) = capture() {
+            let record = HttpConnectionStats {
+                client: self.addr,
+                started_at: self.started_at,
+                duration_ms: self.start.elapsed().as_secs_f64() * 1000.0,
+            };
+            capture.update_manifest(|manifest| manifest.http_connections.push(record));
+        }
+    }
+}
+
+/// Creates the service for each accepted connection, the router with
+/// the listener limits applied
+#[derive(Clone)]
+struct MakeConnection;
+
+impl<'a> Service<IncomingStream<'a>> for MakeConnection {
+    type Response = ConnectionLimit<Router>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, incoming: IncomingStream<'a>) -> Self::Future {
+        let addr = incoming.remote_addr();
+        let router = router(Arc::new(Connection::new(addr)));
+        ready(Ok(limits().layer(addr).layer(router)))
+    }
+}
+
+pub async fn start_server() -> io::Result<()> {
+    // Initializing the underlying TCP listener
+    let listener = TcpListener::bind((config().bind_address, HTTP_PORT))
+        .await
+        .map_err(|source| {
+            errors::report(BindError::Listen {
+                server: "http",
+                port: HTTP_PORT,
+                source,
+            })
+        })?;
+    let shutdown = shutdown_token();
+
+    // In-flight requests are allowed to finish when shutting down
+    axum::serve(listener, MakeConnection)
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
+        .await
+}
+
+fn limits() -> &'static ListenerLimits {
+    LIMITS.get_or_init(|| ListenerLimits::new("http"))
+}
+
+/// Waits up to `limit` for the open HTTP connections to close after
+/// shutdown has started
+pub async fn drain(limit: Duration) {
+    limits().drain(limit).await;
+}
+
+/// Creates the router for the requests on a connection. The plugin's own
+/// routes are matched first and everything else is proxied
+fn router(state: ConnectionState) -> Router {
+    Router::new()
+        .route(&format!("{}*route", CONTROL_PREFIX), any(control))
+        .route("/status", any(local_status))
+        .route("/live", any(local_live))
+        .fallback(proxy)
+        .with_state(state)
+}
+
+/// Handles requests for the control API and the client plugin API
+async fn control(State(state): State<ConnectionState>, req: Request) -> Response<Body> {
+    if let Err(err) = access::authorize(&req, state.addr) {
+        return access_denied(err);
+    }
+
+    let Some(route) = req.uri().path().strip_prefix(CONTROL_PREFIX) else {
+        return json_response(StatusCode::NOT_FOUND, json!({ "error": "Unknown route" }));
     };
+    if let Some(route) = route.strip_prefix(PLUGIN_PREFIX) {
+        let route = route.to_string();
+        return handle_plugin(req, &route).await;
+    }
+    handle_control(req.method(), route, req.uri().query())
+}
 
-    // Accept incoming connections
-    loop {
-        let (stream, _) = match listener.accept().await {
-            Ok(value) => value,
-            Err(_) => break,
-        };
-
-        tokio::task::spawn(async move {
-            if let Err(err) = Http::new()
-                .serve_connection(stream, service_fn(proxy_http))
-                .await
-            {
-                eprintln!("Failed to serve http connection: {:?}", err);
-            }
-        });
+/// Status of the plugin for local tools, the same as the control API
+/// status. Requests for other hosts with the same path are proxied
+async fn local_status(State(state): State<ConnectionState>, req: Request) -> Response<Body> {
+    if req.method() != Method::GET || !is_local_host(&req) {
+        return proxy(State(state), req).await;
     }
+    handle_control(&Method::GET, "status", None)
+}
+
+/// Liveness check for local tools, responds as long as the HTTP server is
+/// running. Requests for other hosts with the same path are proxied
+async fn local_live(State(state): State<ConnectionState>, req: Request) -> Response<Body> {
+    if req.method() != Method::GET || !is_local_host(&req) {
+        return proxy(State(state), req).await;
+    }
+    json_response(StatusCode::OK, json!({ "live": true }))
+}
+
+/// Whether the request was made to this machine rather than to an EA
+/// host redirected to the plugin
+fn is_local_host(req: &Request) -> bool {
+    let Some(host) = req
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    // Remove the port and the brackets around IPv6 addresses
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => host,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|address| address.is_loopback())
 }
 
-async fn proxy_http(req: Request<hyper::body::Body>) -> Result<Response<Body>, Infallible> {
+/// Proxies a request to the official server for its host
+async fn proxy(State(state): State<ConnectionState>, req: Request) -> Response<Body> {
     let path = req
         .uri()
         .path_and_query()
@@ -52,9 +232,9 @@ async fn proxy_http(req: Request<hyper::body::Body>) -> Result<Response<Body>, I
         Some(value) => value,
         None => {
             error!("Failed to send HTTP request: Missing host");
-            let mut error_response = Response::new(hyper::Body::empty());
+            let mut error_response = Response::new(Body::empty());
             *error_response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-            return Ok(error_response);
+            return error_response;
         }
     };
 
@@ -62,35 +242,161 @@ async fn proxy_http(req: Request<hyper::body::Body>) -> Result<Response<Body>, I
 
     debug!("Client HTTP request: {:?}", &req);
 
-    let client = Client::new();
+    if !state.recorded.swap(true, Ordering::AcqRel) {
+        record_destination(&target_url, state.addr);
+    }
+
+    // Requests for the plugin's own servers would be proxied forever
+    if let Err(err) = loop_guard::check_url(&target_url).await {
+        let mut error_response = Response::new(Body::from(err.to_string()));
+        *error_response.status_mut() = StatusCode::LOOP_DETECTED;
+        return error_response;
+    }
+
+    // Upgraded connections are tunnelled rather than buffered
+    if upgrade::is_upgrade(&req) {
+        return upgrade::proxy_upgrade(req, target_url).await;
+    }
+
+    mirror::mirror_http(path);
+
+    let mut record = HttpRecord {
+        time: now_micros(),
+        method: req.method().to_string(),
+        url: target_url.clone(),
+        status: None,
+        duration_ms: 0.0,
+        length: 0,
+        body: None,
+    };
+    let start = Instant::now();
+
+    let client = match client_builder(&target_url).await.build() {
+        Ok(value) => value,
+        Err(err) => {
+            errors::report(UpstreamError::HttpClient(err));
+            record_http(record, start);
+            let mut error_response = Response::new(Body::empty());
+            *error_response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return error_response;
+        }
+    };
     let proxy_response = match client.get(target_url).send().await {
         Ok(value) => value,
         Err(err) => {
-            error!("Failed to send HTTP request: {}", err);
-            let mut error_response = Response::new(hyper::Body::empty());
+            errors::report(UpstreamError::HttpRequest(err));
+            record_http(record, start);
+            let mut error_response = Response::new(Body::empty());
             *error_response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-            return Ok(error_response);
+            return error_response;
         }
     };
 
     debug!("Server HTTP response: {:?}", &proxy_response);
     let status = proxy_response.status();
-    let headers = proxy_response.headers().clone();
+    let mut headers = proxy_response.headers().clone();
+
+    // The body is buffered so it's no longer sent with the upstream
+    // transfer encoding
+    headers.remove(TRANSFER_ENCODING);
 
     let body = match proxy_response.bytes().await {
         Ok(value) => value,
         Err(err) => {
-            error!("Failed to read HTTP response body: {}", err);
-            let mut error_response = Response::new(hyper::Body::empty());
+            errors::report(UpstreamError::HttpBody(err));
+            record.status = Some(status.as_u16());
+            record_http(record, start);
+            let mut error_response = Response::new(Body::empty());
             *error_response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
-            return Ok(error_response);
+            return error_response;
         }
     };
-    debug!("Server HTTP response body: {:?}", &body);
 
-    let mut response = Response::new(hyper::body::Body::from(body));
+    let http_bodies =
+        CaptureProfile::current().http_bodies() && PrivacyCategories::current().http_bodies;
+    if http_bodies {
+        debug!("Server HTTP response body: {:?}", &body);
+    }
+
+    record.status = Some(status.as_u16());
+    record.length = body.len();
+    record.body = http_bodies.then(|| String::from_utf8_lossy(&body).into_owned());
+    record_http(record, start);
+
+    let mut response = Response::new(Body::from(body));
     *response.status_mut() = status;
     *response.headers_mut() = headers;
 
-    Ok(response)
+    response
+}
+
+/// Creates a builder for clients sending requests to the target URL with
+/// the upstream host already resolved from its pin or a lookup, skipping
+/// hosts file redirects and repeated lookups. The client resolves the host
+/// itself if this fails
+pub(crate) async fn client_builder(target_url: &str) -> ClientBuilder {
+    let builder = Client::builder();
+    let Ok(url) = Url::parse(target_url) else {
+        return builder;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return builder;
+    };
+
+    let pin = config()
+        .http_host_pins
+        .iter()
+        .find(|pin| pin.host.eq_ignore_ascii_case(host));
+    if let Some(pin) = pin {
+        if !pin.address.is_loopback() && !pin.address.is_unspecified() {
+            return builder.resolve(host, SocketAddr::new(pin.address, port));
+        }
+        warn!(
+            "Ignoring HTTP host pin {} -> {} as it points back to this machine",
+            pin.host, pin.address
+        );
+    }
+
+    match dns::resolve(host).await {
+        Ok(addresses) => match addresses.first() {
+            Some(&address) => builder.resolve(host, SocketAddr::new(address, port)),
+            None => builder,
+        },
+        Err(err) => {
+            debug!("Failed to resolve HTTP upstream {}: {}", host, err);
+            builder
+        }
+    }
+}
+
+/// Records the host the game intended to reach with the first request
+/// of a connection
+fn record_destination(target_url: &str, addr: SocketAddr) {
+    let Some(capture) = capture() else {
+        return;
+    };
+    let Ok(url) = Url::parse(target_url) else {
+        return;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return;
+    };
+
+    capture.record_destination(DestinationRecord {
+        time: now_millis(),
+        server: "http",
+        connection: None,
+        client: addr,
+        host: host.to_string(),
+        port,
+        source: DestinationSource::HostHeader,
+    });
+}
+
+/// Records the HTTP exchange in the capture session if one is active
+pub(crate) fn record_http(mut record: HttpRecord, start: Instant) {
+    if let Some(capture) = capture() {
+        record.duration_ms = start.elapsed().as_secs_f64() * 1000.0;
+        capture.record_http(record);
+    }
 }
@@ -0,0 +1,203 @@
+//! Limits on the connections accepted by the local servers, so a
+//! misbehaving local process can't exhaust the plugin's resources inside
+//! the game by opening connections faster than they're served
+
+use crate::config::config;
+use axum::body::Body;
+use futures_util::future::Either;
+use hyper::{
+    header::{HeaderValue, CONNECTION},
+    Request, Response, StatusCode,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    future::{ready, Ready},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::timeout,
+};
+use tower::{Layer, Service};
+
+/// Length of the window accepted connections are counted over for the
+/// per address rate limit
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Connection limits applied to each listener
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionLimits {
+    /// Maximum number of connections each server handles at once
+    pub max_connections: u32,
+    /// Maximum number of connections accepted from a single address
+    /// within a second
+    pub max_accepts_per_second: u32,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 64,
+            max_accepts_per_second: 20,
+        }
+    }
+}
+
+/// Connection limits for a single listener, the state lives for the
+/// whole run so it carries over when a server is restarted
+pub struct ListenerLimits {
+    /// Name of the server the listener belongs to
+    name: &'static str,
+    /// Maximum number of connections handled at once
+    max_connections: u32,
+    /// Maximum number of connections accepted per address in a window
+    max_accepts: u32,
+    /// Connections being handled, each connection holds a permit
+    connections: Arc<Semaphore>,
+    /// Start of the current window and the connections accepted within
+    /// it for each address
+    accepts: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl ListenerLimits {
+    /// Creates the limits for the server with the provided `name` from
+    /// the config
+    pub fn new(name: &'static str) -> Self {
+        let limits = &config().connection_limits;
+        let max_connections = limits.max_connections.max(1);
+        Self {
+            name,
+            max_connections,
+            max_accepts: limits.max_accepts_per_second.max(1),
+            connections: Arc::new(Semaphore::new(max_connections as usize)),
+            accepts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks a newly accepted connection from `addr` against the limits.
+    /// The permit must be held until the connection is closed, None if
+    /// the connection should be refused
+    pub fn admit(&self, addr: SocketAddr) -> Option<OwnedSemaphorePermit> {
+        if !self.check_rate(addr.ip()) {
+            return None;
+        }
+
+        match self.connections.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                warn!(
+                    "Refused {} connection from {}, {} connections are already open",
+                    self.name, addr, self.max_connections
+                );
+                None
+            }
+        }
+    }
+
+    /// Checks a newly accepted connection from `addr` against the limits,
+    /// creating the layer applied to the service for the connection
+    pub fn layer(&self, addr: SocketAddr) -> ConnectionLimitLayer {
+        ConnectionLimitLayer {
+            permit: self.admit(addr).map(Arc::new),
+        }
+    }
+
+    /// Counts a connection from `ip` returning whether it's within the
+    /// rate limit. Only the first refusal in each window is logged so a
+    /// flood doesn't also flood the log
+    fn check_rate(&self, ip: IpAddr) -> bool {
+        let accepts = &mut *self.accepts.lock().expect("Accept rate lock poisoned");
+        let now = Instant::now();
+
+        // Forget addresses that haven't connected recently
+        accepts.retain(|_, (started, _)| now.duration_since(*started) < RATE_WINDOW);
+
+        let (_, count) = accepts.entry(ip).or_insert((now, 0));
+        *count += 1;
+
+        if *count <= self.max_accepts {
+            return true;
+        }
+        if *count == self.max_accepts + 1 {
+            warn!(
+                "Refusing {} connections from {}, more than {} connections within {:?}",
+                self.name, ip, self.max_accepts, RATE_WINDOW
+            );
+        }
+        false
+    }
+
+    /// Waits up to `limit` for every connection to close
+    pub async fn drain(&self, limit: Duration) {
+        if timeout(limit, self.connections.acquire_many(self.max_connections))
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} connections were still open after {:?}",
+                self.name, limit
+            );
+        }
+    }
+}
+
+/// Layer applying the listener limits to the service for a single
+/// connection. The permit is held until every clone of the service is
+/// dropped, which happens once the connection is closed
+#[derive(Clone)]
+pub struct ConnectionLimitLayer {
+    /// Permit for the connection, None if the connection was refused
+    permit: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+impl<S> Layer<S> for ConnectionLimitLayer {
+    type Service = ConnectionLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectionLimit {
+            inner,
+            permit: self.permit.clone(),
+        }
+    }
+}
+
+/// Service for a connection within the listener limits, requests on
+/// refused connections are answered without reaching the inner service
+/// and the connection is closed
+#[derive(Clone)]
+pub struct ConnectionLimit<S> {
+    inner: S,
+    permit: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+impl<S, B> Service<Request<B>> for ConnectionLimit<S>
+where
+    S: Service<Request<B>, Response = Response<Body>>,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<Response<Body>, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if self.permit.is_some() {
+            return Either::Left(self.inner.call(req));
+        }
+
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+        Either::Right(ready(Ok(response)))
+    }
+}
@@ -0,0 +1,124 @@
+//! Detection of proxied connections whose upstream is one of the plugin's
+//! own servers. This happens when the hosts file redirecting the game to
+//! Pocket Relay is still active, forwarding would connect the proxy back to
+//! itself until the connection hangs
+
+use crate::{
+    capture::capture,
+    config::config,
+    constants::{HTTP_PORT, MAIN_PORT, REDIRECTOR_PORT, TELEMETRY_PORT},
+    locale,
+    metrics::{self, ErrorCategory},
+    ui::show_warning,
+};
+use log::{error, warn};
+use reqwest::Url;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use thiserror::Error;
+
+/// The plugin's own servers and the ports they listen on
+const LOCAL_SERVERS: [(&str, u16); 4] = [
+    ("redirector", REDIRECTOR_PORT),
+    ("main", MAIN_PORT),
+    ("telemetry", TELEMETRY_PORT),
+    ("http", HTTP_PORT),
+];
+
+/// Whether the warning has been shown, it's only shown once as every
+/// connection would otherwise show it again
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Upstream resolving to one of the plugin's own servers
+#[derive(Debug, Error)]
+#[error("{host}:{port} resolves to the plugin's own {server} server at {address}")]
+pub struct SelfConnection {
+    pub host: String,
+    pub port: u16,
+    /// Name of the plugin server the upstream resolved to
+    pub server: &'static str,
+    pub address: SocketAddr,
+}
+
+impl From<SelfConnection> for io::Error {
+    fn from(value: SelfConnection) -> Self {
+        io::Error::new(io::ErrorKind::ConnectionRefused, value)
+    }
+}
+
+/// Checks that the upstream at `host` and `port` isn't one of the plugin's
+/// own servers, warning the user the first time one is found. Hosts that
+/// fail to resolve are left for the connection to report
+pub async fn check(host: &str, port: u16) -> Result<(), SelfConnection> {
+    if !LOCAL_SERVERS.iter().any(|(_, value)| *value == port) {
+        return Ok(());
+    }
+
+    let Ok(addresses) = tokio::net::lookup_host((host, port)).await else {
+        return Ok(());
+    };
+
+    for address in addresses {
+        if let Some(server) = local_server(address) {
+            let err = SelfConnection {
+                host: host.to_string(),
+                port,
+                server,
+                address,
+            };
+            report(&err);
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the host and port of the provided URL, see [check]
+pub async fn check_url(url: &str) -> Result<(), SelfConnection> {
+    let Ok(url) = Url::parse(url) else {
+        return Ok(());
+    };
+    match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => check(host, port).await,
+        _ => Ok(()),
+    }
+}
+
+/// Finds the name of the plugin server listening on the address
+fn local_server(address: SocketAddr) -> Option<&'static str> {
+    let ip = address.ip();
+    let bind_address = config().bind_address;
+    if !ip.is_loopback() && !ip.is_unspecified() && ip != bind_address {
+        return None;
+    }
+
+    LOCAL_SERVERS
+        .iter()
+        .find(|(_, port)| *port == address.port())
+        .map(|(name, _)| *name)
+}
+
+fn report(err: &SelfConnection) {
+    error!("Refusing to forward connection: {}", err);
+    metrics::count_error(ErrorCategory::ConnectionLoop);
+
+    if WARNED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    warn!(
+        "Hosts file redirect is likely still active for {}",
+        err.host
+    );
+    if let Some(capture) = capture() {
+        capture.record_event("loop", &err.to_string());
+    }
+    show_warning(
+        &locale::text("loop.title"),
+        &locale::format("loop.message", &[("host", &err.host)]),
+    );
+}
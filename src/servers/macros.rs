@@ -0,0 +1,266 @@
+use crate::{
+    capture::{
+        dump_dir, now_millis,
+        value::{decode_fields, encode_fields, fields_to_json, Field, TdfValue},
+    },
+    servers::{
+        packet::{FrameType, Packet},
+        probing,
+        retriever::{OfficialInstance, OfficialSession, RetrieverError},
+    },
+    tasks,
+};
+use bytes::Bytes;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    io,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+/// Name of the directory within the dump directory macros are stored in
+const MACROS_DIR: &str = "macros";
+
+/// Recorded sequence of client requests
+#[derive(Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+/// Single request within a macro
+#[derive(Serialize, Deserialize)]
+pub struct MacroStep {
+    pub component: u16,
+    pub command: u16,
+    /// Milliseconds to wait after the previous step
+    pub delay_ms: u64,
+    /// Fields of the request in their typed form so they can be edited
+    pub fields: Vec<Field>,
+}
+
+/// Result of replaying a single step
+#[derive(Serialize)]
+pub struct StepResult {
+    pub component: u16,
+    pub command: u16,
+    /// Frame type of the response, None if no response was received
+    pub ty: Option<FrameType>,
+    pub error: u16,
+    /// Decoded response contents
+    pub body: Option<Value>,
+    /// Reason the request failed
+    pub failure: Option<String>,
+}
+
+/// Macro that is currently being recorded
+struct Recording {
+    name: String,
+    steps: Vec<MacroStep>,
+    last: Instant,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Directory macros are stored in
+fn macros_dir() -> io::Result<PathBuf> {
+    Ok(dump_dir()?.join(MACROS_DIR))
+}
+
+/// Whether the macro name is safe to use as a file name
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || matches!(char, '-' | '_'))
+}
+
+/// Starts recording client requests into a macro with the provided name,
+/// replacing any recording in progress
+pub fn start_recording(name: &str) {
+    debug!("Recording macro {}", name);
+    *RECORDING.lock().expect("Macro recording lock poisoned") = Some(Recording {
+        name: name.to_string(),
+        steps: Vec::new(),
+        last: Instant::now(),
+    });
+}
+
+/// Stops recording and saves the macro, returns the number of steps
+/// recorded or None if nothing was being recorded
+pub fn stop_recording() -> io::Result<Option<usize>> {
+    let Some(recording) = RECORDING
+        .lock()
+        .expect("Macro recording lock poisoned")
+        .take()
+    else {
+        return Ok(None);
+    };
+
+    let count = recording.steps.len();
+    let value = Macro {
+        name: recording.name,
+        steps: recording.steps,
+    };
+
+    let dir = macros_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    let bytes = serde_json::to_vec_pretty(&value)?;
+    std::fs::write(dir.join(format!("{}.json", value.name)), bytes)?;
+
+    debug!("Saved macro {} with {} steps", value.name, count);
+    Ok(Some(count))
+}
+
+/// Records a packet sent by the client if a macro is being recorded,
+/// only requests with decodable contents are recorded
+pub fn record(packet: &Packet) {
+    let recording = &mut *RECORDING.lock().expect("Macro recording lock poisoned");
+    let Some(recording) = recording else {
+        return;
+    };
+
+    let frame = &packet.frame;
+    if frame.ty != FrameType::Request {
+        return;
+    }
+
+    let fields = match decode_fields(&packet.contents) {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to decode request for macro: {}", err);
+            return;
+        }
+    };
+
+    let now = Instant::now();
+    recording.steps.push(MacroStep {
+        component: frame.component,
+        command: frame.command,
+        delay_ms: now.duration_since(recording.last).as_millis() as u64,
+        fields,
+    });
+    recording.last = now;
+}
+
+/// Loads a saved macro
+pub fn load(name: &str) -> io::Result<Macro> {
+    let bytes = std::fs::read(macros_dir()?.join(format!("{}.json", name)))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Replays a saved macro against the official server in the background,
+/// the results are written next to the macro. Parameters override field
+/// values in every step by their dot separated path (e.g. `GNAM=Test`).
+/// Returns the name of the results file
+pub fn start_replay(value: Macro, parameters: Vec<(String, String)>) -> io::Result<String> {
+    let output = format!("{}-results-{}.json", value.name, now_millis());
+    let path = macros_dir()?.join(&output);
+
+    tasks::spawn("macro-replay", async move {
+        let results = match replay(value, &parameters).await {
+            Ok(value) => value,
+            Err(err) => {
+                error!("Failed to replay macro: {}", err);
+                return;
+            }
+        };
+
+        let result = serde_json::to_vec_pretty(&results)
+            .map_err(io::Error::from)
+            .and_then(|bytes| std::fs::write(&path, bytes));
+        if let Err(err) = result {
+            error!("Failed to write macro results: {}", err);
+        }
+    });
+
+    Ok(output)
+}
+
+/// Replays the macro steps in order through a new official session
+async fn replay(value: Macro, parameters: &[(String, String)]) -> io::Result<Vec<StepResult>> {
+    let instance = OfficialInstance::obtain()
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    let (host, port) = instance.target();
+    let session = OfficialSession::connect(&host, port).await?;
+
+    debug!(
+        "Replaying macro {} ({} steps)",
+        value.name,
+        value.steps.len()
+    );
+
+    let mut results = Vec::with_capacity(value.steps.len());
+    for mut step in value.steps {
+        sleep(Duration::from_millis(step.delay_ms)).await;
+
+        for (path, parameter) in parameters {
+            apply_parameter(&mut step.fields, path, parameter);
+        }
+
+        let contents = Bytes::from(encode_fields(&step.fields));
+        let response = probing::request(&session, step.component, step.command, contents).await;
+        let result = match &response {
+            Ok(packet) => step_result(&step, Some(packet), None),
+            Err(RetrieverError::Packet(err)) => step_result(&step, Some(err.packet()), None),
+            Err(err) => step_result(&step, None, Some(err.to_string())),
+        };
+        results.push(result);
+
+        // Remaining steps would be blocked by the limiter as well
+        if matches!(response, Err(RetrieverError::Probing(_))) {
+            break;
+        }
+    }
+
+    Ok(results)
+}
+
+fn step_result(step: &MacroStep, packet: Option<&Packet>, failure: Option<String>) -> StepResult {
+    StepResult {
+        component: step.component,
+        command: step.command,
+        ty: packet.map(|packet| packet.frame.ty),
+        error: packet.map(|packet| packet.frame.error).unwrap_or_default(),
+        body: packet
+            .and_then(|packet| decode_fields(&packet.contents).ok())
+            .map(|fields| fields_to_json(&fields)),
+        failure,
+    }
+}
+
+/// Replaces the value of the field at the dot separated `path` with the
+/// parameter, the parameter is parsed according to the existing value type.
+/// Fields that don't exist or can't hold the parameter are left unchanged
+pub fn apply_parameter(fields: &mut [Field], path: &str, parameter: &str) {
+    let (tag, rest) = match path.split_once('.') {
+        Some((tag, rest)) => (tag, Some(rest)),
+        None => (path, None),
+    };
+
+    let Some(field) = fields.iter_mut().find(|field| field.tag == tag) else {
+        return;
+    };
+
+    match (&mut field.value, rest) {
+        (TdfValue::Group(fields), Some(rest)) => apply_parameter(fields, rest, parameter),
+        (TdfValue::VarInt(value), None) => {
+            if let Ok(parameter) = parameter.parse() {
+                *value = parameter;
+            }
+        }
+        (TdfValue::String(value), None) => *value = parameter.to_string(),
+        (TdfValue::Float(value), None) => {
+            if let Ok(parameter) = parameter.parse() {
+                *value = parameter;
+            }
+        }
+        _ => {}
+    }
+}
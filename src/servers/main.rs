@@ -1,99 +1,440 @@
 use crate::{
+    capture::{
+        capture,
+        clock::now_micros,
+        crash,
+        manifest::{DestinationRecord, DestinationSource, SessionStats},
+        now_millis, writer, Direction,
+    },
+    config::config,
     constants::MAIN_PORT,
-    servers::packet::{PacketCodec, PacketDebug},
+    errors::{self, BindError, UpstreamError},
+    logging::LIFECYCLE_TARGET,
+    servers::packet::{FrameType, PacketCodec},
 };
-use futures_util::{SinkExt, StreamExt};
-use log::{debug, error};
-use native_windows_gui::error_message;
+use futures_util::{Sink, SinkExt, StreamExt};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
 use std::{
-    net::Ipv4Addr,
-    sync::{atomic::AtomicU32, Arc},
+    io,
+    net::SocketAddr,
+    sync::{atomic::AtomicU32, Arc, OnceLock},
 };
+use thiserror::Error;
 use tokio::{
     net::{TcpListener, TcpStream},
     select,
+    sync::mpsc::{self, error::TrySendError},
+    time::sleep_until,
 };
-use tokio_util::codec::Framed;
+use tokio_util::{codec::Framed, sync::PollSender};
 
-use super::{packet::Packet, retriever::OfficialInstance};
+use super::{
+    accept::AcceptErrors,
+    breakpoints,
+    conditions::{NetworkConditions, Verdict},
+    limits::ListenerLimits,
+    macros,
+    mirror::Mirror,
+    packet::Packet,
+    reconnect::{ReconnectingStream, StreamEvent, StreamTarget},
+    retriever::OfficialInstance,
+    sockets::{CountingStream, SocketCounters},
+    status::{self, ServerStatus},
+    upstream::Upstream,
+};
 
 pub static SESSION_ID: AtomicU32 = AtomicU32::new(1);
 
+/// Connection limits for the main listener
+static LIMITS: OnceLock<ListenerLimits> = OnceLock::new();
+
+/// How packets for the client are handled when the client queue is full
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// Wait for space in the queue, pausing reads from the server
+    #[default]
+    Park,
+    /// Drop notifications and park for everything else
+    DropNotifications,
+}
+
 /// Starts the main server proxy. This creates a connection to the Pocket Relay
 /// which is upgraded and then used as the main connection fro the game.
-pub async fn start_server() {
+pub async fn start_server() -> io::Result<()> {
     // Initializing the underlying TCP listener
-    let listener = match TcpListener::bind((Ipv4Addr::UNSPECIFIED, MAIN_PORT)).await {
-        Ok(value) => value,
-        Err(err) => {
-            error_message("Failed to start main", &err.to_string());
-            error!("Failed to start main: {}", err);
-            return;
-        }
-    };
+    let listener = TcpListener::bind((config().bind_address, MAIN_PORT))
+        .await
+        .map_err(|source| {
+            errors::report(BindError::Listen {
+                server: "main",
+                port: MAIN_PORT,
+                source,
+            })
+        })?;
 
     let instance = match OfficialInstance::obtain().await {
         Ok(value) => value,
         Err(err) => {
-            error_message("Failed to create official instance", &err.to_string());
-            error!("Failed to create official instance: {}", err);
-            return;
+            let detail = err.to_string();
+            let err = errors::report(UpstreamError::Instance(err));
+            // Maintenance errors from the redirector have already been reported
+            if status::status().status != ServerStatus::Maintenance {
+                status::set_status(ServerStatus::Unreachable, Some(detail));
+            }
+            return Err(err.into());
         }
     };
 
+    status::set_status(ServerStatus::Online, None);
+
     let ret = Arc::new(instance);
 
+    let mut accept_errors = AcceptErrors::new("main");
+
     // Accept incoming connections
     loop {
-        let (stream, _) = match listener.accept().await {
+        let (stream, addr) = match listener.accept().await {
             Ok(value) => value,
             Err(err) => {
-                error!("Failed to accept main connection: {}", err);
-                break;
+                accept_errors.handle(err).await?;
+                continue;
             }
         };
+        accept_errors.accepted();
+
+        let Some(permit) = limits().admit(addr) else {
+            continue;
+        };
 
         debug!("Main connection ->");
 
-        // Spawn off a new handler for the connection
-        _ = tokio::spawn(handle_blaze(stream, ret.clone())).await;
+        // Spawn off a new handler for the connection, the permit is held
+        // until the session ends
+        let ret = ret.clone();
+        tokio::spawn(async move {
+            handle_blaze(stream, addr, ret).await;
+            drop(permit);
+        });
     }
 }
 
-async fn handle_blaze(client: TcpStream, ret: Arc<OfficialInstance>) {
-    let server = match ret.stream().await {
+fn limits() -> &'static ListenerLimits {
+    LIMITS.get_or_init(|| ListenerLimits::new("main"))
+}
+
+async fn handle_blaze(client: TcpStream, addr: SocketAddr, ret: Arc<OfficialInstance>) {
+    let id = SESSION_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+    let config = config();
+
+    // The local redirector replaced the official instance the game would
+    // have connected to
+    if let Some(capture) = capture() {
+        let (host, port) = ret.target();
+        capture.record_destination(DestinationRecord {
+            time: now_millis(),
+            server: "main",
+            connection: Some(id),
+            client: addr,
+            host,
+            port,
+            source: DestinationSource::Redirector,
+        });
+    }
+
+    let upstream = Upstream::select(&config.upstreams, id);
+    proxy_session(id, client, upstream, upstream.target(ret)).await;
+}
+
+/// Proxies the game client connection for the session with the provided
+/// `id` to the upstream `target`, recording the traffic in the capture.
+/// Public so the test harness can drive sessions against a fake server
+pub async fn proxy_session(id: u32, client: TcpStream, upstream: &Upstream, target: StreamTarget) {
+    let config = config();
+
+    let (mut server, server_writer) = match ReconnectingStream::connect(target).await {
         Ok(value) => value,
-        Err(err) => {
-            error!("Failed to obtain session with official server: {}", err);
+        Err(source) => {
+            let detail = source.to_string();
+            errors::report(UpstreamError::Connect {
+                upstream: upstream.label(),
+                source,
+            });
+            if matches!(upstream, Upstream::Official) {
+                status::set_status(ServerStatus::Unreachable, Some(detail));
+            }
             return;
         }
     };
 
-    let id = SESSION_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
-    debug!("Starting session {}", id);
+    debug!(
+        target: LIFECYCLE_TARGET,
+        "Starting session {} (Upstream: {})",
+        id,
+        upstream.label()
+    );
 
-    let mut client_framed = Framed::new(client, PacketCodec);
-    let mut server_framed = Framed::new(server, PacketCodec);
+    let mut stats = SessionStats::new(id, now_millis(), upstream.label());
+    let mirror = Mirror::start(id);
 
-    loop {
+    let client_counters = Arc::new(SocketCounters::default());
+    let client = CountingStream::new(client, client_counters.clone());
+    let (client_sink, mut client_stream) = Framed::new(client, PacketCodec).split();
+
+    // Packets are queued for each peer and forwarded on their own task so
+    // that a slow peer or a held breakpoint doesn't stall reading from the other
+    let (client_tx, client_rx) = mpsc::channel(config.client_queue_size.max(1));
+    let (server_tx, server_rx) = mpsc::channel(config.server_queue_size.max(1));
+    let server_sink = PollSender::new(server_writer)
+        .sink_map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Upstream writer stopped"));
+    let client_forward = tokio::spawn(forward(id, Direction::Receive, client_rx, client_sink));
+    let server_forward = tokio::spawn(forward(id, Direction::Send, server_rx, server_sink));
+
+    // Packet from the server parked until the client queue has space
+    let mut client_parked: Option<Packet> = None;
+
+    let mut reason = loop {
         select! {
-            packet = client_framed.next() => {
-                if let Some(Ok(packet)) = packet {
-                    debug_log_packet(&packet, "Send");
-                    _= server_framed.send(packet).await;
+            // Stop reading from the client while the server queue is full
+            packet = client_stream.next(), if server_tx.capacity() > 0 => {
+                let packet = match packet {
+                    Some(Ok(value)) => value,
+                    Some(Err(err)) => break CloseReason::ClientRead(err),
+                    None => break CloseReason::ClientClosed,
+                };
+                stats.packets_sent += 1;
+                record_packet(id, Direction::Send, &packet);
+                macros::record(&packet);
+                if let Some(mirror) = &mirror {
+                    mirror.send(&packet);
                 }
+
+                if let Err(TrySendError::Closed(_)) = server_tx.try_send(packet) {
+                    break CloseReason::ServerWrite(None);
+                }
+                stats.max_server_queue = stats.max_server_queue.max(queued(&server_tx));
             }
-            packet = server_framed.next() => {
-                if let Some(Ok(packet)) = packet {
-                    debug_log_packet(&packet, "Receive");
-                    _ = client_framed.send(packet).await;
+            permit = client_tx.reserve(), if client_parked.is_some() => {
+                let Ok(permit) = permit else {
+                    break CloseReason::ClientWrite(None);
+                };
+                if let Some(packet) = client_parked.take() {
+                    permit.send(packet);
+                }
+                stats.max_client_queue = stats.max_client_queue.max(queued(&client_tx));
+            }
+            // Stop reading from the server while a packet is parked
+            event = server.next(), if client_parked.is_none() => {
+                let packet = match event {
+                    Some(StreamEvent::Packet(packet)) => packet,
+                    // The reconnect is recorded as a capture gap by the stream
+                    Some(StreamEvent::Reconnected) => continue,
+                    None => break CloseReason::ServerLost,
+                };
+                stats.packets_received += 1;
+                status::check_packet(&packet.frame);
+                record_packet(id, Direction::Receive, &packet);
+
+                match queue_client(&client_tx, packet, config.backpressure, &mut stats) {
+                    ClientQueue::Queued => {}
+                    ClientQueue::Parked(packet) => client_parked = Some(packet),
+                    ClientQueue::Closed => break CloseReason::ClientWrite(None),
                 }
             }
         }
+    };
+
+    // Let both peers be sent the packets still queued before the session ends
+    if let Some(packet) = client_parked.take() {
+        _ = client_tx.send(packet).await;
+    }
+    drop((client_tx, server_tx));
+    let (client_forwarded, server_forwarded) = tokio::join!(client_forward, server_forward);
+    if let Ok(forwarded) = client_forwarded {
+        stats.simulated_drops += forwarded.simulated_drops;
+        if let Err(err) = forwarded.result {
+            reason = CloseReason::ClientWrite(Some(err));
+        }
+    }
+    if let Ok(forwarded) = server_forwarded {
+        stats.simulated_drops += forwarded.simulated_drops;
+        if let Err(err) = forwarded.result {
+            reason = CloseReason::ServerWrite(Some(err));
+        }
+    }
+    server.close().await;
+
+    if reason.is_failure() {
+        error!("Session {} terminated: {}", id, reason);
+    } else {
+        debug!(target: LIFECYCLE_TARGET, "Session {} ended: {}", id, reason);
+    }
+
+    stats.ended_at = Some(now_millis());
+    stats.close_reason = Some(reason.to_string());
+    stats.failed = reason.is_failure();
+    stats.client_socket = client_counters.stats();
+    stats.upstream_socket = server.socket_stats();
+    debug!("Session {} stats: {:?}", id, stats);
+
+    if let Some(capture) = capture() {
+        let bandwidth = capture.bandwidth.snapshot();
+        capture.update_manifest(|manifest| {
+            manifest.sessions.push(stats);
+            manifest.bandwidth = bandwidth;
+        });
+    }
+}
+
+/// Reason a proxied session ended
+#[derive(Debug, Error)]
+enum CloseReason {
+    #[error("Game client disconnected")]
+    ClientClosed,
+    #[error("Failed to read from game client: {0}")]
+    ClientRead(io::Error),
+    #[error("Failed to write to game client{}", format_cause(.0))]
+    ClientWrite(Option<io::Error>),
+    #[error("Official server closed the connection or it couldn't be re-established")]
+    ServerLost,
+    #[error("Failed to write to official server{}", format_cause(.0))]
+    ServerWrite(Option<io::Error>),
+}
+
+impl CloseReason {
+    /// Whether the session ended because of a failure rather than
+    /// the game client disconnecting. Broken official server connections
+    /// are re-established, so losing the server means it closed the
+    /// connection or every reconnect attempt failed
+    fn is_failure(&self) -> bool {
+        !matches!(self, CloseReason::ClientClosed)
     }
 }
 
-fn debug_log_packet(packet: &Packet, action: &str) {
-    let debug = PacketDebug { packet };
-    debug!("\nOfficial: {}\n{:?}", action, debug);
+fn format_cause(cause: &Option<io::Error>) -> String {
+    cause
+        .as_ref()
+        .map(|err| format!(": {}", err))
+        .unwrap_or_default()
+}
+
+/// Outcome of queueing a packet for the client
+enum ClientQueue {
+    /// The packet was queued or dropped by the backpressure policy
+    Queued,
+    /// The queue is full, the packet has to wait for space
+    Parked(Packet),
+    /// The client forwarder has stopped
+    Closed,
+}
+
+/// Queues a packet to be written to the client applying the backpressure
+/// policy when the queue is full
+fn queue_client(
+    client_tx: &mpsc::Sender<Packet>,
+    packet: Packet,
+    policy: BackpressurePolicy,
+    stats: &mut SessionStats,
+) -> ClientQueue {
+    let packet = match client_tx.try_send(packet) {
+        Ok(()) => {
+            stats.max_client_queue = stats.max_client_queue.max(queued(client_tx));
+            return ClientQueue::Queued;
+        }
+        Err(TrySendError::Closed(_)) => return ClientQueue::Closed,
+        Err(TrySendError::Full(packet)) => packet,
+    };
+
+    // Notifications aren't replies to anything the client is waiting on
+    let frame = &packet.frame;
+    if policy == BackpressurePolicy::DropNotifications && frame.ty == FrameType::Notify {
+        stats.dropped += 1;
+        warn!(
+            "Client queue full, dropped notification {:#06x}->{:#06x}",
+            frame.component, frame.command
+        );
+        return ClientQueue::Queued;
+    }
+
+    // Park until the client catches up, this stops reading from the server
+    // which leaves the packets buffered upstream rather than losing them
+    stats.parked += 1;
+    ClientQueue::Parked(packet)
+}
+
+/// Result of forwarding the packets queued for a peer
+struct Forwarded {
+    /// Number of packets dropped by the network condition simulator
+    simulated_drops: u64,
+    result: io::Result<()>,
+}
+
+/// Forwards the packets queued for a peer until the queue is closed or
+/// writing fails. Packets are held at breakpoints and delayed by the
+/// network conditions here so neither stalls reading from the peers
+async fn forward<S>(
+    connection: u32,
+    direction: Direction,
+    mut rx: mpsc::Receiver<Packet>,
+    mut sink: S,
+) -> Forwarded
+where
+    S: Sink<Packet, Error = io::Error> + Unpin,
+{
+    let mut conditions = NetworkConditions::new(&config().network_conditions);
+    let mut simulated_drops = 0;
+
+    let result = async {
+        while let Some(packet) = rx.recv().await {
+            let Some(packet) = breakpoints::check(connection, direction, packet).await else {
+                continue;
+            };
+
+            let Verdict::Forward(due) = conditions.apply(direction, &packet.frame) else {
+                simulated_drops += 1;
+                continue;
+            };
+
+            sleep_until(due).await;
+            sink.send(packet).await?;
+        }
+        sink.close().await
+    }
+    .await;
+
+    Forwarded {
+        simulated_drops,
+        result,
+    }
+}
+
+/// The number of packets currently in the queue of the provided sender
+fn queued<T>(tx: &mpsc::Sender<T>) -> usize {
+    tx.max_capacity() - tx.capacity()
+}
+
+/// Queues the packet to be logged and recorded in the capture session if
+/// one is active, packets excluded by sampling are neither logged or
+/// recorded unless they are kept as samples. Formatting and writing happen
+/// on the capture writer thread
+fn record_packet(connection: u32, direction: Direction, packet: &Packet) {
+    let time = now_micros();
+    crash::remember(time, connection, direction, packet);
+
+    let Some(capture) = capture() else {
+        writer::queue(time, connection, direction, packet, false, None);
+        return;
+    };
+
+    // Traffic is counted before sampling so the totals include everything
+    capture.bandwidth.record(connection, direction, packet);
+
+    let sample = capture.take_sample(&packet.frame);
+    let captured = capture.should_capture(&packet.frame);
+    if !captured && sample.is_none() {
+        return;
+    }
+
+    writer::queue(time, connection, direction, packet, captured, sample);
 }
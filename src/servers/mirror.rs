@@ -0,0 +1,110 @@
+//! Mirroring of proxied requests to a local server for testing. Pocket
+//! Relay server developers can feed live traffic into handlers that are
+//! still in development while the game keeps getting its responses from
+//! the official server
+//!
+//! Mirroring is fire-and-forget, responses from the mirror are discarded
+//! and packets are dropped when the mirror can't keep up
+
+use super::packet::Packet;
+use crate::{analysis::conformance::connect, capture::capture, config::config, tasks};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use tokio::{select, sync::mpsc};
+
+/// Maximum number of packets queued for the mirror of each session
+const MIRROR_QUEUE_SIZE: usize = 256;
+
+/// Mirror of a proxied session, packets are sent to the mirror server
+/// over their own connection on a background task
+pub struct Mirror {
+    tx: mpsc::Sender<Packet>,
+}
+
+impl Mirror {
+    /// Starts mirroring the session with the provided `id`, None if no
+    /// mirror is configured
+    pub fn start(id: u32) -> Option<Self> {
+        let url = config().mirror_url.clone()?;
+        let (tx, rx) = mpsc::channel(MIRROR_QUEUE_SIZE);
+        tasks::spawn("mirror", run(id, url, rx));
+        Some(Self { tx })
+    }
+
+    /// Queues a copy of the packet for the mirror, the packet is dropped
+    /// if the queue is full or the mirror disconnected
+    pub fn send(&self, packet: &Packet) {
+        _ = self.tx.try_send(packet.clone());
+    }
+}
+
+/// Forwards the queued packets to the mirror until the session ends or
+/// the mirror disconnects
+async fn run(id: u32, url: String, mut rx: mpsc::Receiver<Packet>) {
+    let mut framed = match connect(&url).await {
+        Ok(value) => value,
+        Err(err) => {
+            warn!(
+                "Failed to connect session {} to mirror {}: {}",
+                id, url, err
+            );
+            if let Some(capture) = capture() {
+                capture.record_event(
+                    "mirror",
+                    &format!("Session {} couldn't connect to mirror {}", id, url),
+                );
+            }
+            return;
+        }
+    };
+
+    debug!("Mirroring session {} to {}", id, url);
+
+    loop {
+        select! {
+            packet = rx.recv() => {
+                let Some(packet) = packet else {
+                    break;
+                };
+                if let Err(err) = framed.send(packet).await {
+                    warn!("Failed to write to mirror for session {}: {}", id, err);
+                    break;
+                }
+            }
+            // Responses are read so the mirror is never blocked writing them
+            response = framed.next() => match response {
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    warn!("Failed to read from mirror for session {}: {}", id, err);
+                    break;
+                }
+                None => {
+                    debug!("Mirror closed the connection for session {}", id);
+                    break;
+                }
+            }
+        }
+    }
+
+    _ = framed.close().await;
+}
+
+/// Sends a copy of a proxied HTTP request to the mirror if one is
+/// configured, `path` is the path and query of the original request
+pub fn mirror_http(path: &str) {
+    let Some(url) = config().mirror_url.as_deref() else {
+        return;
+    };
+    let url = format!("{}{}", url.trim_end_matches('/'), path);
+
+    tasks::spawn("mirror-http", async move {
+        let result = reqwest::Client::new()
+            .get(&url)
+            .timeout(config().request_timeout())
+            .send()
+            .await;
+        if let Err(err) = result {
+            debug!("Failed to mirror HTTP request to {}: {}", url, err);
+        }
+    });
+}
@@ -1,18 +1,61 @@
+use crate::{config::config, tasks};
+use log::warn;
+use std::time::Duration;
+use supervisor::{shutdown_token, supervise};
 use tokio::join;
 
+/// How long shutting down waits for open HTTP connections to finish
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub mod accept;
+pub mod access;
+pub mod breakpoints;
+pub mod client_plugin;
 pub mod components;
+pub mod conditions;
+pub mod control;
+pub mod dns;
+pub mod fuzzer;
 pub mod http;
+pub mod limits;
+pub mod loop_guard;
+pub mod macros;
 pub mod main;
+pub mod mirror;
 pub mod packet;
+pub mod probing;
+pub mod reconnect;
 pub mod redirector;
 pub mod retriever;
+pub mod sockets;
+pub mod status;
+pub mod supervisor;
+pub mod upgrade;
+pub mod upstream;
 
+/// Starts each of the servers under the supervisor so they're
+/// restarted if they stop
 pub fn start_servers() {
-    tokio::spawn(async move {
+    let bind_address = config().bind_address;
+    if !bind_address.is_loopback() {
+        warn!(
+            "Servers are listening on {} which exposes the proxy to other machines on the network",
+            bind_address
+        );
+    }
+
+    tasks::spawn("servers", async move {
         join!(
-            main::start_server(),
-            redirector::start_server(),
-            http::start_server()
+            supervise("main", main::start_server),
+            supervise("redirector", redirector::start_server),
+            supervise("http", http::start_server)
         );
     });
 }
+
+/// Stops the servers accepting new connections and waits for requests
+/// in progress to finish so they're included in the capture
+pub async fn shutdown() {
+    shutdown_token().cancel();
+    http::drain(SHUTDOWN_TIMEOUT).await;
+}
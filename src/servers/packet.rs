@@ -1,22 +1,30 @@
 use super::components::{component_key, get_command_name, get_component_name};
+use crate::{
+    capture::value::{decode_fields, hex_dump},
+    definitions::{describe_fields, error_name, has_hints},
+};
 use bitflags::bitflags;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
+use serde::Serialize;
 use std::fmt::Debug;
 use std::io;
 use tdf::{prelude::*, serialize_vec};
 use tokio_util::codec::{Decoder, Encoder};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum FrameType {
     /// Request to a server
-    Request = 0x0,
+    Request,
     /// Response to a request
-    Response = 0x1,
+    Response,
     /// Async notification from the server
-    Notify = 0x2,
+    Notify,
     /// Error response from the server
-    Error = 0x3,
+    Error,
+    /// Frame type the plugin doesn't know, the raw value is kept so
+    /// the frame is forwarded and recorded unchanged
+    Unknown(u8),
 }
 
 bitflags! {
@@ -25,8 +33,10 @@ bitflags! {
         const NONE = 0x0;
         /// Frame length is extended from 16bits to 32bits
         const JUMBO_FRAME = 0x1;
+        /// Frame header is followed by a context value
         const HAS_CONTEXT = 0x2;
         const IMMEDIATE = 0x4;
+        /// Context value is extended from 16bits to 32bits
         const JUMBO_CONTEXT = 0x8;
     }
 }
@@ -38,7 +48,19 @@ impl From<u8> for FrameType {
             0x1 => FrameType::Response,
             0x2 => FrameType::Notify,
             0x3 => FrameType::Error,
-            _ => FrameType::Request,
+            value => FrameType::Unknown(value),
+        }
+    }
+}
+
+impl From<FrameType> for u8 {
+    fn from(value: FrameType) -> Self {
+        match value {
+            FrameType::Request => 0x0,
+            FrameType::Response => 0x1,
+            FrameType::Notify => 0x2,
+            FrameType::Error => 0x3,
+            FrameType::Unknown(value) => value,
         }
     }
 }
@@ -58,11 +80,19 @@ pub struct FireFrame {
     pub options: PacketOptions,
     /// Sequence number for tracking request and response mappings
     pub seq: u16,
+    /// Context value following the header when the frame has the
+    /// [PacketOptions::HAS_CONTEXT] option
+    pub context: Option<u32>,
+    /// Low nibbles of the type and options bytes, unused by the plugin
+    /// but kept so frames are forwarded exactly as they were received
+    pub reserved: u8,
 }
 
 impl FireFrame {
     const MIN_HEADER_SIZE: usize = 12;
     const JUMBO_SIZE: usize = std::mem::size_of::<u16>();
+    const CONTEXT_SIZE: usize = std::mem::size_of::<u16>();
+    const JUMBO_CONTEXT_SIZE: usize = std::mem::size_of::<u32>();
 
     pub const fn notify(component: u16, command: u16) -> Self {
         Self {
@@ -72,6 +102,8 @@ impl FireFrame {
             ty: FrameType::Notify,
             options: PacketOptions::NONE,
             seq: 0,
+            context: None,
+            reserved: 0,
         }
     }
 
@@ -83,6 +115,8 @@ impl FireFrame {
             ty: FrameType::Request,
             options: PacketOptions::NONE,
             seq,
+            context: None,
+            reserved: 0,
         }
     }
 
@@ -90,26 +124,27 @@ impl FireFrame {
         self.with_type(FrameType::Response)
     }
 
+    /// Creates a frame of the provided type replying to this frame. The
+    /// error code, options and context are kept, except for the jumbo
+    /// length flag which depends on the length of the new contents
     pub const fn with_type(&self, ty: FrameType) -> Self {
         Self {
             component: self.component,
             command: self.command,
             error: self.error,
             ty,
-            options: PacketOptions::NONE,
+            options: self.options.difference(PacketOptions::JUMBO_FRAME),
             seq: self.seq,
+            context: self.context,
+            reserved: self.reserved,
         }
     }
 
+    /// Creates an error frame replying to this frame, see [FireFrame::with_type]
     pub const fn with_error(&self, error: u16) -> Self {
-        Self {
-            component: self.component,
-            command: self.command,
-            error,
-            ty: FrameType::Error,
-            options: PacketOptions::NONE,
-            seq: self.seq,
-        }
+        let mut frame = self.with_type(FrameType::Error);
+        frame.error = error;
+        frame
     }
 
     pub fn path_matches(&self, other: &FireFrame) -> bool {
@@ -121,19 +156,53 @@ impl FireFrame {
         if length > 0xFFFF {
             options |= PacketOptions::JUMBO_FRAME;
         }
+        if let Some(context) = self.context {
+            options |= PacketOptions::HAS_CONTEXT;
+            if context > 0xFFFF {
+                options |= PacketOptions::JUMBO_CONTEXT;
+            }
+        }
 
         dst.put_u16(length as u16);
         dst.put_u16(self.component);
         dst.put_u16(self.command);
         dst.put_u16(self.error);
-        dst.put_u8((self.ty as u8) << 4);
-        dst.put_u8(options.bits() << 4);
+        dst.put_u8((u8::from(self.ty) << 4) | (self.reserved >> 4));
+        dst.put_u8((options.bits() << 4) | (self.reserved & 0xF));
         dst.put_u16(self.seq);
 
         if options.contains(PacketOptions::JUMBO_FRAME) {
             // Put the extended length (The next 16 bits of the value to make the 32bit length)
             dst.put_u16((length >> 16) as u16);
         }
+
+        if options.contains(PacketOptions::HAS_CONTEXT) {
+            let context = self.context.unwrap_or_default();
+            if options.contains(PacketOptions::JUMBO_CONTEXT) {
+                dst.put_u32(context);
+            } else {
+                dst.put_u16(context as u16);
+            }
+        }
+    }
+
+    /// The length of the frame header when encoded, including the extended
+    /// length and context value
+    pub fn header_length(&self, length: usize) -> usize {
+        let mut header = Self::MIN_HEADER_SIZE;
+        if length > 0xFFFF || self.options.contains(PacketOptions::JUMBO_FRAME) {
+            header += Self::JUMBO_SIZE;
+        }
+        if self.context.is_some() || self.options.contains(PacketOptions::HAS_CONTEXT) {
+            let jumbo = self.options.contains(PacketOptions::JUMBO_CONTEXT)
+                || self.context.is_some_and(|context| context > 0xFFFF);
+            header += if jumbo {
+                Self::JUMBO_CONTEXT_SIZE
+            } else {
+                Self::CONTEXT_SIZE
+            };
+        }
+        header
     }
 
     pub fn read(src: &mut BytesMut) -> Option<(FireFrame, usize)> {
@@ -145,8 +214,10 @@ impl FireFrame {
         let component = src.get_u16();
         let command = src.get_u16();
         let error = src.get_u16();
-        let ty = src.get_u8() >> 4;
-        let options = src.get_u8() >> 4;
+        let ty = src.get_u8();
+        let options = src.get_u8();
+        let reserved = (ty << 4) | (options & 0xF);
+        let (ty, options) = (ty >> 4, options >> 4);
         let options = PacketOptions::from_bits_retain(options);
         let seq = src.get_u16();
 
@@ -159,6 +230,22 @@ impl FireFrame {
             length |= ext_length;
         }
 
+        let context = if options.contains(PacketOptions::HAS_CONTEXT) {
+            if options.contains(PacketOptions::JUMBO_CONTEXT) {
+                if src.len() < Self::JUMBO_CONTEXT_SIZE {
+                    return None;
+                }
+                Some(src.get_u32())
+            } else {
+                if src.len() < Self::CONTEXT_SIZE {
+                    return None;
+                }
+                Some(src.get_u16() as u32)
+            }
+        } else {
+            None
+        };
+
         let ty = FrameType::from(ty);
         let header = FireFrame {
             component,
@@ -167,6 +254,8 @@ impl FireFrame {
             ty,
             options,
             seq,
+            context,
+            reserved,
         };
         Some((header, length))
     }
@@ -298,6 +387,12 @@ impl Packet {
         })
     }
 
+    /// The length of the packet when encoded including the frame header
+    pub fn encoded_length(&self) -> usize {
+        let length = self.contents.len();
+        self.frame.header_length(length) + length
+    }
+
     pub fn write(&self, dst: &mut BytesMut) {
         let contents = &self.contents;
         self.frame.write(dst, contents.len());
@@ -338,6 +433,8 @@ impl Encoder<Packet> for PacketCodec {
 pub struct PacketDebug<'a> {
     /// Reference to the packet itself
     pub packet: &'a Packet,
+    /// Whether to include the packet contents
+    pub contents: bool,
 }
 
 impl<'a> Debug for PacketDebug<'a> {
@@ -357,7 +454,10 @@ impl<'a> Debug for PacketDebug<'a> {
 
         if is_error {
             // Write sequence number and error for errors
-            write!(f, " ({}, E?{:#06x})", header.seq, header.error)?;
+            match error_name(header.component, header.error) {
+                Some(name) => write!(f, " ({}, {} {:#06x})", header.seq, name, header.error)?,
+                None => write!(f, " ({}, E?{:#06x})", header.seq, header.error)?,
+            }
         } else if !is_notify {
             // Write sequence number of sequenced types
             write!(f, " ({})", header.seq)?;
@@ -370,16 +470,250 @@ impl<'a> Debug for PacketDebug<'a> {
         )?;
 
         writeln!(f, "Options: {:?}", header.options)?;
+
+        if let Some(context) = header.context {
+            writeln!(f, "Context: {:#x}", context)?;
+        }
+
+        if !self.contents {
+            return Ok(());
+        }
+
         write!(f, "Content: ")?;
 
         let r = TdfDeserializer::new(&self.packet.contents);
         let mut str = TdfStringifier::new(r, f);
 
         if !str.stringify() {
-            // Write the raw content if stringify doesn't complete
-            writeln!(&mut str.w, "Raw: {:?}", &self.packet.contents)?;
+            // Write the undecoded remainder as a hex dump if stringify doesn't complete
+            let contents = &self.packet.contents;
+            let offset = contents.len() - str.r.remaining();
+            writeln!(
+                &mut str.w,
+                "\n--- Decoding stopped at offset {:#x} of {:#x}, raw remainder: ---",
+                offset,
+                contents.len()
+            )?;
+            write!(&mut str.w, "{}", hex_dump(&contents[offset..], offset))?;
+        }
+
+        // Write the names of any values known from the definitions
+        if !has_hints(header.component, header.command) {
+            return Ok(());
+        }
+
+        if let Ok(fields) = decode_fields(&self.packet.contents) {
+            let hints = describe_fields(header.component, header.command, &fields);
+            if !hints.is_empty() {
+                writeln!(f, "Hints:")?;
+                for hint in hints {
+                    writeln!(f, "  {}", hint)?;
+                }
+            }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Notify frame with a 16bit context followed by 3 bytes of contents
+    const CONTEXT_FRAME: &[u8] = &[
+        0x00, 0x03, // Length
+        0x00, 0x04, // Component
+        0x00, 0x14, // Command
+        0x00, 0x00, // Error
+        0x20, // Type (Notify)
+        0x20, // Options (HAS_CONTEXT)
+        0x00, 0x00, // Seq
+        0x12, 0x34, // Context
+        0x01, 0x02, 0x03, // Contents
+    ];
+
+    /// Response frame with a 32bit context followed by 2 bytes of contents
+    const JUMBO_CONTEXT_FRAME: &[u8] = &[
+        0x00, 0x02, // Length
+        0x00, 0x07, // Component
+        0x00, 0x01, // Command
+        0x00, 0x00, // Error
+        0x10, // Type (Response)
+        0xA0, // Options (HAS_CONTEXT | JUMBO_CONTEXT)
+        0x00, 0x2A, // Seq
+        0x12, 0x34, 0x56, 0x78, // Context
+        0xAB, 0xCD, // Contents
+    ];
+
+    /// Decodes every packet in `bytes` with the codec, fails if any bytes
+    /// are left over
+    fn decode_all(bytes: &[u8]) -> Vec<Packet> {
+        let mut src = BytesMut::from(bytes);
+        let mut packets = Vec::new();
+        while let Some(packet) = PacketCodec.decode(&mut src).expect("Failed to decode") {
+            packets.push(packet);
+        }
+        assert!(src.is_empty(), "{} bytes left undecoded", src.len());
+        packets
+    }
+
+    fn encode(packet: &Packet) -> BytesMut {
+        let mut dst = BytesMut::new();
+        PacketCodec
+            .encode(packet.clone(), &mut dst)
+            .expect("Failed to encode");
+        dst
+    }
+
+    #[test]
+    fn decodes_context() {
+        let packets = decode_all(CONTEXT_FRAME);
+        assert_eq!(packets.len(), 1);
+
+        let packet = &packets[0];
+        assert_eq!(packet.frame.ty, FrameType::Notify);
+        assert_eq!(packet.frame.options, PacketOptions::HAS_CONTEXT);
+        assert_eq!(packet.frame.context, Some(0x1234));
+        assert_eq!(packet.contents.as_ref(), &[0x01, 0x02, 0x03]);
+        assert_eq!(packet.encoded_length(), CONTEXT_FRAME.len());
+    }
+
+    #[test]
+    fn decodes_jumbo_context() {
+        let packets = decode_all(JUMBO_CONTEXT_FRAME);
+        assert_eq!(packets.len(), 1);
+
+        let packet = &packets[0];
+        assert_eq!(packet.frame.ty, FrameType::Response);
+        assert_eq!(
+            packet.frame.options,
+            PacketOptions::HAS_CONTEXT | PacketOptions::JUMBO_CONTEXT
+        );
+        assert_eq!(packet.frame.seq, 0x2A);
+        assert_eq!(packet.frame.context, Some(0x12345678));
+        assert_eq!(packet.contents.as_ref(), &[0xAB, 0xCD]);
+        assert_eq!(packet.encoded_length(), JUMBO_CONTEXT_FRAME.len());
+    }
+
+    #[test]
+    fn context_frames_keep_framing() {
+        // The context must be consumed so the next frame starts in the right place
+        let bytes = [CONTEXT_FRAME, JUMBO_CONTEXT_FRAME, CONTEXT_FRAME].concat();
+        let packets = decode_all(&bytes);
+
+        let contexts: Vec<Option<u32>> =
+            packets.iter().map(|packet| packet.frame.context).collect();
+        assert_eq!(contexts, [Some(0x1234), Some(0x12345678), Some(0x1234)]);
+    }
+
+    #[test]
+    fn waits_for_context() {
+        // Frames cut off within the context or contents aren't decoded or consumed
+        for frame in [CONTEXT_FRAME, JUMBO_CONTEXT_FRAME] {
+            for length in [13, frame.len() - 1] {
+                let mut src = BytesMut::from(&frame[..length]);
+                assert!(PacketCodec.decode(&mut src).unwrap().is_none());
+                assert_eq!(src.len(), length);
+            }
+        }
+    }
+
+    #[test]
+    fn context_frames_encode_unchanged() {
+        for frame in [CONTEXT_FRAME, JUMBO_CONTEXT_FRAME] {
+            let packets = decode_all(frame);
+            assert_eq!(encode(&packets[0]).as_ref(), frame);
+        }
+    }
+
+    /// Frame of the provided type with options, a context and reserved bits
+    fn full_frame(ty: FrameType, context: u32) -> FireFrame {
+        let mut options = PacketOptions::IMMEDIATE | PacketOptions::HAS_CONTEXT;
+        if context > 0xFFFF {
+            options |= PacketOptions::JUMBO_CONTEXT;
+        }
+        FireFrame {
+            component: 0x7802,
+            command: 0x0001,
+            error: if ty == FrameType::Error { 0x0F } else { 0 },
+            ty,
+            options,
+            seq: 0x1234,
+            context: Some(context),
+            reserved: 0x5A,
+        }
+    }
+
+    /// Encodes then decodes the packet, checking the encoded length
+    fn round_trip(packet: &Packet) -> Packet {
+        let encoded = encode(packet);
+        assert_eq!(encoded.len(), packet.encoded_length());
+        let mut packets = decode_all(&encoded);
+        assert_eq!(packets.len(), 1);
+        packets.remove(0)
+    }
+
+    #[test]
+    fn round_trips_every_frame_type() {
+        let types = [
+            FrameType::Request,
+            FrameType::Response,
+            FrameType::Notify,
+            FrameType::Error,
+        ];
+        for ty in types {
+            for context in [0x1234, 0x0102_0304] {
+                let packet = Packet::new(full_frame(ty, context), Bytes::from_static(&[1, 2, 3]));
+                let decoded = round_trip(&packet);
+                assert_eq!(decoded.frame, packet.frame);
+                assert_eq!(decoded.contents, packet.contents);
+            }
+        }
+    }
+
+    #[test]
+    fn replies_keep_header() {
+        let request = Packet::new(
+            full_frame(FrameType::Request, 0x0102_0304),
+            Bytes::from_static(&[1]),
+        );
+        let request = round_trip(&request);
+
+        let replies = [
+            Packet::new_response(&request, Bytes::from_static(&[2])),
+            Packet::new_error(&request, 0x0F, Bytes::new()),
+            Packet::new(
+                request.frame.with_type(FrameType::Notify),
+                Bytes::from_static(&[3]),
+            ),
+        ];
+        for reply in replies {
+            let decoded = round_trip(&reply);
+            assert_eq!(decoded.frame, reply.frame);
+            assert_eq!(decoded.frame.seq, request.frame.seq);
+            assert_eq!(decoded.frame.options, request.frame.options);
+            assert_eq!(decoded.frame.context, request.frame.context);
+            assert_eq!(decoded.frame.reserved, request.frame.reserved);
+        }
+    }
+
+    #[test]
+    fn replies_drop_jumbo_length() {
+        let contents = Bytes::from(vec![0u8; 0x10000]);
+        let request = round_trip(&Packet::new(
+            full_frame(FrameType::Request, 0x1234),
+            contents,
+        ));
+        assert!(request.frame.options.contains(PacketOptions::JUMBO_FRAME));
+        assert_eq!(request.contents.len(), 0x10000);
+
+        // The reply is short so its length must not be extended
+        let response = Packet::new_response(&request, Bytes::from_static(&[1]));
+        assert!(!response.frame.options.contains(PacketOptions::JUMBO_FRAME));
+
+        let decoded = round_trip(&response);
+        assert_eq!(decoded.frame, response.frame);
+        assert_eq!(decoded.frame.context, Some(0x1234));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{
+    capture::capture,
+    config::config,
+    consent::{self, ActiveFeature, ConsentError},
+    servers::{
+        packet::Packet,
+        retriever::{OfficialSession, RetrieverError, RetrieverResult},
+    },
+};
+use bytes::Bytes;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::time::{sleep, Instant};
+
+/// Length of the window used for the per minute cap
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Limits for requests the plugin sends on its own rather than forwarding
+/// for the game (macro replays, fuzzing), shared between every feature so
+/// the combined traffic stays within them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbingConfig {
+    /// Blocks every synthetic request when true
+    pub disabled: bool,
+    /// Minimum milliseconds between any two synthetic requests
+    pub min_interval_ms: u64,
+    /// Maximum number of synthetic requests within a minute
+    pub max_per_minute: usize,
+    /// Maximum number of synthetic requests for the whole run of the game
+    pub max_total: u64,
+    /// Milliseconds to wait after the first error response, doubled
+    /// for each consecutive error
+    pub backoff_ms: u64,
+    /// Upper bound for the error backoff in milliseconds
+    pub max_backoff_ms: u64,
+}
+
+impl Default for ProbingConfig {
+    fn default() -> Self {
+        Self {
+            disabled: false,
+            min_interval_ms: 2000,
+            max_per_minute: 20,
+            max_total: 1000,
+            backoff_ms: 5000,
+            max_backoff_ms: 300_000,
+        }
+    }
+}
+
+/// Reason a synthetic request wasn't allowed
+#[derive(Debug, Error)]
+pub enum ProbingError {
+    #[error(transparent)]
+    Consent(#[from] ConsentError),
+    #[error("Synthetic requests have been stopped")]
+    Stopped,
+    #[error("Reached the limit of {0} synthetic requests")]
+    LimitReached(u64),
+}
+
+/// Current state of the limiter
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbingStatus {
+    pub stopped: bool,
+    /// Number of synthetic requests sent
+    pub total: u64,
+    /// Number of consecutive error responses
+    pub errors: u32,
+}
+
+struct Limiter {
+    /// When the next request is allowed to be sent
+    next_allowed: Option<Instant>,
+    /// When each request within the last minute was sent
+    window: VecDeque<Instant>,
+    total: u64,
+    errors: u32,
+}
+
+static LIMITER: Mutex<Limiter> = Mutex::new(Limiter {
+    next_allowed: None,
+    window: VecDeque::new(),
+    total: 0,
+    errors: 0,
+});
+
+/// Kill switch, stops synthetic requests until resumed
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Whether synthetic requests are blocked by the kill switch or config
+pub fn is_stopped() -> bool {
+    STOPPED.load(Ordering::Acquire) || config().probing.disabled
+}
+
+/// Sets the kill switch, stopping requests already waiting for their turn
+pub fn set_stopped(stopped: bool) {
+    STOPPED.store(stopped, Ordering::Release);
+
+    let message = if stopped {
+        "Synthetic requests stopped"
+    } else {
+        "Synthetic requests resumed"
+    };
+    debug!("{}", message);
+    if let Some(capture) = capture() {
+        capture.record_event("probing", message);
+    }
+}
+
+/// Obtains the current limiter state
+pub fn status() -> ProbingStatus {
+    let limiter = LIMITER.lock().expect("Probing limiter lock poisoned");
+    ProbingStatus {
+        stopped: is_stopped(),
+        total: limiter.total,
+        errors: limiter.errors,
+    }
+}
+
+/// Sends a synthetic request through the provided session once the
+/// limiter allows it, error responses and failures extend the backoff
+pub async fn request(
+    session: &OfficialSession,
+    component: u16,
+    command: u16,
+    contents: Bytes,
+) -> RetrieverResult<Packet> {
+    acquire().await?;
+    let result = session.request_bytes(component, command, contents).await;
+    report(&result);
+    result
+}
+
+/// Waits until the limiter allows another request
+async fn acquire() -> Result<(), ProbingError> {
+    consent::require(ActiveFeature::Probing)?;
+
+    let probing = &config().probing;
+    loop {
+        if is_stopped() {
+            return Err(ProbingError::Stopped);
+        }
+
+        let wait = {
+            let limiter = &mut *LIMITER.lock().expect("Probing limiter lock poisoned");
+            if limiter.total >= probing.max_total {
+                return Err(ProbingError::LimitReached(probing.max_total));
+            }
+
+            let now = Instant::now();
+            while limiter
+                .window
+                .front()
+                .is_some_and(|sent| now.duration_since(*sent) >= WINDOW)
+            {
+                limiter.window.pop_front();
+            }
+
+            let mut ready = limiter.next_allowed.unwrap_or(now);
+            if limiter.window.len() >= probing.max_per_minute.max(1) {
+                if let Some(oldest) = limiter.window.front() {
+                    ready = ready.max(*oldest + WINDOW);
+                }
+            }
+
+            if ready <= now {
+                limiter.window.push_back(now);
+                limiter.total += 1;
+                limiter.next_allowed = Some(now + Duration::from_millis(probing.min_interval_ms));
+                return Ok(());
+            }
+            ready - now
+        };
+
+        // Waiting is capped so the kill switch is noticed promptly
+        sleep(wait.min(Duration::from_secs(1))).await;
+    }
+}
+
+/// Updates the backoff from the result of a request, error responses and
+/// failures double the wait before the next request
+fn report(result: &RetrieverResult<Packet>) {
+    let failed = match result {
+        Ok(_) => false,
+        Err(RetrieverError::Probing(_)) => return,
+        Err(_) => true,
+    };
+
+    let probing = &config().probing;
+    let limiter = &mut *LIMITER.lock().expect("Probing limiter lock poisoned");
+    if !failed {
+        limiter.errors = 0;
+        return;
+    }
+
+    limiter.errors = limiter.errors.saturating_add(1);
+    let backoff = probing
+        .backoff_ms
+        .saturating_mul(1 << (limiter.errors - 1).min(16))
+        .min(probing.max_backoff_ms);
+    warn!(
+        "Synthetic request failed ({} in a row), backing off for {}ms",
+        limiter.errors, backoff
+    );
+
+    let backoff = Instant::now() + Duration::from_millis(backoff);
+    limiter.next_allowed = Some(
+        limiter
+            .next_allowed
+            .map_or(backoff, |next| next.max(backoff)),
+    );
+}
@@ -0,0 +1,468 @@
+use super::{
+    packet::{Packet, PacketCodec},
+    retriever::{connect_blaze, OfficialInstance},
+    sockets::{CountingStream, SocketCounters},
+};
+use crate::{
+    capture::{capture, manifest::SocketStats, now_millis},
+    system_events,
+};
+use blaze_ssl_async::stream::BlazeStream;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use log::{debug, error, warn};
+use std::{future::pending, io, sync::Arc, time::Duration};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::{sleep, sleep_until, Instant},
+};
+use tokio_util::codec::Framed;
+
+/// The number of connection attempts made when reconnecting
+/// before the stream is considered dead
+const MAX_RECONNECT_ATTEMPTS: u32 = 3;
+
+/// The delay between each reconnect attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// How long the server has to send anything after the game writes a packet
+/// following sleep or a network change before the connection is considered
+/// dead
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of packets that can be waiting to be written to the stream
+const WRITE_QUEUE_SIZE: usize = 64;
+
+/// The number of packets read from the stream that can be waiting for
+/// the caller, reading pauses while the queue is full
+const READ_QUEUE_SIZE: usize = 64;
+
+/// The upstream target a [ReconnectingStream] connects to
+pub enum StreamTarget {
+    /// Fixed host and port
+    Address(String, u16),
+    /// Official server instance, re-resolved when connecting fails
+    Instance(Arc<OfficialInstance>),
+}
+
+impl StreamTarget {
+    /// Creates a new connection to the target
+    async fn connect(&self) -> io::Result<BlazeStream> {
+        match self {
+            StreamTarget::Address(host, port) => connect_blaze(host, *port).await,
+            StreamTarget::Instance(instance) => instance.stream().await,
+        }
+    }
+
+    /// Re-resolves the target address if the target supports it
+    async fn refresh(&self) {
+        if let StreamTarget::Instance(instance) = self {
+            if let Err(err) = instance.refresh().await {
+                error!("Failed to re-resolve official instance: {}", err);
+            }
+        }
+    }
+}
+
+/// Event read from a [ReconnectingStream]
+pub enum StreamEvent {
+    /// Packet read from the stream
+    Packet(Packet),
+    /// The stream was reconnected, requests written before the reconnect
+    /// may never be responded to
+    Reconnected,
+}
+
+/// Framed Blaze stream to an upstream server
+type UpstreamFramed = Framed<CountingStream<BlazeStream>, PacketCodec>;
+
+/// Wrapper around a framed Blaze stream to an upstream server which will
+/// transparently reconnect when the connection breaks, the stream ends when
+/// the server closes the connection. Each reconnect is recorded as a capture gap as any traffic in-flight
+/// at the time is lost. Reading and reconnecting happen on the stream's own
+/// task and writing on another, so a slow server never stalls reading and a
+/// reconnect is never abandoned when the caller stops waiting for a packet
+pub struct ReconnectingStream {
+    /// Events read from the stream by the connection task
+    rx: mpsc::Receiver<StreamEvent>,
+    /// The connection task, None once the stream is closed
+    task: Option<JoinHandle<()>>,
+    /// Bytes exchanged across every stream connected to the target
+    counters: Arc<SocketCounters>,
+}
+
+impl ReconnectingStream {
+    /// Creates the initial connection to the provided `target`, returns the
+    /// stream along with the sender for the packets to write to it
+    pub async fn connect(target: StreamTarget) -> io::Result<(Self, mpsc::Sender<Packet>)> {
+        let stream = target.connect().await?;
+        let counters = Arc::new(SocketCounters::default());
+        let (sink, reader) =
+            Framed::new(CountingStream::new(stream, counters.clone()), PacketCodec).split();
+        let (write_tx, write_rx) = mpsc::channel(WRITE_QUEUE_SIZE);
+        let (read_tx, read_rx) = mpsc::channel(READ_QUEUE_SIZE);
+        let (written_tx, written) = watch::channel(());
+
+        let connection = Connection {
+            target,
+            reader,
+            writer: Some(StreamWriter::spawn(
+                sink,
+                write_rx,
+                None,
+                written_tx.clone(),
+            )),
+            written_tx,
+            written,
+            suspect: false,
+            probe: None,
+            counters: counters.clone(),
+            reconnects: 0,
+            system_changes: system_events::subscribe(),
+        };
+        let task = tokio::spawn(connection.run(read_tx));
+
+        let stream = Self {
+            rx: read_rx,
+            task: Some(task),
+            counters,
+        };
+        Ok((stream, write_tx))
+    }
+
+    /// Bytes exchanged with the target across every connected stream
+    pub fn socket_stats(&self) -> SocketStats {
+        self.counters.stats()
+    }
+
+    /// Reads the next event from the stream, a [StreamEvent::Reconnected]
+    /// is read after each reconnect. Returns None once the server closed the
+    /// connection or it could not be re-established. Cancelling the read
+    /// never cancels a reconnect in progress
+    pub async fn next(&mut self) -> Option<StreamEvent> {
+        self.rx.recv().await
+    }
+
+    /// Stops reading from the stream, writes the packets still queued once
+    /// every sender has been dropped then closes the stream
+    pub async fn close(&mut self) {
+        self.rx.close();
+        if let Some(task) = self.task.take() {
+            _ = task.await;
+        }
+    }
+}
+
+/// Connection to the target owned by the task of a [ReconnectingStream]
+struct Connection {
+    /// The target to connect to
+    target: StreamTarget,
+    /// Read half of the current framed stream
+    reader: SplitStream<UpstreamFramed>,
+    /// Task writing the queued packets to the current stream, None once
+    /// the writer has finished or couldn't be moved to a new stream
+    writer: Option<StreamWriter>,
+    /// Notified by each writer after it writes a packet
+    written_tx: watch::Sender<()>,
+    /// Changed whenever a packet has been written to the stream
+    written: watch::Receiver<()>,
+    /// Whether the connection may have died during sleep or a network
+    /// change, cleared once anything is read
+    suspect: bool,
+    /// When the suspect connection is considered dead if nothing is read
+    probe: Option<Instant>,
+    /// Bytes exchanged across every stream connected to the target
+    counters: Arc<SocketCounters>,
+    /// The number of times the stream has been reconnected
+    reconnects: u32,
+    /// Notified when the machine resumed from sleep or the network changed
+    system_changes: watch::Receiver<u64>,
+}
+
+/// Why the current connection has to be replaced
+struct Broken {
+    /// The error that broke the connection
+    cause: io::Error,
+    /// The writer if it already stopped because writing failed
+    writer: Option<WriterExit>,
+}
+
+impl Connection {
+    /// Reads events into `tx` until the stream is closed or the connection
+    /// can't be re-established, then waits for the writer to finish
+    async fn run(mut self, tx: mpsc::Sender<StreamEvent>) {
+        loop {
+            let broken = select! {
+                result = self.read() => match result {
+                    Ok(Some(packet)) => {
+                        if tx.send(StreamEvent::Packet(packet)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    // The server chose to end the session so it isn't resumed
+                    Ok(None) => {
+                        debug!("Upstream server closed the connection");
+                        break;
+                    }
+                    Err(broken) => broken,
+                },
+                _ = tx.closed() => break,
+            };
+
+            if let Err(err) = self.reconnect(broken).await {
+                error!("Failed to reconnect upstream stream: {}", err);
+                break;
+            }
+
+            if tx.send(StreamEvent::Reconnected).await.is_err() {
+                break;
+            }
+        }
+
+        drop(tx);
+        if let Some(StreamWriter { task, stop }) = self.writer.take() {
+            _ = task.await;
+            drop(stop);
+        }
+    }
+
+    /// Reads the next packet from the current stream, None when the server
+    /// closed the connection. Fails when reading or writing fails or the
+    /// server sends nothing back to the game after sleep or a network change.
+    ///
+    /// Liveness is judged passively from the game's own traffic so nothing
+    /// is sent to the server on the game's behalf
+    async fn read(&mut self) -> Result<Option<Packet>, Broken> {
+        loop {
+            let writer = self.writer.as_mut().map(|writer| &mut writer.task);
+            let probing = self.probe.is_some();
+            let probe_due = self.probe.unwrap_or_else(Instant::now);
+            let awaiting_write = self.suspect && !probing;
+            let broken = select! {
+                packet = self.reader.next() => match packet {
+                    Some(Ok(packet)) => {
+                        // Anything read shows the connection is still alive
+                        self.suspect = false;
+                        self.probe = None;
+                        return Ok(Some(packet));
+                    }
+                    Some(Err(cause)) => Broken { cause, writer: None },
+                    None => return Ok(None),
+                },
+                exit = wait_writer(writer) => {
+                    self.writer = None;
+                    let mut exit = match exit {
+                        Ok(value) => value,
+                        Err(err) => {
+                            return Err(Broken {
+                                cause: io::Error::other(err),
+                                writer: None,
+                            })
+                        }
+                    };
+                    match std::mem::replace(&mut exit.result, Ok(())) {
+                        Err(cause) => Broken { cause, writer: Some(exit) },
+                        // Every sender was dropped so nothing is left to write
+                        Ok(()) => continue,
+                    }
+                }
+                _ = sleep_until(probe_due), if probing => {
+                    self.suspect = false;
+                    self.probe = None;
+                    Broken {
+                        cause: io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "Nothing read from the server after sleep or a network change",
+                        ),
+                        writer: None,
+                    }
+                }
+                // The server must answer the game's next packet in time
+                Ok(()) = self.written.changed(), if awaiting_write => {
+                    self.probe = Some(Instant::now() + PROBE_TIMEOUT);
+                    continue;
+                }
+                // The connection may be dead after sleep or a network change
+                Ok(()) = self.system_changes.changed() => {
+                    debug!("Checking upstream connection after sleep or a network change");
+                    self.suspect = true;
+                    // Only packets written from now on are checked
+                    self.written.mark_unchanged();
+                    continue;
+                }
+            };
+            return Err(broken);
+        }
+    }
+
+    /// Replaces the underlying stream with a new connection, re-resolving
+    /// the target between failed attempts. The writer is moved to the new
+    /// stream along with the packet it failed to write
+    async fn reconnect(&mut self, broken: Broken) -> io::Result<()> {
+        let Broken { cause, writer } = broken;
+        let started = now_millis();
+        warn!("Upstream connection broken ({}), reconnecting", cause);
+
+        let writer = match writer {
+            Some(writer) => Some(writer),
+            None => self.stop_writer().await,
+        };
+
+        let mut attempt = 1;
+        let stream = loop {
+            match self.target.connect().await {
+                Ok(value) => break value,
+                Err(err) if attempt < MAX_RECONNECT_ATTEMPTS => {
+                    warn!(
+                        "Reconnect attempt {} of {} failed: {}",
+                        attempt, MAX_RECONNECT_ATTEMPTS, err
+                    );
+
+                    // The instance may have moved since it was last resolved
+                    self.target.refresh().await;
+                    sleep(RECONNECT_DELAY).await;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    record_gap(started, &cause, None);
+                    return Err(err);
+                }
+            }
+        };
+
+        let (sink, reader) = Framed::new(
+            CountingStream::new(stream, self.counters.clone()),
+            PacketCodec,
+        )
+        .split();
+        self.reader = reader;
+        self.suspect = false;
+        self.probe = None;
+        self.writer = writer.map(|writer| {
+            StreamWriter::spawn(sink, writer.rx, writer.unsent, self.written_tx.clone())
+        });
+        self.reconnects += 1;
+        record_gap(started, &cause, Some(self.reconnects));
+
+        Ok(())
+    }
+
+    /// Stops the writer so its queue can be moved to a new stream
+    async fn stop_writer(&mut self) -> Option<WriterExit> {
+        let StreamWriter { task, stop } = self.writer.take()?;
+        _ = stop.send(());
+        task.await.ok()
+    }
+}
+
+/// Records the capture gap left by a broken connection as a manifest event
+/// with the time the connection broke and the time it was re-established
+///
+/// `started`   Unix timestamp in milliseconds the connection broke
+/// `reconnect` The reconnect number, None if reconnecting failed
+fn record_gap(started: u64, cause: &io::Error, reconnect: Option<u32>) {
+    let message = match reconnect {
+        Some(reconnect) => format!(
+            "Upstream reconnected (reconnect #{}) after {}, traffic from {} to {} is missing",
+            reconnect,
+            cause,
+            started,
+            now_millis()
+        ),
+        None => format!(
+            "Upstream lost after {}, traffic from {} onwards is missing",
+            cause, started
+        ),
+    };
+    warn!("--- Capture gap: {} ---", message);
+
+    if let Some(capture) = capture() {
+        capture.record_event("capture_gap", &message);
+    }
+}
+
+/// Task writing queued packets to the write half of a stream
+struct StreamWriter {
+    task: JoinHandle<WriterExit>,
+    /// Stops the writer, dropping it also stops the writer
+    stop: oneshot::Sender<()>,
+}
+
+impl StreamWriter {
+    fn spawn(
+        sink: SplitSink<UpstreamFramed, Packet>,
+        rx: mpsc::Receiver<Packet>,
+        unsent: Option<Packet>,
+        written: watch::Sender<()>,
+    ) -> Self {
+        let (stop, stop_rx) = oneshot::channel();
+        let task = tokio::spawn(write_stream(sink, rx, unsent, stop_rx, written));
+        Self { task, stop }
+    }
+}
+
+/// Queue handed back by a writer once it has stopped
+struct WriterExit {
+    rx: mpsc::Receiver<Packet>,
+    /// Packet the writer was writing when it stopped
+    unsent: Option<Packet>,
+    /// Error if the writer stopped because writing failed
+    result: io::Result<()>,
+}
+
+/// Waits for the writer task to finish, never finishes without a writer
+async fn wait_writer(
+    task: Option<&mut JoinHandle<WriterExit>>,
+) -> Result<WriterExit, tokio::task::JoinError> {
+    match task {
+        Some(task) => task.await,
+        None => pending().await,
+    }
+}
+
+/// Writes the queued packets to the stream until every sender is dropped,
+/// writing fails or the writer is stopped
+///
+/// `unsent`  Packet left unwritten by the previous writer, written first
+/// `written` Notified after each packet is written
+async fn write_stream(
+    mut sink: SplitSink<UpstreamFramed, Packet>,
+    mut rx: mpsc::Receiver<Packet>,
+    mut unsent: Option<Packet>,
+    mut stop: oneshot::Receiver<()>,
+    written: watch::Sender<()>,
+) -> WriterExit {
+    let result = loop {
+        let packet = match unsent.take() {
+            Some(packet) => packet,
+            None => select! {
+                packet = rx.recv() => match packet {
+                    Some(packet) => packet,
+                    None => break sink.close().await,
+                },
+                _ = &mut stop => break Ok(()),
+            },
+        };
+
+        select! {
+            result = sink.send(packet.clone()) => {
+                if let Err(err) = result {
+                    unsent = Some(packet);
+                    break Err(err);
+                }
+                written.send_replace(());
+            }
+            _ = &mut stop => {
+                unsent = Some(packet);
+                break Ok(());
+            }
+        }
+    };
+
+    WriterExit { rx, unsent, result }
+}
@@ -1,33 +1,46 @@
 use crate::{
+    capture::{
+        capture,
+        manifest::{DestinationRecord, DestinationSource},
+        now_millis,
+    },
+    config::config,
     constants::{MAIN_PORT, REDIRECTOR_PORT},
-    servers::packet::Packet,
+    errors::{self, BindError},
+    hooks,
+    servers::{
+        accept::AcceptErrors, limits::ListenerLimits, packet::Packet, retriever::OfficialInstance,
+    },
 };
 use blaze_ssl_async::{BlazeAccept, BlazeListener};
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error};
-use native_windows_gui::error_message;
-use std::{io, net::Ipv4Addr, time::Duration};
+use std::{io, sync::OnceLock, time::Duration};
 use tdf::TdfSerialize;
 use tokio::{select, time::sleep};
 use tokio_util::codec::Framed;
 
 use super::packet::PacketCodec;
 
+/// Connection limits for the redirector listener
+static LIMITS: OnceLock<ListenerLimits> = OnceLock::new();
+
 /// Redirector server. Handles directing clients that connect to the local
 /// proxy server that will connect them to the target server.
-pub async fn start_server() {
+pub async fn start_server() -> io::Result<()> {
     // Bind a listener for SSLv3 connections over TCP
     let listener =
-        match BlazeListener::bind((Ipv4Addr::UNSPECIFIED, REDIRECTOR_PORT), Default::default())
+        BlazeListener::bind((config().bind_address, REDIRECTOR_PORT), Default::default())
             .await
-        {
-            Ok(value) => value,
-            Err(err) => {
-                error_message("Failed to start redirector", &err.to_string());
-                error!("Failed to start redirector: {}", err);
-                return;
-            }
-        };
+            .map_err(|source| {
+                errors::report(BindError::Listen {
+                    server: "redirector",
+                    port: REDIRECTOR_PORT,
+                    source,
+                })
+            })?;
+
+    let mut accept_errors = AcceptErrors::new("redirector");
 
     // Accept incoming connections
     loop {
@@ -35,18 +48,23 @@ pub async fn start_server() {
         let accept = match listener.accept().await {
             Ok(value) => value,
             Err(err) => {
-                error!("Failed to accept redirector connection: {}", err);
-                break;
+                accept_errors.handle(err).await?;
+                continue;
             }
         };
+        accept_errors.accepted();
 
         debug!("Redirector connection ->");
 
         // Spawn a handler for the listener
-        _ = tokio::spawn(handle_client(accept)).await;
+        tokio::spawn(handle_client(accept));
     }
 }
 
+fn limits() -> &'static ListenerLimits {
+    LIMITS.get_or_init(|| ListenerLimits::new("redirector"))
+}
+
 /// The timeout before idle redirector connections are terminated
 /// (1 minutes before disconnect timeout)
 static DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
@@ -61,7 +79,7 @@ const GET_SERVER_INSTANCE: u16 = 0x1;
 /// `instance` The server instance information
 async fn handle_client(accept: BlazeAccept) -> io::Result<()> {
     // Complete the SSLv3 handshaking process
-    let (stream, _) = match accept.finish_accept().await {
+    let (stream, addr) = match accept.finish_accept().await {
         Ok(value) => value,
         Err(err) => {
             error!("Failed to accept redirector connection: {}", err);
@@ -69,6 +87,28 @@ async fn handle_client(accept: BlazeAccept) -> io::Result<()> {
         }
     };
 
+    let Some(_permit) = limits().admit(addr) else {
+        return Ok(());
+    };
+
+    // The game only reaches the local redirector when its lookup of the
+    // official redirector was redirected
+    if let Some(capture) = capture() {
+        capture.record_destination(DestinationRecord {
+            time: now_millis(),
+            server: "redirector",
+            connection: None,
+            client: addr,
+            host: OfficialInstance::REDIRECTOR_HOST.to_string(),
+            port: OfficialInstance::REDIRECT_PORT,
+            source: if hooks::lookup_redirected() {
+                DestinationSource::HostLookup
+            } else {
+                DestinationSource::HostsFile
+            },
+        });
+    }
+
     // Create a packet reader
     let mut framed = Framed::new(stream, PacketCodec);
 
@@ -106,8 +146,8 @@ async fn handle_client(accept: BlazeAccept) -> io::Result<()> {
     Ok(())
 }
 
-/// Packet contents for providing the redirection details
-/// for 127.0.0.1 to allow the traffic to be proxied
+/// Packet contents for providing the redirection details for the
+/// local main server to allow the traffic to be proxied
 pub struct ServerInstanceResponse;
 
 impl TdfSerialize for ServerInstanceResponse {
@@ -115,7 +155,7 @@ impl TdfSerialize for ServerInstanceResponse {
         // Local server address
         w.tag_union_start(b"ADDR", 0x0);
         w.group(b"VALU", |w| {
-            w.tag_owned(b"IP", u32::from_be_bytes([127, 0, 0, 1]));
+            w.tag_owned(b"IP", u32::from(config().local_address()));
             w.tag_owned(b"PORT", MAIN_PORT);
         });
 
@@ -1,35 +1,142 @@
 use blaze_ssl_async::stream::BlazeStream;
-use futures_util::{SinkExt, StreamExt};
-use log::{debug, error};
-use reqwest;
-use serde::Deserialize;
-use std::{fmt::Display, net::Ipv4Addr};
+use bytes::Bytes;
+use log::{debug, error, log_enabled, Level};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 use tdf::{DecodeError, GroupSlice, TdfDeserialize, TdfDeserializeOwned, TdfSerialize, TdfTyped};
 use thiserror::Error;
-use tokio::io;
-use tokio_util::codec::Framed;
+use tokio::{
+    io, select,
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+use crate::{
+    capture::{
+        capture, manifest::ConnectionRecord, now_millis, privacy::PrivacyCategories,
+        profile::CaptureProfile,
+    },
+    config::config,
+    servers::{
+        components::redirector,
+        dns::{self, DnsError},
+        loop_guard,
+        packet::PacketDebug,
+        probing::ProbingError,
+        reconnect::{ReconnectingStream, StreamEvent, StreamTarget},
+        status,
+    },
+    tasks,
+};
+
+use super::packet::{FrameType, Packet};
+
+/// Client builds with built-in client details, selected with `client_sku`
+/// in the config
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientSku {
+    /// The patched ME3 PC release
+    #[default]
+    Retail,
+    /// The ME3 PC demo
+    Demo,
+}
 
-use crate::servers::{components::redirector, packet::PacketDebug};
+impl ClientSku {
+    /// The built-in client details for the SKU
+    pub fn profile(&self) -> ClientProfile {
+        match self {
+            ClientSku::Retail => ClientProfile::default(),
+            // The demo was built on the same Blaze SDK as the release and
+            // is told apart by its client and service names
+            ClientSku::Demo => ClientProfile {
+                clnt: "MassEffect3-pc-trial".to_string(),
+                name: "masseffect-3-pc-trial".to_string(),
+                ..ClientProfile::default()
+            },
+        }
+    }
+}
 
-use super::packet::{FireFrame, FrameType, Packet, PacketCodec};
+/// Client details sent to the redirector when requesting the server
+/// instance. Other Blaze-era titles and SKUs without built-in details
+/// can be targeted by providing their details in the config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientProfile {
+    /// Blaze SDK version
+    pub bsdk: String,
+    /// Blaze SDK build time
+    pub btim: String,
+    /// Client name
+    pub clnt: String,
+    /// Client type
+    pub cltp: u8,
+    /// Client SKU
+    pub csku: String,
+    /// Client version
+    pub cver: String,
+    /// DirtySDK version
+    pub dsdk: String,
+    /// Server environment
+    pub env: String,
+    /// Client locale
+    pub loc: u32,
+    /// Service name
+    pub name: String,
+    /// Client platform
+    pub plat: String,
+    /// Connection profile
+    pub prof: String,
+}
+
+impl Default for ClientProfile {
+    /// Details of the patched ME3 PC client
+    fn default() -> Self {
+        Self {
+            bsdk: "3.15.6.0".to_string(),
+            btim: "Dec 21 2012 12:47:10".to_string(),
+            clnt: "MassEffect3-pc".to_string(),
+            cltp: 0,
+            csku: "134845".to_string(),
+            cver: "05427.124".to_string(),
+            dsdk: "8.14.7.1".to_string(),
+            env: "prod".to_string(),
+            loc: 0x656e4e5a,
+            name: "masseffect-3-pc".to_string(),
+            plat: "Windows".to_string(),
+            prof: "standardSecure_v3".to_string(),
+        }
+    }
+}
 
-pub struct InstanceRequest;
+pub struct InstanceRequest<'a>(pub &'a ClientProfile);
 
-impl TdfSerialize for InstanceRequest {
+impl TdfSerialize for InstanceRequest<'_> {
     fn serialize<S: tdf::TdfSerializer>(&self, w: &mut S) {
-        w.tag_str(b"BSDK", "3.15.6.0");
-        w.tag_str(b"BTIM", "Dec 21 2012 12:47:10");
-        w.tag_str(b"CLNT", "MassEffect3-pc");
-        w.tag_u8(b"CLTP", 0);
-        w.tag_str(b"CSKU", "134845");
-        w.tag_str(b"CVER", "05427.124");
-        w.tag_str(b"DSDK", "8.14.7.1");
-        w.tag_str(b"ENV", "prod");
+        let client = self.0;
+        w.tag_str(b"BSDK", &client.bsdk);
+        w.tag_str(b"BTIM", &client.btim);
+        w.tag_str(b"CLNT", &client.clnt);
+        w.tag_u8(b"CLTP", client.cltp);
+        w.tag_str(b"CSKU", &client.csku);
+        w.tag_str(b"CVER", &client.cver);
+        w.tag_str(b"DSDK", &client.dsdk);
+        w.tag_str(b"ENV", &client.env);
         w.tag_union_unset(b"FPID");
-        w.tag_u32(b"LOC", 0x656e4e5a);
-        w.tag_str(b"NAME", "masseffect-3-pc");
-        w.tag_str(b"PLAT", "Windows");
-        w.tag_str(b"PROF", "standardSecure_v3");
+        w.tag_u32(b"LOC", client.loc);
+        w.tag_str(b"NAME", &client.name);
+        w.tag_str(b"PLAT", &client.plat);
+        w.tag_str(b"PROF", &client.prof);
     }
 }
 
@@ -123,18 +230,17 @@ pub enum InstanceNet {
 
 /// Connection details for an official server instance
 pub struct OfficialInstance {
-    /// The host address of the official server
-    pub host: String,
-    /// The port of the official server.
-    pub port: u16,
+    /// The host address and port of the official server, replaced
+    /// when the instance is re-resolved
+    target: RwLock<(String, u16)>,
 }
 
 /// Errors that could occur while attempting to obtain
 /// an official server instance details
 #[derive(Debug, Error)]
 pub enum InstanceError {
-    #[error("Failed to request lookup from cloudflare: {0}")]
-    LookupRequest(#[from] reqwest::Error),
+    #[error("Failed to lookup redirector host: {0}")]
+    Lookup(#[from] DnsError),
     #[error("Failed to lookup server response empty")]
     MissingValue,
     #[error("Failed to connect to server: {0}")]
@@ -146,22 +252,51 @@ pub enum InstanceError {
 }
 
 impl OfficialInstance {
-    const REDIRECTOR_HOST: &'static str = "gosredirector.ea.com";
-    const REDIRECT_PORT: u16 = 42127;
+    /// Host of the official redirector
+    pub const REDIRECTOR_HOST: &'static str = "gosredirector.ea.com";
+    /// Port of the official redirector
+    pub const REDIRECT_PORT: u16 = 42127;
 
     pub async fn obtain() -> Result<OfficialInstance, InstanceError> {
+        let target = Self::resolve().await?;
+        Ok(OfficialInstance {
+            target: RwLock::new(target),
+        })
+    }
+
+    /// Re-resolves the official server instance replacing the current
+    /// host and port, used when the previous instance stops accepting
+    /// connections
+    pub async fn refresh(&self) -> Result<(), InstanceError> {
+        let target = Self::resolve().await?;
+        *self.target.write().expect("Instance target lock poisoned") = target;
+        Ok(())
+    }
+
+    /// Obtains the current host and port of the official server
+    pub fn target(&self) -> (String, u16) {
+        self.target
+            .read()
+            .expect("Instance target lock poisoned")
+            .clone()
+    }
+
+    /// Looks up the redirector and requests the host and port of
+    /// the official server instance
+    async fn resolve() -> Result<(String, u16), InstanceError> {
         let host = Self::lookup_host().await?;
         debug!("Completed host lookup: {}", &host);
 
         // Create a session to the redirector server
         let mut session = OfficialSession::connect(&host, Self::REDIRECT_PORT).await?;
+        session.set_timeout(config().request_timeout());
 
         // Request the server instance
         let instance: InstanceDetails = session
             .request(
                 redirector::COMPONENT,
                 redirector::GET_SERVER_INSTANCE,
-                InstanceRequest,
+                InstanceRequest(&config().client_profile()),
             )
             .await?;
 
@@ -177,45 +312,15 @@ impl OfficialInstance {
             &host, port
         );
 
-        Ok(OfficialInstance { host, port })
+        Ok((host, port))
     }
 
+    /// Looks up the address of the redirector host
     async fn lookup_host() -> Result<String, InstanceError> {
-        let host = Self::REDIRECTOR_HOST;
-
-        // Attempt to lookup using the system DNS
-        {
-            let tokio = tokio::net::lookup_host(host)
-                .await
-                .ok()
-                .and_then(|mut value| value.next());
-
-            if let Some(tokio) = tokio {
-                let ip = tokio.ip();
-                // Loopback value means it was probably redirected in the hosts file
-                // so those are ignored
-                if !ip.is_loopback() {
-                    return Ok(format!("{}", ip));
-                }
-            }
-        }
-
-        // Attempt to lookup using cloudflare DNS over HTTP
-
-        let client = reqwest::Client::new();
-        let url = format!("https://cloudflare-dns.com/dns-query?name={host}&type=A");
-        let mut response: LookupResponse = client
-            .get(url)
-            .header("Accept", "application/dns-json")
-            .send()
-            .await?
-            .json()
-            .await?;
-
-        response
-            .answer
-            .pop()
-            .map(|value| value.data)
+        let addresses = dns::resolve(Self::REDIRECTOR_HOST).await?;
+        addresses
+            .first()
+            .map(|value| value.to_string())
             .ok_or(InstanceError::MissingValue)
     }
 
@@ -223,16 +328,72 @@ impl OfficialInstance {
     /// session returning that session. Will return None if the
     /// stream failed.
     pub async fn stream(&self) -> Result<BlazeStream, io::Error> {
-        BlazeStream::connect((self.host.as_str(), self.port)).await
+        let (host, port) = self.target();
+        connect_blaze(&host, port).await
+    }
+}
+
+/// Connects a Blaze stream to the provided `host` and `port` recording
+/// the connect and handshake details in the capture manifest
+pub async fn connect_blaze(host: &str, port: u16) -> Result<BlazeStream, io::Error> {
+    loop_guard::check(host, port).await?;
+
+    let started_at = now_millis();
+    let start = Instant::now();
+
+    // blaze-ssl-async connects and completes the handshake in one step so
+    // only their combined duration can be measured
+    let result = BlazeStream::connect((host, port)).await;
+    let elapsed = start.elapsed();
+
+    debug!("Blaze connection to {}:{} took {:?}", host, port, elapsed);
+
+    if let Some(capture) = capture() {
+        let record = ConnectionRecord {
+            host: host.to_string(),
+            port,
+            started_at,
+            handshake_ms: elapsed.as_secs_f64() * 1000.0,
+            version: None,
+            cipher_suite: None,
+            certificate_chain: None,
+            error: result.as_ref().err().map(|err| err.to_string()),
+        };
+
+        capture.update_manifest(|manifest| manifest.connections.push(record));
     }
+
+    result
+}
+
+/// The default amount of time to wait for a response to a request
+/// before the request is considered failed
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Requests that are awaiting a response keyed by their sequence number
+type PendingRequests = Arc<Mutex<HashMap<u16, oneshot::Sender<RetrieverResult<Packet>>>>>;
+
+/// Messages sent from the session to the background stream task
+enum SessionMessage {
+    /// Packet to write to the stream
+    Packet(Packet),
+    /// Close the underlying stream
+    Close,
 }
 
-/// Session implementation for a retriever client
+/// Session implementation for a retriever client. The stream is owned by a
+/// background task which routes responses back to the pending request with
+/// the matching sequence number, allowing multiple requests to be in-flight
+/// at the same time
 pub struct OfficialSession {
     /// The ID for the next request packet
-    id: u16,
-    /// The underlying SSL / TCP stream connection
-    stream: Framed<BlazeStream, PacketCodec>,
+    id: AtomicU16,
+    /// Sender for messages to the background stream task
+    tx: mpsc::UnboundedSender<SessionMessage>,
+    /// Requests that are awaiting a response
+    pending: PendingRequests,
+    /// The maximum time to wait for a response to a request
+    timeout: Duration,
 }
 
 /// Error type for retriever errors
@@ -250,6 +411,15 @@ pub enum RetrieverError {
     /// Stream ended early
     #[error("Reached end of stream")]
     EarlyEof,
+    /// Server didn't respond within the request timeout
+    #[error("Request timed out after {0:?}")]
+    Timeout(Duration),
+    /// The stream was reconnected before the response was received
+    #[error("Connection was reconnected before the response was received")]
+    Reconnected,
+    /// Synthetic request blocked by the probing limiter
+    #[error(transparent)]
+    Probing(#[from] ProbingError),
 }
 
 pub type RetrieverResult<T> = Result<T, RetrieverError>;
@@ -257,17 +427,35 @@ pub type RetrieverResult<T> = Result<T, RetrieverError>;
 impl OfficialSession {
     /// Creates a session with an official server at the provided
     /// `host` and `port`
-    async fn connect(host: &str, port: u16) -> Result<OfficialSession, io::Error> {
-        let stream = BlazeStream::connect((host, port)).await?;
+    pub async fn connect(host: &str, port: u16) -> Result<OfficialSession, io::Error> {
+        let target = StreamTarget::Address(host.to_string(), port);
+        let (stream, writer) = ReconnectingStream::connect(target).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let pending: PendingRequests = Default::default();
+
+        tasks::spawn(
+            "official-session",
+            run_session(stream, writer, rx, pending.clone()),
+        );
+
         Ok(Self {
-            id: 0,
-            stream: Framed::new(stream, PacketCodec),
+            id: AtomicU16::new(0),
+            tx,
+            pending,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
         })
     }
+
+    /// Sets the maximum amount of time to wait for the response
+    /// to a request before failing with [RetrieverError::Timeout]
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
     /// Writes a request packet and waits until the response packet is
     /// received returning the contents of that response packet.
     pub async fn request<Req, Res>(
-        &mut self,
+        &self,
         component: u16,
         command: u16,
         contents: Req,
@@ -284,26 +472,19 @@ impl OfficialSession {
     /// Writes a request packet and waits until the response packet is
     /// received returning the contents of that response packet.
     pub async fn request_raw<Req: TdfSerialize>(
-        &mut self,
+        &self,
         component: u16,
         command: u16,
         contents: Req,
     ) -> RetrieverResult<Packet> {
-        let request = Packet::request(self.id, component, command, contents);
-
-        debug_log_packet(&request, "Send");
-        let frame = request.frame.clone();
-
-        self.stream.send(request).await?;
-
-        self.id += 1;
-        self.expect_response(&frame).await
+        let request = Packet::request(self.next_id(), component, command, contents);
+        self.expect_response(request).await
     }
 
     /// Writes a request packet and waits until the response packet is
     /// received returning the contents of that response packet. The
     /// request will have no content
-    pub async fn request_empty<Res>(&mut self, component: u16, command: u16) -> RetrieverResult<Res>
+    pub async fn request_empty<Res>(&self, component: u16, command: u16) -> RetrieverResult<Res>
     where
         for<'a> Res: TdfDeserialize<'a> + 'a,
     {
@@ -314,39 +495,154 @@ impl OfficialSession {
 
     /// Writes a request packet and waits until the response packet is
     /// received returning the raw response packet
-    pub async fn request_empty_raw(
-        &mut self,
+    pub async fn request_empty_raw(&self, component: u16, command: u16) -> RetrieverResult<Packet> {
+        let request = Packet::request_empty(self.next_id(), component, command);
+        self.expect_response(request).await
+    }
+
+    /// Writes a request packet with already encoded contents and waits
+    /// until the response packet is received returning the raw response
+    pub async fn request_bytes(
+        &self,
         component: u16,
         command: u16,
+        contents: Bytes,
     ) -> RetrieverResult<Packet> {
-        let request = Packet::request_empty(self.id, component, command);
-        debug_log_packet(&request, "Send");
+        let request = Packet::new_request(self.next_id(), component, command, contents);
+        self.expect_response(request).await
+    }
+
+    /// Obtains the sequence number to use for the next request
+    fn next_id(&self) -> u16 {
+        self.id.fetch_add(1, Ordering::AcqRel)
+    }
+
+    /// Writes the request to the stream and waits for its response packet to
+    /// be received, if the response isn't received within the session timeout
+    /// the stream is shutdown and a timeout error is returned
+    async fn expect_response(&self, request: Packet) -> RetrieverResult<Packet> {
         let frame = request.frame.clone();
-        self.stream.send(request).await?;
-        self.id += 1;
-        self.expect_response(&frame).await
+        let (tx, rx) = oneshot::channel();
+
+        // Register the request before its sent so the response can't be missed
+        self.pending
+            .lock()
+            .expect("Pending requests lock poisoned")
+            .insert(frame.seq, tx);
+
+        if self.tx.send(SessionMessage::Packet(request)).is_err() {
+            self.remove_pending(frame.seq);
+            return Err(RetrieverError::EarlyEof);
+        }
+
+        let duration = self.timeout;
+        match timeout(duration, rx).await {
+            Ok(Ok(result)) => result,
+            // Background task stopped before the response was received
+            Ok(Err(_)) => Err(RetrieverError::EarlyEof),
+            Err(_) => {
+                error!(
+                    "Timed out waiting for response ({:#06x}->{:#06x}), closing stream",
+                    frame.component, frame.command
+                );
+
+                self.remove_pending(frame.seq);
+
+                // The stream is left in an unknown state so its closed
+                _ = self.tx.send(SessionMessage::Close);
+
+                Err(RetrieverError::Timeout(duration))
+            }
+        }
     }
 
-    /// Waits for a response packet to be received any notification packets
-    /// that are received are handled in the handle_notify function.
-    async fn expect_response(&mut self, request: &FireFrame) -> RetrieverResult<Packet> {
-        loop {
-            let response = match self.stream.next().await {
-                Some(value) => value?,
-                None => return Err(RetrieverError::EarlyEof),
-            };
-            debug_log_packet(&response, "Receive");
-            let frame = &response.frame;
-
-            if let FrameType::Response = frame.ty {
-                if frame.path_matches(request) {
-                    return Ok(response);
+    /// Removes the pending request with the provided sequence number
+    fn remove_pending(&self, seq: u16) {
+        self.pending
+            .lock()
+            .expect("Pending requests lock poisoned")
+            .remove(&seq);
+    }
+}
+
+/// Background task that owns the session stream, writing packets from the
+/// session and routing received responses to their pending request. Any
+/// requests still pending when the stream ends are failed.
+///
+/// `stream`  The stream to the official server
+/// `writer`  Sender for packets to write to the stream
+/// `rx`      Receiver for messages from the session
+/// `pending` The requests awaiting a response
+async fn run_session(
+    mut stream: ReconnectingStream,
+    writer: mpsc::Sender<Packet>,
+    mut rx: mpsc::UnboundedReceiver<SessionMessage>,
+    pending: PendingRequests,
+) {
+    loop {
+        select! {
+            message = rx.recv() => {
+                let packet = match message {
+                    Some(SessionMessage::Packet(packet)) => packet,
+                    // Session requested close or was dropped
+                    Some(SessionMessage::Close) | None => break,
+                };
+
+                debug_log_packet(&packet, "Send");
+
+                if writer.send(packet).await.is_err() {
+                    error!("Failed to write official session packet: upstream writer stopped");
+                    break;
+                }
+            }
+            event = stream.next() => {
+                let packet = match event {
+                    Some(StreamEvent::Packet(packet)) => packet,
+                    // Requests sent before a reconnect may never be responded to
+                    Some(StreamEvent::Reconnected) => {
+                        let requests = std::mem::take(
+                            &mut *pending.lock().expect("Pending requests lock poisoned"),
+                        );
+                        for tx in requests.into_values() {
+                            _ = tx.send(Err(RetrieverError::Reconnected));
+                        }
+                        continue;
+                    }
+                    None => break,
+                };
+
+                debug_log_packet(&packet, "Receive");
+                status::check_packet(&packet.frame);
+
+                let seq = packet.frame.seq;
+                let result = match packet.frame.ty {
+                    FrameType::Response => Ok(packet),
+                    FrameType::Error => Err(RetrieverError::Packet(ErrorPacket(packet))),
+                    // Notifications aren't responses to any request
+                    _ => continue,
+                };
+
+                let tx = pending
+                    .lock()
+                    .expect("Pending requests lock poisoned")
+                    .remove(&seq);
+
+                if let Some(tx) = tx {
+                    _ = tx.send(result);
                 }
-            } else if let FrameType::Error = frame.ty {
-                return Err(RetrieverError::Packet(ErrorPacket(response)));
             }
         }
     }
+
+    // The stream is closed once the queued packets are written
+    drop(writer);
+    stream.close().await;
+
+    // Dropping the senders fails any requests still awaiting a response
+    pending
+        .lock()
+        .expect("Pending requests lock poisoned")
+        .clear();
 }
 
 /// Logs the contents of the provided packet to the debug output along with
@@ -356,7 +652,15 @@ impl OfficialSession {
 /// `packet`    The packet that is being logged
 /// `direction` The direction name for the packet
 fn debug_log_packet(packet: &Packet, action: &str) {
-    let debug = PacketDebug { packet };
+    if !config().log_packets || !log_enabled!(Level::Debug) {
+        return;
+    }
+
+    let debug = PacketDebug {
+        packet,
+        contents: CaptureProfile::current().packet_contents()
+            && PrivacyCategories::current().packet_contents(&packet.frame),
+    };
     debug!("\nOfficial: {}\n{:?}", action, debug);
 }
 
@@ -365,6 +669,13 @@ fn debug_log_packet(packet: &Packet, action: &str) {
 #[derive(Debug)]
 pub struct ErrorPacket(Packet);
 
+impl ErrorPacket {
+    /// The error response packet
+    pub fn packet(&self) -> &Packet {
+        &self.0
+    }
+}
+
 impl std::error::Error for ErrorPacket {}
 
 impl Display for ErrorPacket {
@@ -372,55 +683,3 @@ impl Display for ErrorPacket {
         write!(f, "{:#X}", self.0.frame.error)
     }
 }
-
-/// Structure for the lookup responses from the google DNS API
-///
-/// # Structure
-///
-/// ```
-/// {
-///   "Status": 0,
-///   "TC": false,
-///   "RD": true,
-///   "RA": true,
-///   "AD": false,
-///   "CD": false,
-///   "Question": [
-///     {
-///       "name": "gosredirector.ea.com.",
-///       "type": 1
-///     }
-///   ],
-///   "Answer": [
-///     {
-///       "name": "gosredirector.ea.com.",
-///       "type": 1,
-///       "TTL": 300,
-///       "data": "159.153.64.175"
-///     }
-///   ],
-///   "Comment": "Response from 2600:1403:a::43."
-/// }
-/// ```
-#[derive(Deserialize)]
-struct LookupResponse {
-    #[serde(rename = "Answer")]
-    answer: Vec<Answer>,
-}
-
-/// Structure for answer portion of request. Only the data value is
-/// being used so only that is present here.
-///
-/// # Structure
-/// ```
-/// {
-///   "name": "gosredirector.ea.com.",
-///   "type": 1,
-///   "TTL": 300,
-///   "data": "159.153.64.175"
-/// }
-/// ```
-#[derive(Deserialize)]
-struct Answer {
-    data: String,
-}
@@ -0,0 +1,90 @@
+//! Byte counting for the TCP streams the proxy reads and writes, the
+//! counters are shared so they can be read while the stream is in use
+//! and survive upstream reconnects
+
+use crate::capture::manifest::SocketStats;
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Number of bytes written to and read from a stream
+#[derive(Debug, Default)]
+pub struct SocketCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl SocketCounters {
+    /// Takes a snapshot of the counters
+    pub fn stats(&self) -> SocketStats {
+        SocketStats {
+            bytes_sent: self.sent.load(Ordering::Relaxed),
+            bytes_received: self.received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Stream wrapper counting the bytes passing through the stream
+pub struct CountingStream<S> {
+    inner: S,
+    counters: Arc<SocketCounters>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, counters: Arc<SocketCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<S> AsyncRead for CountingStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = (buf.filled().len() - before) as u64;
+            self.counters.received.fetch_add(read, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<S> AsyncWrite for CountingStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.counters
+                .sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    capture::{capture, now_millis},
+    definitions::error_name,
+    locale,
+    servers::packet::{FireFrame, FrameType},
+    ui::show_warning,
+};
+use log::{debug, warn};
+use serde::Serialize;
+use std::sync::Mutex;
+
+/// Availability of the official server as seen by the proxy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerStatus {
+    /// Nothing is known about the server yet
+    Unknown,
+    /// The server is accepting connections
+    Online,
+    /// The server responded with a maintenance or unavailable error
+    Maintenance,
+    /// The redirector or server could not be reached
+    Unreachable,
+}
+
+/// The current server status along with details about its cause
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusInfo {
+    pub status: ServerStatus,
+    /// Message describing why the status was set
+    pub detail: Option<String>,
+    /// Unix timestamp in milliseconds of when the status last changed
+    pub since: u64,
+}
+
+static STATUS: Mutex<StatusInfo> = Mutex::new(StatusInfo {
+    status: ServerStatus::Unknown,
+    detail: None,
+    since: 0,
+});
+
+/// Obtains the current server status
+pub fn status() -> StatusInfo {
+    STATUS.lock().expect("Server status lock poisoned").clone()
+}
+
+/// Updates the server status, changes are recorded in the capture manifest
+/// and the user is notified when the server becomes unavailable
+pub fn set_status(status: ServerStatus, detail: Option<String>) {
+    {
+        let current = &mut *STATUS.lock().expect("Server status lock poisoned");
+        if current.status == status {
+            return;
+        }
+        *current = StatusInfo {
+            status,
+            detail: detail.clone(),
+            since: now_millis(),
+        };
+    }
+
+    let message = match &detail {
+        Some(detail) => format!("Official server status {:?}: {}", status, detail),
+        None => format!("Official server status {:?}", status),
+    };
+
+    if let Some(capture) = capture() {
+        capture.record_event("server_status", &message);
+    }
+
+    match status {
+        ServerStatus::Maintenance => {
+            warn!("{}", message);
+            show_warning(
+                &locale::text("status.maintenance.title"),
+                &locale::text("status.maintenance.message"),
+            );
+        }
+        ServerStatus::Unreachable => {
+            warn!("{}", message);
+            show_warning(
+                &locale::text("status.unreachable.title"),
+                &locale::text("status.unreachable.message"),
+            );
+        }
+        _ => debug!("{}", message),
+    }
+}
+
+/// Whether the provided error from the server indicates that the server is
+/// down for maintenance, errors are matched by their names from the packet
+/// definitions so additional codes can be added there
+pub fn is_maintenance_error(component: u16, error: u16) -> bool {
+    error_name(component, error).is_some_and(|name| {
+        let name = name.to_ascii_lowercase();
+        name.contains("maintenance") || name.contains("unavailable")
+    })
+}
+
+/// Checks a packet from the official server for maintenance errors
+pub fn check_packet(frame: &FireFrame) {
+    if frame.ty == FrameType::Error && is_maintenance_error(frame.component, frame.error) {
+        set_status(
+            ServerStatus::Maintenance,
+            Some(format!(
+                "Error {:#06x} from {:#06x}->{:#06x}",
+                frame.error, frame.component, frame.command
+            )),
+        );
+    }
+}
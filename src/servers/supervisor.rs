@@ -0,0 +1,149 @@
+use crate::{
+    capture::{capture, now_millis},
+    locale,
+    metrics::{self, ErrorCategory},
+    ui::show_warning,
+};
+use futures_util::FutureExt;
+use log::{debug, error};
+use serde::Serialize;
+use std::{
+    future::Future,
+    io,
+    panic::AssertUnwindSafe,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+/// Delay before the first restart of a server
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest delay between restarts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Servers running for at least this long before stopping restart
+/// with the minimum delay again
+const STABLE_TIME: Duration = Duration::from_secs(120);
+
+/// State of a supervised server
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerState {
+    pub name: &'static str,
+    /// Whether the server is currently running
+    pub running: bool,
+    /// Number of times the server has been restarted
+    pub restarts: u32,
+    /// Why the server last stopped
+    pub last_error: Option<String>,
+    /// Unix timestamp in milliseconds of when the server last started or stopped
+    pub since: u64,
+}
+
+static SERVERS: Mutex<Vec<ServerState>> = Mutex::new(Vec::new());
+
+/// Cancelled when the game is closing, servers watching it stop accepting
+/// connections and aren't restarted
+static SHUTDOWN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Obtains the token cancelled when the servers are shutting down
+pub fn shutdown_token() -> &'static CancellationToken {
+    SHUTDOWN.get_or_init(CancellationToken::new)
+}
+
+/// Obtains the state of each supervised server
+pub fn servers() -> Vec<ServerState> {
+    SERVERS.lock().expect("Supervisor lock poisoned").clone()
+}
+
+/// Runs the server created by `start`, restarting it with an increasing
+/// delay whenever it stops or panics. Servers are only expected to stop
+/// when they fail (e.g. binding fails after resuming from sleep)
+pub async fn supervise<F, Fut>(name: &'static str, start: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    let mut backoff = MIN_BACKOFF;
+    let mut restarts = 0;
+
+    loop {
+        update(name, |state| {
+            state.running = true;
+            state.restarts = restarts;
+            state.since = now_millis();
+        });
+
+        let started = Instant::now();
+        let reason = match AssertUnwindSafe(start()).catch_unwind().await {
+            Ok(Ok(())) => "Server stopped".to_string(),
+            Ok(Err(err)) => err.to_string(),
+            Err(_) => "Server panicked".to_string(),
+        };
+
+        if shutdown_token().is_cancelled() {
+            debug!("Server {} stopped for shutdown", name);
+            update(name, |state| {
+                state.running = false;
+                state.since = now_millis();
+            });
+            return;
+        }
+
+        if started.elapsed() >= STABLE_TIME {
+            backoff = MIN_BACKOFF;
+        }
+
+        error!(
+            "Server {} stopped, restarting in {:?}: {}",
+            name, backoff, reason
+        );
+        metrics::count_error(ErrorCategory::ServerRestart);
+
+        // Only the first failure is shown, restarts are reported through the status
+        if restarts == 0 {
+            show_warning(
+                &locale::format("server.failed.title", &[("name", name)]),
+                &locale::format("server.failed.message", &[("reason", &reason)]),
+            );
+        }
+
+        if let Some(capture) = capture() {
+            capture.record_event(
+                "server_restart",
+                &format!("Server {} stopped: {}", name, reason),
+            );
+        }
+
+        update(name, |state| {
+            state.running = false;
+            state.last_error = Some(reason);
+            state.since = now_millis();
+        });
+
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        restarts += 1;
+        debug!("Restarting server {} (Restart {})", name, restarts);
+    }
+}
+
+fn update<F>(name: &'static str, update: F)
+where
+    F: FnOnce(&mut ServerState),
+{
+    let servers = &mut *SERVERS.lock().expect("Supervisor lock poisoned");
+    let index = match servers.iter().position(|state| state.name == name) {
+        Some(index) => index,
+        None => {
+            servers.push(ServerState {
+                name,
+                running: false,
+                restarts: 0,
+                last_error: None,
+                since: 0,
+            });
+            servers.len() - 1
+        }
+    };
+    update(&mut servers[index]);
+}
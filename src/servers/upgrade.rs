@@ -0,0 +1,346 @@
+//! Transparent tunnels for HTTP connections upgraded to other protocols,
+//! some EA services hold long lived WebSocket connections which are
+//! tunnelled to the official server with each frame recorded
+
+use super::http::{client_builder, record_http};
+use crate::{
+    capture::{
+        capture, clock::now_micros, http_log::HttpRecord, privacy::PrivacyCategories,
+        profile::CaptureProfile, value::to_hex, websocket_log::WebSocketRecord, Direction,
+    },
+    errors::{self, ServeError, UpstreamError},
+    tasks,
+};
+use axum::{body::Body, extract::Request};
+use hyper::{
+    header::{CONNECTION, HOST, UPGRADE},
+    Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use log::debug;
+use std::{
+    io,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Instant,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the buffer used when relaying data through a tunnel
+const RELAY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Frames declaring a larger payload are relayed without being recorded,
+/// the rest of the tunnel is then relayed without parsing
+const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Maximum number of payload bytes recorded for each frame
+const MAX_RECORDED_PAYLOAD: usize = 64 * 1024;
+
+/// WebSocket text frame opcode
+const OPCODE_TEXT: u8 = 0x1;
+
+/// ID for the next tunnel
+static NEXT_TUNNEL: AtomicU32 = AtomicU32::new(0);
+
+/// Whether the request asks to upgrade the connection
+pub fn is_upgrade(req: &Request) -> bool {
+    let connection = req
+        .headers()
+        .get(CONNECTION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    req.headers().contains_key(UPGRADE)
+        && connection
+            .split(',')
+            .any(|value| value.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Forwards the upgrade request to the official server, when the server
+/// switches protocols the client connection is tunnelled to the server
+/// connection. Other responses are forwarded as is
+pub async fn proxy_upgrade(mut req: Request, target_url: String) -> Response<Body> {
+    let client_upgrade = hyper::upgrade::on(&mut req);
+    let websocket = req
+        .headers()
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+
+    let mut record = HttpRecord {
+        time: now_micros(),
+        method: req.method().to_string(),
+        url: target_url.clone(),
+        status: None,
+        duration_ms: 0.0,
+        length: 0,
+        body: None,
+    };
+    let start = Instant::now();
+
+    // Upgrades are only possible over HTTP/1
+    let client = match client_builder(&target_url).await.http1_only().build() {
+        Ok(value) => value,
+        Err(err) => {
+            errors::report(UpstreamError::HttpClient(err));
+            return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    let mut headers = req.headers().clone();
+    headers.remove(HOST);
+
+    let upstream = match client
+        .request(req.method().clone(), &target_url)
+        .headers(headers)
+        .send()
+        .await
+    {
+        Ok(value) => value,
+        Err(err) => {
+            errors::report(UpstreamError::HttpRequest(err));
+            record_http(record, start);
+            return status_response(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let status = upstream.status();
+    let headers = upstream.headers().clone();
+    record.status = Some(status.as_u16());
+
+    if status != StatusCode::SWITCHING_PROTOCOLS {
+        debug!("Server refused HTTP upgrade: {:?}", &upstream);
+        let body = upstream.bytes().await.unwrap_or_default();
+        record.length = body.len();
+        record_http(record, start);
+
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        return response;
+    }
+
+    record_http(record, start);
+
+    let tunnel = NEXT_TUNNEL.fetch_add(1, Ordering::AcqRel);
+    debug!("Tunnel {} opened for {}", tunnel, target_url);
+
+    tasks::spawn("http-tunnel", async move {
+        let (client, server) = match tokio::join!(client_upgrade, upstream.upgrade()) {
+            (Ok(client), Ok(server)) => (client, server),
+            (Err(err), _) => {
+                errors::report(ServeError::Upgrade(err));
+                return;
+            }
+            (_, Err(err)) => {
+                errors::report(UpstreamError::HttpUpgrade(err));
+                return;
+            }
+        };
+
+        let (client_read, client_write) = tokio::io::split(TokioIo::new(client));
+        let (server_read, server_write) = tokio::io::split(server);
+
+        let recorder =
+            |direction| FrameRecorder::new(tunnel, target_url.clone(), direction, websocket);
+        let (sent, received) = tokio::join!(
+            relay(client_read, server_write, recorder(Direction::Send)),
+            relay(server_read, client_write, recorder(Direction::Receive))
+        );
+        if let Err(err) = sent.and(received) {
+            debug!("Tunnel {} closed with error: {}", tunnel, err);
+        } else {
+            debug!("Tunnel {} closed", tunnel);
+        }
+    });
+
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+/// Copies data from the reader to the writer until the reader closes,
+/// recording the data passing through
+async fn relay<R, W>(mut reader: R, mut writer: W, mut recorder: FrameRecorder) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; RELAY_BUFFER_SIZE];
+    loop {
+        let count = reader.read(&mut buffer).await?;
+        if count == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buffer[..count]).await?;
+        recorder.push(&buffer[..count]);
+    }
+}
+
+/// Result of parsing a frame from the start of a buffer
+enum ParsedFrame {
+    /// More data is needed
+    Incomplete,
+    /// The frame payload is larger than [MAX_FRAME_SIZE]
+    TooLarge,
+    Frame {
+        fin: bool,
+        opcode: u8,
+        /// The unmasked payload
+        payload: Vec<u8>,
+        /// Number of bytes the frame used from the buffer
+        consumed: usize,
+    },
+}
+
+/// Records the data relayed in one direction of a tunnel, WebSocket
+/// data is split into frames while other protocols are recorded as
+/// the chunks they arrive in
+struct FrameRecorder {
+    tunnel: u32,
+    url: String,
+    direction: Direction,
+    websocket: bool,
+    /// Data not yet parsed into a frame
+    buffer: Vec<u8>,
+    /// Whether parsing stopped after an oversized frame
+    passthrough: bool,
+}
+
+impl FrameRecorder {
+    fn new(tunnel: u32, url: String, direction: Direction, websocket: bool) -> Self {
+        Self {
+            tunnel,
+            url,
+            direction,
+            websocket,
+            buffer: Vec::new(),
+            passthrough: false,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if !self.websocket {
+            self.record(None, true, data);
+            return;
+        }
+        if self.passthrough {
+            return;
+        }
+
+        self.buffer.extend_from_slice(data);
+        loop {
+            match parse_frame(&self.buffer) {
+                ParsedFrame::Incomplete => return,
+                ParsedFrame::TooLarge => {
+                    debug!(
+                        "Tunnel {} sent an oversized frame, no further frames are recorded",
+                        self.tunnel
+                    );
+                    self.passthrough = true;
+                    self.buffer = Vec::new();
+                    return;
+                }
+                ParsedFrame::Frame {
+                    fin,
+                    opcode,
+                    payload,
+                    consumed,
+                } => {
+                    self.buffer.drain(..consumed);
+                    self.record(Some(opcode), fin, &payload);
+                }
+            }
+        }
+    }
+
+    fn record(&self, opcode: Option<u8>, fin: bool, payload: &[u8]) {
+        let Some(capture) = capture() else {
+            return;
+        };
+
+        let bodies =
+            CaptureProfile::current().http_bodies() && PrivacyCategories::current().http_bodies;
+        let truncated = payload.len() > MAX_RECORDED_PAYLOAD;
+        let recorded = &payload[..payload.len().min(MAX_RECORDED_PAYLOAD)];
+
+        capture.record_websocket(&WebSocketRecord {
+            time: now_micros(),
+            tunnel: self.tunnel,
+            url: self.url.clone(),
+            direction: self.direction,
+            opcode,
+            fin,
+            length: payload.len() as u64,
+            payload: bodies.then(|| match opcode {
+                Some(OPCODE_TEXT) => String::from_utf8_lossy(recorded).into_owned(),
+                _ => to_hex(recorded),
+            }),
+            truncated: bodies && truncated,
+        });
+    }
+}
+
+/// Parses a WebSocket frame from the start of the buffer
+fn parse_frame(buffer: &[u8]) -> ParsedFrame {
+    let [first, second, ..] = *buffer else {
+        return ParsedFrame::Incomplete;
+    };
+
+    let fin = first & 0x80 != 0;
+    let opcode = first & 0x0F;
+    let masked = second & 0x80 != 0;
+
+    let (length, mut offset) = match second & 0x7F {
+        126 => match buffer.get(2..4) {
+            Some(bytes) => (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4),
+            None => return ParsedFrame::Incomplete,
+        },
+        127 => match buffer.get(2..10).and_then(|bytes| bytes.try_into().ok()) {
+            Some(bytes) => (u64::from_be_bytes(bytes), 10),
+            None => return ParsedFrame::Incomplete,
+        },
+        length => (length as u64, 2),
+    };
+
+    if length > MAX_FRAME_SIZE {
+        return ParsedFrame::TooLarge;
+    }
+
+    let mask = if masked {
+        let Some(mask) = buffer.get(offset..offset + 4) else {
+            return ParsedFrame::Incomplete;
+        };
+        offset += 4;
+        Some([mask[0], mask[1], mask[2], mask[3]])
+    } else {
+        None
+    };
+
+    let end = offset + length as usize;
+    let Some(payload) = buffer.get(offset..end) else {
+        return ParsedFrame::Incomplete;
+    };
+
+    let payload = match mask {
+        Some(mask) => payload
+            .iter()
+            .enumerate()
+            .map(|(index, value)| value ^ mask[index % 4])
+            .collect(),
+        None => payload.to_vec(),
+    };
+
+    ParsedFrame::Frame {
+        fin,
+        opcode,
+        payload,
+        consumed: end,
+    }
+}
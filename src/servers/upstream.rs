@@ -0,0 +1,52 @@
+use super::{reconnect::StreamTarget, retriever::OfficialInstance};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Upstream server a proxied connection can be directed to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Upstream {
+    /// The official server instance obtained through the redirector
+    Official,
+    /// Server at a fixed address, must accept Blaze connections over SSLv3
+    /// in the same way as the official server
+    Address {
+        /// Name used to identify the upstream in the capture
+        #[serde(default)]
+        name: Option<String>,
+        host: String,
+        port: u16,
+    },
+}
+
+impl Upstream {
+    /// Selects the upstream for the connection with the provided session `id`,
+    /// connections cycle through the configured upstreams in order with the
+    /// official server used when none are configured
+    pub fn select(upstreams: &[Upstream], id: u32) -> &Upstream {
+        if upstreams.is_empty() {
+            return &Upstream::Official;
+        }
+        let index = (id.saturating_sub(1) as usize) % upstreams.len();
+        &upstreams[index]
+    }
+
+    /// Label identifying the upstream in logs and the capture manifest
+    pub fn label(&self) -> String {
+        match self {
+            Upstream::Official => "official".to_string(),
+            Upstream::Address {
+                name: Some(name), ..
+            } => name.clone(),
+            Upstream::Address { host, port, .. } => format!("{}:{}", host, port),
+        }
+    }
+
+    /// Creates the stream target for connecting to this upstream
+    pub fn target(&self, official: Arc<OfficialInstance>) -> StreamTarget {
+        match self {
+            Upstream::Official => StreamTarget::Instance(official),
+            Upstream::Address { host, port, .. } => StreamTarget::Address(host.clone(), *port),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Power and network change notifications from Windows, upstream
+//! connections that died while the machine slept or the network changed are
+//! re-established. Notifications are only registered in builds with the
+//! plugin
+
+use std::sync::OnceLock;
+use tokio::sync::watch;
+
+#[cfg(feature = "plugin")]
+use crate::{
+    capture::{capture, now_millis},
+    tasks,
+};
+#[cfg(feature = "plugin")]
+use log::{debug, error, warn};
+#[cfg(feature = "plugin")]
+use std::{ffi::c_void, ptr::null, time::Duration};
+#[cfg(feature = "plugin")]
+use tokio::{sync::mpsc, time::timeout};
+#[cfg(feature = "plugin")]
+use windows_sys::Win32::{
+    Foundation::{ERROR_SUCCESS, HANDLE},
+    NetworkManagement::IpHelper::{
+        NotifyIpInterfaceChange, MIB_IPINTERFACE_ROW, MIB_NOTIFICATION_TYPE,
+    },
+    Networking::WinSock::AF_UNSPEC,
+    System::Power::{PowerRegisterSuspendResumeNotification, DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS},
+    UI::WindowsAndMessaging::{DEVICE_NOTIFY_CALLBACK, PBT_APMRESUMEAUTOMATIC, PBT_APMSUSPEND},
+};
+
+#[cfg(feature = "plugin")]
+/// Network change notifications arrive in bursts, changes within this
+/// time of each other are handled as a single change
+const NETWORK_SETTLE_TIME: Duration = Duration::from_secs(3);
+
+#[cfg(feature = "plugin")]
+/// Power and network events from Windows
+#[derive(Debug, Clone, Copy)]
+enum SystemEvent {
+    /// The machine is about to sleep
+    Suspend,
+    /// The machine resumed from sleep
+    Resume,
+    /// A network interface was added, removed or changed
+    NetworkChanged,
+}
+
+#[cfg(feature = "plugin")]
+/// Sender for events from the notification callbacks
+static EVENTS: OnceLock<mpsc::UnboundedSender<SystemEvent>> = OnceLock::new();
+
+/// Counter increased whenever upstream connections should be re-established
+static RECONNECT: OnceLock<watch::Sender<u64>> = OnceLock::new();
+
+fn reconnect_sender() -> &'static watch::Sender<u64> {
+    RECONNECT.get_or_init(|| watch::channel(0).0)
+}
+
+/// Subscribes to requests to re-establish upstream connections, the
+/// receiver is notified after the machine resumes from sleep or the
+/// network changes as connections made before are likely dead
+pub fn subscribe() -> watch::Receiver<u64> {
+    reconnect_sender().subscribe()
+}
+
+#[cfg(feature = "plugin")]
+/// Registers for the power and network change notifications and starts
+/// the task handling them
+pub fn start() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    if EVENTS.set(tx).is_err() {
+        return;
+    }
+
+    register_power();
+    register_network();
+
+    tasks::spawn("system-events", handle_events(rx));
+}
+
+#[cfg(feature = "plugin")]
+fn register_power() {
+    // The parameters must outlive the registration which lasts for the process
+    let parameters = Box::leak(Box::new(DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS {
+        Callback: Some(on_power),
+        Context: std::ptr::null_mut(),
+    }));
+    let mut registration = std::ptr::null_mut();
+
+    let result = unsafe {
+        PowerRegisterSuspendResumeNotification(
+            DEVICE_NOTIFY_CALLBACK,
+            parameters as *mut DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS as HANDLE,
+            &mut registration,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        error!("Failed to register for sleep notifications: {}", result);
+    }
+}
+
+#[cfg(feature = "plugin")]
+fn register_network() {
+    let mut handle: HANDLE = 0;
+    let result =
+        unsafe { NotifyIpInterfaceChange(AF_UNSPEC, Some(on_network), null(), 0, &mut handle) };
+    if result != ERROR_SUCCESS {
+        error!("Failed to register for network notifications: {}", result);
+    }
+}
+
+#[cfg(feature = "plugin")]
+unsafe extern "system" fn on_power(_: *const c_void, ty: u32, _: *const c_void) -> u32 {
+    match ty {
+        PBT_APMSUSPEND => send(SystemEvent::Suspend),
+        PBT_APMRESUMEAUTOMATIC => send(SystemEvent::Resume),
+        _ => {}
+    }
+    ERROR_SUCCESS
+}
+
+#[cfg(feature = "plugin")]
+unsafe extern "system" fn on_network(
+    _: *const c_void,
+    _: *const MIB_IPINTERFACE_ROW,
+    _: MIB_NOTIFICATION_TYPE,
+) {
+    send(SystemEvent::NetworkChanged);
+}
+
+#[cfg(feature = "plugin")]
+fn send(event: SystemEvent) {
+    if let Some(tx) = EVENTS.get() {
+        _ = tx.send(event);
+    }
+}
+
+#[cfg(feature = "plugin")]
+async fn handle_events(mut rx: mpsc::UnboundedReceiver<SystemEvent>) {
+    // Unix timestamp in milliseconds the machine went to sleep
+    let mut suspended_at: Option<u64> = None;
+
+    // Event received while waiting for network changes to settle
+    let mut deferred: Option<SystemEvent> = None;
+
+    loop {
+        let event = match deferred.take() {
+            Some(event) => event,
+            None => match rx.recv().await {
+                Some(event) => event,
+                None => break,
+            },
+        };
+
+        match event {
+            SystemEvent::Suspend => {
+                debug!("Machine is going to sleep");
+                suspended_at = Some(now_millis());
+                record_event("power", "Machine is going to sleep");
+            }
+            SystemEvent::Resume => {
+                let message = match suspended_at.take() {
+                    Some(time) => format!(
+                        "Machine resumed after sleeping for {}s, capture has a gap",
+                        now_millis().saturating_sub(time) / 1000
+                    ),
+                    None => "Machine resumed from sleep, capture has a gap".to_string(),
+                };
+                warn!("{}", message);
+                record_event("power", &message);
+                request_reconnect();
+            }
+            SystemEvent::NetworkChanged => {
+                // Wait for the burst of changes to settle
+                loop {
+                    match timeout(NETWORK_SETTLE_TIME, rx.recv()).await {
+                        Ok(Some(SystemEvent::NetworkChanged)) => {}
+                        Ok(Some(event)) => {
+                            deferred = Some(event);
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+
+                // Network changes while asleep are covered by the resume
+                if suspended_at.is_some() {
+                    continue;
+                }
+
+                warn!("Network configuration changed, reconnecting upstream connections");
+                record_event("network", "Network configuration changed");
+                request_reconnect();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "plugin")]
+fn request_reconnect() {
+    reconnect_sender().send_modify(|value| *value += 1);
+}
+
+#[cfg(feature = "plugin")]
+fn record_event(kind: &'static str, message: &str) {
+    if let Some(capture) = capture() {
+        capture.record_event(kind, message);
+    }
+}
@@ -0,0 +1,38 @@
+use futures_util::FutureExt;
+use log::error;
+use std::{future::Future, panic::AssertUnwindSafe};
+use tokio::task::JoinHandle;
+
+/// Prefix for the names of threads created by the plugin so they can be
+/// told apart from the game threads in a debugger or crash dump
+pub const THREAD_PREFIX: &str = "prdc-";
+
+/// Spawns a background task on the runtime, a panic within the task is
+/// logged along with the task name and ends only that task
+pub fn spawn<F>(name: &'static str, future: F) -> JoinHandle<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        if AssertUnwindSafe(future).catch_unwind().await.is_err() {
+            error!("Task {} panicked and has stopped", name);
+        }
+    })
+}
+
+/// Spawns a named thread, returns None if the thread couldn't be created
+pub fn spawn_thread<F>(name: &str, f: F) -> Option<std::thread::JoinHandle<()>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    match std::thread::Builder::new()
+        .name(format!("{}{}", THREAD_PREFIX, name))
+        .spawn(f)
+    {
+        Ok(handle) => Some(handle),
+        Err(err) => {
+            error!("Failed to start {} thread: {}", name, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,674 @@
+//! Message boxes and windows shown to the player. Builds without the
+//! plugin have no windows, messages are logged instead
+
+#[cfg(feature = "plugin")]
+use crate::{
+    capture::{
+        anonymize::Anonymization,
+        capture,
+        inspect::side_by_side,
+        packet_log::{read_packet_log, PacketRecord},
+        privacy::PrivacyCategories,
+        profile::CaptureProfile,
+        review::{self, SessionSummary},
+        Direction,
+    },
+    config, locale,
+    servers::{
+        components::{component_key, get_command_name, get_component_name},
+        packet::FrameType,
+    },
+    tasks::spawn_thread,
+};
+#[cfg(feature = "plugin")]
+use native_windows_gui::{message, MessageButtons, MessageChoice, MessageIcons, MessageParams};
+#[cfg(feature = "plugin")]
+use std::path::PathBuf;
+
+/// Shows a warning message box on its own thread so the
+/// caller isn't blocked waiting for it to be closed
+#[cfg(feature = "plugin")]
+pub fn show_warning(title: &str, content: &str) {
+    let title = title.to_string();
+    let content = content.to_string();
+
+    spawn_thread("warning", move || {
+        message(&MessageParams {
+            title: &title,
+            content: &content,
+            buttons: MessageButtons::Ok,
+            icons: MessageIcons::Warning,
+        });
+    });
+}
+
+/// Shows an information message box on its own thread so the
+/// caller isn't blocked waiting for it to be closed
+#[cfg(feature = "plugin")]
+pub fn show_notification(title: &str, content: &str) {
+    let title = title.to_string();
+    let content = content.to_string();
+
+    spawn_thread("notification", move || {
+        message(&MessageParams {
+            title: &title,
+            content: &content,
+            buttons: MessageButtons::Ok,
+            icons: MessageIcons::Info,
+        });
+    });
+}
+
+/// Choice made in the packet editor
+pub enum EditorChoice {
+    /// Forward the packet unchanged
+    Forward,
+    /// Forward the packet with the edited text
+    ForwardEdited(String),
+    /// Drop the packet
+    Drop,
+}
+
+/// Shows an editor window for the provided text on its own thread. Edited
+/// text is checked by `validate` which returns an error message if the text
+/// is invalid, the window stays open until valid text is provided. Closing
+/// the window forwards the packet unchanged
+#[cfg(feature = "plugin")]
+pub fn show_editor<V, F>(title: &str, content: &str, text: &str, validate: V, on_choice: F)
+where
+    V: Fn(&str) -> Option<String> + Send + 'static,
+    F: FnOnce(EditorChoice) + Send + 'static,
+{
+    let title = title.to_string();
+    let content = content.to_string();
+    // Multi-line text boxes require CRLF line endings
+    let text = text.replace('\n', "\r\n");
+
+    spawn_thread("editor", move || {
+        let choice = match run_editor(&title, &content, &text, validate) {
+            Ok(value) => value,
+            Err(err) => {
+                log::error!("Failed to show packet editor: {}", err);
+                EditorChoice::Forward
+            }
+        };
+        on_choice(choice);
+    });
+}
+
+#[cfg(feature = "plugin")]
+fn run_editor<V>(
+    title: &str,
+    content: &str,
+    text: &str,
+    validate: V,
+) -> Result<EditorChoice, native_windows_gui::NwgError>
+where
+    V: Fn(&str) -> Option<String> + 'static,
+{
+    use native_windows_gui as nwg;
+    use std::{cell::RefCell, rc::Rc};
+
+    nwg::init()?;
+
+    let mut window = nwg::Window::default();
+    nwg::Window::builder()
+        .size((640, 560))
+        .position((300, 200))
+        .title(title)
+        .build(&mut window)?;
+
+    let mut label = nwg::Label::default();
+    nwg::Label::builder()
+        .text(content)
+        .size((620, 40))
+        .position((10, 10))
+        .parent(&window)
+        .build(&mut label)?;
+
+    let mut editor = nwg::TextBox::default();
+    nwg::TextBox::builder()
+        .text(text)
+        .size((620, 440))
+        .position((10, 55))
+        .parent(&window)
+        .build(&mut editor)?;
+
+    let mut buttons = [
+        nwg::Button::default(),
+        nwg::Button::default(),
+        nwg::Button::default(),
+    ];
+    for (index, (button, label)) in buttons
+        .iter_mut()
+        .zip(["editor.forward", "editor.forward_edited", "editor.drop"])
+        .enumerate()
+    {
+        nwg::Button::builder()
+            .text(&locale::text(label))
+            .size((200, 40))
+            .position((10 + index as i32 * 210, 505))
+            .parent(&window)
+            .build(button)?;
+    }
+
+    let [forward, forward_edited, drop] = &buttons;
+    let (forward, forward_edited, drop) = (forward.handle, forward_edited.handle, drop.handle);
+
+    let choice = Rc::new(RefCell::new(None));
+    let editor = Rc::new(editor);
+    let handler = {
+        let choice = choice.clone();
+        let editor = editor.clone();
+        nwg::full_bind_event_handler(&window.handle, move |event, _, handle| match event {
+            nwg::Event::OnButtonClick => {
+                let value = if handle == forward {
+                    EditorChoice::Forward
+                } else if handle == drop {
+                    EditorChoice::Drop
+                } else if handle == forward_edited {
+                    let text = editor.text().replace("\r\n", "\n");
+                    if let Some(err) = validate(&text) {
+                        nwg::modal_error_message(
+                            handle,
+                            &locale::text("editor.invalid.title"),
+                            &err,
+                        );
+                        return;
+                    }
+                    EditorChoice::ForwardEdited(text)
+                } else {
+                    return;
+                };
+
+                *choice.borrow_mut() = Some(value);
+                nwg::stop_thread_dispatch();
+            }
+            nwg::Event::OnWindowClose => nwg::stop_thread_dispatch(),
+            _ => {}
+        })
+    };
+
+    nwg::dispatch_thread_events();
+    nwg::unbind_event_handler(&handler);
+
+    let choice = choice.borrow_mut().take();
+    Ok(choice.unwrap_or(EditorChoice::Forward))
+}
+
+/// Shows the setup wizard on its own thread, the choices are written
+/// to the config file and applied to the current capture. Closing the
+/// wizard keeps the default config
+#[cfg(feature = "plugin")]
+pub fn show_setup_wizard() {
+    spawn_thread("setup-wizard", || {
+        let (profile, anonymization, privacy) = match run_wizard() {
+            Ok(Some(value)) => value,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("Failed to show setup wizard: {}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = config::write_choices(profile, anonymization, privacy) {
+            log::error!("Failed to write setup choices: {}", err);
+        }
+
+        if let Some(capture) = capture() {
+            capture.set_profile(profile);
+            capture.set_anonymization(anonymization);
+            capture.set_privacy(privacy);
+        }
+    });
+}
+
+/// Choices made in the setup wizard
+#[cfg(feature = "plugin")]
+type WizardChoices = (CaptureProfile, Anonymization, PrivacyCategories);
+
+#[cfg(feature = "plugin")]
+fn run_wizard() -> Result<Option<WizardChoices>, native_windows_gui::NwgError> {
+    use native_windows_gui as nwg;
+    use std::{cell::RefCell, rc::Rc};
+
+    const PROFILES: [CaptureProfile; 3] = [
+        CaptureProfile::Minimal,
+        CaptureProfile::Standard,
+        CaptureProfile::Full,
+    ];
+    const LEVELS: [Anonymization; 3] = [
+        Anonymization::Off,
+        Anonymization::Pseudonymize,
+        Anonymization::Redact,
+    ];
+
+    nwg::init()?;
+
+    let mut window = nwg::Window::default();
+    nwg::Window::builder()
+        .size((520, 540))
+        .position((300, 200))
+        .title(&locale::text("wizard.title"))
+        .build(&mut window)?;
+
+    let mut label = nwg::Label::default();
+    nwg::Label::builder()
+        .text(&locale::text("wizard.text"))
+        .size((500, 190))
+        .position((10, 10))
+        .parent(&window)
+        .build(&mut label)?;
+
+    let mut profile_label = nwg::Label::default();
+    nwg::Label::builder()
+        .text(&locale::text("wizard.profile"))
+        .size((500, 20))
+        .position((10, 210))
+        .parent(&window)
+        .build(&mut profile_label)?;
+
+    let mut profiles = nwg::ComboBox::default();
+    nwg::ComboBox::builder()
+        .collection(
+            PROFILES
+                .iter()
+                .map(|profile| locale::text(&format!("wizard.profile.{}", profile)))
+                .collect(),
+        )
+        .selected_index(Some(1))
+        .size((500, 25))
+        .position((10, 232))
+        .parent(&window)
+        .build(&mut profiles)?;
+
+    let mut level_label = nwg::Label::default();
+    nwg::Label::builder()
+        .text(&locale::text("wizard.anonymization"))
+        .size((500, 20))
+        .position((10, 270))
+        .parent(&window)
+        .build(&mut level_label)?;
+
+    let mut levels = nwg::ComboBox::default();
+    nwg::ComboBox::builder()
+        .collection(
+            LEVELS
+                .iter()
+                .map(|level| locale::text(&format!("wizard.anonymization.{}", level)))
+                .collect(),
+        )
+        .selected_index(Some(1))
+        .size((500, 25))
+        .position((10, 292))
+        .parent(&window)
+        .build(&mut levels)?;
+
+    let mut privacy_label = nwg::Label::default();
+    nwg::Label::builder()
+        .text(&locale::text("wizard.privacy"))
+        .size((500, 20))
+        .position((10, 330))
+        .parent(&window)
+        .build(&mut privacy_label)?;
+
+    // Telemetry, HTTP bodies and messages in that order
+    let mut categories = [
+        nwg::CheckBox::default(),
+        nwg::CheckBox::default(),
+        nwg::CheckBox::default(),
+    ];
+    for (index, (checkbox, label)) in categories
+        .iter_mut()
+        .zip([
+            "wizard.privacy.telemetry",
+            "wizard.privacy.http_bodies",
+            "wizard.privacy.messages",
+        ])
+        .enumerate()
+    {
+        nwg::CheckBox::builder()
+            .text(&locale::text(label))
+            .check_state(nwg::CheckBoxState::Checked)
+            .size((500, 25))
+            .position((10, 352 + index as i32 * 28))
+            .parent(&window)
+            .build(checkbox)?;
+    }
+
+    let mut save = nwg::Button::default();
+    nwg::Button::builder()
+        .text(&locale::text("wizard.save"))
+        .size((200, 40))
+        .position((310, 485))
+        .parent(&window)
+        .build(&mut save)?;
+
+    let save_handle = save.handle;
+    let choice = Rc::new(RefCell::new(None));
+    let handler = {
+        let choice = choice.clone();
+        nwg::full_bind_event_handler(&window.handle, move |event, _, handle| match event {
+            nwg::Event::OnButtonClick if handle == save_handle => {
+                let profile = profiles.selection().map(|index| PROFILES[index]);
+                let level = levels.selection().map(|index| LEVELS[index]);
+                let checked =
+                    |index: usize| categories[index].check_state() == nwg::CheckBoxState::Checked;
+                let privacy = PrivacyCategories {
+                    telemetry: checked(0),
+                    http_bodies: checked(1),
+                    messages: checked(2),
+                };
+                *choice.borrow_mut() = Some((
+                    profile.unwrap_or_default(),
+                    level.unwrap_or_default(),
+                    privacy,
+                ));
+                nwg::stop_thread_dispatch();
+            }
+            nwg::Event::OnWindowClose => nwg::stop_thread_dispatch(),
+            _ => {}
+        })
+    };
+
+    nwg::dispatch_thread_events();
+    nwg::unbind_event_handler(&handler);
+
+    let choice = choice.borrow_mut().take();
+    Ok(choice)
+}
+
+/// Shows the data review window on its own thread, listing the capture
+/// sessions in the dump directory with a preview of the selected session
+/// and buttons to view its packets and to securely delete it
+#[cfg(feature = "plugin")]
+pub fn show_data_review() {
+    spawn_thread("data-review", || {
+        if let Err(err) = run_data_review() {
+            log::error!("Failed to show data review: {}", err);
+        }
+    });
+}
+
+#[cfg(feature = "plugin")]
+fn run_data_review() -> Result<(), native_windows_gui::NwgError> {
+    use native_windows_gui as nwg;
+    use std::{cell::RefCell, rc::Rc};
+
+    nwg::init()?;
+
+    let mut window = nwg::Window::default();
+    nwg::Window::builder()
+        .size((860, 560))
+        .position((300, 200))
+        .title(&locale::text("review.title"))
+        .build(&mut window)?;
+
+    let mut sessions_list = nwg::ListBox::default();
+    nwg::ListBox::builder()
+        .size((300, 490))
+        .position((10, 10))
+        .parent(&window)
+        .build(&mut sessions_list)?;
+
+    let mut preview = nwg::TextBox::default();
+    nwg::TextBox::builder()
+        .readonly(true)
+        .size((530, 490))
+        .position((320, 10))
+        .parent(&window)
+        .build(&mut preview)?;
+
+    let mut delete = nwg::Button::default();
+    nwg::Button::builder()
+        .text(&locale::text("review.delete"))
+        .size((200, 40))
+        .position((10, 510))
+        .parent(&window)
+        .build(&mut delete)?;
+
+    let mut packets = nwg::Button::default();
+    nwg::Button::builder()
+        .text(&locale::text("review.packets"))
+        .size((200, 40))
+        .position((220, 510))
+        .parent(&window)
+        .build(&mut packets)?;
+
+    let sessions: Rc<RefCell<Vec<SessionSummary>>> = Rc::new(RefCell::new(Vec::new()));
+    let sessions_list = Rc::new(sessions_list);
+
+    // Reloads the sessions from the dump directory into the list
+    let refresh = {
+        let sessions = sessions.clone();
+        let sessions_list = sessions_list.clone();
+        move || {
+            let loaded = review::list_sessions().unwrap_or_else(|err| {
+                log::error!("Failed to list capture sessions: {}", err);
+                Vec::new()
+            });
+            sessions_list.set_collection(loaded.iter().map(session_label).collect());
+            *sessions.borrow_mut() = loaded;
+        }
+    };
+    refresh();
+
+    let (list_handle, delete_handle, packets_handle) =
+        (sessions_list.handle, delete.handle, packets.handle);
+    let handler = nwg::full_bind_event_handler(&window.handle, move |event, _, handle| {
+        match event {
+            nwg::Event::OnListBoxSelect if handle == list_handle => {
+                let sessions = sessions.borrow();
+                let Some(session) = sessions_list
+                    .selection()
+                    .and_then(|index| sessions.get(index))
+                else {
+                    return;
+                };
+                let text = review::preview_session(session).unwrap_or_else(|err| err.to_string());
+                // Multi-line text boxes require CRLF line endings
+                preview.set_text(&text.replace('\n', "\r\n"));
+            }
+            nwg::Event::OnButtonClick if handle == packets_handle => {
+                let sessions = sessions.borrow();
+                if let Some(session) = sessions_list
+                    .selection()
+                    .and_then(|index| sessions.get(index))
+                {
+                    show_packet_viewer(session.path.clone());
+                }
+            }
+            nwg::Event::OnButtonClick if handle == delete_handle => {
+                let path = {
+                    let sessions = sessions.borrow();
+                    let Some(session) = sessions_list
+                        .selection()
+                        .and_then(|index| sessions.get(index))
+                    else {
+                        return;
+                    };
+                    session.path.clone()
+                };
+
+                let choice = message(&MessageParams {
+                    title: &locale::text("review.confirm.title"),
+                    content: &locale::format(
+                        "review.confirm.message",
+                        &[("path", &path.display().to_string())],
+                    ),
+                    buttons: MessageButtons::YesNo,
+                    icons: MessageIcons::Warning,
+                });
+                if !matches!(choice, MessageChoice::Yes) {
+                    return;
+                }
+
+                if let Err(err) = review::delete_session(&path) {
+                    nwg::modal_error_message(
+                        handle,
+                        &locale::text("review.failed.title"),
+                        &err.to_string(),
+                    );
+                }
+                preview.set_text("");
+                refresh();
+            }
+            nwg::Event::OnWindowClose => nwg::stop_thread_dispatch(),
+            _ => {}
+        }
+    });
+
+    nwg::dispatch_thread_events();
+    nwg::unbind_event_handler(&handler);
+    Ok(())
+}
+
+/// Shows the packet viewer window for the session in `dir` on its own
+/// thread, listing its packets with a hex dump of the selected packet next
+/// to the stringified packet for checking decoded values against the bytes
+#[cfg(feature = "plugin")]
+pub fn show_packet_viewer(dir: PathBuf) {
+    spawn_thread("packet-viewer", move || {
+        if let Err(err) = run_packet_viewer(dir) {
+            log::error!("Failed to show packet viewer: {}", err);
+        }
+    });
+}
+
+#[cfg(feature = "plugin")]
+fn run_packet_viewer(dir: PathBuf) -> Result<(), native_windows_gui::NwgError> {
+    use native_windows_gui as nwg;
+
+    nwg::init()?;
+
+    let records = read_packet_log(&dir).unwrap_or_else(|err| {
+        log::error!("Failed to read packet log: {}", err);
+        Vec::new()
+    });
+
+    let mut window = nwg::Window::default();
+    nwg::Window::builder()
+        .size((1240, 640))
+        .position((250, 150))
+        .title(&locale::format(
+            "viewer.title",
+            &[("session", &dir.display().to_string())],
+        ))
+        .build(&mut window)?;
+
+    let mut packets_list = nwg::ListBox::default();
+    nwg::ListBox::builder()
+        .size((360, 620))
+        .position((10, 10))
+        .collection(records.iter().map(packet_label).collect())
+        .parent(&window)
+        .build(&mut packets_list)?;
+
+    // The hex dump columns only line up in a fixed width font
+    let mut font = nwg::Font::default();
+    nwg::Font::builder()
+        .family("Consolas")
+        .size(15)
+        .build(&mut font)?;
+
+    let mut view = nwg::TextBox::default();
+    nwg::TextBox::builder()
+        .readonly(true)
+        .flags(nwg::TextBoxFlags::VISIBLE | nwg::TextBoxFlags::VSCROLL | nwg::TextBoxFlags::HSCROLL)
+        .font(Some(&font))
+        .size((850, 620))
+        .position((380, 10))
+        .parent(&window)
+        .build(&mut view)?;
+
+    let list_handle = packets_list.handle;
+    let handler = nwg::full_bind_event_handler(&window.handle, move |event, _, handle| {
+        match event {
+            nwg::Event::OnListBoxSelect if handle == list_handle => {
+                let Some(record) = packets_list
+                    .selection()
+                    .and_then(|index| records.get(index))
+                else {
+                    return;
+                };
+
+                let mut text = side_by_side(&record.to_packet());
+                if record.contents.is_none() {
+                    text.push_str(&locale::text("viewer.no_contents"));
+                }
+                // Multi-line text boxes require CRLF line endings
+                view.set_text(&text.replace('\n', "\r\n"));
+            }
+            nwg::Event::OnWindowClose => nwg::stop_thread_dispatch(),
+            _ => {}
+        }
+    });
+
+    nwg::dispatch_thread_events();
+    nwg::unbind_event_handler(&handler);
+    Ok(())
+}
+
+/// Label for a packet in the packet viewer list
+#[cfg(feature = "plugin")]
+fn packet_label(record: &PacketRecord) -> String {
+    let ty = FrameType::from(record.ty);
+    let key = component_key(record.component, record.command);
+    let component = get_component_name(record.component).unwrap_or("Unknown");
+    let command = get_command_name(key, ty == FrameType::Notify).unwrap_or("Unknown");
+    let direction = match record.direction {
+        Direction::Send => "->",
+        Direction::Receive => "<-",
+    };
+    format!(
+        "#{} {} {:?} ({}) {}->{}",
+        record.index, direction, ty, record.seq, component, command
+    )
+}
+
+/// Label for a session in the review list
+#[cfg(feature = "plugin")]
+fn session_label(session: &SessionSummary) -> String {
+    let mut label = format!(
+        "{} ({:.1} MB)",
+        session.name,
+        session.size as f64 / (1024.0 * 1024.0)
+    );
+    if session.current {
+        label.push_str(&locale::text("review.current"));
+    }
+    if session.queued_for_upload {
+        label.push_str(&locale::text("review.queued"));
+    }
+    label
+}
+
+#[cfg(not(feature = "plugin"))]
+pub fn show_warning(title: &str, content: &str) {
+    log::warn!("{}: {}", title, content);
+}
+
+#[cfg(not(feature = "plugin"))]
+pub fn show_notification(title: &str, content: &str) {
+    log::info!("{}: {}", title, content);
+}
+
+/// Without an editor window packets are forwarded unchanged
+#[cfg(not(feature = "plugin"))]
+pub fn show_editor<V, F>(_title: &str, _content: &str, _text: &str, _validate: V, on_choice: F)
+where
+    V: Fn(&str) -> Option<String> + Send + 'static,
+    F: FnOnce(EditorChoice) + Send + 'static,
+{
+    on_choice(EditorChoice::Forward);
+}
+
+#[cfg(not(feature = "plugin"))]
+pub fn show_data_review() {
+    log::warn!("The data review window is only available in the plugin");
+}
+
+#[cfg(not(feature = "plugin"))]
+pub fn show_packet_viewer(_dir: std::path::PathBuf) {
+    log::warn!("The packet viewer window is only available in the plugin");
+}
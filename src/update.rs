@@ -0,0 +1,156 @@
+//! Check for newer releases of the plugin on startup. Outdated collectors
+//! produce captures with known decoding bugs so users are notified when a
+//! release compatible with their game build is available. The check is
+//! only made if the user opts in through the config
+
+use crate::{
+    capture::capture,
+    config::config,
+    consent::{self, ActiveFeature, ConsentError},
+    constants::APP_VERSION,
+    fingerprint::file_version,
+    locale, tasks, ui,
+};
+use log::{debug, info, warn};
+use reqwest::header::USER_AGENT;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Default endpoint listing the plugin releases
+pub const DEFAULT_UPDATE_ENDPOINT: &str =
+    "https://api.github.com/repos/PocketRelay/PocketRelayDataCollectPlugin/releases";
+
+/// Prefix of the line in the release notes listing the game versions the
+/// release supports, releases without the line support every version
+const GAME_VERSIONS_PREFIX: &str = "Game versions:";
+
+/// Release from the release endpoint
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    /// Tag of the release, the version with an optional "v" prefix
+    pub tag_name: String,
+    /// Page the release can be downloaded from
+    pub html_url: String,
+    /// Release notes
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+}
+
+impl Release {
+    /// Parsed version of the release, None if the tag isn't a version
+    pub fn version(&self) -> Option<Vec<u64>> {
+        parse_version(&self.tag_name)
+    }
+
+    /// Whether the release supports the game version, releases that don't
+    /// list game versions support every version
+    pub fn supports(&self, game_version: Option<&str>) -> bool {
+        let Some(line) = self.body.as_deref().and_then(|body| {
+            body.lines()
+                .find_map(|line| line.trim().strip_prefix(GAME_VERSIONS_PREFIX))
+        }) else {
+            return true;
+        };
+
+        // Unknown game versions can't be ruled out
+        let Some(game_version) = game_version else {
+            return true;
+        };
+        line.split(',')
+            .any(|version| version.trim() == game_version)
+    }
+}
+
+/// Errors that can occur checking for updates
+#[derive(Debug, Error)]
+pub enum UpdateError {
+    #[error(transparent)]
+    Consent(#[from] ConsentError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+/// Parses a version such as "v1.2.3" into its numeric parts
+pub fn parse_version(value: &str) -> Option<Vec<u64>> {
+    value
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Checks for updates in the background if the user opted in
+pub fn start() {
+    if consent::require(ActiveFeature::UpdateCheck).is_err() {
+        return;
+    }
+
+    tasks::spawn("update-check", async {
+        match check().await {
+            Ok(Some(release)) => notify(&release),
+            Ok(None) => debug!("Plugin is up to date"),
+            Err(err) => warn!("Failed to check for plugin updates: {}", err),
+        }
+    });
+}
+
+/// Finds the newest release newer than the running plugin that supports
+/// the game build, None if the plugin is up to date
+pub async fn check() -> Result<Option<Release>, UpdateError> {
+    consent::require(ActiveFeature::UpdateCheck)?;
+
+    let releases: Vec<Release> = reqwest::Client::new()
+        .get(&config().update_endpoint)
+        .header(USER_AGENT, format!("pocket-relay-dump/{}", APP_VERSION))
+        .timeout(config().request_timeout())
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let current = parse_version(APP_VERSION).unwrap_or_default();
+    let game_version = std::env::current_exe()
+        .ok()
+        .and_then(|path| unsafe { file_version(&path) });
+
+    Ok(releases
+        .into_iter()
+        .filter(|release| !release.draft && !release.prerelease)
+        .filter(|release| release.supports(game_version.as_deref()))
+        .filter_map(|release| Some((release.version()?, release)))
+        .filter(|(version, _)| *version > current)
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, release)| release))
+}
+
+/// Notifies the user about the newer release
+fn notify(release: &Release) {
+    info!(
+        "Plugin update {} is available: {}",
+        release.tag_name, release.html_url
+    );
+
+    if let Some(capture) = capture() {
+        capture.record_event(
+            "update",
+            &format!("Plugin update {} is available", release.tag_name),
+        );
+    }
+
+    ui::show_notification(
+        &locale::text("update.title"),
+        &locale::format(
+            "update.message",
+            &[
+                ("version", release.tag_name.as_str()),
+                ("url", release.html_url.as_str()),
+            ],
+        ),
+    );
+}
@@ -0,0 +1,66 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Distribution of the game that is running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum GameVariant {
+    /// Release distributed through Origin
+    Origin,
+    /// Release distributed through Steam
+    Steam,
+    /// Build with the DRM removed or emulated
+    Cracked,
+    /// Distribution could not be identified
+    Unknown,
+}
+
+/// Files placed next to the executable by Steam
+const STEAM_FILES: &[&str] = &["steam_api.dll", "steam_appid.txt"];
+
+/// Files placed next to the executable by common DRM emulators
+const CRACKED_FILES: &[&str] = &[
+    "steam_emu.ini",
+    "3dmgame.dll",
+    "ALI213.ini",
+    "OriginEmu.ini",
+];
+
+/// Directories created by the Origin installer, these live in the game
+/// root two levels above the executable
+const ORIGIN_DIRS: &[&str] = &["__Installer"];
+
+impl GameVariant {
+    /// Identifies the distribution from the files installed alongside
+    /// the game executable. Emulator files are checked first as they
+    /// are usually installed over an existing distribution
+    pub fn detect() -> GameVariant {
+        let Some(dir) = std::env::current_exe()
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            return GameVariant::Unknown;
+        };
+
+        let exists = |dir: &Path, names: &[&str]| names.iter().any(|name| dir.join(name).exists());
+
+        let variant = if exists(&dir, CRACKED_FILES) {
+            GameVariant::Cracked
+        } else if exists(&dir, STEAM_FILES) {
+            GameVariant::Steam
+        } else if dir
+            .ancestors()
+            .nth(2)
+            .is_some_and(|root| exists(root, ORIGIN_DIRS))
+        {
+            GameVariant::Origin
+        } else {
+            GameVariant::Unknown
+        };
+
+        debug!("Detected game variant {:?} from {}", variant, dir.display());
+        variant
+    }
+}
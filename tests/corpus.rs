@@ -0,0 +1,123 @@
+//! Checks every packet in the sample corpus at `tests/fixtures/corpus`
+//! decodes, stringifies and converts to JSON, guarding the TDF decoding
+//! against regressions.
+//!
+//! The corpus is a packet log in the capture format recorded with the
+//! `redact` anonymization level, more packets can be added by appending
+//! lines from the `packets.jsonl` of a redacted capture
+
+use bytes::BytesMut;
+use pocket_relay_dump::{
+    capture::{
+        packet_log::PacketRecord,
+        read_jsonl,
+        value::{decode_fields, encode_fields, fields_to_json},
+    },
+    servers::{
+        components,
+        packet::{Packet, PacketCodec, PacketDebug},
+    },
+};
+use std::{path::Path, sync::Once};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Line written by [PacketDebug] when the contents couldn't be stringified
+const STRINGIFY_STOPPED: &str = "--- Decoding stopped";
+
+static INITIALIZE: Once = Once::new();
+
+/// Every packet in the corpus along with a label for failure messages
+fn corpus() -> Vec<(String, Packet)> {
+    INITIALIZE.call_once(components::initialize);
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/corpus/packets.jsonl");
+    let records: Vec<PacketRecord> = read_jsonl(&path).expect("Failed to read corpus");
+    assert!(!records.is_empty(), "Corpus is empty");
+
+    records
+        .iter()
+        .map(|record| {
+            assert!(
+                record.contents.is_some(),
+                "Corpus packet {} has no contents",
+                record.index
+            );
+            let packet = record.to_packet();
+            let label = format!(
+                "packet {} ({:#06x}->{:#06x} {:?})",
+                record.index, record.component, record.command, packet.frame.ty
+            );
+            (label, packet)
+        })
+        .collect()
+}
+
+#[test]
+fn corpus_packets_decode() {
+    for (label, packet) in corpus() {
+        let fields = decode_fields(&packet.contents)
+            .unwrap_or_else(|err| panic!("Failed to decode {}: {}", label, err));
+        assert_eq!(
+            encode_fields(&fields),
+            packet.contents,
+            "Re-encoding {} changed its contents",
+            label
+        );
+    }
+}
+
+#[test]
+fn corpus_packets_stringify() {
+    for (label, packet) in corpus() {
+        let output = format!(
+            "{:?}",
+            PacketDebug {
+                packet: &packet,
+                contents: true,
+            }
+        );
+        assert!(
+            !output.contains(STRINGIFY_STOPPED),
+            "Failed to stringify {}:\n{}",
+            label,
+            output
+        );
+    }
+}
+
+#[test]
+fn corpus_packets_convert_to_json() {
+    for (label, packet) in corpus() {
+        let fields = decode_fields(&packet.contents)
+            .unwrap_or_else(|err| panic!("Failed to decode {}: {}", label, err));
+        let value = fields_to_json(&fields);
+        let object = value
+            .as_object()
+            .unwrap_or_else(|| panic!("JSON for {} isn't an object", label));
+        assert_eq!(object.len(), fields.len(), "JSON for {} lost fields", label);
+        serde_json::to_string(&value)
+            .unwrap_or_else(|err| panic!("Failed to serialize JSON for {}: {}", label, err));
+    }
+}
+
+#[test]
+fn corpus_packets_keep_framing() {
+    for (label, packet) in corpus() {
+        let mut buffer = BytesMut::new();
+        PacketCodec
+            .encode(packet.clone(), &mut buffer)
+            .unwrap_or_else(|err| panic!("Failed to encode {}: {}", label, err));
+        let decoded = PacketCodec
+            .decode(&mut buffer)
+            .unwrap_or_else(|err| panic!("Failed to read {}: {}", label, err))
+            .unwrap_or_else(|| panic!("Encoded {} is incomplete", label));
+
+        assert!(buffer.is_empty(), "Encoded {} has trailing bytes", label);
+        assert_eq!(decoded.frame, packet.frame, "Frame of {} changed", label);
+        assert_eq!(
+            decoded.contents, packet.contents,
+            "Contents of {} changed",
+            label
+        );
+    }
+}
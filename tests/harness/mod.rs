@@ -0,0 +1,305 @@
+//! Test harness driving the main proxy and the capture pipeline over
+//! loopback. A scripted fake Blaze server stands in for the official
+//! server and a fake game client connects through a proxy session, so
+//! framing, capture and redaction can be checked without the game
+
+#![allow(dead_code)]
+
+use blaze_ssl_async::BlazeListener;
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use pocket_relay_dump::{
+    capture::{
+        self, anonymize::Anonymization, packet_log::PacketRecord, read_jsonl, writer, MANIFEST_FILE,
+    },
+    config::{self, Config},
+    servers::{
+        main::{proxy_session, SESSION_ID},
+        packet::{FrameType, Packet, PacketCodec},
+        reconnect::StreamTarget,
+        upstream::Upstream,
+    },
+};
+use std::{
+    future::Future,
+    path::PathBuf,
+    sync::{atomic::Ordering, OnceLock},
+    time::Duration,
+};
+use tokio::{
+    join,
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+    time::timeout,
+};
+use tokio_util::codec::Framed;
+
+/// Longest any single step of a test waits before failing
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Name of the fake upstream in the capture manifest
+pub const UPSTREAM_NAME: &str = "fake";
+
+/// Error code the fake server answers requests missing from its script with
+pub const UNSCRIPTED_ERROR: u16 = 0x1;
+
+static HARNESS: OnceLock<Harness> = OnceLock::new();
+
+/// Config and capture session shared by every test within a test binary,
+/// both are global to the process in the same way they are in the game
+pub struct Harness {
+    /// Directory the capture session is written to
+    pub session_dir: PathBuf,
+}
+
+/// Sets up the harness on first use. Captures are redacted so every test
+/// also covers identifiers being removed before reaching the disk
+pub fn harness() -> &'static Harness {
+    HARNESS.get_or_init(|| {
+        config::set(Config {
+            anonymization: Anonymization::Redact,
+            ..Default::default()
+        });
+
+        let root = std::env::temp_dir().join(format!("prdc-harness-{}", std::process::id()));
+        capture::initialize_in(&root);
+
+        let capture = capture::capture().expect("Capture session wasn't created");
+        Harness {
+            session_dir: capture.dir.clone(),
+        }
+    })
+}
+
+impl Harness {
+    /// Packets recorded in the packet log for the provided connection,
+    /// waits for the capture writer to finish writing queued packets
+    pub fn captured(&self, connection: u32) -> Vec<PacketRecord> {
+        writer::flush();
+        read_jsonl::<PacketRecord>(&self.session_dir.join(capture::packet_log::PACKETS_FILE))
+            .expect("Failed to read packet log")
+            .into_iter()
+            .filter(|record| record.connection == connection)
+            .collect()
+    }
+
+    /// The capture manifest as it currently is on disk
+    pub fn manifest(&self) -> serde_json::Value {
+        let bytes =
+            std::fs::read(self.session_dir.join(MANIFEST_FILE)).expect("Failed to read manifest");
+        serde_json::from_slice(&bytes).expect("Manifest isn't valid JSON")
+    }
+}
+
+/// Request the fake server knows how to answer
+pub struct Exchange {
+    pub component: u16,
+    pub command: u16,
+    /// Packets sent back in order when the request is received
+    pub replies: Vec<Reply>,
+}
+
+impl Exchange {
+    pub fn new(component: u16, command: u16, replies: Vec<Reply>) -> Self {
+        Self {
+            component,
+            command,
+            replies,
+        }
+    }
+}
+
+/// Packet sent by the fake server in reply to a request
+pub enum Reply {
+    /// Response to the request with the provided contents
+    Response(Bytes),
+    /// Empty error response to the request with the provided error code
+    Error(u16),
+    /// Notification for the component and command with the provided contents
+    Notify(u16, u16, Bytes),
+}
+
+impl Reply {
+    fn packet(&self, request: &Packet) -> Packet {
+        match self {
+            Reply::Response(contents) => Packet::new_response(request, contents.clone()),
+            Reply::Error(error) => Packet::error_empty(request, *error),
+            Reply::Notify(component, command, contents) => {
+                Packet::new_notify(*component, *command, contents.clone())
+            }
+        }
+    }
+}
+
+/// Fake official server accepting a single Blaze connection over SSLv3
+/// and answering requests from its script
+pub struct FakeServer {
+    pub port: u16,
+    /// Task serving the connection, completes with every packet received
+    task: JoinHandle<Vec<Packet>>,
+}
+
+impl FakeServer {
+    /// Starts the fake server on a free loopback port
+    pub async fn start(script: Vec<Exchange>) -> Self {
+        // Reserve a free port, the Blaze listener only binds fixed addresses
+        let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to find a free port")
+            .port();
+
+        let listener = BlazeListener::bind(("127.0.0.1", port), Default::default())
+            .await
+            .expect("Failed to bind fake server");
+
+        let task = tokio::spawn(async move {
+            let accept = listener
+                .accept()
+                .await
+                .expect("Fake server failed to accept");
+            let (stream, _) = accept
+                .finish_accept()
+                .await
+                .expect("Fake server handshake failed");
+            serve(Framed::new(stream, PacketCodec), script).await
+        });
+
+        Self { port, task }
+    }
+
+    /// The upstream directing proxy sessions to this server
+    pub fn upstream(&self) -> Upstream {
+        Upstream::Address {
+            name: Some(UPSTREAM_NAME.to_string()),
+            host: "127.0.0.1".to_string(),
+            port: self.port,
+        }
+    }
+
+    /// Waits for the proxy to close the connection, returns every packet
+    /// the server received
+    pub async fn received(self) -> Vec<Packet> {
+        within(self.task).await.expect("Fake server task panicked")
+    }
+}
+
+async fn serve<S>(mut framed: Framed<S, PacketCodec>, script: Vec<Exchange>) -> Vec<Packet>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut received = Vec::new();
+    while let Some(Ok(packet)) = framed.next().await {
+        if packet.frame.ty == FrameType::Request {
+            let exchange = script.iter().find(|exchange| {
+                exchange.component == packet.frame.component
+                    && exchange.command == packet.frame.command
+            });
+            let replies: Vec<Packet> = match exchange {
+                Some(exchange) => exchange
+                    .replies
+                    .iter()
+                    .map(|reply| reply.packet(&packet))
+                    .collect(),
+                None => vec![Packet::error_empty(&packet, UNSCRIPTED_ERROR)],
+            };
+            for reply in replies {
+                if framed.send(reply).await.is_err() {
+                    return received;
+                }
+            }
+        }
+        received.push(packet);
+    }
+    received
+}
+
+/// Fake game client connected through a proxy session to a fake server
+pub struct FakeClient {
+    /// ID of the proxied connection, used to find its captured packets
+    pub id: u32,
+    framed: Framed<TcpStream, PacketCodec>,
+    /// Task running the proxy session
+    session: JoinHandle<()>,
+    /// Sequence number of the next request
+    next_seq: u16,
+    /// Notifications received while waiting for responses
+    pub notifications: Vec<Packet>,
+}
+
+impl FakeClient {
+    /// Starts a proxy session to the provided server and connects to it
+    pub async fn connect(server: &FakeServer) -> Self {
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .expect("Failed to bind proxy listener");
+        let addr = listener
+            .local_addr()
+            .expect("Proxy listener has no address");
+
+        let (client, accepted) = join!(TcpStream::connect(addr), listener.accept());
+        let client = client.expect("Fake client failed to connect");
+        let (stream, _) = accepted.expect("Failed to accept fake client");
+
+        let id = SESSION_ID.fetch_add(1, Ordering::AcqRel);
+        let upstream = server.upstream();
+        let target = StreamTarget::Address("127.0.0.1".to_string(), server.port);
+        let session = tokio::spawn(async move {
+            proxy_session(id, stream, &upstream, target).await;
+        });
+
+        Self {
+            id,
+            framed: Framed::new(client, PacketCodec),
+            session,
+            next_seq: 1,
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Sends the packet to the proxy as is
+    pub async fn send(&mut self, packet: Packet) {
+        within(self.framed.send(packet))
+            .await
+            .expect("Fake client failed to send");
+    }
+
+    /// Reads the next packet from the proxy
+    pub async fn next(&mut self) -> Packet {
+        within(self.framed.next())
+            .await
+            .expect("Proxy closed the connection")
+            .expect("Fake client failed to read")
+    }
+
+    /// Sends a request and waits for its response, notifications received
+    /// in the meantime are kept in [FakeClient::notifications]
+    pub async fn request(&mut self, component: u16, command: u16, contents: Bytes) -> Packet {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.send(Packet::new_request(seq, component, command, contents))
+            .await;
+
+        loop {
+            let packet = self.next().await;
+            if packet.frame.ty == FrameType::Notify {
+                self.notifications.push(packet);
+                continue;
+            }
+            assert_eq!(packet.frame.seq, seq, "Response out of order");
+            return packet;
+        }
+    }
+
+    /// Disconnects from the proxy and waits for the session to end
+    pub async fn close(self) {
+        drop(self.framed);
+        within(self.session).await.expect("Proxy session panicked");
+    }
+}
+
+/// Awaits the future, failing the test if it takes longer than [TIMEOUT]
+async fn within<F: Future>(future: F) -> F::Output {
+    timeout(TIMEOUT, future)
+        .await
+        .expect("Timed out waiting on the proxy")
+}
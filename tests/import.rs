@@ -0,0 +1,127 @@
+//! Imports the sample pcap at `tests/fixtures/import/blaze.pcap` checking
+//! the reassembled Blaze packets and that other traffic is left out.
+//!
+//! The capture holds one Blaze connection on the redirector port with a
+//! response split over out of order and retransmitted segments, an HTTP
+//! connection on port 80 and an HTTP connection on the main server port
+
+use pocket_relay_dump::capture::{
+    import::{import_pcap, ImportError, ImportSummary, DEFAULT_PORTS},
+    packet_log::{PacketRecord, PACKETS_FILE},
+    read_jsonl, Direction,
+};
+use std::path::{Path, PathBuf};
+
+/// Offset of the first record header in the fixture
+const FIRST_RECORD: usize = 24;
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/import/blaze.pcap")
+}
+
+/// Fresh directory named `run` for the outputs of a test
+fn output_dir(run: &str) -> PathBuf {
+    let output = std::env::temp_dir()
+        .join(format!("prdc-import-{}", std::process::id()))
+        .join(run);
+    if output.exists() {
+        std::fs::remove_dir_all(&output).expect("Failed to clear import output");
+    }
+    output
+}
+
+/// Imports the pcap at `input` returning the summary and imported packets
+fn import(
+    input: &Path,
+    run: &str,
+    ports: &[u16],
+) -> Result<(ImportSummary, Vec<PacketRecord>), ImportError> {
+    let output = output_dir(run);
+    let summary = import_pcap(input, &output, ports)?;
+    let records = read_jsonl(&output.join(PACKETS_FILE)).expect("Failed to read packets");
+    Ok((summary, records))
+}
+
+/// Imports a copy of the fixture after `patch` modifies its bytes
+fn import_patched(run: &str, patch: impl FnOnce(&mut Vec<u8>)) -> Result<(), ImportError> {
+    let mut data = std::fs::read(fixture()).expect("Failed to read fixture");
+    patch(&mut data);
+
+    let input = output_dir(run).with_extension("pcap");
+    std::fs::create_dir_all(input.parent().expect("Missing parent")).expect("Failed to create dir");
+    std::fs::write(&input, data).expect("Failed to write patched fixture");
+
+    import(&input, run, &DEFAULT_PORTS).map(|_| ())
+}
+
+#[test]
+fn imports_blaze_connection() {
+    let (summary, records) = import(&fixture(), "default", &DEFAULT_PORTS).unwrap();
+
+    assert_eq!(summary.connections, 1);
+    assert_eq!(summary.packets, 3);
+    assert_eq!(summary.rejected, 1, "HTTP on the main port wasn't rejected");
+
+    let packets: Vec<_> = records
+        .iter()
+        .map(|record| (record.direction, record.ty, record.component, record.seq))
+        .collect();
+    assert_eq!(
+        packets,
+        [
+            (Direction::Send, 0, 0x5, 1),
+            (Direction::Receive, 1, 0x5, 1),
+            (Direction::Receive, 2, 0x7802, 0),
+        ]
+    );
+    assert!(records.iter().all(|record| record.connection == 1));
+}
+
+#[test]
+fn reassembles_split_segments() {
+    let (_, records) = import(&fixture(), "reassembly", &DEFAULT_PORTS).unwrap();
+
+    let response = &records[1];
+    assert_eq!(response.length, 20);
+    assert_eq!(
+        response.contents.as_deref(),
+        Some("000102030405060708090a0b0c0d0e0f10111213")
+    );
+}
+
+#[test]
+fn skips_other_ports() {
+    let (summary, records) = import(&fixture(), "redirector", &[42127]).unwrap();
+    assert_eq!(summary.packets, 3);
+    assert_eq!(summary.rejected, 0);
+    assert_eq!(records.len(), 3);
+
+    let (summary, records) = import(&fixture(), "http", &[80]).unwrap();
+    assert_eq!(summary.connections, 0);
+    assert_eq!(summary.rejected, 1);
+    assert!(records.is_empty());
+}
+
+#[test]
+fn rejects_unsupported_version() {
+    let result = import_patched("version", |data| data[4] = 3);
+    assert!(matches!(result, Err(ImportError::UnsupportedVersion(3, 4))));
+}
+
+#[test]
+fn rejects_implausible_record_length() {
+    // Captured length larger than the original length
+    let result = import_patched("length", |data| {
+        data[FIRST_RECORD + 8..FIRST_RECORD + 12].copy_from_slice(&100u32.to_le_bytes());
+    });
+    assert!(matches!(
+        result,
+        Err(ImportError::ImplausibleRecord(FIRST_RECORD))
+    ));
+}
+
+#[test]
+fn rejects_non_pcap() {
+    let result = import_patched("magic", |data| data[..4].copy_from_slice(b"GET "));
+    assert!(matches!(result, Err(ImportError::InvalidMagic)));
+}
@@ -0,0 +1,285 @@
+//! Drives proxy sessions between a fake game client and a scripted fake
+//! server, checking packets are forwarded unchanged and captured with
+//! identifiers redacted
+
+mod harness;
+
+use bytes::Bytes;
+use harness::{harness, Exchange, FakeClient, FakeServer, Reply, UNSCRIPTED_ERROR, UPSTREAM_NAME};
+use pocket_relay_dump::{
+    capture::{
+        value::{decode_fields, encode_fields, from_hex, to_hex, Field, TdfValue},
+        Direction,
+    },
+    servers::{
+        components::{authentication, user_sessions, util},
+        packet::FrameType,
+    },
+};
+
+fn field(tag: &str, value: TdfValue) -> Field {
+    Field {
+        tag: tag.to_string(),
+        value,
+    }
+}
+
+fn contents(fields: Vec<Field>) -> Bytes {
+    Bytes::from(encode_fields(&fields))
+}
+
+/// Contents without any identifying fields, captured unchanged
+fn pre_auth_contents() -> Bytes {
+    contents(vec![
+        field("CVER", TdfValue::String("ME3 1.5".into())),
+        field("LOCL", TdfValue::VarInt(1701729619)),
+    ])
+}
+
+/// Login response holding every kind of identifier the capture redacts
+fn login_contents() -> Bytes {
+    contents(vec![
+        field("LDHT", TdfValue::String(String::new())),
+        field(
+            "SESS",
+            TdfValue::Group(vec![
+                field("BUID", TdfValue::VarInt(978651371)),
+                field("MAIL", TdfValue::String("test@example.com".into())),
+                field(
+                    "PDTL",
+                    TdfValue::Group(vec![
+                        field("DSNM", TdfValue::String("Player".into())),
+                        field("PID", TdfValue::VarInt(978651371)),
+                    ]),
+                ),
+            ]),
+        ),
+    ])
+}
+
+/// Finds the field at the provided tag path
+fn find<'a>(fields: &'a [Field], path: &[&str]) -> &'a TdfValue {
+    let (tag, rest) = path.split_first().expect("Empty tag path");
+    let field = fields
+        .iter()
+        .find(|field| field.tag.trim_end() == *tag)
+        .unwrap_or_else(|| panic!("Missing field {}", tag));
+    match (&field.value, rest.is_empty()) {
+        (value, true) => value,
+        (TdfValue::Group(fields), false) => find(fields, rest),
+        (value, false) => panic!("Field {} isn't a group: {:?}", tag, value),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forwards_responses_and_notifications() {
+    harness();
+    let notify = contents(vec![field("FLGS", TdfValue::VarInt(3))]);
+    let server = FakeServer::start(vec![Exchange::new(
+        util::COMPONENT,
+        util::PRE_AUTH,
+        vec![
+            Reply::Notify(
+                user_sessions::COMPONENT,
+                user_sessions::USER_ADDED,
+                notify.clone(),
+            ),
+            Reply::Response(pre_auth_contents()),
+        ],
+    )])
+    .await;
+    let mut client = FakeClient::connect(&server).await;
+
+    let response = client
+        .request(util::COMPONENT, util::PRE_AUTH, pre_auth_contents())
+        .await;
+    assert_eq!(response.frame.ty, FrameType::Response);
+    assert_eq!(response.frame.component, util::COMPONENT);
+    assert_eq!(response.frame.command, util::PRE_AUTH);
+    assert_eq!(response.contents, pre_auth_contents());
+
+    assert_eq!(client.notifications.len(), 1);
+    let notification = &client.notifications[0];
+    assert_eq!(notification.frame.component, user_sessions::COMPONENT);
+    assert_eq!(notification.frame.command, user_sessions::USER_ADDED);
+    assert_eq!(notification.contents, notify);
+
+    client.close().await;
+
+    let received = server.received().await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].frame.seq, 1);
+    assert_eq!(received[0].contents, pre_auth_contents());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forwards_error_responses() {
+    harness();
+    let server = FakeServer::start(vec![Exchange::new(
+        authentication::COMPONENT,
+        authentication::LOGIN,
+        vec![Reply::Error(0xB)],
+    )])
+    .await;
+    let mut client = FakeClient::connect(&server).await;
+
+    let response = client
+        .request(
+            authentication::COMPONENT,
+            authentication::LOGIN,
+            Bytes::new(),
+        )
+        .await;
+    assert_eq!(response.frame.ty, FrameType::Error);
+    assert_eq!(response.frame.error, 0xB);
+
+    let response = client
+        .request(util::COMPONENT, util::PING, Bytes::new())
+        .await;
+    assert_eq!(response.frame.error, UNSCRIPTED_ERROR);
+
+    client.close().await;
+    assert_eq!(server.received().await.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keeps_framing_of_jumbo_packets() {
+    let harness = harness();
+    // Larger than the 16 bit length field so the extended length is used
+    let blob = contents(vec![field("DATA", TdfValue::Blob(vec![0x5A; 0x12000]))]);
+    let server = FakeServer::start(vec![Exchange::new(
+        util::COMPONENT,
+        util::PRE_AUTH,
+        vec![Reply::Response(blob.clone())],
+    )])
+    .await;
+    let mut client = FakeClient::connect(&server).await;
+    let id = client.id;
+
+    let response = client
+        .request(util::COMPONENT, util::PRE_AUTH, blob.clone())
+        .await;
+    assert_eq!(response.contents, blob);
+
+    // The connection still frames packets correctly afterwards
+    let response = client
+        .request(util::COMPONENT, util::PRE_AUTH, pre_auth_contents())
+        .await;
+    assert_eq!(response.contents, blob);
+
+    client.close().await;
+    assert_eq!(server.received().await[0].contents, blob);
+
+    let captured = harness.captured(id);
+    assert_eq!(captured.len(), 4);
+    assert_eq!(captured[0].length, blob.len());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn captures_session() {
+    let harness = harness();
+    let server = FakeServer::start(vec![Exchange::new(
+        util::COMPONENT,
+        util::PRE_AUTH,
+        vec![Reply::Response(pre_auth_contents())],
+    )])
+    .await;
+    let mut client = FakeClient::connect(&server).await;
+    let id = client.id;
+
+    client
+        .request(util::COMPONENT, util::PRE_AUTH, pre_auth_contents())
+        .await;
+    client
+        .request(util::COMPONENT, util::PRE_AUTH, pre_auth_contents())
+        .await;
+    client.close().await;
+    server.received().await;
+
+    let captured = harness.captured(id);
+    let directions: Vec<Direction> = captured.iter().map(|record| record.direction).collect();
+    assert_eq!(
+        directions,
+        [
+            Direction::Send,
+            Direction::Receive,
+            Direction::Send,
+            Direction::Receive
+        ]
+    );
+    for (record, seq) in captured.iter().zip([1, 1, 2, 2]) {
+        assert_eq!(record.component, util::COMPONENT);
+        assert_eq!(record.command, util::PRE_AUTH);
+        assert_eq!(record.seq, seq);
+        assert_eq!(
+            record.contents.as_deref(),
+            Some(to_hex(&pre_auth_contents()).as_str())
+        );
+    }
+
+    let manifest = harness.manifest();
+    let session = manifest["sessions"]
+        .as_array()
+        .expect("Manifest has no sessions")
+        .iter()
+        .find(|session| session["id"] == id)
+        .expect("Session missing from manifest");
+    assert_eq!(session["upstream"], UPSTREAM_NAME);
+    assert_eq!(session["packets_sent"], 2);
+    assert_eq!(session["packets_received"], 2);
+    assert_eq!(session["failed"], false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redacts_identifiers_in_capture() {
+    let harness = harness();
+    let server = FakeServer::start(vec![Exchange::new(
+        authentication::COMPONENT,
+        authentication::LOGIN,
+        vec![Reply::Response(login_contents())],
+    )])
+    .await;
+    let mut client = FakeClient::connect(&server).await;
+    let id = client.id;
+
+    // The game still receives the identifiers
+    let response = client
+        .request(
+            authentication::COMPONENT,
+            authentication::LOGIN,
+            Bytes::new(),
+        )
+        .await;
+    assert_eq!(response.contents, login_contents());
+
+    client.close().await;
+    server.received().await;
+
+    let captured = harness.captured(id);
+    let record = captured
+        .iter()
+        .find(|record| record.direction == Direction::Receive)
+        .expect("Login response wasn't captured");
+    let contents = from_hex(
+        record
+            .contents
+            .as_deref()
+            .expect("Contents weren't captured"),
+    )
+    .expect("Captured contents aren't hex");
+    let fields = decode_fields(&contents).expect("Captured contents don't decode");
+
+    assert_eq!(
+        find(&fields, &["SESS", "MAIL"]),
+        &TdfValue::String(String::new())
+    );
+    assert_eq!(find(&fields, &["SESS", "BUID"]), &TdfValue::VarInt(0));
+    assert_eq!(
+        find(&fields, &["SESS", "PDTL", "DSNM"]),
+        &TdfValue::String(String::new())
+    );
+    assert_eq!(
+        find(&fields, &["SESS", "PDTL", "PID"]),
+        &TdfValue::VarInt(0)
+    );
+}
@@ -0,0 +1,164 @@
+//! Golden file tests replaying the sample sessions in `tests/fixtures/sessions`
+//! through the capture decoders and comparing the outputs against the
+//! expected outputs stored alongside each session.
+//!
+//! After an intended change to a decoder's output the expected outputs can
+//! be regenerated by running the tests with `PRDC_BLESS=1`
+
+use pocket_relay_dump::{capture::replay::replay_session, servers::components};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Once,
+};
+
+/// Directory of the expected outputs within a sample session
+const EXPECTED_DIR: &str = "expected";
+
+/// Start of the header line written to every JSONL file
+const JSONL_HEADER_START: &str = "{\"header\":";
+
+static INITIALIZE: Once = Once::new();
+
+fn sessions_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sessions")
+}
+
+/// Replays the sample session into a fresh output directory named `run`
+fn replay(name: &str, run: &str) -> PathBuf {
+    // Only the built-in names are loaded so outputs don't depend on the
+    // definitions in the documents folder
+    INITIALIZE.call_once(components::initialize);
+
+    let output = std::env::temp_dir()
+        .join(format!("prdc-replay-{}", std::process::id()))
+        .join(run);
+    if output.exists() {
+        std::fs::remove_dir_all(&output).expect("Failed to clear replay output");
+    }
+
+    let summary = replay_session(&sessions_dir().join(name), &output)
+        .unwrap_or_else(|err| panic!("Failed to replay {}: {}", name, err));
+    assert!(
+        summary.failed.is_empty(),
+        "Decoders failed replaying {}: {:?}",
+        name,
+        summary.failed
+    );
+    output
+}
+
+/// Paths of every file within `dir` relative to it
+fn files(dir: &Path) -> BTreeSet<PathBuf> {
+    fn visit(root: &Path, dir: &Path, out: &mut BTreeSet<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries {
+            let path = entry.expect("Failed to read directory entry").path();
+            if path.is_dir() {
+                visit(root, &path, out);
+            } else {
+                let relative = path.strip_prefix(root).expect("Path outside of root");
+                out.insert(relative.to_path_buf());
+            }
+        }
+    }
+
+    let mut out = BTreeSet::new();
+    visit(dir, dir, &mut out);
+    out
+}
+
+/// Records of a JSONL file, the header line holds the build that wrote the
+/// file so it's left out of the comparison
+fn jsonl_records(contents: &str) -> Vec<serde_json::Value> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with(JSONL_HEADER_START))
+        .map(|line| serde_json::from_str(line).expect("Invalid JSONL record"))
+        .collect()
+}
+
+/// Contents of an output file in the form it's compared in
+fn normalize(path: &Path) -> String {
+    let contents = std::fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+    let value = match path.extension().and_then(|value| value.to_str()) {
+        Some("jsonl") => serde_json::Value::Array(jsonl_records(&contents)),
+        Some("json") => serde_json::from_str(&contents).expect("Invalid JSON output"),
+        _ => return contents,
+    };
+    serde_json::to_string_pretty(&value).expect("Failed to format JSON")
+}
+
+/// Replaces the expected outputs of the session with the replay outputs
+fn bless(name: &str, output: &Path) {
+    let expected = sessions_dir().join(name).join(EXPECTED_DIR);
+    if expected.exists() {
+        std::fs::remove_dir_all(&expected).expect("Failed to clear expected outputs");
+    }
+    for file in files(output) {
+        let target = expected.join(&file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).expect("Failed to create expected directory");
+        }
+
+        let contents = std::fs::read_to_string(output.join(&file)).expect("Failed to read output");
+        let contents = match file.extension().and_then(|value| value.to_str()) {
+            Some("jsonl") => contents
+                .lines()
+                .filter(|line| !line.starts_with(JSONL_HEADER_START))
+                .map(|line| format!("{}\n", line))
+                .collect(),
+            _ => contents,
+        };
+        std::fs::write(target, contents).expect("Failed to write expected output");
+    }
+}
+
+fn check_session(name: &str) {
+    let output = replay(name, name);
+    if std::env::var_os("PRDC_BLESS").is_some() {
+        bless(name, &output);
+        return;
+    }
+
+    let expected = sessions_dir().join(name).join(EXPECTED_DIR);
+    let expected_files = files(&expected);
+    assert_eq!(
+        files(&output),
+        expected_files,
+        "Replaying {} produced a different set of outputs",
+        name
+    );
+
+    for file in expected_files {
+        assert_eq!(
+            normalize(&output.join(&file)),
+            normalize(&expected.join(&file)),
+            "Replaying {} produced a different {}",
+            name,
+            file.display()
+        );
+    }
+}
+
+#[test]
+fn replays_lobby_session() {
+    check_session("lobby");
+}
+
+#[test]
+fn replays_are_repeatable() {
+    let first = replay("lobby", "lobby-first");
+    let second = replay("lobby", "lobby-second");
+    for file in files(&first) {
+        assert_eq!(
+            normalize(&first.join(&file)),
+            normalize(&second.join(&file)),
+            "Replay output {} changed between runs",
+            file.display()
+        );
+    }
+}
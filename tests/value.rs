@@ -0,0 +1,32 @@
+//! Checks the generic TDF value decoding rejects malformed contents
+//! without panicking
+
+use pocket_relay_dump::capture::value::{
+    decode_fields, encode_fields, Field, TdfValue, ValueError,
+};
+
+/// Contents of a group nested `depth` groups deep, built from the bytes
+/// of an empty group so deep nesting doesn't need deeply nested values
+fn nested_groups(depth: usize) -> Vec<u8> {
+    let empty = encode_fields(&[Field {
+        tag: "GRP".to_string(),
+        value: TdfValue::Group(Vec::new()),
+    }]);
+    let (start, end) = empty.split_at(empty.len() - 1);
+
+    let mut contents = start.repeat(depth);
+    contents.extend_from_slice(&end.repeat(depth));
+    contents
+}
+
+#[test]
+fn decodes_nested_groups() {
+    let fields = decode_fields(&nested_groups(32)).expect("Failed to decode nested groups");
+    assert_eq!(fields.len(), 1);
+}
+
+#[test]
+fn rejects_deeply_nested_groups() {
+    let result = decode_fields(&nested_groups(10_000));
+    assert!(matches!(result, Err(ValueError::TooDeep(_))));
+}